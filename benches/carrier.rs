@@ -0,0 +1,317 @@
+//! Carrier benchmarks.
+//!
+//! The carriers communicate over the in-memory transport, and the TLS
+//! handshakes run over loopback sockets, to keep the network out of the
+//! measurements. Run with `cargo bench --features test-util`, and with the
+//! `bincode` feature to compare the codecs.
+
+#![warn(clippy::pedantic)]
+
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion, Throughput};
+use mpc_carrier::channels::{Incoming, Outgoing};
+#[cfg(feature = "bincode")]
+use mpc_carrier::codec::BincodeCodec;
+use mpc_carrier::codec::{Codec, ProstCodec};
+use mpc_carrier::config::Direction;
+use mpc_carrier::messages::{fixtures, NodeRequest};
+use mpc_carrier::transport::memory::MemoryNetwork;
+use mpc_carrier::Carrier;
+use rcgen::{CertificateParams, KeyPair};
+use rustls::client::Resumption;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, PrivatePkcs8KeyDer, ServerName};
+use rustls::{ClientConfig, RootCertStore, ServerConfig};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::runtime::Runtime;
+use tokio::task::JoinHandle;
+use tokio_rustls::{TlsAcceptor, TlsConnector};
+
+const FAN_OUT: usize = 8;
+const THROUGHPUT_MESSAGES: usize = 10_000;
+const CORRELATED_REQUESTS: usize = 10_000;
+/// Payload of the codec benchmarks, such as a distance list, leaving room for
+/// the other fields within the 8 MiB frame limit.
+const CODEC_PAYLOAD_LEN: usize = (8 << 20) - 1024;
+
+fn round_trip(c: &mut Criterion) {
+    let runtime = Runtime::new().unwrap();
+    let mut outgoing = runtime.block_on(async {
+        let network = MemoryNetwork::new();
+        serve(&network, "b", &["a"]);
+        dial(&network, "a", &["b"])
+    });
+    let request = fixtures::large_request(1);
+    c.bench_function("round_trip_1b", |b| {
+        b.iter(|| {
+            runtime
+                .block_on(outgoing.send("b", request.clone()))
+                .unwrap()
+        });
+    });
+}
+
+fn throughput(c: &mut Criterion) {
+    let runtime = Runtime::new().unwrap();
+    let mut outgoing = runtime.block_on(async {
+        let network = MemoryNetwork::new();
+        serve(&network, "b", &["a"]);
+        dial(&network, "a", &["b"])
+    });
+    let requests = fixtures::random_requests(THROUGHPUT_MESSAGES, 0)
+        .into_iter()
+        .map(|request| NodeRequest {
+            payload: fixtures::large_request(1024).payload,
+            ..request
+        })
+        .collect::<Vec<_>>();
+    let mut group = c.benchmark_group("throughput_1kb");
+    group.throughput(Throughput::Elements(THROUGHPUT_MESSAGES as u64));
+    group.sample_size(10);
+    group.bench_function("10k_messages", |b| {
+        b.iter(|| {
+            runtime.block_on(async {
+                for request in &requests {
+                    outgoing.send("b", request.clone()).await.unwrap();
+                }
+            });
+        });
+    });
+    group.finish();
+}
+
+fn fan_out(c: &mut Criterion) {
+    let runtime = Runtime::new().unwrap();
+    let mut outgoing = runtime.block_on(async {
+        let network = MemoryNetwork::new();
+        let peers = (0..FAN_OUT).map(|i| format!("peer{i}")).collect::<Vec<_>>();
+        for peer in &peers {
+            serve(&network, peer, &["a"]);
+        }
+        let peers = peers.iter().map(String::as_str).collect::<Vec<_>>();
+        dial(&network, "a", &peers)
+    });
+    let request = fixtures::large_request(1);
+    c.bench_function("broadcast_8_nodes", |b| {
+        b.iter(|| {
+            let responses = runtime.block_on(outgoing.broadcast(request.clone()));
+            assert!(responses.values().all(Result::is_ok));
+        });
+    });
+}
+
+fn reconnect(c: &mut Criterion) {
+    let runtime = Runtime::new().unwrap();
+    let network = MemoryNetwork::new();
+    let (mut outgoing, mut peer) = runtime.block_on(async {
+        let peer = serve(&network, "b", &["a"]);
+        (dial(&network, "a", &["b"]), peer)
+    });
+    let request = fixtures::large_request(1);
+    let mut group = c.benchmark_group("reconnect");
+    group.sample_size(10);
+    group.bench_function("drop_to_first_send", |b| {
+        b.iter_custom(|iters| {
+            runtime.block_on(async {
+                let mut total = Duration::ZERO;
+                for _ in 0..iters {
+                    outgoing.send("b", request.clone()).await.unwrap();
+                    let start = Instant::now();
+                    peer.abort();
+                    peer = serve(&network, "b", &["a"]);
+                    // Requests written to the dropped connection fail.
+                    while outgoing.send("b", request.clone()).await.is_err() {}
+                    total += start.elapsed();
+                }
+                total
+            })
+        });
+    });
+    group.finish();
+}
+
+/// Correlation of the responses with the requests in flight, keyed by
+/// `request_id` as without `Feature::RequestSeq`, and by `request_seq` as with
+/// it. Every request is registered and then completed, as by a connection.
+fn correlation_map(c: &mut Criterion) {
+    let requests = fixtures::random_requests(CORRELATED_REQUESTS, 0);
+    let written = Instant::now();
+    let mut group = c.benchmark_group("correlation_map");
+    group.throughput(Throughput::Elements(CORRELATED_REQUESTS as u64));
+    group.bench_function("request_id", |b| {
+        b.iter(|| {
+            let mut pending = HashMap::new();
+            for request in &requests {
+                pending.insert(request.request_id.clone(), written);
+            }
+            for request in &requests {
+                pending.remove(&request.request_id).unwrap();
+            }
+        });
+    });
+    group.bench_function("request_seq", |b| {
+        b.iter(|| {
+            let mut pending = HashMap::new();
+            for seq in 1..=CORRELATED_REQUESTS as u64 {
+                pending.insert(seq, written);
+            }
+            for seq in 1..=CORRELATED_REQUESTS as u64 {
+                pending.remove(&seq).unwrap();
+            }
+        });
+    });
+    group.finish();
+}
+
+fn tls_handshake(c: &mut Criterion) {
+    let runtime = Runtime::new().unwrap();
+    let (server_config, client_config) = tls_configs();
+    let addr = runtime.block_on(async {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let acceptor = TlsAcceptor::from(server_config);
+        tokio::spawn(async move {
+            loop {
+                let (stream, _) = listener.accept().await.unwrap();
+                let acceptor = acceptor.clone();
+                tokio::spawn(async move {
+                    let mut stream = acceptor.accept(stream).await.unwrap();
+                    // Delivers the session tickets along with the data.
+                    stream.write_all(&[0]).await.unwrap();
+                    stream.flush().await.unwrap();
+                });
+            }
+        });
+        addr
+    });
+    let handshake = |connector: TlsConnector| async move {
+        let stream = TcpStream::connect(addr).await.unwrap();
+        let name = ServerName::try_from("localhost").unwrap();
+        let mut stream = connector.connect(name, stream).await.unwrap();
+        stream.read_u8().await.unwrap();
+    };
+    let mut full = (*client_config).clone();
+    full.resumption = Resumption::disabled();
+    let full = TlsConnector::from(Arc::new(full));
+    let resumed = TlsConnector::from(client_config);
+    let mut group = c.benchmark_group("tls_handshake");
+    group.bench_function("full", |b| {
+        b.iter(|| runtime.block_on(handshake(full.clone())));
+    });
+    group.bench_function("resumed", |b| {
+        b.iter_batched(
+            // Makes sure a session ticket is cached.
+            || runtime.block_on(handshake(resumed.clone())),
+            |()| runtime.block_on(handshake(resumed.clone())),
+            BatchSize::PerIteration,
+        );
+    });
+    group.finish();
+}
+
+/// Runs a carrier for `name` accepting the connections of `peers` and
+/// answering their requests.
+fn codec(c: &mut Criterion) {
+    let request = fixtures::large_request(CODEC_PAYLOAD_LEN);
+    let mut group = c.benchmark_group("codec_8mib");
+    group.throughput(Throughput::Bytes(CODEC_PAYLOAD_LEN as u64));
+    group.sample_size(10);
+    encode_decode::<ProstCodec>(&mut group, "prost", &request);
+    #[cfg(feature = "bincode")]
+    encode_decode::<BincodeCodec>(&mut group, "bincode", &request);
+    group.finish();
+}
+
+fn encode_decode<C: Codec>(
+    group: &mut criterion::BenchmarkGroup<'_, criterion::measurement::WallTime>,
+    name: &str,
+    request: &NodeRequest,
+) {
+    let mut buf = Vec::new();
+    group.bench_function(format!("{name}_encode"), |b| {
+        b.iter(|| {
+            buf.clear();
+            C::encode(request, &mut buf).unwrap();
+        });
+    });
+    group.bench_function(format!("{name}_decode"), |b| {
+        b.iter(|| C::decode::<NodeRequest>(&buf).unwrap());
+    });
+}
+
+fn serve(network: &MemoryNetwork, name: &str, peers: &[&str]) -> JoinHandle<()> {
+    let (mut carrier, mut incoming, _outgoing) = carrier(peers);
+    for peer in peers {
+        carrier = carrier.direction(*peer, Direction::Accept);
+    }
+    let transport = network.transport(name);
+    tokio::spawn(async move {
+        let respond = async move {
+            while let Some((_, callback)) = incoming.recv().await {
+                let response = fixtures::node_response(&callback.message);
+                let _ = callback.respond(response);
+            }
+        };
+        tokio::select! {
+            _ = carrier.run_with_transport(transport) => {}
+            () = respond => {}
+        }
+    })
+}
+
+/// Runs a carrier for `name` dialing `peers`, and returns its outgoing
+/// channels.
+fn dial(network: &MemoryNetwork, name: &str, peers: &[&str]) -> Outgoing {
+    let (mut carrier, _incoming, outgoing) = carrier(peers);
+    for peer in peers {
+        carrier = carrier.direction(*peer, Direction::Dial);
+    }
+    let mut carrier = carrier.skip_unused_listener(true);
+    let transport = network.transport(name);
+    tokio::spawn(async move { carrier.run_with_transport(transport).await });
+    outgoing
+}
+
+fn carrier(peers: &[&str]) -> (Carrier, Incoming, Outgoing) {
+    let nodes = peers
+        .iter()
+        .map(|peer| ((*peer).to_string(), 1))
+        .collect::<HashMap<_, _>>();
+    Carrier::new(nodes)
+}
+
+/// Creates a self-signed certificate for `localhost`, and the configurations
+/// trusting it.
+fn tls_configs() -> (Arc<ServerConfig>, Arc<ClientConfig>) {
+    let key = KeyPair::generate().unwrap();
+    let cert = CertificateParams::new(vec!["localhost".to_string()])
+        .unwrap()
+        .self_signed(&key)
+        .unwrap();
+    let cert = CertificateDer::from(cert.der().to_vec());
+    let key = PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(key.serialize_der()));
+    let server_config = ServerConfig::builder()
+        .with_no_client_auth()
+        .with_single_cert(vec![cert.clone()], key)
+        .unwrap();
+    let mut roots = RootCertStore::empty();
+    roots.add(cert).unwrap();
+    let client_config = ClientConfig::builder()
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+    (Arc::new(server_config), Arc::new(client_config))
+}
+
+criterion_group!(
+    benches,
+    round_trip,
+    throughput,
+    fan_out,
+    reconnect,
+    correlation_map,
+    tls_handshake,
+    codec
+);
+criterion_main!(benches);
@@ -1,6 +1,53 @@
+use std::env;
+use std::fmt::Write;
+use std::fs;
 use std::io::Result;
+use std::path::PathBuf;
+
+/// Fields of the `bytes` type, encoded in base64 by the `serde` feature.
+const BYTES_FIELDS: &[&str] = &[
+    ".messages.NodeRequest.request_id",
+    ".messages.NodeRequest.payload",
+    ".messages.NodeRequest.distance_list",
+    ".messages.NodeRequest.auth_tag",
+    ".messages.NodeResponse.request_id",
+    ".messages.NodeResponse.auth_tag",
+    ".messages.NodeResponse.payload",
+    ".messages.NodeNotification.payload",
+    ".messages.Cancel.request_id",
+];
 
 fn main() -> Result<()> {
-    prost_build::compile_protos(&["src/messages.proto"], &["src/"])?;
+    let mut config = prost_build::Config::new();
+    if env::var_os("CARGO_FEATURE_SERDE").is_some() {
+        config.type_attribute(".", "#[derive(::serde::Serialize, ::serde::Deserialize)]");
+        for field in BYTES_FIELDS {
+            config.field_attribute(field, "#[serde(with = \"crate::messages::base64\")]");
+        }
+    }
+    config.compile_protos(&["src/messages.proto"], &["src/"])?;
+    if env::var_os("CARGO_FEATURE_TEST_UTIL").is_some() {
+        // The schema of the first release, for `messages::compat`.
+        prost_build::Config::new()
+            .compile_protos(&["src/messages/compat/v0.proto"], &["src/messages/compat/"])?;
+        write_message_names()?;
+    }
     Ok(())
 }
+
+/// Lists the top-level messages of the schema, each needing a golden fixture
+/// in `messages::compat`.
+fn write_message_names() -> Result<()> {
+    let schema = fs::read_to_string("src/messages.proto")?;
+    let mut names = String::from("const MESSAGE_NAMES: &[&str] = &[\n");
+    for line in schema.lines() {
+        if let Some(name) = line.strip_prefix("message ") {
+            let end = name.find(|c: char| !c.is_alphanumeric() && c != '_');
+            let name = &name[..end.unwrap_or(name.len())];
+            writeln!(names, "    {name:?},").expect("string to grow");
+        }
+    }
+    names.push_str("];\n");
+    let out_dir = PathBuf::from(env::var_os("OUT_DIR").expect("set by cargo"));
+    fs::write(out_dir.join("message_names.rs"), names)
+}
@@ -0,0 +1,233 @@
+//! Three parties flipping a shared random coin with a commit-reveal protocol,
+//! each over a carrier of its own on the loopback interface:
+//!
+//! `cargo run --example=coin_flip`
+//!
+//! Every party draws a random seed, and:
+//!
+//! 1. commits to it by broadcasting its SHA-256 hash, so it can no longer
+//!    change it once it sees the others;
+//! 2. once it holds the commitments of all the others, reveals it by
+//!    broadcasting the seed itself;
+//! 3. checks every revealed seed against its commitment, and XORs all the
+//!    seeds into the shared value, random as long as one party is honest.
+//!
+//! The broadcasts of the rounds go through a [`CommitReveal`] exchange.
+//!
+//! The carriers authenticate each other with a self-signed certificate
+//! generated on the fly. Every pair of parties is identified by a TLS server
+//! name of its own, covered by the certificate, as the parties share the
+//! address `127.0.0.1`.
+
+#![warn(clippy::pedantic)]
+
+use mpc_carrier::messages::{NodeRequest, NodeResponse};
+use mpc_carrier::protocol::CommitReveal;
+use mpc_carrier::transport::{NodeAddr, TlsTcpTransport};
+use mpc_carrier::Carrier;
+use rand::RngCore;
+use rcgen::{CertificateParams, KeyPair};
+use ring::digest::{digest, SHA256};
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, PrivatePkcs8KeyDer, ServerName};
+use rustls::{ClientConfig, RootCertStore, ServerConfig};
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::sync::Arc;
+use tokio::sync::mpsc;
+use tracing::info;
+use tracing::level_filters::LevelFilter;
+use tracing_subscriber::filter::EnvFilter;
+
+const PARTIES: [(&str, u16); 3] = [("alice", 9101), ("bob", 9102), ("carol", 9103)];
+const SEED_LEN: usize = 32;
+/// `request_id` of the requests of the commit round.
+const COMMIT: &[u8] = b"commit";
+/// `request_id` of the requests of the reveal round.
+const REVEAL: &[u8] = b"reveal";
+
+type Seed = [u8; SEED_LEN];
+
+#[tokio::main]
+async fn main() {
+    let filter = EnvFilter::default()
+        .add_directive(LevelFilter::WARN.into())
+        .add_directive(
+            format!("{}={}", env!("CARGO_CRATE_NAME"), LevelFilter::INFO)
+                .parse()
+                .unwrap(),
+        );
+    tracing_subscriber::fmt().with_env_filter(filter).init();
+
+    let (server_config, client_config) = tls_configs();
+    // Agreed on beforehand by the parties, to tell this run of the protocol
+    // apart from any other one between them.
+    let session_id = rand::thread_rng().next_u64();
+    let parties = PARTIES
+        .iter()
+        .map(|&(party, port)| {
+            let (server_config, client_config) =
+                (Arc::clone(&server_config), Arc::clone(&client_config));
+            tokio::spawn(async move {
+                let transport =
+                    TlsTcpTransport::new("127.0.0.1", port, server_config, client_config);
+                flip(party, session_id, transport).await
+            })
+        })
+        .collect::<Vec<_>>();
+    let mut values = Vec::new();
+    for party in parties {
+        values.push(party.await.unwrap());
+    }
+    assert!(values.windows(2).all(|pair| pair[0] == pair[1]));
+    println!("Shared random value: {}", hex(&values[0]));
+}
+
+/// Runs the protocol as `party`, and returns the shared value.
+async fn flip(party: &'static str, session_id: u64, transport: TlsTcpTransport) -> Seed {
+    // The carrier of the party connects to the two others, and accepts their
+    // connections.
+    let peers = PARTIES
+        .iter()
+        .filter(|(peer, _)| *peer != party)
+        .map(|&(peer, port)| (peer, peer_addr(party, peer, port)));
+    let (mut carrier, mut incoming, mut outgoing) = Carrier::with_addrs(peers);
+    tokio::spawn(async move { carrier.run_with_transport(transport).await });
+
+    // The requests of the peers in the session are answered right away, and
+    // their payloads handed to the protocol below by round.
+    let (rounds, mut received) = mpsc::unbounded_channel();
+    tokio::spawn(async move {
+        while let Some((peer, callback)) = incoming.recv_session(session_id).await {
+            let request = &callback.message;
+            let message = (
+                request.request_id.clone(),
+                peer.to_string(),
+                request.payload.clone(),
+            );
+            rounds.send(message).unwrap();
+            let response = NodeResponse {
+                request_id: request.request_id.clone(),
+                ..Default::default()
+            };
+            callback.respond(response).unwrap();
+        }
+    });
+
+    let mut seed = Seed::default();
+    rand::thread_rng().fill_bytes(&mut seed);
+
+    // Every request of the exchange carries the session. The broadcasts send
+    // the request to every peer and await all the responses, queuing it until
+    // the connections are up.
+    let mut exchange = CommitReveal::new(&mut outgoing, session_id);
+
+    // Commit round: the hash of the seed binds the party to it without
+    // disclosing it.
+    let commitment = digest(&SHA256, &seed).as_ref().to_vec();
+    exchange
+        .broadcast_commit(request(COMMIT, commitment))
+        .await
+        .unwrap_or_else(|err| panic!("{party}: {err}"));
+    info!(party, "Committed");
+
+    // A party reveals its seed only once it holds the commitments of all the
+    // others, which can then no longer depend on it. A peer reveals once its
+    // commitments are answered, so its commitment always comes first.
+    let mut commitments = HashMap::new();
+    let mut seeds = HashMap::new();
+    while commitments.len() < PARTIES.len() - 1 {
+        let (round, peer, payload) = received.recv().await.unwrap();
+        match round.as_slice() {
+            COMMIT => commitments.insert(peer, payload),
+            _ => seeds.insert(peer, payload),
+        };
+    }
+
+    // Reveal round: the seed itself.
+    exchange
+        .broadcast_reveal(request(REVEAL, seed.to_vec()))
+        .await
+        .unwrap_or_else(|err| panic!("{party}: {err}"));
+    info!(party, "Revealed");
+    while seeds.len() < PARTIES.len() - 1 {
+        let (_, peer, payload) = received.recv().await.unwrap();
+        seeds.insert(peer, payload);
+    }
+
+    // Output: every revealed seed must match its commitment, or its party
+    // cheated and the value is discarded.
+    let mut value = seed;
+    for (peer, peer_seed) in &seeds {
+        assert_eq!(
+            digest(&SHA256, peer_seed).as_ref(),
+            commitments[peer].as_slice(),
+            "{peer} revealed a seed not matching its commitment"
+        );
+        for (byte, peer_byte) in value.iter_mut().zip(peer_seed) {
+            *byte ^= peer_byte;
+        }
+    }
+    info!(party, "Shared value: {}", hex(&value));
+    value
+}
+
+/// Returns the request of `round` with `payload`.
+fn request(round: &[u8], payload: Vec<u8>) -> NodeRequest {
+    NodeRequest {
+        request_id: round.to_vec(),
+        payload,
+        ..Default::default()
+    }
+}
+
+/// Returns the address of `peer` for `party`. The TLS server name names the
+/// pair, the same both ways, for each end to identify the other one from the
+/// name sent by its connections.
+fn peer_addr(party: &str, peer: &str, port: u16) -> NodeAddr {
+    NodeAddr {
+        host: "127.0.0.1".to_string(),
+        port,
+        tls_name: ServerName::try_from(pair_name(party, peer)).unwrap(),
+    }
+}
+
+fn pair_name(party: &str, peer: &str) -> String {
+    let mut pair = [party, peer];
+    pair.sort_unstable();
+    format!("{}-{}.coin-flip", pair[0], pair[1])
+}
+
+/// Returns the TLS configurations shared by the parties, with a self-signed
+/// certificate for the server names of all the pairs.
+fn tls_configs() -> (Arc<ServerConfig>, Arc<ClientConfig>) {
+    let mut names = Vec::new();
+    for (i, (party, _)) in PARTIES.iter().enumerate() {
+        for (peer, _) in &PARTIES[i + 1..] {
+            names.push(pair_name(party, peer));
+        }
+    }
+    let key = KeyPair::generate().unwrap();
+    let cert = CertificateParams::new(names)
+        .unwrap()
+        .self_signed(&key)
+        .unwrap();
+    let cert = CertificateDer::from(cert.der().to_vec());
+    let key = PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(key.serialize_der()));
+    let server_config = ServerConfig::builder()
+        .with_no_client_auth()
+        .with_single_cert(vec![cert.clone()], key)
+        .unwrap();
+    let mut roots = RootCertStore::empty();
+    roots.add(cert).unwrap();
+    let client_config = ClientConfig::builder()
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+    (Arc::new(server_config), Arc::new(client_config))
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().fold(String::new(), |mut s, byte| {
+        let _ = write!(s, "{byte:02x}");
+        s
+    })
+}
@@ -11,25 +11,17 @@
 #![warn(clippy::pedantic)]
 
 use clap::Parser;
-use mpc_carrier::channels::Callback;
-use mpc_carrier::messages::{NodeRequest, NodeResponse};
+use mpc_carrier::config;
+use mpc_carrier::messages::{redacted, NodeRequest, NodeResponse};
+use mpc_carrier::tls::TlsMode;
 use mpc_carrier::{Carrier, Error};
+use std::path::PathBuf;
 use std::time::Duration;
-use std::{num::ParseIntError, path::PathBuf};
-use thiserror::Error;
 use tokio::time::sleep;
 use tracing::info;
 use tracing::level_filters::LevelFilter;
 use tracing_subscriber::filter::EnvFilter;
 
-#[derive(Error, Debug)]
-pub enum NodeArgError {
-    #[error("node argument doesn't have a colon")]
-    NoColon,
-    #[error("port parse error: {0}")]
-    ParseIntError(#[from] ParseIntError),
-}
-
 #[derive(Debug, Parser)]
 pub struct Cli {
     /// IP address to listen for incoming connections
@@ -44,7 +36,7 @@ pub struct Cli {
     /// This node port.
     pub node_port: u16,
     /// Other nodes in form of domainname:port.
-    #[clap(value_parser = parse_node)]
+    #[clap(value_parser = config::parse_node)]
     pub nodes: Vec<(String, u16)>,
 }
 
@@ -74,49 +66,45 @@ async fn main() -> Result<(), Error> {
         .with_target(false)
         .init();
 
-    let (carrier, mut incoming, mut outgoing) = Carrier::new(nodes.iter().cloned().collect());
+    let (mut carrier, mut incoming, mut outgoing) =
+        Carrier::try_new(nodes.iter().cloned().collect())?;
 
     tokio::spawn(async move {
         let mut request_id = vec![0];
-        let mut distance_list = vec![1, 2, 3, 4, 5, 6, 7, 8];
-        distance_list.extend_from_slice(&node_port.to_be_bytes());
+        let mut payload = vec![1, 2, 3, 4, 5, 6, 7, 8];
+        payload.extend_from_slice(&node_port.to_be_bytes());
         loop {
             let request = NodeRequest {
                 request_id: request_id.clone(),
-                distance_list: distance_list.clone(),
+                payload: payload.clone(),
+                ..Default::default()
             };
             for (node, _) in &nodes {
-                info!("Sent {request:?} to {node}");
+                info!("Sent {} to {node}", redacted(&request));
                 let response = outgoing.send(node, request.clone()).await.unwrap();
-                info!("Received {response:?} from {node}");
+                info!("Received {} from {node}", redacted(&response));
             }
             sleep(Duration::from_secs(1)).await;
             request_id[0] = request_id[0].wrapping_add(1);
-            distance_list.rotate_right(1);
+            payload.rotate_right(1);
         }
     });
 
     tokio::spawn(async move {
-        while let Some((node, Callback { message, callback })) = incoming.recv().await {
-            info!("Received {message:?} from {node}");
+        while let Some((node, callback)) = incoming.recv().await {
+            info!("Received {} from {node}", redacted(&callback.message));
             let response = NodeResponse {
-                request_id: message.request_id.clone(),
+                request_id: callback.message.request_id.clone(),
+                ..Default::default()
             };
-            info!("Sent {response:?} to {node}");
-            callback.send(response).unwrap();
+            info!("Sent {} to {node}", redacted(&response));
+            callback.respond(response).unwrap();
         }
     });
 
-    carrier
-        .run(&bind, node_port, &cert_chain, &cert_priv_key)
-        .await
-}
-
-fn parse_node(s: &str) -> Result<(String, u16), NodeArgError> {
-    let [node, port] = s
-        .splitn(2, ':')
-        .collect::<Vec<_>>()
-        .try_into()
-        .map_err(|_| NodeArgError::NoColon)?;
-    Ok((node.to_string(), port.parse()?))
+    let tls_mode = TlsMode::Required {
+        cert_chain,
+        cert_priv_key,
+    };
+    carrier.run(&bind, node_port, tls_mode).await
 }
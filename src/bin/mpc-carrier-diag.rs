@@ -0,0 +1,308 @@
+//! Checks the connectivity with the nodes, as a node with the given
+//! certificate would, and prints a table of the outcomes followed by the
+//! certificates presented by the nodes:
+//!
+//! `cargo run --bin=mpc-carrier-diag --features=cert-expiry-check -- \
+//! --cert-chain fullchain.pem --cert-priv-key privkey.pem <node>:<port>...`
+//!
+//! Every node is pinged with an empty request through a carrier dialing the
+//! nodes, which reports the connection and TLS failures. The ping reaches the
+//! application of the node, so a node that does not answer it within 5
+//! seconds is reported as `no response` rather than down. The certificate of
+//! every node is read over a connection of its own, without verifying it, to
+//! be shown even when the carrier rejects it.
+
+#![warn(clippy::pedantic)]
+
+use mpc_carrier::channels::SendError;
+use mpc_carrier::config::{self, Direction};
+use mpc_carrier::lifecycle::CarrierEvent;
+use mpc_carrier::messages::NodeRequest;
+use mpc_carrier::tls::{self, CertInfo};
+use mpc_carrier::transport::TlsTcpTransport;
+use mpc_carrier::Carrier;
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::crypto::{self, CryptoProvider};
+use rustls::pki_types::{CertificateDer, ServerName, UnixTime};
+use rustls::{ClientConfig, DigitallySignedStruct, SignatureScheme};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::process::ExitCode;
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
+use std::{env, fmt};
+use tokio::net::TcpStream;
+use tokio::runtime::Runtime;
+use tokio::sync::mpsc;
+use tokio::time::timeout;
+use tokio_rustls::TlsConnector;
+
+const PING_TIMEOUT: Duration = Duration::from_secs(5);
+const SECS_PER_DAY: u64 = 24 * 60 * 60;
+const USAGE: &str =
+    "Usage: mpc-carrier-diag --cert-chain <file> --cert-priv-key <file> <node>:<port>...";
+
+struct Args {
+    cert_chain: PathBuf,
+    cert_priv_key: PathBuf,
+    nodes: Vec<(String, u16)>,
+}
+
+/// Outcome of the checks of a node.
+struct Report {
+    node: String,
+    status: String,
+    latency: Option<Duration>,
+    cert: Result<CertInfo, String>,
+}
+
+/// Time left until a certificate expires, or since it expired.
+struct Expiry(SystemTime);
+
+/// Verifier accepting any certificate, to read the ones failing the
+/// verification.
+#[derive(Debug)]
+struct AcceptAny(CryptoProvider);
+
+fn main() -> ExitCode {
+    let args = match parse_args() {
+        Ok(args) => args,
+        Err(err) => {
+            eprintln!("{err}\n{USAGE}");
+            return ExitCode::FAILURE;
+        }
+    };
+    let runtime = match Runtime::new() {
+        Ok(runtime) => runtime,
+        Err(err) => {
+            eprintln!("Runtime: {err}");
+            return ExitCode::FAILURE;
+        }
+    };
+    match runtime.block_on(diagnose(args)) {
+        Ok(reports) => {
+            print(&reports);
+            if reports.iter().all(|report| report.latency.is_some()) {
+                ExitCode::SUCCESS
+            } else {
+                ExitCode::FAILURE
+            }
+        }
+        Err(err) => {
+            eprintln!("{err}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn parse_args() -> Result<Args, String> {
+    let mut cert_chain = None;
+    let mut cert_priv_key = None;
+    let mut nodes = Vec::new();
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--cert-chain" => cert_chain = args.next().map(PathBuf::from),
+            "--cert-priv-key" => cert_priv_key = args.next().map(PathBuf::from),
+            node => nodes.push(config::parse_node(node).map_err(|err| format!("{node}: {err}"))?),
+        }
+    }
+    match (cert_chain, cert_priv_key) {
+        (Some(cert_chain), Some(cert_priv_key)) if !nodes.is_empty() => Ok(Args {
+            cert_chain,
+            cert_priv_key,
+            nodes,
+        }),
+        _ => Err("Missing arguments".to_owned()),
+    }
+}
+
+async fn diagnose(args: Args) -> Result<Vec<Report>, String> {
+    let Args {
+        cert_chain,
+        cert_priv_key,
+        nodes,
+    } = args;
+    let (server_config, client_config) =
+        tls::init(&cert_chain, &cert_priv_key).map_err(|err| err.to_string())?;
+    let transport = TlsTcpTransport::new("0.0.0.0", 0, server_config, client_config);
+    let (mut carrier, _incoming, mut outgoing) =
+        Carrier::try_new(nodes.iter().cloned().collect()).map_err(|err| err.to_string())?;
+    let mut events = carrier.events();
+    for (node, _) in &nodes {
+        carrier = carrier.direction(node.as_str(), Direction::Dial);
+    }
+    let mut carrier = carrier.skip_unused_listener(true);
+    tokio::spawn(async move { carrier.run_with_transport(transport).await });
+    let connector = inspecting_connector()?;
+    let mut certs = Vec::new();
+    for (node, port) in &nodes {
+        certs.push(peer_cert(&connector, node, *port).await);
+    }
+
+    let mut failures = HashMap::new();
+    let mut reports = Vec::new();
+    for ((node, _), cert) in nodes.into_iter().zip(certs) {
+        let ping = NodeRequest {
+            request_id: b"mpc-carrier-diag".to_vec(),
+            ..Default::default()
+        };
+        let started = Instant::now();
+        let result = timeout(PING_TIMEOUT, outgoing.send(node.as_str(), ping)).await;
+        let latency = started.elapsed();
+        let (status, latency) = match result {
+            Ok(Ok(_)) => ("ok".to_owned(), Some(latency)),
+            Ok(Err(SendError::Remote { status, .. })) => {
+                (format!("ok (status {status})"), Some(latency))
+            }
+            Ok(Err(err)) => (err.to_string(), None),
+            Err(_) => {
+                drain_failures(&mut events, &mut failures);
+                let status = failures
+                    .get(&node)
+                    .map_or_else(|| "no response".to_owned(), Clone::clone);
+                (status, None)
+            }
+        };
+        reports.push(Report {
+            node,
+            status,
+            latency,
+            cert,
+        });
+    }
+    Ok(reports)
+}
+
+/// Returns a connector reading the certificates without verifying them.
+fn inspecting_connector() -> Result<TlsConnector, String> {
+    let provider = crypto::ring::default_provider();
+    let config = ClientConfig::builder_with_provider(Arc::new(provider.clone()))
+        .with_safe_default_protocol_versions()
+        .map_err(|err| err.to_string())?
+        .dangerous()
+        .with_custom_certificate_verifier(Arc::new(AcceptAny(provider)))
+        .with_no_client_auth();
+    Ok(TlsConnector::from(Arc::new(config)))
+}
+
+/// Returns the certificate presented by `node`.
+async fn peer_cert(connector: &TlsConnector, node: &str, port: u16) -> Result<CertInfo, String> {
+    let tls_name = ServerName::try_from(node.to_owned()).map_err(|err| err.to_string())?;
+    let stream = TcpStream::connect((node, port))
+        .await
+        .map_err(|err| err.to_string())?;
+    let stream = connector
+        .connect(tls_name, stream)
+        .await
+        .map_err(|err| err.to_string())?;
+    let (_, connection) = stream.get_ref();
+    let cert = connection
+        .peer_certificates()
+        .and_then(<[_]>::first)
+        .ok_or("no certificate")?;
+    tls::cert_info(cert).map_err(|err| err.to_string())
+}
+
+/// Keeps the last failure of every node streamed by the carrier.
+fn drain_failures(
+    events: &mut mpsc::Receiver<CarrierEvent>,
+    failures: &mut HashMap<String, String>,
+) {
+    while let Ok(event) = events.try_recv() {
+        match event {
+            CarrierEvent::ConnectionFailed { node, error, .. }
+            | CarrierEvent::HandshakeFailed { node, error, .. } => {
+                failures.insert(node, error);
+            }
+            _ => {}
+        }
+    }
+}
+
+fn print(reports: &[Report]) {
+    let width = |column: fn(&Report) -> usize, header: &str| {
+        reports.iter().map(column).chain([header.len()]).max()
+    };
+    let peer = width(|report| report.node.len(), "PEER").unwrap_or_default();
+    let status = width(|report| report.status.len(), "STATUS").unwrap_or_default();
+    println!(
+        "{:peer$} | {:status$} | {:9} | CERT EXPIRY",
+        "PEER", "STATUS", "LATENCY"
+    );
+    for report in reports {
+        let latency = report
+            .latency
+            .map_or_else(|| "-".to_owned(), |latency| format!("{latency:.1?}"));
+        let expiry = report.cert.as_ref().map_or_else(
+            |_| "-".to_owned(),
+            |cert| Expiry(cert.not_after).to_string(),
+        );
+        println!(
+            "{:peer$} | {:status$} | {latency:9} | {expiry}",
+            report.node, report.status
+        );
+    }
+    for report in reports {
+        println!();
+        match &report.cert {
+            Ok(cert) => {
+                println!("{}: {}", report.node, cert.subject);
+                println!("  SANs: {}", cert.sans.join(", "));
+                println!("  Key: {}", cert.key_type);
+                println!("  Expiry: {}", Expiry(cert.not_after));
+            }
+            Err(err) => println!("{}: certificate unavailable: {err}", report.node),
+        }
+    }
+}
+
+impl fmt::Display for Expiry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0.duration_since(SystemTime::now()) {
+            Ok(left) => write!(f, "in {} days", left.as_secs() / SECS_PER_DAY),
+            Err(err) => write!(
+                f,
+                "expired {} days ago",
+                err.duration().as_secs() / SECS_PER_DAY
+            ),
+        }
+    }
+}
+
+impl ServerCertVerifier for AcceptAny {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        let algorithms = &self.0.signature_verification_algorithms;
+        crypto::verify_tls12_signature(message, cert, dss, algorithms)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        let algorithms = &self.0.signature_verification_algorithms;
+        crypto::verify_tls13_signature(message, cert, dss, algorithms)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.0.signature_verification_algorithms.supported_schemes()
+    }
+}
@@ -0,0 +1,74 @@
+//! Prints the connection events of an event log written by a carrier with
+//! `Carrier::event_log`, one per line:
+//!
+//! `cargo run --bin=mpc-carrier-event-reader -- events.log`
+
+#![warn(clippy::pedantic)]
+
+use mpc_carrier::event_log::{self, EventType};
+use std::fs::File;
+use std::io::{self, BufReader, Write};
+use std::process::ExitCode;
+use std::time::SystemTime;
+use std::{env, fmt};
+
+struct Timestamp(SystemTime);
+
+fn main() -> ExitCode {
+    let mut args = env::args_os().skip(1);
+    let (Some(path), None) = (args.next(), args.next()) else {
+        eprintln!("Usage: mpc-carrier-event-reader <event log>");
+        return ExitCode::FAILURE;
+    };
+    let file = match File::open(&path) {
+        Ok(file) => file,
+        Err(err) => {
+            eprintln!("{}: {err}", path.to_string_lossy());
+            return ExitCode::FAILURE;
+        }
+    };
+    let mut stdout = io::stdout().lock();
+    for event in event_log::read(BufReader::new(file)) {
+        let event = match event {
+            Ok(event) => event,
+            Err(err) => {
+                eprintln!("{}: {err}", path.to_string_lossy());
+                return ExitCode::FAILURE;
+            }
+        };
+        let event_type = match event.event_type {
+            EventType::Connected => "connected".to_owned(),
+            EventType::Disconnected => "disconnected".to_owned(),
+            EventType::Failed => "failed".to_owned(),
+            EventType::Unknown(event_type) => format!("unknown({event_type})"),
+            _ => "unknown".to_owned(),
+        };
+        let line = writeln!(
+            stdout,
+            "{} {event_type:<12} {} {}",
+            Timestamp(event.timestamp),
+            event.node,
+            String::from_utf8_lossy(&event.data)
+        );
+        // Stops quietly when piped to a closed reader, such as `head`.
+        if line.is_err() {
+            break;
+        }
+    }
+    ExitCode::SUCCESS
+}
+
+impl fmt::Display for Timestamp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let since_epoch = self
+            .0
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default();
+        write!(
+            f,
+            "{}.{:09}",
+            since_epoch.as_secs(),
+            since_epoch.subsec_nanos()
+        )
+    }
+}
@@ -0,0 +1,176 @@
+//! Event bus between the network layer and the [`Incoming`](crate::channels::Incoming)
+//! channels.
+//!
+//! Every request received from a node is dispatched through an [`EventBus`].
+//! Middleware can wrap another bus to intercept the dispatched requests.
+
+use crate::channels::Services;
+use crate::config::NodeId;
+use crate::stats::{NodeStats, Stats};
+use crate::{status, NodeCallback};
+use futures::channel::mpsc;
+use futures::future::BoxFuture;
+use futures::prelude::*;
+use std::collections::HashMap;
+use std::sync::atomic::Ordering;
+use std::sync::{Arc, RwLock};
+use thiserror::Error;
+use tracing::debug;
+
+/// Error returned by [`EventBus::dispatch`].
+#[derive(Error, Debug)]
+pub enum BusError {
+    /// The node has no associated channel.
+    #[error("unknown node")]
+    UnknownNode,
+    /// The receiving side of the channel is gone.
+    #[error("channel closed")]
+    Closed,
+}
+
+/// Dispatcher of incoming requests.
+///
+/// Each incoming connection gets its own clone of the bus.
+pub trait EventBus: BusClone + Send + Sync + 'static {
+    /// Dispatches a `callback` received from `node`.
+    fn dispatch<'a>(
+        &'a mut self,
+        node: &'a NodeId,
+        callback: NodeCallback,
+    ) -> BoxFuture<'a, Result<(), BusError>>;
+}
+
+/// Helper trait for cloning boxed [`EventBus`]es. Implemented for every
+/// [`EventBus`] which is [`Clone`].
+pub trait BusClone {
+    /// Clones the bus into a new box.
+    fn clone_box(&self) -> Box<dyn EventBus>;
+}
+
+/// Middleware constructor registered with
+/// [`Carrier::wrap_bus`](crate::Carrier::wrap_bus).
+pub(crate) type BusLayer = Box<dyn FnOnce(Box<dyn EventBus>) -> Box<dyn EventBus> + Send>;
+
+/// Channels to the [`Incoming`](crate::channels::Incoming) set, updated as the
+/// nodes are added and removed.
+pub(crate) type SharedChannels = Arc<RwLock<HashMap<NodeId, mpsc::Sender<NodeCallback>>>>;
+
+/// The default [`EventBus`] which forwards requests to the
+/// [`Incoming`](crate::channels::Incoming) channels, or to the
+/// [`ServiceIncoming`](crate::channels::ServiceIncoming) channel of their
+/// service.
+#[derive(Clone)]
+pub struct ChannelBus {
+    channels: SharedChannels,
+    services: Services,
+    stats: Stats,
+    /// Senders already looked up by this clone of the bus, with the
+    /// statistics of their nodes.
+    cache: HashMap<NodeId, (mpsc::Sender<NodeCallback>, Arc<NodeStats>)>,
+}
+
+impl ChannelBus {
+    pub(crate) fn new(channels: SharedChannels, services: Services, stats: Stats) -> Self {
+        Self {
+            channels,
+            services,
+            stats,
+            cache: HashMap::new(),
+        }
+    }
+
+    /// Queues a request addressed to a service, without waiting for room in
+    /// the queue so that a slow service does not delay the others. A request
+    /// to a service not routed is answered with the [`status::UNSUPPORTED`]
+    /// status, and one to a full queue with [`status::OVERLOADED`].
+    fn dispatch_service(&self, node: &NodeId, callback: NodeCallback) {
+        let mut services = self.services.lock().unwrap();
+        let service = callback.message.service.clone();
+        let Some(channel) = services.get_mut(&service) else {
+            debug!("Request from {node} to an unknown service `{service}`");
+            let _ = callback.respond_err(
+                status::UNSUPPORTED,
+                format!("service `{service}` not routed"),
+            );
+            return;
+        };
+        let stats = self.stats.read().unwrap().get(node).cloned();
+        let stats = stats.unwrap_or_default();
+        // Counted before sending, as `ServiceIncoming` may take it right away.
+        stats.incoming_queue.fetch_add(1, Ordering::Relaxed);
+        if let Err(err) = channel.try_send((node.clone(), callback)) {
+            stats.incoming_queue.fetch_sub(1, Ordering::Relaxed);
+            let (code, detail) = if err.is_full() {
+                debug!("Shed a request from {node} to service `{service}`");
+                (
+                    status::OVERLOADED,
+                    format!("service `{service}` overloaded"),
+                )
+            } else {
+                services.remove(&service);
+                (
+                    status::UNSUPPORTED,
+                    format!("service `{service}` not routed"),
+                )
+            };
+            let (_, callback) = err.into_inner();
+            let _ = callback.respond_err(code, detail);
+        }
+    }
+}
+
+impl EventBus for ChannelBus {
+    fn dispatch<'a>(
+        &'a mut self,
+        node: &'a NodeId,
+        callback: NodeCallback,
+    ) -> BoxFuture<'a, Result<(), BusError>> {
+        async move {
+            if !callback.message.service.is_empty() {
+                self.dispatch_service(node, callback);
+                return Ok(());
+            }
+            if !self.cache.contains_key(node) {
+                let channel = self.channels.read().unwrap().get(node).cloned();
+                let channel = channel.ok_or(BusError::UnknownNode)?;
+                let stats = self.stats.read().unwrap().get(node).cloned();
+                self.cache
+                    .insert(node.clone(), (channel, stats.unwrap_or_default()));
+            }
+            let (channel, stats) = self.cache.get_mut(node).unwrap();
+            // Counted before sending, as `Incoming` may take it right away.
+            stats.incoming_queue.fetch_add(1, Ordering::Relaxed);
+            let result = channel.send(callback).await;
+            if result.is_err() {
+                stats.incoming_queue.fetch_sub(1, Ordering::Relaxed);
+                // The node has been removed. Drop the sender to not miss a
+                // re-added node with the same name.
+                self.cache.remove(node);
+            }
+            result.map_err(|_| BusError::Closed)
+        }
+        .boxed()
+    }
+}
+
+impl<T: EventBus + Clone> BusClone for T {
+    fn clone_box(&self) -> Box<dyn EventBus> {
+        Box::new(self.clone())
+    }
+}
+
+impl Clone for Box<dyn EventBus> {
+    fn clone(&self) -> Self {
+        self.as_ref().clone_box()
+    }
+}
+
+impl EventBus for Box<dyn EventBus> {
+    fn dispatch<'a>(
+        &'a mut self,
+        node: &'a NodeId,
+        callback: NodeCallback,
+    ) -> BoxFuture<'a, Result<(), BusError>> {
+        self.as_mut().dispatch(node, callback)
+    }
+}
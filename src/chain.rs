@@ -0,0 +1,137 @@
+//! Gateway relaying requests between two disjoint networks.
+//!
+//! A [`ChainedCarrier`] runs an upstream and a downstream [`Carrier`]. The
+//! requests received upstream from a routed node are written as is to a
+//! downstream node, keeping their `request_id`, `session_id` and payload, and
+//! the downstream response goes back to the upstream requester.
+
+use crate::bus::{BusError, EventBus};
+use crate::channels::{NodeCallback, OutgoingMessage};
+use crate::config::NodeId;
+use crate::stats::NodeStats;
+use crate::transport::Transport;
+use crate::{Carrier, Error};
+use futures::channel::mpsc;
+use futures::future::BoxFuture;
+use futures::prelude::*;
+use std::collections::HashMap;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+
+/// Two [`Carrier`]s chained with [`Carrier::chain`].
+pub struct ChainedCarrier {
+    upstream: Carrier,
+    downstream: Carrier,
+    /// Downstream node by upstream node.
+    routes: HashMap<NodeId, NodeId>,
+}
+
+/// Downstream queue and statistics of a routed node.
+type Route = (mpsc::Sender<OutgoingMessage>, Arc<NodeStats>);
+
+/// [`EventBus`] of the upstream carrier, relaying the requests from the routed
+/// nodes and dispatching the others to the inner bus.
+#[derive(Clone)]
+struct Relay {
+    inner: Box<dyn EventBus>,
+    routes: HashMap<NodeId, Route>,
+}
+
+impl ChainedCarrier {
+    pub(crate) fn new(upstream: Carrier, downstream: Carrier) -> Self {
+        Self {
+            upstream,
+            downstream,
+            routes: HashMap::new(),
+        }
+    }
+
+    /// Relays the requests from the upstream `from_node` to the downstream
+    /// `to_node`. The requests from the nodes without a rule are delivered to
+    /// the upstream [`Incoming`](crate::channels::Incoming) as usual.
+    ///
+    /// # Panics
+    ///
+    /// If `from_node` is not configured upstream or `to_node` downstream.
+    #[must_use]
+    pub fn add_routing_rule(
+        mut self,
+        from_node: impl Into<NodeId>,
+        to_node: impl Into<NodeId>,
+    ) -> Self {
+        let (from_node, to_node) = (from_node.into(), to_node.into());
+        assert!(
+            self.upstream.nodes.contains_key(&from_node),
+            "node `{from_node}` not configured upstream"
+        );
+        assert!(
+            self.downstream.nodes.contains_key(&to_node),
+            "node `{to_node}` not configured downstream"
+        );
+        self.routes.insert(from_node, to_node);
+        self
+    }
+
+    /// Runs both carriers, the upstream one over `upstream` and the downstream
+    /// one over `downstream`. Returns the first error of either.
+    ///
+    /// See [`Carrier::run_with_transport`] for the details.
+    pub async fn run<T: Transport, U: Transport>(
+        self,
+        upstream: T,
+        downstream: U,
+    ) -> Result<(), Error> {
+        let Self {
+            upstream: mut upstream_carrier,
+            downstream: mut downstream_carrier,
+            routes,
+        } = self;
+        let routes = {
+            let stats = downstream_carrier.stats.read().unwrap();
+            routes
+                .into_iter()
+                .map(|(from_node, to_node)| {
+                    let queue = downstream_carrier.queues[&to_node].clone();
+                    let stats = stats.get(&to_node).cloned().unwrap_or_default();
+                    (from_node, (queue, stats))
+                })
+                .collect::<HashMap<_, _>>()
+        };
+        // Innermost, so the middleware applies to the relayed requests too.
+        upstream_carrier
+            .bus_layers
+            .insert(0, Box::new(|inner| Box::new(Relay { inner, routes })));
+        future::try_join(
+            upstream_carrier.run_with_transport(upstream),
+            downstream_carrier.run_with_transport(downstream),
+        )
+        .await?;
+        Ok(())
+    }
+}
+
+impl EventBus for Relay {
+    fn dispatch<'a>(
+        &'a mut self,
+        node: &'a NodeId,
+        callback: NodeCallback,
+    ) -> BoxFuture<'a, Result<(), BusError>> {
+        let Some((queue, stats)) = self.routes.get_mut(node) else {
+            return self.inner.dispatch(node, callback);
+        };
+        async move {
+            // Counted before sending, as the connection may take it right away.
+            stats.outgoing_queue.fetch_add(1, Ordering::Relaxed);
+            let message = OutgoingMessage::Request {
+                callback,
+                written: None,
+            };
+            if queue.send(message).await.is_err() {
+                stats.outgoing_queue.fetch_sub(1, Ordering::Relaxed);
+                return Err(BusError::Closed);
+            }
+            Ok(())
+        }
+        .boxed()
+    }
+}
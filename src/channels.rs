@@ -1,13 +1,32 @@
 //! Communication channels.
 
+use crate::config::NodeId;
 use crate::messages;
+use crate::middleware::{Epoch, ValidationError, ValidatorConfig};
+use crate::stats::{
+    self, NodeStats, RequestHook, RequestOutcome, RequestTiming, SharedRequestHook, Stats,
+};
+use crate::{status, CHANNEL_CAPACITY};
 use futures::channel::{mpsc, oneshot};
 use futures::prelude::*;
-use std::collections::HashMap;
+use futures::stream::FusedStream;
+use prost::Message;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::sync::atomic::Ordering;
+use std::sync::{Arc, Mutex, Weak};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
 use thiserror::Error;
 
 /// A message with a value of `T`, which expected to be returned back with a
 /// value of `U`.
+///
+/// Dropping a callback without [`respond`](Self::respond)ing fails its
+/// receiver with [`oneshot::Canceled`]. A [`NodeCallback`] dropped so is
+/// answered with [`status::HANDLER_DROPPED`] and counted as a
+/// [`LossReason::HandlerDropped`](crate::loss::LossReason::HandlerDropped),
+/// warned of in the log.
 pub struct Callback<T, U> {
     /// Message sent in the forward direction.
     pub message: T,
@@ -18,16 +37,117 @@ pub struct Callback<T, U> {
 /// Node request with a response callback.
 pub type NodeCallback = Callback<messages::NodeRequest, messages::NodeResponse>;
 
+/// Node request without its response callback, for checkpointing the requests
+/// awaiting their responses. Replayed with
+/// [`Carrier::restore_checkpoint`](crate::Carrier::restore_checkpoint).
+#[allow(missing_docs)]
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SerializableNodeRequest {
+    /// Node the request was sent to, checked on restore. Empty in the
+    /// checkpoints predating it, and in the ones converted from a bare
+    /// request.
+    #[serde(default)]
+    pub node: String,
+    pub request_id: Vec<u8>,
+    pub payload: Vec<u8>,
+    pub session_id: Option<u64>,
+    pub epoch: Option<u64>,
+    pub priority: u32,
+    #[serde(default)]
+    pub auth_tag: Vec<u8>,
+    #[serde(default)]
+    pub request_seq: u64,
+    #[serde(default)]
+    pub traceparent: String,
+    #[serde(default)]
+    pub tracestate: String,
+    #[serde(default)]
+    pub service: String,
+    #[serde(default)]
+    pub is_idempotent: bool,
+}
+
+/// Message queued for an outgoing connection.
+pub(crate) enum OutgoingMessage {
+    Request {
+        callback: NodeCallback,
+        /// Notified with the time the request was written to the connection.
+        written: Option<oneshot::Sender<Instant>>,
+    },
+    Notification {
+        notification: messages::NodeNotification,
+        /// Notified once acknowledged by the receiving carrier, for
+        /// [`Outgoing::notify_acked`].
+        acked: Option<oneshot::Sender<()>>,
+    },
+}
+
+/// Sender of the received notifications.
+pub(crate) type Notifications = mpsc::Sender<(NodeId, messages::NodeNotification)>;
+
+/// Channel of the notifications received from all nodes.
+pub type NotificationReceiver = mpsc::Receiver<(NodeId, messages::NodeNotification)>;
+
+/// Channels of the services routed with [`Incoming::route`], by name. Owned
+/// by the [`Carrier`](crate::Carrier), so that the channels close once it
+/// stops.
+pub(crate) type Services = Arc<Mutex<ServiceChannels>>;
+
+type ServiceChannels = HashMap<String, mpsc::Sender<(NodeId, NodeCallback)>>;
+
 /// Set of incoming communication channels for a [`Carrier`](crate::Carrier).
 pub struct Incoming {
-    channels: HashMap<String, mpsc::Receiver<NodeCallback>>,
+    channels: HashMap<NodeId, mpsc::Receiver<NodeCallback>>,
+    added: mpsc::UnboundedReceiver<(NodeId, mpsc::Receiver<NodeCallback>)>,
+    /// Requests received ahead of [`Incoming::recv`], up to
+    /// [`CHANNEL_CAPACITY`] to pick the one of the highest priority, or by
+    /// [`Incoming::recv_session`], in order.
+    buffered: VecDeque<(NodeId, NodeCallback)>,
+    notifications: Option<NotificationReceiver>,
+    services: Weak<Mutex<ServiceChannels>>,
+    stats: Stats,
+    _handle: HandleGuard,
+}
+
+/// Channel of the requests to a service from all nodes, returned by
+/// [`Incoming::route`]. Dropping it stops the routing of the service.
+pub struct ServiceIncoming {
+    service: String,
+    rx: mpsc::Receiver<(NodeId, NodeCallback)>,
+    services: Weak<Mutex<ServiceChannels>>,
+    stats: Stats,
 }
 
 /// Set of outgoing communication channels for a [`Carrier`](crate::Carrier).
 pub struct Outgoing {
-    channels: HashMap<String, mpsc::Sender<NodeCallback>>,
+    channels: HashMap<NodeId, mpsc::Sender<OutgoingMessage>>,
+    stats: HashMap<NodeId, Arc<NodeStats>>,
+    validator: Option<ValidatorConfig>,
+    raw_responses: bool,
+    session_id: Option<u64>,
+    epoch: Option<Epoch>,
+    request_hook: SharedRequestHook,
+    _handle: HandleGuard,
 }
 
+/// Request sent with [`Outgoing`], reporting its [`RequestTiming`] to the hook
+/// of [`Carrier::on_request_complete`](crate::Carrier::on_request_complete)
+/// once dropped, so on the side of the caller.
+struct InFlight {
+    hook: Option<RequestHook>,
+    node: NodeId,
+    request_id_hash: u64,
+    queued: Instant,
+    /// Notified with the time the request was written to the connection.
+    written: Option<oneshot::Receiver<Instant>>,
+    written_at: Option<Instant>,
+    /// Time and outcome of the completion, abandoned if dropped without.
+    completed: Option<(Instant, RequestOutcome)>,
+}
+
+/// Notifies the [`Carrier`](crate::Carrier) when the owning handle is dropped.
+pub(crate) type HandleGuard = oneshot::Sender<()>;
+
 /// Error returned by [`Callback::send`].
 #[derive(Error, Debug)]
 pub enum SendError {
@@ -37,54 +157,758 @@ pub enum SendError {
     /// Return channel closed.
     #[error("return channel closed")]
     ReturnClosed(#[from] oneshot::Canceled),
+    /// No response within the attempt timeout of a
+    /// [`RetryOutgoing`](crate::middleware::RetryOutgoing).
+    #[error("response timeout")]
+    Timeout,
+    /// Request or notification past the largest frame accepted by the node,
+    /// as announced on the last connection.
+    #[error("message of {len} bytes past the frame limit of the node of {peer_max_frame_len} bytes, {max_frame_len} bytes locally")]
+    TooLarge {
+        /// Encoded length of the message.
+        len: usize,
+        /// Largest frame accepted by the node.
+        peer_max_frame_len: usize,
+        /// Largest frame accepted by this node.
+        max_frame_len: usize,
+    },
+    /// Request aborted by a middleware of an
+    /// [`OutgoingPipeline`](crate::pipeline::OutgoingPipeline).
+    #[error("request aborted by a middleware")]
+    Aborted,
+    /// Request rejected by the validator set with
+    /// [`Outgoing::set_validator`].
+    #[error("invalid request: {0}")]
+    Invalid(#[from] ValidationError),
+    /// Response with a non-zero [`status`](crate::status), unless disabled
+    /// with [`Outgoing::set_raw_responses`].
+    #[error("remote error {status}: {detail}")]
+    Remote {
+        /// Status code of the response.
+        status: u32,
+        /// Error detail of the response.
+        detail: String,
+    },
 }
 
 impl Incoming {
-    pub(crate) fn new(channels: HashMap<String, mpsc::Receiver<NodeCallback>>) -> Self {
-        Self { channels }
+    pub(crate) fn new(
+        channels: HashMap<NodeId, mpsc::Receiver<NodeCallback>>,
+        added: mpsc::UnboundedReceiver<(NodeId, mpsc::Receiver<NodeCallback>)>,
+        notifications: NotificationReceiver,
+        services: Weak<Mutex<ServiceChannels>>,
+        stats: Stats,
+        handle: HandleGuard,
+    ) -> Self {
+        Self {
+            channels,
+            added,
+            buffered: VecDeque::new(),
+            notifications: Some(notifications),
+            services,
+            stats,
+            _handle: handle,
+        }
     }
 
     /// Receives the next request message from one of the nodes. The response is
-    /// in the form `(node, callback)`. The response should be send back via the
-    /// callback channel.
-    pub async fn recv(&mut self) -> Option<(&str, NodeCallback)> {
-        let (callback, _, _) = future::select_all(self.channels.iter_mut().map(|(node, rx)| {
-            rx.next()
-                .map(|callback| callback.map(|callback| (node.as_str(), callback)))
-        }))
-        .await;
-        callback
+    /// in the form `(node, callback)`. The response should be send back with
+    /// [`Callback::respond`].
+    ///
+    /// The requests already received are returned by their `priority`, higher
+    /// first, and in order among equal ones.
+    ///
+    /// The node is owned, so `self` is free to receive again while the request
+    /// is processed.
+    pub async fn recv(&mut self) -> Option<(NodeId, NodeCallback)> {
+        self.buffer_received();
+        if let Some(index) = self.highest_priority() {
+            return self.buffered.remove(index);
+        }
+        self.recv_channels().await
+    }
+
+    /// Receives the next request message stamped with `session_id`, from one
+    /// of the nodes, like [`Incoming::recv`].
+    ///
+    /// The requests of other sessions, or of none, received meanwhile stay
+    /// buffered in order for the following receives, and are not answered
+    /// until then.
+    pub async fn recv_session(&mut self, session_id: u64) -> Option<(NodeId, NodeCallback)> {
+        let in_session = |(_, callback): &(NodeId, NodeCallback)| {
+            callback.message.session_id == Some(session_id)
+        };
+        if let Some(index) = self.buffered.iter().position(in_session) {
+            return self.buffered.remove(index);
+        }
+        loop {
+            let received = self.recv_channels().await?;
+            if in_session(&received) {
+                return Some(received);
+            }
+            self.buffered.push_back(received);
+        }
+    }
+
+    /// Waits for the next request message without consuming it. The message
+    /// stays buffered and is returned by the following [`Incoming::recv`].
+    pub async fn peek(&mut self) -> Option<(&NodeId, &messages::NodeRequest)> {
+        self.buffer_received();
+        if self.buffered.is_empty() {
+            let received = self.recv_channels().await?;
+            self.buffered.push_back(received);
+        }
+        let index = self.highest_priority()?;
+        self.buffered
+            .get(index)
+            .map(|(node, callback)| (node, &callback.message))
+    }
+
+    /// Takes the channel of the notifications from all nodes, delivered apart
+    /// from the requests. Returns [`None`] if already taken.
+    ///
+    /// The notifications must be consumed when the peers send them, or the
+    /// connections stall once the channel fills up.
+    pub fn take_notifications(&mut self) -> Option<NotificationReceiver> {
+        self.notifications.take()
+    }
+
+    /// Routes the requests to `service`, sent with [`Outgoing::send_service`],
+    /// to a channel of their own instead of these ones.
+    ///
+    /// Every service has its own queue, so a slow service does not delay the
+    /// others: a request finding the queue of its service full is answered
+    /// with the [`status::OVERLOADED`] status. A request to a service not
+    /// routed is answered with [`status::UNSUPPORTED`].
+    ///
+    /// # Panics
+    ///
+    /// If `service` is empty or already routed.
+    #[must_use]
+    pub fn route(&self, service: impl Into<String>) -> ServiceIncoming {
+        let service = service.into();
+        assert!(!service.is_empty(), "empty service name");
+        let (tx, rx) = mpsc::channel(CHANNEL_CAPACITY);
+        // Once the carrier stopped, the channel is left closed.
+        if let Some(services) = self.services.upgrade() {
+            let mut services = services.lock().unwrap();
+            let routed = services.get(&service).is_some_and(|tx| !tx.is_closed());
+            assert!(!routed, "service `{service}` already routed");
+            services.insert(service.clone(), tx);
+        }
+        ServiceIncoming {
+            service,
+            rx,
+            services: Weak::clone(&self.services),
+            stats: Arc::clone(&self.stats),
+        }
+    }
+
+    /// Buffers the requests already received, up to [`CHANNEL_CAPACITY`].
+    fn buffer_received(&mut self) {
+        while self.buffered.len() < CHANNEL_CAPACITY {
+            let Some(Some(received)) = self.recv_channels().now_or_never() else {
+                break;
+            };
+            self.buffered.push_back(received);
+        }
+    }
+
+    /// Returns the index of the first buffered request of the highest
+    /// priority.
+    fn highest_priority(&self) -> Option<usize> {
+        let priority = |(_, callback): &(NodeId, NodeCallback)| callback.message.priority;
+        let highest = self.buffered.iter().map(priority).max()?;
+        self.buffered.iter().position(|r| priority(r) == highest)
+    }
+
+    async fn recv_channels(&mut self) -> Option<(NodeId, NodeCallback)> {
+        future::poll_fn(|cx| self.poll_channels(cx)).await
+    }
+
+    fn poll_channels(&mut self, cx: &mut Context<'_>) -> Poll<Option<(NodeId, NodeCallback)>> {
+        while let Poll::Ready(Some((node, rx))) = self.added.poll_next_unpin(cx) {
+            self.channels.insert(node, rx);
+        }
+        let mut closed = Vec::new();
+        let mut received = None;
+        for (node, rx) in &mut self.channels {
+            match rx.poll_next_unpin(cx) {
+                Poll::Ready(Some(callback)) => {
+                    received = Some((node.clone(), callback));
+                    break;
+                }
+                Poll::Ready(None) => closed.push(node.clone()),
+                Poll::Pending => {}
+            }
+        }
+        // The channel of a removed node closes once drained.
+        for node in closed {
+            self.channels.remove(&node);
+        }
+        if let Some((node, _)) = &received {
+            if let Some(stats) = self.stats.read().unwrap().get(node) {
+                stats.incoming_queue.fetch_sub(1, Ordering::Relaxed);
+            }
+            Poll::Ready(received)
+        } else if self.channels.is_empty() && self.added.is_terminated() {
+            Poll::Ready(None)
+        } else {
+            Poll::Pending
+        }
+    }
+}
+
+impl ServiceIncoming {
+    /// Receives the next request to the service from one of the nodes, in the
+    /// form `(node, callback)`. The response should be sent back with
+    /// [`Callback::respond`]. Returns [`None`] once the carrier stopped.
+    pub async fn recv(&mut self) -> Option<(NodeId, NodeCallback)> {
+        let (node, callback) = self.rx.next().await?;
+        if let Some(stats) = self.stats.read().unwrap().get(&node) {
+            stats.incoming_queue.fetch_sub(1, Ordering::Relaxed);
+        }
+        Some((node, callback))
+    }
+
+    /// Returns the name of the service.
+    #[must_use]
+    pub fn service(&self) -> &str {
+        &self.service
+    }
+}
+
+impl Drop for ServiceIncoming {
+    fn drop(&mut self) {
+        if let Some(services) = self.services.upgrade() {
+            let mut services = services.lock().unwrap();
+            // The service may have been routed again since this channel
+            // closed.
+            if services
+                .get(&self.service)
+                .is_some_and(mpsc::Sender::is_closed)
+            {
+                services.remove(&self.service);
+            }
+        }
     }
 }
 
 impl Outgoing {
-    pub(crate) fn new(channels: HashMap<String, mpsc::Sender<NodeCallback>>) -> Self {
-        Self { channels }
+    pub(crate) fn new(
+        channels: HashMap<NodeId, mpsc::Sender<OutgoingMessage>>,
+        stats: &Stats,
+        request_hook: SharedRequestHook,
+        handle: HandleGuard,
+    ) -> Self {
+        let stats = stats.read().unwrap();
+        let stats = channels
+            .keys()
+            .map(|node| (node.clone(), stats.get(node).cloned().unwrap_or_default()))
+            .collect();
+        Self {
+            channels,
+            stats,
+            validator: None,
+            raw_responses: false,
+            session_id: None,
+            epoch: None,
+            request_hook,
+            _handle: handle,
+        }
     }
 
     /// Sends a request `message` to `node` and awaits for the response.
     ///
+    /// The requests to a node queued behind others, as the connection is
+    /// saturated, are written by their `priority`, higher first, and in order
+    /// among equal ones, and received by [`Incoming::recv`] alike. The
+    /// notifications go with the lowest priority.
+    ///
     /// # Panics
     ///
     /// If `node` was not configured in [`Carrier::new`](crate::Carrier::new).
     pub async fn send(
         &mut self,
-        node: &str,
+        node: impl Into<NodeId>,
         message: messages::NodeRequest,
     ) -> Result<messages::NodeResponse, SendError> {
-        let (message, rx) = Callback::new(message);
-        self.channels
-            .get_mut(node)
-            .expect("to be configured")
-            .send(message)
-            .await?;
+        let node = node.into();
+        let (mut in_flight, written) = self.in_flight(&node, &message, false);
+        let result = self.exchange(node, message, written).await;
+        in_flight.complete(&result);
+        check_status(result?, self.raw_responses)
+    }
+
+    /// Sends a request `message` to the `service` of `node`, routed by the
+    /// receiver with [`Incoming::route`], and awaits for the response.
+    ///
+    /// A receiver of a version without services delivers the request to its
+    /// [`Incoming`] channels.
+    ///
+    /// # Panics
+    ///
+    /// If `node` was not configured in [`Carrier::new`](crate::Carrier::new).
+    pub async fn send_service(
+        &mut self,
+        node: impl Into<NodeId>,
+        service: impl Into<String>,
+        mut message: messages::NodeRequest,
+    ) -> Result<messages::NodeResponse, SendError> {
+        message.service = service.into();
+        self.send(node, message).await
+    }
+
+    /// Sends a request `message` to `node` and awaits for the response,
+    /// together with the time elapsed since the request was enqueued.
+    ///
+    /// # Panics
+    ///
+    /// If `node` was not configured in [`Carrier::new`](crate::Carrier::new).
+    pub async fn send_timed(
+        &mut self,
+        node: impl Into<NodeId>,
+        message: messages::NodeRequest,
+    ) -> Result<(messages::NodeResponse, Duration), SendError> {
+        let start = Instant::now();
+        let response = self.send(node, message).await?;
+        Ok((response, start.elapsed()))
+    }
+
+    /// Sends a request `message` to `node` and awaits for the response,
+    /// together with the time elapsed since the request was written to the
+    /// connection. Unlike [`Outgoing::send_timed`], this excludes the time
+    /// spent waiting in the queue or for the connection to be established.
+    ///
+    /// # Panics
+    ///
+    /// If `node` was not configured in [`Carrier::new`](crate::Carrier::new).
+    pub async fn send_timed_wire(
+        &mut self,
+        node: impl Into<NodeId>,
+        message: messages::NodeRequest,
+    ) -> Result<(messages::NodeResponse, Duration), SendError> {
+        let node = node.into();
+        let (mut in_flight, written) = self.in_flight(&node, &message, true);
+        let result = self.exchange(node, message, written).await;
+        let received = Instant::now();
+        in_flight.complete(&result);
+        let response = check_status(result?, self.raw_responses)?;
+        // The write time is sent before the response is delivered.
+        let written = in_flight.written().await?;
+        Ok((response, received.saturating_duration_since(written)))
+    }
+
+    /// Sends a one-way notification with `payload` to `node`, without awaiting
+    /// any response. The notification keeps its order relative to the
+    /// requests sent to the same node.
+    ///
+    /// The notification is dropped if the connection to `node` does not
+    /// support notifications: the connections carrying requests in a single
+    /// direction need the [`Feature::Notifications`](crate::hello::Feature)
+    /// negotiated by the [`Hello`](crate::hello) handshake.
+    ///
+    /// # Panics
+    ///
+    /// If `node` was not configured in [`Carrier::new`](crate::Carrier::new).
+    pub async fn notify(
+        &mut self,
+        node: impl Into<NodeId>,
+        payload: Vec<u8>,
+    ) -> Result<(), SendError> {
+        let notification = messages::NodeNotification {
+            payload,
+            ..Default::default()
+        };
+        let message = OutgoingMessage::Notification {
+            notification,
+            acked: None,
+        };
+        self.push(node.into(), message).await
+    }
+
+    /// Sends a one-way notification with `payload` to `node`, and waits until
+    /// the carrier of `node` queued it to its application. The application
+    /// neither sees nor sends the acknowledgement.
+    ///
+    /// A notification not acknowledged is kept and written again on every new
+    /// connection to `node`, so it is delivered at least once, even across
+    /// reconnections. With [`Carrier::deduplication`](crate::Carrier::deduplication)
+    /// configured on the receiving node, its application gets every
+    /// notification once.
+    ///
+    /// The connection needs [`Feature::AckedNotifications`] and
+    /// [`Feature::Envelope`] negotiated by the [`Hello`](crate::hello)
+    /// handshake, or the notification is dropped and
+    /// [`SendError::ReturnClosed`] returned.
+    ///
+    /// [`Feature::AckedNotifications`]: crate::hello::Feature::AckedNotifications
+    /// [`Feature::Envelope`]: crate::hello::Feature::Envelope
+    ///
+    /// # Panics
+    ///
+    /// If `node` was not configured in [`Carrier::new`](crate::Carrier::new).
+    pub async fn notify_acked(
+        &mut self,
+        node: impl Into<NodeId>,
+        payload: Vec<u8>,
+    ) -> Result<(), SendError> {
+        let (acked, rx) = oneshot::channel();
+        let notification = messages::NodeNotification {
+            payload,
+            ack_id: ack_id(),
+        };
+        let message = OutgoingMessage::Notification {
+            notification,
+            acked: Some(acked),
+        };
+        self.push(node.into(), message).await?;
         Ok(rx.await?)
     }
+
+    /// Sends a request `message` to every node and awaits for all the
+    /// responses.
+    pub async fn broadcast(
+        &mut self,
+        message: messages::NodeRequest,
+    ) -> HashMap<NodeId, Result<messages::NodeResponse, SendError>> {
+        let nodes = self.channels.keys().cloned().collect::<Vec<_>>();
+        let raw_responses = self.raw_responses;
+        let mut responses = Vec::with_capacity(nodes.len());
+        for node in nodes {
+            let (mut in_flight, written) = self.in_flight(&node, &message, false);
+            let (callback, rx) = Callback::new(message.clone());
+            let enqueued = self.enqueue(node.clone(), callback, written).await;
+            responses.push(async move {
+                let response = match enqueued {
+                    Ok(()) => rx.await.map_err(SendError::from),
+                    Err(err) => Err(err),
+                };
+                in_flight.complete(&response);
+                let response = response.and_then(|response| check_status(response, raw_responses));
+                (node, response)
+            });
+        }
+        future::join_all(responses).await.into_iter().collect()
+    }
+
+    /// Checks every sent request against `config`, rejecting the invalid ones
+    /// with [`SendError::Invalid`] before they are queued. Disabled by default.
+    pub fn set_validator(&mut self, config: Option<ValidatorConfig>) {
+        self.validator = config;
+    }
+
+    /// Returns the responses with a non-zero status as they are, instead of
+    /// failing with [`SendError::Remote`]. Disabled by default.
+    pub fn set_raw_responses(&mut self, raw: bool) {
+        self.raw_responses = raw;
+    }
+
+    /// Stamps the requests sent without a `session_id` with `session_id`.
+    /// Disabled by default.
+    pub fn set_session_id(&mut self, session_id: Option<u64>) {
+        self.session_id = session_id;
+    }
+
+    /// Stamps the requests sent without an `epoch` with the current value of
+    /// `epoch` as they are queued, so the requests queued before a bump keep
+    /// the previous epoch. Sharing the [`Epoch`] with an
+    /// [`EpochFilter`](crate::middleware::EpochFilter) moves both ends of the
+    /// node at once. Disabled by default.
+    pub fn set_epoch(&mut self, epoch: Option<Epoch>) {
+        self.epoch = epoch;
+    }
+
+    /// Starts timing a request to `node`, returning the sender of its write
+    /// time, needed with a hook or `timed_wire`.
+    fn in_flight(
+        &self,
+        node: &NodeId,
+        request: &messages::NodeRequest,
+        timed_wire: bool,
+    ) -> (InFlight, Option<oneshot::Sender<Instant>>) {
+        let hook = self.request_hook.read().unwrap().clone();
+        let (written_tx, written) = if hook.is_some() || timed_wire {
+            let (tx, rx) = oneshot::channel();
+            (Some(tx), Some(rx))
+        } else {
+            (None, None)
+        };
+        let in_flight = InFlight {
+            hook,
+            node: node.clone(),
+            request_id_hash: stats::request_id_hash(&request.request_id),
+            queued: Instant::now(),
+            written,
+            written_at: None,
+            completed: None,
+        };
+        (in_flight, written_tx)
+    }
+
+    /// Enqueues the request `message` to `node`, and awaits its response. With
+    /// the `otel` feature, in a span of the client kind, whose context the
+    /// request carries.
+    async fn exchange(
+        &mut self,
+        node: NodeId,
+        message: messages::NodeRequest,
+        written: Option<oneshot::Sender<Instant>>,
+    ) -> Result<messages::NodeResponse, SendError> {
+        #[cfg(feature = "otel")]
+        let span =
+            tracing::info_span!("request", %node, otel.name = "mpc.rpc", otel.kind = "client");
+        let exchange = async move {
+            let (callback, rx) = Callback::new(message);
+            self.enqueue(node, callback, written).await?;
+            Ok(rx.await?)
+        };
+        #[cfg(feature = "otel")]
+        let exchange = tracing::Instrument::instrument(exchange, span);
+        exchange.await
+    }
+
+    async fn enqueue(
+        &mut self,
+        node: NodeId,
+        mut callback: NodeCallback,
+        written: Option<oneshot::Sender<Instant>>,
+    ) -> Result<(), SendError> {
+        let message = &mut callback.message;
+        message.migrate_distance_list();
+        message.session_id = message.session_id.or(self.session_id);
+        if message.epoch.is_none() {
+            message.epoch = self.epoch.as_ref().map(Epoch::get);
+        }
+        #[cfg(feature = "otel")]
+        crate::trace_context::inject(message);
+        if let Some(validator) = &self.validator {
+            validator.validate(&callback.message)?;
+        }
+        self.push(node, OutgoingMessage::Request { callback, written })
+            .await
+    }
+
+    async fn push(&mut self, node: NodeId, message: OutgoingMessage) -> Result<(), SendError> {
+        let channel = self.channels.get_mut(&node).expect("to be configured");
+        let stats = &self.stats[&node];
+        // Not known before the first connection, which checks the frames.
+        if let Some((max_frame_len, peer_max_frame_len)) = *stats.frame_limits.lock().unwrap() {
+            let len = match &message {
+                OutgoingMessage::Request { callback, .. } => callback.message.encoded_len(),
+                OutgoingMessage::Notification { notification, .. } => notification.encoded_len(),
+            };
+            if len > peer_max_frame_len {
+                return Err(SendError::TooLarge {
+                    len,
+                    peer_max_frame_len,
+                    max_frame_len,
+                });
+            }
+        }
+        // Counted before sending, as the connection may take it right away.
+        let queue = &stats.outgoing_queue;
+        queue.fetch_add(1, Ordering::Relaxed);
+        if let Err(err) = channel.send(message).await {
+            queue.fetch_sub(1, Ordering::Relaxed);
+            return Err(err.into());
+        }
+        Ok(())
+    }
+}
+
+impl InFlight {
+    /// Records the completion of the request with `result`, before the
+    /// status is checked.
+    fn complete(&mut self, result: &Result<messages::NodeResponse, SendError>) {
+        let outcome = match result {
+            Ok(response) if response.status == status::OK => RequestOutcome::Ok,
+            Ok(response) => RequestOutcome::Remote(response.status),
+            Err(SendError::Remote { status, .. }) => RequestOutcome::Remote(*status),
+            Err(SendError::Timeout) => RequestOutcome::Abandoned,
+            Err(_) => RequestOutcome::Failed,
+        };
+        self.completed = Some((Instant::now(), outcome));
+    }
+
+    /// Returns the time the request was written to the connection.
+    async fn written(&mut self) -> Result<Instant, oneshot::Canceled> {
+        if let Some(written) = self.written.take() {
+            self.written_at = Some(written.await?);
+        }
+        self.written_at.ok_or(oneshot::Canceled)
+    }
+}
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        let Some(hook) = &self.hook else {
+            return;
+        };
+        let written_at = self.written_at.or_else(|| {
+            let written = self.written.as_mut()?;
+            written.try_recv().ok().flatten()
+        });
+        let (completed, outcome) = self
+            .completed
+            .unwrap_or_else(|| (Instant::now(), RequestOutcome::Abandoned));
+        let total = completed.saturating_duration_since(self.queued);
+        hook(RequestTiming {
+            node: self.node.clone(),
+            request_id_hash: self.request_id_hash,
+            queued: written_at.map_or(total, |written| {
+                written.saturating_duration_since(self.queued)
+            }),
+            on_wire: written_at.map_or(Duration::ZERO, |written| {
+                completed.saturating_duration_since(written)
+            }),
+            total,
+            outcome,
+        });
+    }
+}
+
+impl NodeCallback {
+    /// Returns the trace context the request was sent with, for the handler
+    /// spans to continue the trace of the requester. Empty if the request
+    /// carries none or a malformed one, so the spans parented to it start a
+    /// new trace.
+    #[cfg(feature = "otel")]
+    #[must_use]
+    pub fn trace_context(&self) -> opentelemetry::Context {
+        crate::trace_context::extract(&self.message)
+    }
+
+    /// Answers the request with a failure `status` and its `detail`. See
+    /// [`status`](crate::status) for the reserved codes. Returns the response
+    /// back if the requester is gone.
+    #[allow(clippy::result_large_err)]
+    pub fn respond_err(
+        self,
+        status: u32,
+        detail: impl Into<String>,
+    ) -> Result<(), messages::NodeResponse> {
+        self.callback.send(messages::NodeResponse {
+            request_id: self.message.request_id,
+            error_detail: detail.into(),
+            status,
+            ..Default::default()
+        })
+    }
+}
+
+/// Returns a random non-zero `ack_id`, so that the ids of a restarted node do
+/// not repeat the ones remembered by the receivers.
+fn ack_id() -> u64 {
+    let bytes = ring::rand::generate::<[u8; 8]>(&ring::rand::SystemRandom::new())
+        .expect("system random generator")
+        .expose();
+    u64::from_le_bytes(bytes).max(1)
+}
+
+/// Turns a response with a non-zero status into [`SendError::Remote`], unless
+/// `raw` is set.
+fn check_status(
+    response: messages::NodeResponse,
+    raw: bool,
+) -> Result<messages::NodeResponse, SendError> {
+    if raw || response.status == status::OK {
+        Ok(response)
+    } else {
+        Err(SendError::Remote {
+            status: response.status,
+            detail: response.error_detail,
+        })
+    }
+}
+
+impl SerializableNodeRequest {
+    /// Checkpoints a `request` sent to `node`.
+    #[must_use]
+    pub fn new(node: &str, request: messages::NodeRequest) -> Self {
+        Self {
+            node: node.to_string(),
+            ..request.into()
+        }
+    }
+}
+
+impl From<messages::NodeRequest> for SerializableNodeRequest {
+    #[allow(deprecated)]
+    fn from(mut request: messages::NodeRequest) -> Self {
+        request.migrate_distance_list();
+        let messages::NodeRequest {
+            request_id,
+            payload,
+            distance_list: _,
+            session_id,
+            epoch,
+            priority,
+            auth_tag,
+            request_seq,
+            traceparent,
+            tracestate,
+            service,
+            is_idempotent,
+        } = request;
+        Self {
+            node: String::new(),
+            request_id,
+            payload,
+            session_id,
+            epoch,
+            priority,
+            auth_tag,
+            request_seq,
+            traceparent,
+            tracestate,
+            service,
+            is_idempotent,
+        }
+    }
+}
+
+impl From<SerializableNodeRequest> for messages::NodeRequest {
+    #[allow(deprecated)]
+    fn from(request: SerializableNodeRequest) -> Self {
+        let SerializableNodeRequest {
+            node: _,
+            request_id,
+            payload,
+            session_id,
+            epoch,
+            priority,
+            auth_tag,
+            request_seq,
+            traceparent,
+            tracestate,
+            service,
+            is_idempotent,
+        } = request;
+        Self {
+            request_id,
+            payload,
+            distance_list: Vec::new(),
+            session_id,
+            epoch,
+            priority,
+            auth_tag,
+            request_seq,
+            traceparent,
+            tracestate,
+            service,
+            is_idempotent,
+        }
+    }
 }
 
 impl<T, U> Callback<T, U> {
     /// Creates a pair of a new [`Callback`] message and a corresponding callback
     /// from `message`.
+    ///
+    /// The receiver is the only end the response reaches: dropped, it makes
+    /// [`respond`](Self::respond) fail, returning the response back.
+    #[must_use = "dropping the receiver without awaiting it will cause the callback to fail silently"]
     pub fn new(message: T) -> (Self, oneshot::Receiver<U>) {
         let (tx, rx) = oneshot::channel();
         let callback = Self {
@@ -93,4 +917,10 @@ impl<T, U> Callback<T, U> {
         };
         (callback, rx)
     }
+
+    /// Sends the `response` to the requester. Returns the response back if the
+    /// requester is gone.
+    pub fn respond(self, response: U) -> Result<(), U> {
+        self.callback.send(response)
+    }
 }
@@ -0,0 +1,317 @@
+//! Connection churn of the nodes.
+//!
+//! A node goes up when its first connection is established, and down when its
+//! last one closes. Every node counts its reconnects, the lifetimes of its
+//! connections, and the time it spent down in the last [`DOWNTIME_WINDOW`],
+//! from the last [`MAX_TRANSITIONS`] transitions.
+//!
+//! A node going up and down more than
+//! [`Carrier::flap_threshold`](crate::Carrier::flap_threshold) times within
+//! the window of the threshold is flapping: mostly up, but dropping its
+//! requests. A single task sweeps the nodes every [`SWEEP_INTERVAL`], and
+//! reports a node starting and stopping flapping as a warning and a
+//! [`CarrierEvent::FlappingStarted`], then as a
+//! [`CarrierEvent::FlappingStopped`] once the window holds no more than the
+//! threshold.
+
+use crate::config::NodeId;
+use crate::lifecycle::{CarrierEvent, Lifecycle};
+use crate::stats::{LatencyHistogram, Stats};
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::time;
+use tracing::{info, warn};
+
+const SECS_PER_MINUTE: u64 = 60;
+
+/// Upper bounds of the buckets of the connection lifetimes, in seconds: from
+/// 1 second to 1 day.
+pub const LIFETIME_BUCKETS: &[f64] = &[1.0, 10.0, 60.0, 600.0, 3_600.0, 21_600.0, 86_400.0];
+
+/// Window of the time spent down, in
+/// [`NodeState::downtime_last_hour`](crate::stats::NodeState::downtime_last_hour).
+pub const DOWNTIME_WINDOW: Duration = Duration::from_secs(60 * SECS_PER_MINUTE);
+
+/// Number of the transitions kept by node. Past it, the oldest ones are
+/// forgotten, and the time down before the oldest kept is estimated from it.
+pub const MAX_TRANSITIONS: usize = 256;
+
+/// Interval between two sweeps of the nodes for the flapping ones.
+pub const SWEEP_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Default number of the transitions within [`DEFAULT_FLAP_WINDOW`] above
+/// which a node is flapping.
+pub const DEFAULT_FLAP_TRANSITIONS: usize = 6;
+
+/// Default window of [`DEFAULT_FLAP_TRANSITIONS`].
+pub const DEFAULT_FLAP_WINDOW: Duration = Duration::from_secs(5 * SECS_PER_MINUTE);
+
+/// Number of the transitions within a window above which a node is flapping.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct FlapThreshold {
+    pub(crate) transitions: usize,
+    pub(crate) window: Duration,
+}
+
+/// Churn of a node, in its [`NodeStats`](crate::stats::NodeStats).
+pub(crate) struct Churn {
+    /// Lifetimes of the closed connections.
+    pub(crate) lifetimes: Arc<LatencyHistogram>,
+    state: Mutex<State>,
+}
+
+struct State {
+    /// Time the node started being tracked, down.
+    since: Instant,
+    /// Last transitions, with whether to up, the oldest first.
+    transitions: VecDeque<(Instant, bool)>,
+    up: bool,
+    /// Number of the transitions to up.
+    ups: u64,
+    /// Time the node started flapping, if flapping.
+    flapping: Option<Instant>,
+}
+
+/// Start or stop of the flapping of a node, found by a sweep.
+#[derive(Clone, Copy)]
+enum Flapping {
+    Started { transitions: usize },
+    Stopped { duration: Duration },
+}
+
+impl Default for FlapThreshold {
+    fn default() -> Self {
+        Self {
+            transitions: DEFAULT_FLAP_TRANSITIONS,
+            window: DEFAULT_FLAP_WINDOW,
+        }
+    }
+}
+
+impl Default for Churn {
+    fn default() -> Self {
+        let state = State {
+            since: Instant::now(),
+            transitions: VecDeque::new(),
+            up: false,
+            ups: 0,
+            flapping: None,
+        };
+        Self {
+            lifetimes: Arc::new(LatencyHistogram::new(LIFETIME_BUCKETS)),
+            state: Mutex::new(state),
+        }
+    }
+}
+
+impl Churn {
+    /// Records the node going up, on its first connection established.
+    pub(crate) fn up(&self) {
+        self.state.lock().unwrap().transition(true);
+    }
+
+    /// Records the node going down, on its last connection closed after
+    /// `lifetime`.
+    pub(crate) fn down(&self, lifetime: Duration) {
+        self.lifetimes.record(lifetime);
+        self.state.lock().unwrap().transition(false);
+    }
+
+    /// Records a connection closed after `lifetime`, with others still open.
+    pub(crate) fn closed(&self, lifetime: Duration) {
+        self.lifetimes.record(lifetime);
+    }
+
+    /// Returns the number of the reconnects, the transitions to up but the
+    /// first.
+    pub(crate) fn reconnects(&self) -> u64 {
+        self.state.lock().unwrap().ups.saturating_sub(1)
+    }
+
+    /// Returns the time spent down in the last [`DOWNTIME_WINDOW`].
+    pub(crate) fn downtime(&self) -> Duration {
+        self.state.lock().unwrap().downtime(Instant::now())
+    }
+
+    /// Returns whether the node is flapping, as of the last sweep.
+    pub(crate) fn flapping(&self) -> bool {
+        self.state.lock().unwrap().flapping.is_some()
+    }
+}
+
+impl State {
+    fn transition(&mut self, up: bool) {
+        if self.transitions.len() == MAX_TRANSITIONS {
+            self.transitions.pop_front();
+        }
+        self.transitions.push_back((Instant::now(), up));
+        self.up = up;
+        if up {
+            self.ups += 1;
+        }
+    }
+
+    fn downtime(&self, now: Instant) -> Duration {
+        let start = now
+            .checked_sub(DOWNTIME_WINDOW)
+            .map_or(self.since, |start| start.max(self.since));
+        let mut transitions = self
+            .transitions
+            .iter()
+            .filter(|(at, _)| *at > start)
+            .peekable();
+        // The state at the start of the window is the one the first
+        // transition within it left.
+        let mut up = transitions.peek().map_or(self.up, |(_, up)| !up);
+        let (mut from, mut downtime) = (start, Duration::ZERO);
+        for &(at, to_up) in transitions {
+            if !up {
+                downtime += at - from;
+            }
+            (from, up) = (at, to_up);
+        }
+        if !up {
+            downtime += now - from;
+        }
+        downtime
+    }
+
+    fn sweep(&mut self, now: Instant, threshold: FlapThreshold) -> Option<Flapping> {
+        let transitions = self
+            .transitions
+            .iter()
+            .rev()
+            .take_while(|(at, _)| now.duration_since(*at) <= threshold.window)
+            .count();
+        match self.flapping {
+            None if transitions > threshold.transitions => {
+                self.flapping = Some(now);
+                Some(Flapping::Started { transitions })
+            }
+            Some(started) if transitions <= threshold.transitions => {
+                self.flapping = None;
+                let duration = now.duration_since(started);
+                Some(Flapping::Stopped { duration })
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Sweeps the nodes for the ones starting or stopping flapping, forever.
+pub(crate) async fn watch(stats: Stats, threshold: FlapThreshold, lifecycle: Lifecycle) {
+    let mut interval = time::interval(SWEEP_INTERVAL);
+    interval.set_missed_tick_behavior(time::MissedTickBehavior::Delay);
+    loop {
+        interval.tick().await;
+        let now = Instant::now();
+        let changes = stats
+            .read()
+            .unwrap()
+            .iter()
+            .filter_map(|(node, stats)| {
+                let change = stats.churn.state.lock().unwrap().sweep(now, threshold);
+                change.map(|change| (node.clone(), change))
+            })
+            .collect::<Vec<_>>();
+        for (node, change) in changes {
+            report(&node, change, threshold, &lifecycle);
+        }
+    }
+}
+
+fn report(node: &NodeId, change: Flapping, threshold: FlapThreshold, lifecycle: &Lifecycle) {
+    match change {
+        Flapping::Started { transitions } => {
+            let window = threshold.window;
+            warn!(node = %node, "Connection flapping: {transitions} transitions in {window:?}");
+            lifecycle.emit(|at| CarrierEvent::FlappingStarted {
+                at,
+                node: node.to_string(),
+                transitions,
+                window,
+            });
+        }
+        Flapping::Stopped { duration } => {
+            info!(node = %node, "Connection no longer flapping after {duration:?}");
+            lifecycle.emit(|at| CarrierEvent::FlappingStopped {
+                at,
+                node: node.to_string(),
+                duration,
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const THRESHOLD: FlapThreshold = FlapThreshold {
+        transitions: 3,
+        window: Duration::from_secs(10),
+    };
+
+    /// Returns a state tracked since `start`, with a transition every second
+    /// from then on, alternately up and down.
+    fn bouncing(start: Instant, transitions: u32) -> State {
+        let mut state = Churn::default().state.into_inner().unwrap();
+        state.since = start;
+        for n in 1..=transitions {
+            let up = n % 2 == 1;
+            state
+                .transitions
+                .push_back((start + Duration::from_secs(n.into()), up));
+            state.up = up;
+        }
+        state
+    }
+
+    #[test]
+    fn transitions_bounded() {
+        let churn = Churn::default();
+        for _ in 0..MAX_TRANSITIONS {
+            churn.up();
+            churn.down(Duration::from_millis(1));
+        }
+        assert_eq!(churn.reconnects(), MAX_TRANSITIONS as u64 - 1);
+        assert_eq!(churn.lifetimes.count(), MAX_TRANSITIONS as u64);
+        let state = churn.state.lock().unwrap();
+        assert_eq!(state.transitions.len(), MAX_TRANSITIONS);
+        assert!(!state.up);
+    }
+
+    #[test]
+    fn downtime_sums_down_intervals() {
+        let start = Instant::now();
+        // Down 1 s, up 1 s, down 1 s, up 1 s, down since.
+        let state = bouncing(start, 4);
+        let now = start + Duration::from_secs(10);
+        assert_eq!(state.downtime(now), Duration::from_secs(2 + 6));
+        // Up at the start of the window, until the last transition in it.
+        let now = start + DOWNTIME_WINDOW + Duration::from_millis(3_500);
+        let up = Duration::from_millis(500);
+        assert_eq!(state.downtime(now) + up, DOWNTIME_WINDOW);
+    }
+
+    #[test]
+    fn flapping_starts_and_stops() {
+        let start = Instant::now();
+        let mut state = bouncing(start, 3);
+        let at = |secs| start + Duration::from_secs(secs);
+        assert!(state.sweep(at(4), THRESHOLD).is_none());
+        state.transitions.push_back((at(4), false));
+        let Some(Flapping::Started { transitions }) = state.sweep(at(5), THRESHOLD) else {
+            panic!("not flapping");
+        };
+        assert_eq!(transitions, 4);
+        assert!(state.sweep(at(6), THRESHOLD).is_none());
+        // The first transition out of the window.
+        let Some(Flapping::Stopped { duration }) = state.sweep(at(12), THRESHOLD) else {
+            panic!("still flapping");
+        };
+        assert_eq!(duration, Duration::from_secs(7));
+        assert!(state.flapping.is_none());
+    }
+}
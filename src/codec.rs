@@ -0,0 +1,140 @@
+//! Encodings of the node messages.
+//!
+//! The node messages are encoded with the [`Codec`] negotiated in the
+//! [`Hello`](crate::hello::Hello), Protobuf by default. The `bincode` feature
+//! adds [`BincodeCodec`], cheaper to encode and decode for the large payloads.
+//! Both ends of a connection must use the same codec: with another one, or
+//! without the handshake while a codec other than Protobuf is configured, the
+//! connection is rejected. The `Hello`, compression negotiation and hook
+//! messages are always encoded with Protobuf.
+
+use crate::messages::CodecId;
+use crate::protobuf_tcp::Error;
+use std::fmt;
+
+/// Message encoded with any [`Codec`].
+#[cfg(not(feature = "bincode"))]
+pub trait Frame: prost::Message + Default {}
+
+/// Message encoded with any [`Codec`].
+#[cfg(feature = "bincode")]
+pub trait Frame: prost::Message + Default + serde::Serialize + serde::de::DeserializeOwned {}
+
+#[cfg(not(feature = "bincode"))]
+impl<T: prost::Message + Default> Frame for T {}
+
+#[cfg(feature = "bincode")]
+impl<T> Frame for T where
+    T: prost::Message + Default + serde::Serialize + serde::de::DeserializeOwned
+{
+}
+
+/// Encoding of the node messages.
+pub trait Codec {
+    /// Appends the encoding of `message` to `buf`.
+    ///
+    /// # Errors
+    ///
+    /// If the message fails to encode.
+    fn encode<T: Frame>(message: &T, buf: &mut Vec<u8>) -> Result<(), Error>;
+
+    /// Decodes a message from `buf`.
+    ///
+    /// # Errors
+    ///
+    /// If `buf` is not a valid encoding of a `T`.
+    fn decode<T: Frame>(buf: &[u8]) -> Result<T, Error>;
+}
+
+/// Protobuf encoding, the default.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ProstCodec;
+
+/// Bincode encoding of the serde representation, with the `bincode` feature.
+#[cfg(feature = "bincode")]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct BincodeCodec;
+
+/// Codec of a connection.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum CodecKind {
+    /// [`ProstCodec`].
+    #[default]
+    Prost,
+    /// [`BincodeCodec`].
+    #[cfg(feature = "bincode")]
+    Bincode,
+}
+
+impl Codec for ProstCodec {
+    fn encode<T: Frame>(message: &T, buf: &mut Vec<u8>) -> Result<(), Error> {
+        Ok(message.encode(buf)?)
+    }
+
+    fn decode<T: Frame>(buf: &[u8]) -> Result<T, Error> {
+        Ok(T::decode(buf)?)
+    }
+}
+
+#[cfg(feature = "bincode")]
+impl Codec for BincodeCodec {
+    fn encode<T: Frame>(message: &T, buf: &mut Vec<u8>) -> Result<(), Error> {
+        bincode::serialize_into(buf, message).map_err(Error::Bincode)
+    }
+
+    fn decode<T: Frame>(buf: &[u8]) -> Result<T, Error> {
+        bincode::deserialize(buf).map_err(Error::Bincode)
+    }
+}
+
+impl CodecKind {
+    /// Appends the encoding of `message` to `buf` with this codec.
+    pub(crate) fn encode<T: Frame>(self, message: &T, buf: &mut Vec<u8>) -> Result<(), Error> {
+        match self {
+            Self::Prost => ProstCodec::encode(message, buf),
+            #[cfg(feature = "bincode")]
+            Self::Bincode => BincodeCodec::encode(message, buf),
+        }
+    }
+
+    /// Decodes a message from `buf` with this codec.
+    pub(crate) fn decode<T: Frame>(self, buf: &[u8]) -> Result<T, Error> {
+        match self {
+            Self::Prost => ProstCodec::decode(buf),
+            #[cfg(feature = "bincode")]
+            Self::Bincode => BincodeCodec::decode(buf),
+        }
+    }
+
+    /// Returns the identifier announced in the [`Hello`](crate::hello::Hello).
+    pub(crate) fn id(self) -> CodecId {
+        match self {
+            Self::Prost => CodecId::Prost,
+            #[cfg(feature = "bincode")]
+            Self::Bincode => CodecId::Bincode,
+        }
+    }
+
+    /// Returns the codec announced in a [`Hello`](crate::hello::Hello), if
+    /// supported.
+    pub(crate) fn from_id(id: i32) -> Option<Self> {
+        match CodecId::try_from(id).ok()? {
+            CodecId::Prost => Some(Self::Prost),
+            #[cfg(feature = "bincode")]
+            CodecId::Bincode => Some(Self::Bincode),
+            #[cfg(not(feature = "bincode"))]
+            CodecId::Bincode => None,
+        }
+    }
+}
+
+impl fmt::Display for CodecKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Prost => f.write_str("Protobuf"),
+            #[cfg(feature = "bincode")]
+            Self::Bincode => f.write_str("bincode"),
+        }
+    }
+}
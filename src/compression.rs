@@ -0,0 +1,194 @@
+//! Message compression.
+//!
+//! Whole connections are compressed with the [`CompressionNegotiator`] hook,
+//! and the payloads of single messages with the [`PayloadCompression`] of the
+//! connections with [`Feature::Compression`](crate::hello::Feature) negotiated.
+//!
+//! The hook decides: a connection it compresses sends its payloads as they
+//! are, while one it leaves uncompressed, with a peer declining or not
+//! registering the hook, falls back to the payload compression if negotiated.
+
+use crate::hook::{Error, PreConnectHook};
+use crate::messages::{CompressionAlgorithm, CompressionCapabilities, CompressionSelection};
+use crate::node::MAX_LEN;
+use crate::protobuf_tcp::{Reader, Writer};
+use crate::stats::NodeStats;
+use futures::future::BoxFuture;
+use futures::prelude::*;
+use std::io;
+use std::sync::atomic::Ordering;
+use tracing::debug;
+
+const ZSTD_LEVEL: i32 = 3;
+
+/// Pre-connect hook which negotiates the compression algorithm for the
+/// connection.
+///
+/// The connecting side sends its [`CompressionCapabilities`], and the accepting
+/// side replies with a [`CompressionSelection`] of the first algorithm from its
+/// own preference list the peer supports, falling back to
+/// [`CompressionAlgorithm::None`]. Both sides then switch their readers and
+/// writers to the selected algorithm, and skip the [`PayloadCompression`] on a
+/// connection compressed whole.
+///
+/// The negotiation runs on every new connection, reconnects included, and
+/// takes a single round trip: the capabilities are sent without waiting for
+/// anything, and the selection is the only reply. Remembering the algorithm
+/// agreed on the previous connection would not save that round trip, as the
+/// accepting side still has to confirm it, while a peer upgraded in between
+/// could select another one.
+pub struct CompressionNegotiator {
+    supported: Vec<CompressionAlgorithm>,
+}
+
+impl CompressionNegotiator {
+    /// Creates a new [`CompressionNegotiator`] with the `supported` algorithms
+    /// in the order of preference.
+    #[must_use]
+    pub fn new(supported: impl IntoIterator<Item = CompressionAlgorithm>) -> Self {
+        Self {
+            supported: supported.into_iter().collect(),
+        }
+    }
+}
+
+/// Per-message compression of the request and notification payloads, used
+/// with the peers which negotiated [`Feature::Compression`] in the
+/// [`Hello`](crate::hello::Hello) handshake. Set with
+/// [`Carrier::payload_compression`](crate::Carrier::payload_compression).
+///
+/// The payloads are decompressed on receipt, so the applications see the same
+/// bytes either way.
+///
+/// [`Feature::Compression`]: crate::hello::Feature::Compression
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PayloadCompression {
+    /// Smallest payload compressed, in bytes. Defaults to 4 KiB.
+    pub threshold: usize,
+    /// Largest payload accepted after decompression, in bytes, rejecting the
+    /// compression bombs. Defaults to the maximum frame length.
+    pub max_decompressed_len: usize,
+}
+
+impl Default for PayloadCompression {
+    fn default() -> Self {
+        Self {
+            threshold: 4 * 1024,
+            max_decompressed_len: MAX_LEN,
+        }
+    }
+}
+
+impl PayloadCompression {
+    /// Compresses a `payload` to be sent to a peer if it is over the threshold
+    /// and shrinks, and returns the algorithm used. The payload is left as is
+    /// without `negotiated` compression.
+    pub(crate) fn compress(
+        &self,
+        payload: &mut Vec<u8>,
+        negotiated: bool,
+        stats: &NodeStats,
+    ) -> CompressionAlgorithm {
+        if negotiated && payload.len() >= self.threshold {
+            match zstd::bulk::compress(payload, ZSTD_LEVEL) {
+                Ok(compressed) if compressed.len() < payload.len() => {
+                    stats.compressed_frames.fetch_add(1, Ordering::Relaxed);
+                    let before = payload.len() as u64;
+                    stats
+                        .bytes_before_compression
+                        .fetch_add(before, Ordering::Relaxed);
+                    let after = compressed.len() as u64;
+                    stats
+                        .bytes_after_compression
+                        .fetch_add(after, Ordering::Relaxed);
+                    *payload = compressed;
+                    return CompressionAlgorithm::Zstd;
+                }
+                Ok(_) => {}
+                Err(err) => debug!("Sending a payload uncompressed: {err}"),
+            }
+        }
+        stats.uncompressed_frames.fetch_add(1, Ordering::Relaxed);
+        CompressionAlgorithm::None
+    }
+
+    /// Decompresses a received `payload` compressed with `algorithm`.
+    pub(crate) fn decompress(&self, payload: &mut Vec<u8>, algorithm: i32) -> io::Result<()> {
+        match CompressionAlgorithm::try_from(algorithm) {
+            Ok(CompressionAlgorithm::None) => Ok(()),
+            Ok(CompressionAlgorithm::Zstd) => {
+                *payload = zstd::bulk::decompress(payload, self.max_decompressed_len)?;
+                Ok(())
+            }
+            Err(_) => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("unknown compression algorithm {algorithm}"),
+            )),
+        }
+    }
+}
+
+impl Default for CompressionNegotiator {
+    fn default() -> Self {
+        Self::new([CompressionAlgorithm::Zstd, CompressionAlgorithm::None])
+    }
+}
+
+impl PreConnectHook for CompressionNegotiator {
+    fn on_connect<'a>(
+        &'a self,
+        reader: &'a mut Reader,
+        writer: &'a mut Writer,
+    ) -> BoxFuture<'a, Result<(), Error>> {
+        async move {
+            let capabilities = CompressionCapabilities {
+                supported: self
+                    .supported
+                    .iter()
+                    .map(|&algorithm| algorithm.into())
+                    .collect(),
+            };
+            writer.write(capabilities).await?;
+            writer.flush().await?;
+            let CompressionSelection { selected } = reader.read().await?;
+            let algorithm = CompressionAlgorithm::try_from(selected)
+                .ok()
+                .filter(|algorithm| {
+                    *algorithm == CompressionAlgorithm::None || self.supported.contains(algorithm)
+                })
+                .ok_or(Error::UnsupportedCompression(selected))?;
+            debug!("Negotiated compression: {}", algorithm.as_str_name());
+            reader.set_compression(algorithm);
+            writer.set_compression(algorithm);
+            Ok(())
+        }
+        .boxed()
+    }
+
+    fn on_accept<'a>(
+        &'a self,
+        reader: &'a mut Reader,
+        writer: &'a mut Writer,
+    ) -> BoxFuture<'a, Result<(), Error>> {
+        async move {
+            let capabilities = reader.read::<CompressionCapabilities>().await?;
+            let algorithm = self
+                .supported
+                .iter()
+                .copied()
+                .find(|&algorithm| capabilities.supported().any(|peer| peer == algorithm))
+                .unwrap_or(CompressionAlgorithm::None);
+            writer
+                .write(CompressionSelection {
+                    selected: algorithm.into(),
+                })
+                .await?;
+            writer.flush().await?;
+            debug!("Negotiated compression: {}", algorithm.as_str_name());
+            reader.set_compression(algorithm);
+            writer.set_compression(algorithm);
+            Ok(())
+        }
+        .boxed()
+    }
+}
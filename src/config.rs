@@ -0,0 +1,198 @@
+//! Carrier configuration.
+
+use crate::transport::NodeAddr;
+use rustls::pki_types::ServerName;
+use std::borrow::Borrow;
+use std::collections::HashMap;
+use std::fmt;
+use std::num::ParseIntError;
+use std::ops::Deref;
+use std::sync::{Arc, RwLock};
+use thiserror::Error;
+
+/// Configuration error.
+#[allow(missing_docs)]
+#[derive(Error, Debug)]
+#[non_exhaustive]
+pub enum ConfigError {
+    #[error("node `{0}` has no port")]
+    MissingPort(String),
+    #[error("node `{0}` port: {1}")]
+    InvalidPort(String, ParseIntError),
+    #[error("node `{0}` has an empty host")]
+    EmptyHost(String),
+    #[error("node `{0}` is an IPv6 address without brackets")]
+    UnbracketedIpv6(String),
+    #[error("node `{0}` is configured more than once")]
+    DuplicateNode(String),
+    #[error("node `{0}` is not a valid server name")]
+    InvalidServerName(String),
+}
+
+/// Stable identity of a node, such as its party index in the ceremony.
+///
+/// The identity is decoupled from the network location of the node, which is
+/// looked up in the carrier registry and can be re-pointed at runtime with
+/// [`CarrierHandle::set_addr`](crate::control::CarrierHandle::set_addr). By
+/// default a node is dialed at the host equal to its identity.
+///
+/// APIs taking an `impl Into<NodeId>` accept plain strings as well.
+#[derive(Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct NodeId(Arc<str>);
+
+/// Network addresses of the nodes, updated as the nodes are added, removed
+/// and re-pointed.
+pub(crate) type Registry = Arc<RwLock<HashMap<NodeId, NodeAddr>>>;
+
+/// Which side establishes the connections with a node.
+///
+/// With [`Direction::Both`], every node dials every other node and each
+/// connection carries the requests one way. Otherwise, a single connection
+/// dialed by one side carries the requests both ways, so a node configured
+/// with [`Direction::Dial`] must in turn have us configured with
+/// [`Direction::Accept`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum Direction {
+    /// Only dial the node.
+    Dial,
+    /// Only accept the connections from the node.
+    Accept,
+    /// Dial the node and accept the connections from it.
+    #[default]
+    Both,
+}
+
+impl NodeId {
+    /// Returns the identity as a string slice.
+    #[must_use]
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl Deref for NodeId {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl Borrow<str> for NodeId {
+    fn borrow(&self) -> &str {
+        &self.0
+    }
+}
+
+impl AsRef<str> for NodeId {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl From<&str> for NodeId {
+    fn from(id: &str) -> Self {
+        Self(id.into())
+    }
+}
+
+impl From<String> for NodeId {
+    fn from(id: String) -> Self {
+        Self(id.into())
+    }
+}
+
+impl From<&String> for NodeId {
+    fn from(id: &String) -> Self {
+        Self(id.as_str().into())
+    }
+}
+
+impl From<&NodeId> for NodeId {
+    fn from(id: &NodeId) -> Self {
+        id.clone()
+    }
+}
+
+impl PartialEq<str> for NodeId {
+    fn eq(&self, other: &str) -> bool {
+        &*self.0 == other
+    }
+}
+
+impl PartialEq<&str> for NodeId {
+    fn eq(&self, other: &&str) -> bool {
+        &*self.0 == *other
+    }
+}
+
+impl fmt::Display for NodeId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl fmt::Debug for NodeId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&*self.0, f)
+    }
+}
+
+/// Parses a node in the form of `host:port` or `[ipv6]:port`.
+pub fn parse_node(s: &str) -> Result<(String, u16), ConfigError> {
+    let (host, port) = s
+        .rsplit_once(':')
+        .ok_or_else(|| ConfigError::MissingPort(s.to_string()))?;
+    let host = if let Some(host) = host.strip_prefix('[') {
+        host.strip_suffix(']')
+            .ok_or_else(|| ConfigError::UnbracketedIpv6(s.to_string()))?
+    } else if host.contains(':') {
+        return Err(ConfigError::UnbracketedIpv6(s.to_string()));
+    } else {
+        host
+    };
+    if host.is_empty() {
+        return Err(ConfigError::EmptyHost(s.to_string()));
+    }
+    if port.is_empty() {
+        return Err(ConfigError::MissingPort(s.to_string()));
+    }
+    let port = port
+        .parse()
+        .map_err(|err| ConfigError::InvalidPort(s.to_string(), err))?;
+    Ok((host.to_string(), port))
+}
+
+/// Parses a list of nodes in the form of `host:port`, rejecting duplicates.
+/// Entries with the host equal to `self_name` are skipped.
+pub fn parse_nodes(
+    nodes: impl IntoIterator<Item = impl AsRef<str>>,
+    self_name: Option<&str>,
+) -> Result<HashMap<String, u16>, ConfigError> {
+    let mut map = HashMap::new();
+    for node in nodes {
+        let (host, port) = parse_node(node.as_ref())?;
+        if Some(host.as_str()) == self_name {
+            continue;
+        }
+        if map.insert(host.clone(), port).is_some() {
+            return Err(ConfigError::DuplicateNode(host));
+        }
+    }
+    Ok(map)
+}
+
+/// Returns the default address of `node`, dialed at the host equal to its
+/// identity.
+pub(crate) fn default_addr(node: &str, port: u16) -> Result<NodeAddr, ConfigError> {
+    Ok(NodeAddr {
+        host: node.to_string(),
+        port,
+        tls_name: server_name(node)?,
+    })
+}
+
+pub(crate) fn server_name(node: &str) -> Result<ServerName<'static>, ConfigError> {
+    ServerName::try_from(node.to_string())
+        .map_err(|_| ConfigError::InvalidServerName(node.to_string()))
+}
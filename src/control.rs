@@ -0,0 +1,228 @@
+//! Runtime control of a [`Carrier`](crate::Carrier).
+
+use crate::channels::Outgoing;
+use crate::config::{self, ConfigError, NodeId};
+use crate::errors::{ErrorAccounting, ErrorCounts};
+use crate::loss::LossAccounting;
+use crate::stats::{DebugState, LatencyHistogram, RttEstimate, Stats};
+use crate::transport::NodeAddr;
+use futures::channel::{mpsc, oneshot};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
+use thiserror::Error;
+
+/// Error returned by [`CarrierHandle::add_node`].
+#[allow(missing_docs)]
+#[derive(Error, Debug)]
+#[non_exhaustive]
+pub enum AddError {
+    #[error("node `{0}` is already configured")]
+    Exists(NodeId),
+    #[error("configuration: {0}")]
+    Config(#[from] ConfigError),
+    #[error("carrier is not running")]
+    Stopped,
+}
+
+/// Error returned by [`CarrierHandle::remove_node`] and
+/// [`CarrierHandle::set_addr`].
+#[allow(missing_docs)]
+#[derive(Error, Debug)]
+#[non_exhaustive]
+pub enum RemoveError {
+    #[error("node `{0}` is not configured")]
+    NotFound(NodeId),
+    #[error("carrier is not running")]
+    Stopped,
+}
+
+/// Handle for changing the topology of a [`Carrier`](crate::Carrier) while it
+/// runs. Obtained from [`Carrier::handle`](crate::Carrier::handle).
+///
+/// Requests made before the carrier is started are applied once it runs, and
+/// the ones made after it stopped fail until it runs again.
+#[derive(Clone)]
+pub struct CarrierHandle {
+    commands: mpsc::UnboundedSender<Command>,
+    handshakes: Arc<AtomicUsize>,
+    stats: Stats,
+    errors: Arc<ErrorCounts>,
+    dropped_events: Arc<AtomicU64>,
+    running: Arc<AtomicBool>,
+}
+
+pub(crate) enum Command {
+    AddNode {
+        node: NodeId,
+        addr: NodeAddr,
+        reply: oneshot::Sender<Result<Outgoing, AddError>>,
+    },
+    RemoveNode {
+        node: NodeId,
+        reply: oneshot::Sender<Result<(), RemoveError>>,
+    },
+    SetAddr {
+        node: NodeId,
+        addr: NodeAddr,
+        reply: oneshot::Sender<Result<(), RemoveError>>,
+    },
+}
+
+impl CarrierHandle {
+    pub(crate) fn new(
+        commands: mpsc::UnboundedSender<Command>,
+        handshakes: Arc<AtomicUsize>,
+        stats: Stats,
+        errors: Arc<ErrorCounts>,
+        dropped_events: Arc<AtomicU64>,
+        running: Arc<AtomicBool>,
+    ) -> Self {
+        Self {
+            commands,
+            handshakes,
+            stats,
+            errors,
+            dropped_events,
+            running,
+        }
+    }
+
+    /// Returns the number of the incoming connection handshakes in progress.
+    /// See [`Carrier::max_concurrent_handshakes`](crate::Carrier::max_concurrent_handshakes).
+    #[must_use]
+    pub fn handshakes_in_progress(&self) -> usize {
+        self.handshakes.load(Ordering::Relaxed)
+    }
+
+    /// Returns the number of the [`CarrierEvent`](crate::lifecycle::CarrierEvent)s
+    /// dropped as the stream of [`Carrier::events`](crate::Carrier::events)
+    /// was full.
+    #[must_use]
+    pub fn dropped_events(&self) -> u64 {
+        self.dropped_events.load(Ordering::Relaxed)
+    }
+
+    /// Returns `true` until the carrier stops running, and again once it is
+    /// [run](crate::Carrier::run) again.
+    #[must_use]
+    pub fn is_running(&self) -> bool {
+        self.running.load(Ordering::Acquire) && !self.commands.is_closed()
+    }
+
+    /// Returns the histogram of the response latencies of `node`, or [`None`]
+    /// if the node is not configured. See
+    /// [`Carrier::latency_histogram`](crate::Carrier::latency_histogram).
+    #[must_use]
+    pub fn latency_histogram(&self, node: impl Into<NodeId>) -> Option<Arc<LatencyHistogram>> {
+        let stats = self.stats.read().unwrap();
+        stats
+            .get(&node.into())
+            .map(|stats| Arc::clone(&stats.latency))
+    }
+
+    /// Returns the histogram of the lifetimes of the closed connections with
+    /// `node`, or [`None`] if the node is not configured. See
+    /// [`Carrier::connection_lifetimes`](crate::Carrier::connection_lifetimes).
+    #[must_use]
+    pub fn connection_lifetimes(&self, node: impl Into<NodeId>) -> Option<Arc<LatencyHistogram>> {
+        let stats = self.stats.read().unwrap();
+        stats
+            .get(&node.into())
+            .map(|stats| Arc::clone(&stats.churn.lifetimes))
+    }
+
+    /// Returns the estimate of the round-trip time of the network to `node`,
+    /// or [`None`] until sampled on the current connection with the node, or
+    /// if the node is not configured.
+    ///
+    /// The estimate is seeded from the round-trip probes written every
+    /// [`HEARTBEAT_INTERVAL`](crate::node::HEARTBEAT_INTERVAL), and refined from
+    /// the requests answered by nodes reporting their handling time, which is
+    /// left out. It is also in the [`DebugState`], as
+    /// [`NodeState::network_rtt`](crate::stats::NodeState::network_rtt).
+    #[must_use]
+    pub fn rtt(&self, node: impl Into<NodeId>) -> Option<RttEstimate> {
+        let stats = self.stats.read().unwrap();
+        let estimate = stats.get(&node.into())?.rtt.lock().unwrap().estimate();
+        estimate
+    }
+
+    /// Returns a snapshot of the state of every node for diagnostics. See
+    /// [`Carrier::debug_state`](crate::Carrier::debug_state).
+    #[must_use]
+    pub fn debug_state(&self) -> DebugState {
+        DebugState::new(&self.stats)
+    }
+
+    /// Returns the accounting of the messages discarded by the carrier. See
+    /// [`Carrier::losses`](crate::Carrier::losses).
+    #[must_use]
+    pub fn losses(&self) -> LossAccounting {
+        LossAccounting::new(Arc::clone(&self.stats))
+    }
+
+    /// Returns the counts of the errors by kind. See
+    /// [`Carrier::errors`](crate::Carrier::errors).
+    #[must_use]
+    pub fn errors(&self) -> ErrorAccounting {
+        ErrorAccounting::new(Arc::clone(&self.stats), Arc::clone(&self.errors))
+    }
+
+    /// Adds `node` listening on `port`, and starts connecting to it. Returns an
+    /// [`Outgoing`] handle for sending requests to the node. The requests from
+    /// the node are received by the existing
+    /// [`Incoming`](crate::channels::Incoming) handle.
+    pub async fn add_node(&self, node: impl Into<NodeId>, port: u16) -> Result<Outgoing, AddError> {
+        let node = node.into();
+        let addr = config::default_addr(&node, port)?;
+        self.add_node_at(node, addr).await
+    }
+
+    /// Adds `node` at the network address `addr`. See
+    /// [`CarrierHandle::add_node`].
+    pub async fn add_node_at(
+        &self,
+        node: impl Into<NodeId>,
+        addr: NodeAddr,
+    ) -> Result<Outgoing, AddError> {
+        let (reply, rx) = oneshot::channel();
+        let node = node.into();
+        if !self.send(Command::AddNode { node, addr, reply }) {
+            return Err(AddError::Stopped);
+        }
+        rx.await.map_err(|_| AddError::Stopped)?
+    }
+
+    /// Removes `node`. New requests to the node are rejected, and the method
+    /// returns once the requests already sent to the node are answered and its
+    /// connection is closed.
+    pub async fn remove_node(&self, node: impl Into<NodeId>) -> Result<(), RemoveError> {
+        let (reply, rx) = oneshot::channel();
+        let node = node.into();
+        if !self.send(Command::RemoveNode { node, reply }) {
+            return Err(RemoveError::Stopped);
+        }
+        rx.await.map_err(|_| RemoveError::Stopped)?
+    }
+
+    /// Re-points `node` to the network address `addr`. The established
+    /// connection is kept, and the next reconnect dials the new address.
+    pub async fn set_addr(
+        &self,
+        node: impl Into<NodeId>,
+        addr: NodeAddr,
+    ) -> Result<(), RemoveError> {
+        let (reply, rx) = oneshot::channel();
+        let node = node.into();
+        if !self.send(Command::SetAddr { node, addr, reply }) {
+            return Err(RemoveError::Stopped);
+        }
+        rx.await.map_err(|_| RemoveError::Stopped)?
+    }
+
+    /// Sends `command` to the carrier, unless it stopped. A command sent as
+    /// the carrier stops is dropped, failing its reply.
+    fn send(&self, command: Command) -> bool {
+        self.is_running() && self.commands.unbounded_send(command).is_ok()
+    }
+}
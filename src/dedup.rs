@@ -0,0 +1,155 @@
+//! Deduplication of the retransmitted requests.
+
+use crate::messages::NodeResponse;
+use crate::node::RequestKey;
+use std::collections::{HashMap, VecDeque};
+use std::time::{Duration, Instant};
+
+/// Deduplication of the requests received on a connection, for the peers with
+/// at-least-once delivery. Set with
+/// [`Carrier::deduplication`](crate::Carrier::deduplication).
+///
+/// Every connection remembers the `request_id`s of the recently received
+/// requests. A duplicate of an answered request is answered with the same
+/// response again, and a duplicate of a request still being handled is
+/// dropped, as the response answers both.
+///
+/// The acknowledged notifications received from every node are remembered
+/// likewise, across the connections, to drop the ones resent.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DeduplicationConfig {
+    /// Largest number of the remembered requests of a connection, forgetting
+    /// the oldest ones past the limit.
+    pub max_entries: usize,
+    /// Time a request is remembered for.
+    pub ttl: Duration,
+}
+
+/// Recently received requests of a connection.
+pub(crate) struct DeduplicationCache {
+    config: DeduplicationConfig,
+    /// Responses by request, [`None`] until answered.
+    entries: HashMap<RequestKey, Option<NodeResponse>>,
+    /// The requests in the order of receipt, with the time.
+    order: VecDeque<(RequestKey, Instant)>,
+}
+
+/// Outcome of [`DeduplicationCache::check`].
+pub(crate) enum Seen {
+    /// The request was not received recently.
+    New,
+    /// The request is still being handled.
+    InFlight,
+    /// The request was answered with the response.
+    Answered(NodeResponse),
+}
+
+impl DeduplicationCache {
+    pub(crate) fn new(config: DeduplicationConfig) -> Self {
+        Self {
+            config,
+            entries: HashMap::new(),
+            order: VecDeque::new(),
+        }
+    }
+
+    /// Looks up a received request, remembering it if new.
+    pub(crate) fn check(&mut self, key: RequestKey) -> Seen {
+        let now = Instant::now();
+        self.evict(now);
+        if let Some(response) = self.entries.get(&key) {
+            return response.clone().map_or(Seen::InFlight, Seen::Answered);
+        }
+        if self.config.max_entries == 0 {
+            return Seen::New;
+        }
+        if self.entries.len() == self.config.max_entries {
+            self.pop();
+        }
+        self.entries.insert(key.clone(), None);
+        self.order.push_back((key, now));
+        Seen::New
+    }
+
+    /// Returns whether a request was received recently, without remembering
+    /// it.
+    pub(crate) fn contains(&mut self, key: &RequestKey) -> bool {
+        self.evict(Instant::now());
+        self.entries.contains_key(key)
+    }
+
+    /// Records the `response` of a remembered request.
+    pub(crate) fn answer(&mut self, key: &RequestKey, response: &NodeResponse) {
+        if let Some(entry) = self.entries.get_mut(key) {
+            *entry = Some(response.clone());
+        }
+    }
+
+    fn evict(&mut self, now: Instant) {
+        while self
+            .order
+            .front()
+            .is_some_and(|(_, received)| now.duration_since(*received) >= self.config.ttl)
+        {
+            self.pop();
+        }
+    }
+
+    fn pop(&mut self) {
+        if let Some((key, _)) = self.order.pop_front() {
+            self.entries.remove(&key);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cache(max_entries: usize, ttl: Duration) -> DeduplicationCache {
+        DeduplicationCache::new(DeduplicationConfig { max_entries, ttl })
+    }
+
+    fn key(id: u8) -> RequestKey {
+        RequestKey::Id(vec![id])
+    }
+
+    #[test]
+    fn duplicate_in_flight_then_answered() {
+        let mut cache = cache(8, Duration::from_secs(10));
+        assert!(matches!(cache.check(key(1)), Seen::New));
+        assert!(matches!(cache.check(key(1)), Seen::InFlight));
+        let response = NodeResponse {
+            request_id: vec![1],
+            ..NodeResponse::default()
+        };
+        cache.answer(&key(1), &response);
+        assert!(matches!(cache.check(key(1)), Seen::Answered(answered) if answered == response));
+        assert!(matches!(cache.check(RequestKey::Seq(1)), Seen::New));
+    }
+
+    #[test]
+    fn oldest_forgotten_past_max_entries() {
+        let mut cache = cache(2, Duration::from_secs(10));
+        for id in 1..=3 {
+            assert!(matches!(cache.check(key(id)), Seen::New));
+        }
+        assert!(!cache.contains(&key(1)));
+        assert!(cache.contains(&key(2)));
+        assert!(cache.contains(&key(3)));
+    }
+
+    #[test]
+    fn expired_forgotten() {
+        let mut cache = cache(8, Duration::ZERO);
+        assert!(matches!(cache.check(key(1)), Seen::New));
+        assert!(matches!(cache.check(key(1)), Seen::New));
+    }
+
+    #[test]
+    fn disabled_without_entries() {
+        let mut cache = cache(0, Duration::from_secs(10));
+        assert!(matches!(cache.check(key(1)), Seen::New));
+        assert!(!cache.contains(&key(1)));
+    }
+}
@@ -0,0 +1,316 @@
+//! Counts of the errors by kind.
+//!
+//! Every error of a connection is counted by its [`ErrorKind`], the variant of
+//! [`node::Error`], or of the [`protobuf_tcp::Error`] it wraps, and by node
+//! once the node is known, for the alerts to have rates to watch rather than
+//! log lines. The failures of the carrier itself, the [`Error`](enum@Error)s,
+//! are counted without a node. The counts are read from the
+//! [`ErrorAccounting`] of [`Carrier::errors`](crate::Carrier::errors) or
+//! [`CarrierHandle::errors`](crate::control::CarrierHandle::errors).
+//!
+//! An error wrapping another one is counted by the kind of the wrapped error:
+//! a frame failing to decode is a [`ErrorKind::Decode`], and a listener failing
+//! under supervision is counted by the kind of its failure.
+
+use crate::config::NodeId;
+use crate::stats::Stats;
+use crate::{node, protobuf_tcp, Error};
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+
+/// Kind of an error, by its variant.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[non_exhaustive]
+pub enum ErrorKind {
+    /// [`node::Error::Tls`].
+    Tls,
+    /// [`node::Error::Socket`].
+    Socket,
+    /// [`node::Error::Sni`].
+    Sni,
+    /// [`node::Error::UnknownServerName`].
+    UnknownServerName,
+    /// [`node::Error::Bus`].
+    Bus,
+    /// [`node::Error::Hello`].
+    Hello,
+    /// [`node::Error::Hook`].
+    Hook,
+    /// [`node::Error::UnexpectedResponse`].
+    UnexpectedResponse,
+    /// [`node::Error::UnexpectedResponseSeq`].
+    UnexpectedResponseSeq,
+    /// [`node::Error::PeerDisconnected`].
+    PeerDisconnected,
+    /// [`protobuf_tcp::Error::Io`].
+    Io,
+    /// [`protobuf_tcp::Error::Decode`].
+    Decode,
+    /// [`protobuf_tcp::Error::Encode`].
+    Encode,
+    /// [`protobuf_tcp::Error::MessageTooLarge`].
+    MessageTooLarge,
+    /// [`protobuf_tcp::Error::Compression`].
+    Compression,
+    /// [`protobuf_tcp::Error::Closed`].
+    Closed,
+    /// `protobuf_tcp::Error::Bincode`, with the `bincode` feature.
+    Bincode,
+    /// [`Error::Config`].
+    Config,
+    /// [`Error::TlsInit`].
+    TlsInit,
+    /// [`Error::Bind`].
+    Bind,
+    /// [`Error::Listener`].
+    Listener,
+    /// `Error::Resolve`, with the `no-tls` feature.
+    Resolve,
+    /// [`Error::UnknownNode`].
+    UnknownNode,
+    /// [`Error::CheckpointNode`].
+    CheckpointNode,
+    /// [`Error::HandlesDropped`].
+    HandlesDropped,
+    /// [`Error::EventLog`].
+    EventLog,
+    /// `Error::Otlp`, with the `otlp` feature.
+    Otlp,
+    /// [`Error::Panicked`] and [`node::Error::Panicked`].
+    Panicked,
+}
+
+/// Number of the errors of a kind, with a node or not.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ErrorCount {
+    /// Node of the errors, [`None`] for the errors of the carrier and of the
+    /// incoming connections failing before naming their node.
+    pub node: Option<NodeId>,
+    /// Kind of the errors.
+    pub kind: ErrorKind,
+    /// Number of the errors.
+    pub count: u64,
+}
+
+/// Handle reading the error counts of a [`Carrier`](crate::Carrier), live.
+#[derive(Clone)]
+pub struct ErrorAccounting {
+    stats: Stats,
+    carrier: Arc<ErrorCounts>,
+}
+
+/// Counts of the errors by kind, of a node in its
+/// [`NodeStats`](crate::stats::NodeStats), or of the carrier.
+#[derive(Default)]
+pub(crate) struct ErrorCounts {
+    counts: Mutex<HashMap<ErrorKind, u64>>,
+}
+
+impl ErrorKind {
+    /// Returns the kind as labeled in the metrics: the name of its variant in
+    /// snake case.
+    #[must_use]
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Tls => "tls",
+            Self::Socket => "socket",
+            Self::Sni => "sni",
+            Self::UnknownServerName => "unknown_server_name",
+            Self::Bus => "bus",
+            Self::Hello => "hello",
+            Self::Hook => "hook",
+            Self::UnexpectedResponse => "unexpected_response",
+            Self::UnexpectedResponseSeq => "unexpected_response_seq",
+            Self::PeerDisconnected => "peer_disconnected",
+            Self::Io => "io",
+            Self::Decode => "decode",
+            Self::Encode => "encode",
+            Self::MessageTooLarge => "message_too_large",
+            Self::Compression => "compression",
+            Self::Closed => "closed",
+            Self::Bincode => "bincode",
+            Self::Config => "config",
+            Self::TlsInit => "tls_init",
+            Self::Bind => "bind",
+            Self::Listener => "listener",
+            Self::Resolve => "resolve",
+            Self::UnknownNode => "unknown_node",
+            Self::CheckpointNode => "checkpoint_node",
+            Self::HandlesDropped => "handles_dropped",
+            Self::EventLog => "event_log",
+            Self::Otlp => "otlp",
+            Self::Panicked => "panicked",
+        }
+    }
+}
+
+impl fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl From<&protobuf_tcp::Error> for ErrorKind {
+    fn from(err: &protobuf_tcp::Error) -> Self {
+        match err {
+            protobuf_tcp::Error::Io(_) => Self::Io,
+            protobuf_tcp::Error::Decode(_) => Self::Decode,
+            protobuf_tcp::Error::Encode(_) => Self::Encode,
+            protobuf_tcp::Error::MessageTooLarge { .. } => Self::MessageTooLarge,
+            protobuf_tcp::Error::Compression(_) => Self::Compression,
+            protobuf_tcp::Error::Closed => Self::Closed,
+            #[cfg(feature = "bincode")]
+            protobuf_tcp::Error::Bincode(_) => Self::Bincode,
+        }
+    }
+}
+
+impl From<&node::Error> for ErrorKind {
+    fn from(err: &node::Error) -> Self {
+        match err {
+            node::Error::Tls(_) => Self::Tls,
+            node::Error::Socket(_) => Self::Socket,
+            node::Error::Sni => Self::Sni,
+            node::Error::UnknownServerName => Self::UnknownServerName,
+            node::Error::ProtocolRead { inner, .. } | node::Error::ProtocolWrite { inner, .. } => {
+                inner.into()
+            }
+            node::Error::Bus(_) => Self::Bus,
+            node::Error::Hello(_) => Self::Hello,
+            node::Error::Hook(_) => Self::Hook,
+            node::Error::UnexpectedResponse(_) => Self::UnexpectedResponse,
+            node::Error::UnexpectedResponseSeq(_) => Self::UnexpectedResponseSeq,
+            node::Error::PeerDisconnected => Self::PeerDisconnected,
+            node::Error::Panicked(_) => Self::Panicked,
+        }
+    }
+}
+
+impl From<&Error> for ErrorKind {
+    fn from(err: &Error) -> Self {
+        match err {
+            Error::Config(_) => Self::Config,
+            Error::TlsInit(_) => Self::TlsInit,
+            Error::Bind { .. } => Self::Bind,
+            Error::Listener { .. } => Self::Listener,
+            Error::Node { source, .. } | Error::Incoming { source, .. } => source.into(),
+            #[cfg(feature = "no-tls")]
+            Error::Resolve { .. } => Self::Resolve,
+            Error::UnknownNode(_) => Self::UnknownNode,
+            Error::CheckpointNode { .. } => Self::CheckpointNode,
+            Error::HandlesDropped => Self::HandlesDropped,
+            Error::EventLog { .. } => Self::EventLog,
+            #[cfg(feature = "otlp")]
+            Error::Otlp(_) => Self::Otlp,
+            Error::Panicked { .. } => Self::Panicked,
+            Error::Component { source, .. } => (&**source).into(),
+        }
+    }
+}
+
+impl fmt::Display for ErrorCount {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.count, self.kind)?;
+        if let Some(node) = &self.node {
+            write!(f, " with {node}")?;
+        }
+        Ok(())
+    }
+}
+
+impl ErrorAccounting {
+    pub(crate) fn new(stats: Stats, carrier: Arc<ErrorCounts>) -> Self {
+        Self { stats, carrier }
+    }
+
+    /// Returns the error counts, sorted by node and kind, the ones without a
+    /// node first. The kinds never counted are left out.
+    #[must_use]
+    pub fn snapshot(&self) -> Vec<ErrorCount> {
+        let mut counts = self.carrier.snapshot(None);
+        for (node, stats) in self.stats.read().unwrap().iter() {
+            counts.extend(stats.errors.snapshot(Some(node)));
+        }
+        counts.sort_by(|a, b| (&a.node, a.kind).cmp(&(&b.node, b.kind)));
+        counts
+    }
+
+    /// Returns the number of the errors of `kind`, with every node and
+    /// without.
+    #[must_use]
+    pub fn count(&self, kind: ErrorKind) -> u64 {
+        self.snapshot()
+            .iter()
+            .filter(|count| count.kind == kind)
+            .map(|count| count.count)
+            .sum()
+    }
+}
+
+impl ErrorCounts {
+    /// Counts an error.
+    pub(crate) fn record(&self, err: impl Into<ErrorKind>) {
+        *self.counts.lock().unwrap().entry(err.into()).or_default() += 1;
+    }
+
+    /// Returns the number of the errors of every kind.
+    pub(crate) fn total(&self) -> u64 {
+        self.counts.lock().unwrap().values().sum()
+    }
+
+    fn snapshot(&self, node: Option<&NodeId>) -> Vec<ErrorCount> {
+        self.counts
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(&kind, &count)| ErrorCount {
+                node: node.cloned(),
+                kind,
+                count,
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::supervisor::Component;
+    use std::io;
+
+    #[test]
+    fn wrapped_error_counted_by_inner_kind() {
+        let decode = prost::DecodeError::new("invalid wire type");
+        let err = Error::Component {
+            component: Component::Listener,
+            source: Box::new(Error::Incoming {
+                peer: "127.0.0.1:4000".to_string(),
+                source: node::Error::ProtocolRead {
+                    inner: protobuf_tcp::Error::Decode(decode),
+                    message_type: "NodeRequest",
+                },
+            }),
+        };
+        assert_eq!(ErrorKind::from(&err), ErrorKind::Decode);
+        let err = Error::Node {
+            node: "b".into(),
+            addr: "b:1".to_string(),
+            source: node::Error::Socket(io::ErrorKind::ConnectionRefused.into()),
+        };
+        assert_eq!(ErrorKind::from(&err), ErrorKind::Socket);
+    }
+
+    #[test]
+    fn counts_snapshot_by_kind() {
+        let counts = ErrorCounts::default();
+        counts.record(&node::Error::PeerDisconnected);
+        counts.record(&node::Error::PeerDisconnected);
+        counts.record(&node::Error::Sni);
+        assert_eq!(counts.total(), 3);
+        let mut snapshot = counts.snapshot(Some(&"b".into()));
+        snapshot.sort_by_key(|count| count.kind);
+        let rendered = snapshot.iter().map(ToString::to_string).collect::<Vec<_>>();
+        assert_eq!(rendered, ["1 sni with b", "2 peer_disconnected with b"]);
+    }
+}
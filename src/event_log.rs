@@ -0,0 +1,175 @@
+//! Persistent log of the connection events, for post-mortem debugging.
+//!
+//! With [`Carrier::event_log`](crate::Carrier::event_log) set, every
+//! connection opened or closed and every connection failure is appended to a
+//! binary file, written through on every event. Each record is laid out as:
+//!
+//! ```text
+//! [u64 ts_ns][u8 event_type][u8 node_len][node][u8 data_len][data]
+//! ```
+//!
+//! with the timestamp in nanoseconds since the Unix epoch, little-endian, and
+//! the node and data truncated to 255 bytes. Once the file would exceed its
+//! maximum size, it is renamed with a `.1` suffix, replacing the previous one,
+//! and a new file is started.
+//!
+//! The `mpc-carrier-event-reader` binary prints a log with [`read`].
+
+use crate::config::NodeId;
+use crate::Error;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime};
+use tracing::warn;
+
+/// Kind of a logged event.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum EventType {
+    /// Connection with a node opened. The data is the direction, `incoming`
+    /// or `outgoing`.
+    Connected,
+    /// Connection with a node closed. The data is the direction.
+    Disconnected,
+    /// Connection with a node failed. The data is the error.
+    Failed,
+    /// Event written by a later version.
+    Unknown(u8),
+}
+
+/// Event read from a log.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Event {
+    /// Time of the event.
+    pub timestamp: SystemTime,
+    /// Kind of the event.
+    pub event_type: EventType,
+    /// Node of the connection.
+    pub node: String,
+    /// Details depending on the [`EventType`].
+    pub data: Vec<u8>,
+}
+
+/// Writer of an event log.
+pub(crate) struct EventLog {
+    path: PathBuf,
+    max_size_bytes: u64,
+    file: Mutex<(File, u64)>,
+}
+
+impl EventType {
+    fn to_u8(self) -> u8 {
+        match self {
+            Self::Connected => 1,
+            Self::Disconnected => 2,
+            Self::Failed => 3,
+            Self::Unknown(event_type) => event_type,
+        }
+    }
+
+    fn from_u8(event_type: u8) -> Self {
+        match event_type {
+            1 => Self::Connected,
+            2 => Self::Disconnected,
+            3 => Self::Failed,
+            _ => Self::Unknown(event_type),
+        }
+    }
+}
+
+impl EventLog {
+    /// Opens the log at `path` for appending.
+    pub(crate) fn open(path: PathBuf, max_size_bytes: u64) -> Result<Self, Error> {
+        let opened = append(&path).and_then(|file| Ok((file.metadata()?.len(), file)));
+        let (size, file) = match opened {
+            Ok(opened) => opened,
+            Err(source) => return Err(Error::EventLog { path, source }),
+        };
+        Ok(Self {
+            path,
+            max_size_bytes,
+            file: Mutex::new((file, size)),
+        })
+    }
+
+    /// Appends an event, logging a failure.
+    pub(crate) fn record(&self, event_type: EventType, node: &NodeId, data: &[u8]) {
+        if let Err(err) = self.write(event_type, node, data) {
+            warn!("Event log {}: {err}", self.path.display());
+        }
+    }
+
+    fn write(&self, event_type: EventType, node: &NodeId, data: &[u8]) -> io::Result<()> {
+        let timestamp = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default();
+        let timestamp = u64::try_from(timestamp.as_nanos()).unwrap_or(u64::MAX);
+        let ((node_len, node), (data_len, data)) = (truncate(node.as_bytes()), truncate(data));
+        let mut record = Vec::with_capacity(11 + node.len() + data.len());
+        record.extend_from_slice(&timestamp.to_le_bytes());
+        record.push(event_type.to_u8());
+        record.push(node_len);
+        record.extend_from_slice(node);
+        record.push(data_len);
+        record.extend_from_slice(data);
+
+        let mut file = self.file.lock().unwrap();
+        let (file, size) = &mut *file;
+        if *size > 0 && *size + record.len() as u64 > self.max_size_bytes {
+            let mut rotated = self.path.clone().into_os_string();
+            rotated.push(".1");
+            fs::rename(&self.path, rotated)?;
+            *file = append(&self.path)?;
+            *size = 0;
+        }
+        file.write_all(&record)?;
+        *size += record.len() as u64;
+        Ok(())
+    }
+}
+
+/// Reads the events of a log. A truncated last record, as left by a crash
+/// during a write, ends the log.
+pub fn read(mut reader: impl Read) -> impl Iterator<Item = io::Result<Event>> {
+    std::iter::from_fn(move || read_event(&mut reader).transpose())
+}
+
+fn read_event(reader: &mut impl Read) -> io::Result<Option<Event>> {
+    let mut header = [0; 10];
+    match reader.read_exact(&mut header) {
+        Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        result => result?,
+    }
+    let timestamp = u64::from_le_bytes(header[..8].try_into().expect("8 bytes"));
+    let mut node = vec![0; header[9].into()];
+    let mut data_len = [0];
+    let mut read = || -> io::Result<Vec<u8>> {
+        reader.read_exact(&mut node)?;
+        reader.read_exact(&mut data_len)?;
+        let mut data = vec![0; data_len[0].into()];
+        reader.read_exact(&mut data)?;
+        Ok(data)
+    };
+    let data = match read() {
+        Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        result => result?,
+    };
+    Ok(Some(Event {
+        timestamp: SystemTime::UNIX_EPOCH + Duration::from_nanos(timestamp),
+        event_type: EventType::from_u8(header[8]),
+        node: String::from_utf8_lossy(&node).into_owned(),
+        data,
+    }))
+}
+
+fn append(path: &Path) -> io::Result<File> {
+    OpenOptions::new().create(true).append(true).open(path)
+}
+
+/// Truncates `bytes` to 255 bytes, with the length.
+fn truncate(bytes: &[u8]) -> (u8, &[u8]) {
+    let len = u8::try_from(bytes.len()).unwrap_or(u8::MAX);
+    (len, &bytes[..len.into()])
+}
@@ -0,0 +1,76 @@
+//! Events of the connections with the nodes, streamed to the application.
+//!
+//! [`Carrier::connection_events`](crate::Carrier::connection_events)
+//! subscribes to the [`ConnectionEvent`]s of every node. A subscriber lagging
+//! behind misses the oldest events instead of slowing the connections down.
+//!
+//! A node whose application falls behind its requests blocks the connection
+//! once its [`Incoming`](crate::channels::Incoming) queue is full, which the
+//! requester only notices as TCP backpressure. Instead, the receiving carrier
+//! sends a [`BackpressureSignal`] to the requester as the queue passes 80% of
+//! its capacity, and another once it drains below half. The requester streams
+//! the signals as [`ConnectionEvent::Backpressure`], for the application to
+//! slow down, and pauses writing the requests to the node meanwhile, for at
+//! most a second per signal.
+
+use tokio::sync::broadcast;
+use tracing::debug;
+
+/// Events kept for a subscriber lagging behind.
+pub(crate) const CAPACITY: usize = 256;
+
+/// Event of a connection with a node.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ConnectionEvent {
+    /// Fill of the queue of the requests sent to a node, reported by the node.
+    Backpressure(BackpressureSignal),
+}
+
+/// Fill of the queue of the requests from this node in the
+/// [`Incoming`](crate::channels::Incoming) channels of a node, reported by
+/// the node.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BackpressureSignal {
+    /// Node reporting the fill of its queue.
+    pub node: String,
+    /// Number of the requests queued.
+    pub depth: usize,
+    /// Capacity of the queue.
+    pub capacity: usize,
+}
+
+/// Subscription to the [`ConnectionEvent`]s, returned by
+/// [`Carrier::connection_events`](crate::Carrier::connection_events).
+pub struct ConnectionEvents {
+    rx: broadcast::Receiver<ConnectionEvent>,
+}
+
+impl BackpressureSignal {
+    /// Returns whether the queue is past 80% of its capacity, as opposed to
+    /// drained below half.
+    #[must_use]
+    pub fn is_congested(&self) -> bool {
+        self.depth * 5 > self.capacity * 4
+    }
+}
+
+impl ConnectionEvents {
+    pub(crate) fn new(rx: broadcast::Receiver<ConnectionEvent>) -> Self {
+        Self { rx }
+    }
+
+    /// Receives the next event, skipping the ones missed while lagging behind.
+    /// Returns [`None`] once the carrier stopped.
+    pub async fn recv(&mut self) -> Option<ConnectionEvent> {
+        loop {
+            match self.rx.recv().await {
+                Ok(event) => return Some(event),
+                Err(broadcast::error::RecvError::Lagged(missed)) => {
+                    debug!("Missed {missed} connection events");
+                }
+                Err(broadcast::error::RecvError::Closed) => return None,
+            }
+        }
+    }
+}
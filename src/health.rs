@@ -0,0 +1,191 @@
+//! Health probe HTTP endpoints, with the `health-server` feature.
+//!
+//! [`serve`] answers the probes of an orchestrator such as Kubernetes:
+//!
+//! - `/healthz`, the liveness probe: 200 while the carrier runs, 503 once it
+//!   stopped. The connectivity with the nodes is not checked, so an outage of
+//!   the peers does not restart every node.
+//! - `/readyz`, the readiness probe: 200 with every node connected and none
+//!   [flapping](crate::Carrier::flap_threshold), 200 with a `degraded` JSON
+//!   body with only some connected or some flapping, and 503 with none
+//!   connected or once the carrier stopped.
+//!
+//! The JSON bodies list the connected, the disconnected and the flapping
+//! nodes:
+//!
+//! ```json
+//! {"status":"degraded","connected":["node-1"],"disconnected":["node-2"],"flapping":["node-1"]}
+//! ```
+
+use crate::control::CarrierHandle;
+use crate::{is_transient_accept_error, spawn_named, ACCEPT_RETRY_INTERVAL};
+use http_body_util::Full;
+use hyper::body::Bytes;
+use hyper::server::conn::http1;
+use hyper::service::service_fn;
+use hyper::{Request, Response, StatusCode};
+use hyper_util::rt::TokioIo;
+use serde::Serialize;
+use std::convert::Infallible;
+use std::io;
+use std::net::SocketAddr;
+use tokio::net::TcpListener;
+use tokio::task::JoinHandle;
+use tokio::time::sleep;
+use tracing::{debug, info, warn};
+
+/// Running health probe server, returned by [`serve`]. Dropping it leaves the
+/// server running.
+pub struct HealthServer {
+    local_addr: SocketAddr,
+    task: JoinHandle<()>,
+}
+
+/// Connectivity reported by the probes.
+#[derive(Serialize)]
+struct Health {
+    status: Status,
+    connected: Vec<String>,
+    disconnected: Vec<String>,
+    flapping: Vec<String>,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+enum Status {
+    /// Every node connected, and none flapping.
+    Ok,
+    /// Some nodes connected, or some flapping.
+    Degraded,
+    /// No node connected, or the carrier stopped.
+    Unavailable,
+}
+
+/// Starts serving the probes of the carrier of `carrier_handle` on `bind`,
+/// until [stopped](HealthServer::stop).
+///
+/// # Errors
+///
+/// If the listener fails to bind.
+///
+/// # Panics
+///
+/// If called outside of a Tokio runtime.
+pub async fn serve(carrier_handle: &CarrierHandle, bind: SocketAddr) -> io::Result<HealthServer> {
+    let listener = TcpListener::bind(bind).await?;
+    let local_addr = listener.local_addr()?;
+    info!("Serving health probes on {local_addr}");
+    let handle = carrier_handle.clone();
+    let task = spawn_named("health-server", async move {
+        loop {
+            let stream = match listener.accept().await {
+                Ok((stream, _)) => stream,
+                Err(err) if is_transient_accept_error(&err) => {
+                    warn!("Health probe transient accept failure: {err}");
+                    sleep(ACCEPT_RETRY_INTERVAL).await;
+                    continue;
+                }
+                Err(err) => {
+                    warn!("Health probe listener {local_addr} failed: {err}");
+                    return;
+                }
+            };
+            let handle = handle.clone();
+            let service = service_fn(move |request| {
+                let response = respond(&handle, &request);
+                async move { Ok::<_, Infallible>(response) }
+            });
+            spawn_named("health-probe", async move {
+                let connection =
+                    http1::Builder::new().serve_connection(TokioIo::new(stream), service);
+                if let Err(err) = connection.await {
+                    debug!("Health probe connection failed: {err}");
+                }
+            });
+        }
+    });
+    Ok(HealthServer { local_addr, task })
+}
+
+impl HealthServer {
+    /// Returns the address the server listens on, with the actual port if
+    /// bound to port 0.
+    #[must_use]
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    /// Stops accepting probes.
+    pub fn stop(self) {
+        self.task.abort();
+    }
+}
+
+fn respond<B>(handle: &CarrierHandle, request: &Request<B>) -> Response<Full<Bytes>> {
+    match request.uri().path() {
+        "/healthz" => {
+            if handle.is_running() {
+                reply(StatusCode::OK, "ok")
+            } else {
+                reply(StatusCode::SERVICE_UNAVAILABLE, "stopped")
+            }
+        }
+        "/readyz" => {
+            let health = Health::new(handle);
+            let status = if health.status == Status::Unavailable {
+                StatusCode::SERVICE_UNAVAILABLE
+            } else {
+                StatusCode::OK
+            };
+            let body = serde_json::to_string(&health).expect("serializable health");
+            let mut response = reply(status, body);
+            response.headers_mut().insert(
+                hyper::header::CONTENT_TYPE,
+                hyper::header::HeaderValue::from_static("application/json"),
+            );
+            response
+        }
+        _ => reply(StatusCode::NOT_FOUND, "not found"),
+    }
+}
+
+fn reply(status: StatusCode, body: impl Into<Bytes>) -> Response<Full<Bytes>> {
+    let mut response = Response::new(Full::new(body.into()));
+    *response.status_mut() = status;
+    response
+}
+
+impl Health {
+    fn new(handle: &CarrierHandle) -> Self {
+        let nodes = handle.debug_state().nodes;
+        let flapping = nodes
+            .iter()
+            .filter(|(_, state)| state.flapping)
+            .map(|(node, _)| node.clone())
+            .collect::<Vec<_>>();
+        let (connected, disconnected) = nodes
+            .into_iter()
+            .partition::<Vec<_>, _>(|(_, state)| state.connections > 0);
+        let connected = connected
+            .into_iter()
+            .map(|(node, _)| node)
+            .collect::<Vec<_>>();
+        let disconnected = disconnected
+            .into_iter()
+            .map(|(node, _)| node)
+            .collect::<Vec<_>>();
+        let status = if !handle.is_running() || (connected.is_empty() && !disconnected.is_empty()) {
+            Status::Unavailable
+        } else if disconnected.is_empty() && flapping.is_empty() {
+            Status::Ok
+        } else {
+            Status::Degraded
+        };
+        Self {
+            status,
+            connected,
+            disconnected,
+            flapping,
+        }
+    }
+}
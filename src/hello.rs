@@ -0,0 +1,271 @@
+//! Version handshake.
+//!
+//! With the handshake enabled, both ends of a new connection exchange a
+//! [`Hello`] right after the transport handshake, before the pre-connect hooks
+//! and any requests. The connecting side sends its [`Hello`] first, and the
+//! accepting side replies with its own. The optional behaviors of the
+//! connection are then limited to the [`Negotiated`] features supported by both
+//! ends, and the connection is rejected unless both ends use the same
+//! [`codec`](crate::codec).
+
+use crate::codec::CodecKind;
+use crate::messages::CodecId;
+use crate::node::MAX_LEN;
+use crate::protobuf_tcp::{self, Reader, Writer};
+use std::collections::BTreeSet;
+use thiserror::Error;
+
+pub use crate::messages::{Feature, Hello};
+
+/// Value of [`Hello::magic`], telling a [`Hello`] apart from the first message
+/// of a peer without the handshake.
+pub const HELLO_MAGIC: u32 = 0x4845_4c4f;
+
+/// Version handshake error.
+#[allow(missing_docs)]
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("Protocol: {0}")]
+    Protocol(#[from] protobuf_tcp::Error),
+    #[error("Peer did not send a Hello")]
+    Missing,
+    #[error("Peer uses the {theirs} codec instead of {ours}")]
+    CodecMismatch { ours: CodecKind, theirs: String },
+}
+
+/// Whether the version handshake runs on the new connections.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum HelloMode {
+    /// No [`Hello`] is sent nor expected, as before the handshake existed.
+    #[default]
+    Disabled,
+    /// No [`Hello`] is sent on the outgoing connections, and the incoming
+    /// connections are answered with a [`Hello`] only if they start with one.
+    /// Lets the nodes be upgraded one by one before switching to
+    /// [`HelloMode::Required`].
+    Compat,
+    /// Every connection starts with the exchange of [`Hello`] messages, and
+    /// the connections without one are rejected.
+    Required,
+}
+
+/// Configuration of the version handshake.
+#[derive(Clone, Debug, Default)]
+pub struct HelloConfig {
+    /// Whether the handshake runs.
+    pub mode: HelloMode,
+    /// Name of this node announced to the peers.
+    pub node_name: String,
+    /// Features of this node announced to the peers.
+    pub features: Vec<Feature>,
+}
+
+/// Outcome of the version handshake of a connection.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Negotiated {
+    /// Name announced by the peer, if it sent a [`Hello`].
+    pub peer_name: Option<String>,
+    /// Software version announced by the peer, if it sent a [`Hello`].
+    pub peer_version: Option<String>,
+    /// Features supported by both ends.
+    pub features: BTreeSet<Feature>,
+    /// Largest frame accepted by the peer, in bytes: the one it announced,
+    /// or the historical 8 MiB from a peer without the handshake or
+    /// predating the field.
+    pub max_frame_len: usize,
+    /// Codec of the node messages, used by both ends.
+    pub codec: CodecKind,
+}
+
+impl HelloConfig {
+    fn hello(&self, max_frame_len: usize, codec: CodecKind) -> Hello {
+        let mut hello = Hello {
+            node_name: self.node_name.clone(),
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            features: Vec::new(),
+            max_frame_len: max_frame_len.try_into().unwrap_or(u32::MAX),
+            codec: codec.id().into(),
+            magic: HELLO_MAGIC,
+        };
+        for feature in &self.features {
+            hello.push_features(*feature);
+        }
+        hello
+    }
+}
+
+impl Negotiated {
+    /// Returns whether `feature` is supported by both ends.
+    #[must_use]
+    pub fn supports(&self, feature: Feature) -> bool {
+        self.features.contains(&feature)
+    }
+
+    /// Outcome of a connection without the handshake, using Protobuf.
+    fn without_hello(codec: CodecKind) -> Result<Self, Error> {
+        Ok(Self {
+            peer_name: None,
+            peer_version: None,
+            features: BTreeSet::new(),
+            max_frame_len: MAX_LEN,
+            codec: check_codec(codec, CodecId::Prost.into())?,
+        })
+    }
+
+    /// Intersects the features of `ours` and `theirs`, keeps the frame length
+    /// of `theirs`, and checks that both use `codec`.
+    fn new(ours: &Hello, theirs: Hello, codec: CodecKind) -> Result<Self, Error> {
+        let features = theirs.features().collect::<BTreeSet<_>>();
+        let features = ours
+            .features()
+            .filter(|feature| *feature != Feature::Unspecified && features.contains(feature))
+            .collect();
+        // Zero is the default of a missing field.
+        let max_frame_len = match usize::try_from(theirs.max_frame_len) {
+            Ok(0) => MAX_LEN,
+            Ok(theirs) => theirs,
+            Err(_) => usize::MAX,
+        };
+        Ok(Self {
+            codec: check_codec(codec, theirs.codec)?,
+            peer_name: Some(theirs.node_name),
+            peer_version: Some(theirs.version),
+            features,
+            max_frame_len,
+        })
+    }
+}
+
+/// Returns `ours` if the peer announced the same codec.
+fn check_codec(ours: CodecKind, theirs: i32) -> Result<CodecKind, Error> {
+    match CodecKind::from_id(theirs) {
+        Some(theirs) if theirs == ours => Ok(ours),
+        _ => Err(Error::CodecMismatch {
+            ours,
+            theirs: match CodecKind::from_id(theirs) {
+                Some(theirs) => theirs.to_string(),
+                None => CodecId::try_from(theirs)
+                    .map_or_else(|_| theirs.to_string(), |id| id.as_str_name().to_string()),
+            },
+        }),
+    }
+}
+
+/// Runs the handshake on the connecting side.
+pub(crate) async fn connect(
+    config: &HelloConfig,
+    reader: &mut Reader,
+    writer: &mut Writer,
+    max_frame_len: usize,
+    codec: CodecKind,
+) -> Result<Negotiated, Error> {
+    if config.mode != HelloMode::Required {
+        return Negotiated::without_hello(codec);
+    }
+    let hello = config.hello(max_frame_len, codec);
+    writer.write(hello.clone()).await?;
+    writer.flush().await?;
+    let theirs = reader.read::<Hello>().await?;
+    if theirs.magic != HELLO_MAGIC {
+        return Err(Error::Missing);
+    }
+    Negotiated::new(&hello, theirs, codec)
+}
+
+/// Runs the handshake on the accepting side.
+pub(crate) async fn accept(
+    config: &HelloConfig,
+    reader: &mut Reader,
+    writer: &mut Writer,
+    max_frame_len: usize,
+    codec: CodecKind,
+) -> Result<Negotiated, Error> {
+    if config.mode == HelloMode::Disabled {
+        return Negotiated::without_hello(codec);
+    }
+    // The first message of a peer without the handshake may fail to decode as
+    // a `Hello`, or decode without the magic.
+    let theirs = match reader.read::<Hello>().await {
+        Ok(theirs) if theirs.magic == HELLO_MAGIC => theirs,
+        Ok(_) | Err(protobuf_tcp::Error::Decode(_)) if config.mode == HelloMode::Compat => {
+            reader.unread();
+            return Negotiated::without_hello(codec);
+        }
+        Ok(_) | Err(protobuf_tcp::Error::Decode(_)) => return Err(Error::Missing),
+        Err(err) => return Err(err.into()),
+    };
+    let hello = config.hello(max_frame_len, codec);
+    writer.write(hello.clone()).await?;
+    writer.flush().await?;
+    Negotiated::new(&hello, theirs, codec)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(features: &[Feature]) -> HelloConfig {
+        HelloConfig {
+            mode: HelloMode::Required,
+            node_name: "a".to_string(),
+            features: features.to_vec(),
+        }
+    }
+
+    #[test]
+    fn negotiates_common_features() {
+        let ours = config(&[Feature::Compression, Feature::Chunking, Feature::Envelope]);
+        let theirs = config(&[Feature::Envelope, Feature::Compression, Feature::RequestSeq]);
+        let ours = ours.hello(MAX_LEN, CodecKind::Prost);
+        let theirs = theirs.hello(1024, CodecKind::Prost);
+        let negotiated = Negotiated::new(&ours, theirs.clone(), CodecKind::Prost).unwrap();
+        let common = BTreeSet::from([Feature::Compression, Feature::Envelope]);
+        assert_eq!(negotiated.features, common);
+        assert!(!negotiated.supports(Feature::Chunking));
+        assert_eq!(negotiated.max_frame_len, 1024);
+        assert_eq!(negotiated.peer_name.as_deref(), Some("a"));
+        // Both ends agree.
+        let negotiated = Negotiated::new(&theirs, ours, CodecKind::Prost).unwrap();
+        assert_eq!(negotiated.features, common);
+        assert_eq!(negotiated.max_frame_len, MAX_LEN);
+    }
+
+    #[test]
+    fn missing_frame_len_defaults() {
+        let ours = config(&[]).hello(MAX_LEN, CodecKind::Prost);
+        let theirs = Hello {
+            max_frame_len: 0,
+            ..ours.clone()
+        };
+        let negotiated = Negotiated::new(&ours, theirs, CodecKind::Prost).unwrap();
+        assert_eq!(negotiated.max_frame_len, MAX_LEN);
+        assert!(negotiated.features.is_empty());
+    }
+
+    #[test]
+    fn rejects_other_codec() {
+        let ours = config(&[]).hello(MAX_LEN, CodecKind::Prost);
+        let theirs = Hello {
+            codec: CodecId::Bincode.into(),
+            ..ours.clone()
+        };
+        let err = Negotiated::new(&ours, theirs, CodecKind::Prost).unwrap_err();
+        assert!(
+            matches!(
+                err,
+                Error::CodecMismatch {
+                    ours: CodecKind::Prost,
+                    ..
+                }
+            ),
+            "{err}"
+        );
+    }
+
+    #[test]
+    fn without_hello_uses_protobuf() {
+        let negotiated = Negotiated::without_hello(CodecKind::Prost).unwrap();
+        assert_eq!(negotiated.peer_name, None);
+        assert_eq!(negotiated.max_frame_len, MAX_LEN);
+    }
+}
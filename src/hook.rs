@@ -0,0 +1,65 @@
+//! Pre-connect hooks.
+//!
+//! A hook runs on every newly established node-to-node connection, right after
+//! the TLS handshake and before any requests flow. Hooks are executed in the
+//! order they were registered, and the same set of hooks must be registered on
+//! both ends of a connection.
+
+use crate::protobuf_tcp::{self, Reader, Writer};
+use futures::future::BoxFuture;
+use std::sync::Arc;
+use thiserror::Error;
+
+/// Pre-connect hook error.
+#[allow(missing_docs)]
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("Protocol: {0}")]
+    Protocol(#[from] protobuf_tcp::Error),
+    #[error("Unsupported compression algorithm: {0}")]
+    UnsupportedCompression(i32),
+}
+
+/// A set of registered hooks shared between connections.
+pub type Hooks = Arc<Vec<Arc<dyn PreConnectHook>>>;
+
+/// Hook executed on a newly established connection.
+pub trait PreConnectHook: Send + Sync {
+    /// Runs on the side which initiated the connection.
+    fn on_connect<'a>(
+        &'a self,
+        reader: &'a mut Reader,
+        writer: &'a mut Writer,
+    ) -> BoxFuture<'a, Result<(), Error>>;
+
+    /// Runs on the side which accepted the connection.
+    fn on_accept<'a>(
+        &'a self,
+        reader: &'a mut Reader,
+        writer: &'a mut Writer,
+    ) -> BoxFuture<'a, Result<(), Error>>;
+}
+
+/// Runs all `hooks` on the connecting side.
+pub(crate) async fn run_connect(
+    hooks: &Hooks,
+    reader: &mut Reader,
+    writer: &mut Writer,
+) -> Result<(), Error> {
+    for hook in hooks.iter() {
+        hook.on_connect(reader, writer).await?;
+    }
+    Ok(())
+}
+
+/// Runs all `hooks` on the accepting side.
+pub(crate) async fn run_accept(
+    hooks: &Hooks,
+    reader: &mut Reader,
+    writer: &mut Writer,
+) -> Result<(), Error> {
+    for hook in hooks.iter() {
+        hook.on_accept(reader, writer).await?;
+    }
+    Ok(())
+}
@@ -4,130 +4,1154 @@
 #![warn(clippy::pedantic)]
 #![allow(clippy::missing_errors_doc, clippy::missing_panics_doc)]
 
+pub mod auth;
+pub mod bus;
+pub mod chain;
 pub mod channels;
+pub mod churn;
+pub mod codec;
+pub mod compression;
+pub mod config;
+pub mod control;
+pub mod dedup;
+pub mod errors;
+pub mod event_log;
+pub mod events;
+#[cfg(feature = "health-server")]
+pub mod health;
+pub mod hello;
+pub mod hook;
+pub mod lifecycle;
+mod log_limit;
+pub mod loss;
+#[cfg(feature = "metrics")]
+pub mod metrics;
+pub mod middleware;
 pub mod node;
+#[cfg(feature = "otel")]
+pub mod otel;
+#[cfg(feature = "otlp")]
+mod otlp;
+pub mod pipeline;
 pub mod protobuf_tcp;
+pub mod protocol;
+pub mod router;
+mod runtime;
+mod slow;
+pub mod stats;
+pub mod status;
+mod summary;
+pub mod supervisor;
+mod sync;
+#[cfg(feature = "test-util")]
+pub mod testing;
 pub mod tls;
+#[cfg(feature = "otel")]
+mod trace_context;
+pub mod transport;
+#[cfg(feature = "config-watch")]
+pub mod watch;
 
 /// Communication messages.
-#[allow(missing_docs)]
+#[allow(missing_docs, clippy::pedantic)]
 pub mod messages {
     include!(concat!(env!("OUT_DIR"), "/messages.rs"));
+
+    #[cfg(feature = "serde")]
+    mod base64;
+    mod redact;
+
+    pub use redact::{redacted, Redact, Redacted, WIRE_TRACE_ENV};
+
+    #[cfg(feature = "test-util")]
+    pub mod compat;
+    #[cfg(any(test, feature = "test-util"))]
+    pub mod fixtures;
+
+    impl NodeRequest {
+        /// Moves the deprecated `distance_list` into the `payload` if empty,
+        /// dropping it otherwise.
+        #[allow(deprecated)]
+        pub(crate) fn migrate_distance_list(&mut self) {
+            let distance_list = std::mem::take(&mut self.distance_list);
+            if self.payload.is_empty() {
+                self.payload = distance_list;
+            }
+        }
+    }
 }
 
+/// Capacity of the per-node request queues. The queues allocate a node per
+/// message, one of the 18 allocations of a request round trip, so a
+/// preallocated ring buffer would save little.
 const CHANNEL_CAPACITY: usize = 64;
+const ACCEPT_RETRY_INTERVAL: Duration = Duration::from_millis(100);
+const LISTENER_RESTART_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_CONCURRENT_HANDSHAKES: usize = 16;
 
-use channels::{Incoming, NodeCallback, Outgoing};
-use futures::channel::mpsc;
-use futures::future;
+use auth::{AuthKey, KeyProvider};
+use bus::{BusLayer, ChannelBus, EventBus, SharedChannels};
+use chain::ChainedCarrier;
+use channels::{
+    Incoming, NodeCallback, Notifications, Outgoing, OutgoingMessage, SerializableNodeRequest,
+    Services,
+};
+use churn::FlapThreshold;
+use codec::CodecKind;
+use compression::PayloadCompression;
+use config::{ConfigError, Direction, NodeId};
+use control::{CarrierHandle, Command};
+use dedup::DeduplicationConfig;
+use errors::{ErrorAccounting, ErrorCounts};
+use events::{ConnectionEvent, ConnectionEvents};
+use futures::channel::{mpsc, oneshot};
+use futures::future::{self, Either};
 use futures::prelude::*;
-use rustls::pki_types::ServerName;
-use std::collections::HashMap;
+use hello::HelloConfig;
+use hook::PreConnectHook;
+use lifecycle::{CarrierEvent, Lifecycle};
+use log_limit::{log_limited, LogLimiter};
+use loss::LossAccounting;
+use rustls::ClientConfig;
+use stats::{DebugState, LatencyHistogram, RequestTiming, SharedRequestHook, Stats};
+use std::collections::{HashMap, HashSet};
 use std::io;
-use std::path::Path;
-use std::sync::Arc;
+use std::path::PathBuf;
+use std::pin::pin;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use supervisor::{Component, Policy};
+use sync::TracingMutex;
 use thiserror::Error;
-use tokio::net::{TcpListener, TcpStream};
-use tokio::task;
-use tokio_rustls::TlsAcceptor;
-use tokio_rustls::TlsConnector;
-use tokio_stream::wrappers::TcpListenerStream;
-use tracing::info;
+use tls::{TlsConfig, TlsMode};
+use tokio::sync::broadcast;
+use tokio::task::{self, JoinHandle, JoinSet};
+use tokio::time::sleep;
+use tokio_rustls::{TlsAcceptor, TlsConnector};
+use tokio_util::sync::CancellationToken;
+use tracing::{error, info, warn};
+#[cfg(feature = "no-tls")]
+use transport::PlainTcpTransport;
+use transport::{NodeAddr, TlsTcpTransport, Transport};
 
 /// Service error.
 #[allow(missing_docs)]
 #[derive(Error, Debug)]
+#[non_exhaustive]
 pub enum Error {
+    #[error("configuration: {0}")]
+    Config(#[from] ConfigError),
     #[error("TLS initialization: {0}")]
     TlsInit(#[from] tls::Error),
-    #[error("socket: {0}")]
-    Socket(io::Error),
+    #[error("listener bind to {addr}: {source}")]
+    Bind { addr: String, source: io::Error },
+    #[error("listener {addr} accept: {source}")]
+    Listener { addr: String, source: io::Error },
+    #[error("node {node} ({addr}): {source}")]
+    Node {
+        node: NodeId,
+        addr: String,
+        source: node::Error,
+    },
+    #[error("incoming connection from {peer}: {source}")]
+    Incoming { peer: String, source: node::Error },
+    #[cfg(feature = "no-tls")]
+    #[error("node {node} address resolution: {source}")]
+    Resolve { node: NodeId, source: io::Error },
+    #[error("node `{0}` is not configured")]
+    UnknownNode(NodeId),
+    #[error("checkpoint of a request to `{checkpointed}` restored for node `{node}`")]
+    CheckpointNode { node: NodeId, checkpointed: String },
+    #[error("both Incoming and Outgoing handles were dropped")]
+    HandlesDropped,
+    #[error("event log {}: {source}", path.display())]
+    EventLog { path: PathBuf, source: io::Error },
+    #[cfg(feature = "otlp")]
+    #[error("OTLP exporter: {0}")]
+    Otlp(String),
+    #[error("task {task} panicked: {message}")]
+    Panicked { task: String, message: String },
+    #[error("{component}: {source}")]
+    Component {
+        component: Component,
+        source: Box<Error>,
+    },
 }
 
 /// Communication worker.
 pub struct Carrier {
-    nodes: HashMap<String, u16>,
-    incoming: HashMap<String, mpsc::Sender<NodeCallback>>,
-    outgoing: HashMap<String, mpsc::Receiver<NodeCallback>>,
+    nodes: HashMap<NodeId, NodeAddr>,
+    incoming: SharedChannels,
+    incoming_added: mpsc::UnboundedSender<(NodeId, mpsc::Receiver<NodeCallback>)>,
+    notifications: Notifications,
+    /// Outgoing queues of the nodes, taken by their outgoing loops or
+    /// connections while running.
+    outgoing: HashMap<NodeId, node::SharedOutgoing>,
+    queues: HashMap<NodeId, mpsc::Sender<OutgoingMessage>>,
+    hooks: Vec<Arc<dyn PreConnectHook>>,
+    hello: HelloConfig,
+    payload_compression: PayloadCompression,
+    auth: Option<KeyProvider>,
+    deduplication: Option<DeduplicationConfig>,
+    max_frame_len: usize,
+    codec: CodecKind,
+    tls_config: TlsConfig,
+    event_log: Option<(PathBuf, u64)>,
+    events: broadcast::Sender<ConnectionEvent>,
+    request_hook: SharedRequestHook,
+    lifecycle: Lifecycle,
+    listener_policy: Policy,
+    outgoing_policy: Policy,
+    /// Completes once both the [`Incoming`] and the [`Outgoing`] handles are
+    /// dropped.
+    handles: future::Shared<future::BoxFuture<'static, ()>>,
+    shutdown_on_handles_dropped: bool,
+    directions: HashMap<NodeId, Direction>,
+    ordered: HashSet<NodeId>,
+    skip_unused_listener: bool,
+    commands: mpsc::UnboundedReceiver<Command>,
+    commands_tx: mpsc::UnboundedSender<Command>,
+    /// Whether the carrier is not stopped, shared with its handles.
+    running: Arc<AtomicBool>,
+    /// Bus dispatching the incoming requests, wrapped into the
+    /// [`bus_layers`](Self::bus_layers) on every run.
+    bus: Box<dyn EventBus>,
+    /// Middlewares registered since the last run.
+    bus_layers: Vec<BusLayer>,
+    max_handshakes: usize,
+    handshakes: Arc<AtomicUsize>,
+    slow_request_threshold: Option<Duration>,
+    flap_threshold: FlapThreshold,
+    status_summary: Option<Duration>,
+    #[cfg(feature = "otel")]
+    otel: Option<otel::Instruments>,
+    #[cfg(feature = "otlp")]
+    otlp: otlp::Otlp,
+    stats: Stats,
+    /// Counts of the errors without a node.
+    errors: Arc<ErrorCounts>,
 }
 
 impl Carrier {
     /// Creates a new [`Carrier`] together with an associated [`Incoming`] and
     /// [`Outgoing`] channel sets.
+    ///
+    /// # Panics
+    ///
+    /// If a node name is not a valid server name. See [`Carrier::try_new`] for
+    /// a non-panicking version.
     #[must_use]
     pub fn new(nodes: HashMap<String, u16>) -> (Self, Incoming, Outgoing) {
+        Self::try_new(nodes).expect("invalid node configuration")
+    }
+
+    /// Creates a new [`Carrier`] from a list of nodes in the form of
+    /// `host:port` or `[ipv6]:port`. Entries with the host equal to `self_name`
+    /// are skipped, so the same list can be shared by all nodes.
+    pub fn from_node_strs(
+        nodes: impl IntoIterator<Item = impl AsRef<str>>,
+        self_name: Option<&str>,
+    ) -> Result<(Self, Incoming, Outgoing), ConfigError> {
+        Self::try_new(config::parse_nodes(nodes, self_name)?)
+    }
+
+    /// Creates a new [`Carrier`] together with an associated [`Incoming`] and
+    /// [`Outgoing`] channel sets, validating the node names.
+    pub fn try_new(nodes: HashMap<String, u16>) -> Result<(Self, Incoming, Outgoing), ConfigError> {
+        let nodes = nodes
+            .into_iter()
+            .map(|(node, port)| Ok((node.clone(), config::default_addr(&node, port)?)))
+            .collect::<Result<Vec<_>, ConfigError>>()?;
+        Ok(Self::with_addrs(nodes))
+    }
+
+    /// Creates a new [`Carrier`] together with an associated [`Incoming`] and
+    /// [`Outgoing`] channel sets, with the nodes identified independently of
+    /// their network addresses.
+    #[must_use]
+    pub fn with_addrs(
+        nodes: impl IntoIterator<Item = (impl Into<NodeId>, NodeAddr)>,
+    ) -> (Self, Incoming, Outgoing) {
+        let nodes = nodes
+            .into_iter()
+            .map(|(node, addr)| (node.into(), addr))
+            .collect::<HashMap<NodeId, _>>();
         let (mut incoming_tx, mut incoming_rx) = (HashMap::new(), HashMap::new());
         let (mut outgoing_tx, mut outgoing_rx) = (HashMap::new(), HashMap::new());
+        let stats = nodes
+            .keys()
+            .map(|node| (node.clone(), Arc::default()))
+            .collect();
+        let stats = Arc::new(RwLock::new(stats));
         for node in nodes.keys() {
             let (tx, rx) = mpsc::channel(CHANNEL_CAPACITY);
             incoming_tx.insert(node.clone(), tx);
             incoming_rx.insert(node.clone(), rx);
             let (tx, rx) = mpsc::channel(CHANNEL_CAPACITY);
             outgoing_tx.insert(node.clone(), tx);
-            outgoing_rx.insert(node.clone(), rx);
+            outgoing_rx.insert(node.clone(), node::OutgoingQueue::new(rx));
         }
+        let outgoing_rx = outgoing_rx
+            .into_iter()
+            .map(|(node, rx)| (node, Arc::new(TracingMutex::new("outgoing", rx))))
+            .collect();
+        let (incoming_handle, incoming_handle_rx) = oneshot::channel();
+        let (outgoing_handle, outgoing_handle_rx) = oneshot::channel();
+        // The guards are never sent on, so each receiver completes when its
+        // handle is dropped.
+        let handles = future::join_all([incoming_handle_rx, outgoing_handle_rx])
+            .map(drop)
+            .boxed()
+            .shared();
+        let (incoming_added, incoming_added_rx) = mpsc::unbounded();
+        let (notifications, notifications_rx) = mpsc::channel(CHANNEL_CAPACITY);
+        let (commands_tx, commands) = mpsc::unbounded();
+        let services = Services::default();
+        let incoming_tx = Arc::new(RwLock::new(incoming_tx));
+        let bus = ChannelBus::new(
+            Arc::clone(&incoming_tx),
+            Arc::clone(&services),
+            Arc::clone(&stats),
+        );
         let carrier = Self {
             nodes,
             incoming: incoming_tx,
+            incoming_added,
+            notifications,
             outgoing: outgoing_rx,
+            queues: outgoing_tx.clone(),
+            hooks: Vec::new(),
+            hello: HelloConfig::default(),
+            payload_compression: PayloadCompression::default(),
+            auth: None,
+            deduplication: None,
+            max_frame_len: node::MAX_LEN,
+            codec: CodecKind::Prost,
+            tls_config: TlsConfig::default(),
+            event_log: None,
+            events: broadcast::channel(events::CAPACITY).0,
+            request_hook: SharedRequestHook::default(),
+            lifecycle: Lifecycle::default(),
+            listener_policy: Policy::Restart {
+                backoff: LISTENER_RESTART_BACKOFF,
+            },
+            outgoing_policy: Policy::Escalate,
+            handles,
+            shutdown_on_handles_dropped: false,
+            directions: HashMap::new(),
+            ordered: HashSet::new(),
+            skip_unused_listener: false,
+            commands,
+            commands_tx,
+            running: Arc::new(AtomicBool::new(true)),
+            bus: Box::new(bus),
+            bus_layers: Vec::new(),
+            max_handshakes: MAX_CONCURRENT_HANDSHAKES,
+            handshakes: Arc::new(AtomicUsize::new(0)),
+            slow_request_threshold: None,
+            flap_threshold: FlapThreshold::default(),
+            status_summary: None,
+            #[cfg(feature = "otel")]
+            otel: None,
+            #[cfg(feature = "otlp")]
+            otlp: otlp::Otlp::default(),
+            stats: Arc::clone(&stats),
+            errors: Arc::default(),
         };
-        let incoming = Incoming::new(incoming_rx);
-        let outgoing = Outgoing::new(outgoing_tx);
+        let outgoing = Outgoing::new(
+            outgoing_tx,
+            &stats,
+            Arc::clone(&carrier.request_hook),
+            outgoing_handle,
+        );
+        let incoming = Incoming::new(
+            incoming_rx,
+            incoming_added_rx,
+            notifications_rx,
+            Arc::downgrade(&services),
+            stats,
+            incoming_handle,
+        );
         (carrier, incoming, outgoing)
     }
 
-    /// Runs the communication.
+    /// Sets the [`Direction`] of the connections with `node`. Defaults to
+    /// [`Direction::Both`].
+    ///
+    /// # Panics
+    ///
+    /// If `node` was not configured in [`Carrier::new`].
+    #[must_use]
+    pub fn direction(mut self, node: impl Into<NodeId>, direction: Direction) -> Self {
+        let node = node.into();
+        assert!(
+            self.nodes.contains_key(&node),
+            "node `{node}` not configured"
+        );
+        self.directions.insert(node, direction);
+        self
+    }
+
+    /// Sets whether the responses to the requests of `node` are written in the
+    /// order the requests arrived on its connection, instead of as soon as the
+    /// application answers them. A response answered early then waits for the
+    /// ones before it. On a connection accepted from `node` and serving only
+    /// its requests, so do the acknowledgements of its notifications.
+    /// Disabled by default.
+    ///
+    /// # Panics
+    ///
+    /// If `node` was not configured in [`Carrier::new`].
+    #[must_use]
+    pub fn ordered(mut self, node: impl Into<NodeId>, ordered: bool) -> Self {
+        let node = node.into();
+        assert!(
+            self.nodes.contains_key(&node),
+            "node `{node}` not configured"
+        );
+        if ordered {
+            self.ordered.insert(node);
+        } else {
+            self.ordered.remove(&node);
+        }
+        self
+    }
+
+    /// Skips binding the listener when every node has [`Direction::Dial`].
+    /// Disabled by default.
+    #[must_use]
+    pub fn skip_unused_listener(mut self, skip: bool) -> Self {
+        self.skip_unused_listener = skip;
+        self
+    }
+
+    /// Returns a [`CarrierHandle`] for adding and removing nodes at runtime.
+    #[must_use]
+    pub fn handle(&self) -> CarrierHandle {
+        CarrierHandle::new(
+            self.commands_tx.clone(),
+            Arc::clone(&self.handshakes),
+            Arc::clone(&self.stats),
+            Arc::clone(&self.errors),
+            self.lifecycle.dropped(),
+            Arc::clone(&self.running),
+        )
+    }
+
+    /// Returns the histogram of the response latencies of `node`, or [`None`]
+    /// if the node is not configured. The histogram is updated while the
+    /// carrier runs.
+    #[must_use]
+    pub fn latency_histogram(&self, node: impl Into<NodeId>) -> Option<Arc<LatencyHistogram>> {
+        let stats = self.stats.read().unwrap();
+        stats
+            .get(&node.into())
+            .map(|stats| Arc::clone(&stats.latency))
+    }
+
+    /// Returns the histogram of the lifetimes of the closed connections with
+    /// `node`, in the buckets of [`churn::LIFETIME_BUCKETS`], or [`None`] if
+    /// the node is not configured. The histogram is updated while the carrier
+    /// runs.
+    #[must_use]
+    pub fn connection_lifetimes(&self, node: impl Into<NodeId>) -> Option<Arc<LatencyHistogram>> {
+        let stats = self.stats.read().unwrap();
+        stats
+            .get(&node.into())
+            .map(|stats| Arc::clone(&stats.churn.lifetimes))
+    }
+
+    /// Returns a snapshot of the state of every node for diagnostics: the open
+    /// connections, the queued and inflight messages, the transferred bytes and
+    /// the last connection error. The snapshot is cheap to take, and stays
+    /// live while the carrier runs, including from a [`CarrierHandle`] with
+    /// [`CarrierHandle::debug_state`].
+    #[must_use]
+    pub fn debug_state(&self) -> DebugState {
+        DebugState::new(&self.stats)
+    }
+
+    /// Returns the accounting of the messages discarded by the carrier, live
+    /// while it runs. See [`loss`].
+    #[must_use]
+    pub fn losses(&self) -> LossAccounting {
+        LossAccounting::new(Arc::clone(&self.stats))
+    }
+
+    /// Returns the counts of the errors of the carrier and its connections by
+    /// kind, live while it runs. See [`errors`].
+    #[must_use]
+    pub fn errors(&self) -> ErrorAccounting {
+        ErrorAccounting::new(Arc::clone(&self.stats), Arc::clone(&self.errors))
+    }
+
+    /// Subscribes to the [`ConnectionEvent`]s of every node, such as the
+    /// [`BackpressureSignal`](events::BackpressureSignal)s. See [`events`].
+    ///
+    /// Only the events following the subscription are received, so subscribe
+    /// before running the carrier.
+    #[must_use]
+    pub fn connection_events(&self) -> ConnectionEvents {
+        ConnectionEvents::new(self.events.subscribe())
+    }
+
+    /// Returns the stream of the lifecycle [`CarrierEvent`]s of the listener
+    /// and the connections, replacing the previous one. See [`lifecycle`].
+    ///
+    /// The channel is bounded, and the events past its capacity are dropped
+    /// and counted by [`CarrierHandle::dropped_events`].
+    pub fn events(&mut self) -> tokio::sync::mpsc::Receiver<CarrierEvent> {
+        self.lifecycle.subscribe()
+    }
+
+    /// Restores a request to `node` checkpointed with
+    /// [`SerializableNodeRequest`], to replay it after a restart. Returns the
+    /// receiver of its response.
+    ///
+    /// The response to the connection the request was sent on is lost with
+    /// it, so the request is queued to `node` again, like one sent with
+    /// [`Outgoing::send`], and awaits its response once written. The receiver
+    /// is cancelled if the carrier was shut down.
+    ///
+    /// Fails if `node` is not configured, or is not the node of the
+    /// checkpoint.
+    pub fn restore_checkpoint(
+        &self,
+        node: &str,
+        request: SerializableNodeRequest,
+    ) -> Result<oneshot::Receiver<messages::NodeResponse>, Error> {
+        let node = NodeId::from(node);
+        let Some(queue) = self.queues.get(&node) else {
+            return Err(Error::UnknownNode(node));
+        };
+        if !request.node.is_empty() && request.node != node.as_str() {
+            let checkpointed = request.node;
+            return Err(Error::CheckpointNode { node, checkpointed });
+        }
+        let (callback, response) = NodeCallback::new(request.into());
+        let stats = self.stats.read().unwrap();
+        let queued = &stats[&node].outgoing_queue;
+        // Counted before sending, as taken off the queue. A new sender always
+        // has room for its message.
+        queued.fetch_add(1, Ordering::Relaxed);
+        let message = OutgoingMessage::Request {
+            callback,
+            written: None,
+        };
+        if queue.clone().try_send(message).is_err() {
+            queued.fetch_sub(1, Ordering::Relaxed);
+        }
+        Ok(response)
+    }
+
+    /// Registers a [`PreConnectHook`] to run on every new connection. Hooks run
+    /// in the order of registration.
+    #[must_use]
+    pub fn pre_connect_hook(mut self, hook: impl PreConnectHook + 'static) -> Self {
+        self.hooks.push(Arc::new(hook));
+        self
+    }
+
+    /// Configures the [`Hello`](hello::Hello) version handshake of the new
+    /// connections. Disabled by default.
+    #[must_use]
+    pub fn hello(mut self, config: HelloConfig) -> Self {
+        self.hello = config;
+        self
+    }
+
+    /// Configures the compression of the request and notification payloads.
+    /// Payloads are compressed on the connections where both ends list
+    /// [`Feature::Compression`](hello::Feature) in their
+    /// [`HelloConfig::features`], unless compressed whole by a
+    /// [`CompressionNegotiator`](compression::CompressionNegotiator).
+    #[must_use]
+    pub fn payload_compression(mut self, config: PayloadCompression) -> Self {
+        self.payload_compression = config;
+        self
+    }
+
+    /// Authenticates the requests and responses exchanged with every node for
+    /// which `keys` returns an [`AuthKey`]. See [`auth`]. Disabled by default.
+    ///
+    /// The keys are looked up for every message, so a rotation takes effect
+    /// without reconnecting.
+    #[must_use]
+    pub fn message_auth(
+        mut self,
+        keys: impl Fn(&NodeId) -> Option<AuthKey> + Send + Sync + 'static,
+    ) -> Self {
+        self.auth = Some(Arc::new(keys));
+        self
+    }
+
+    /// Enables the deduplication of the requests retransmitted on a
+    /// connection. See [`DeduplicationConfig`]. Disabled by default.
+    #[must_use]
+    pub fn deduplication(mut self, config: DeduplicationConfig) -> Self {
+        self.deduplication = Some(config);
+        self
+    }
+
+    /// Sets the largest frame accepted from the nodes, in bytes. 8 MiB by
+    /// default.
+    ///
+    /// The limit is announced in the [`Hello`](hello::Hello) of the
+    /// connections, and each node enforces the limit of the other when
+    /// writing: a request or notification past it fails at the sender with
+    /// [`SendError::TooLarge`](channels::SendError::TooLarge), without closing
+    /// the connection. A node without the handshake is assumed to accept
+    /// 8 MiB.
+    #[must_use]
+    pub fn max_frame_len(mut self, max_frame_len: usize) -> Self {
+        self.max_frame_len = max_frame_len;
+        self
+    }
+
+    /// Sets the codec of the node messages. Protobuf by default.
+    ///
+    /// Both ends of a connection must use the same codec, announced in the
+    /// [`Hello`](hello::Hello): the connections with a node using another
+    /// one, or without the handshake while a codec other than Protobuf is
+    /// set, fail with [`hello::Error::CodecMismatch`].
+    #[must_use]
+    pub fn codec(mut self, codec: CodecKind) -> Self {
+        self.codec = codec;
+        self
+    }
+
+    /// Configures the TLS handshakes of [`Carrier::run`], such as
+    /// [`TlsVersion::Tls13Only`](tls::TlsVersion::Tls13Only). TLS 1.2 and 1.3
+    /// by default. A custom transport of [`Carrier::run_with_transport`]
+    /// takes its configurations from [`tls::init_with_config`] instead.
+    #[must_use]
+    pub fn tls_config(mut self, config: TlsConfig) -> Self {
+        self.tls_config = config;
+        self
+    }
+
+    /// Records the connection events of every node to a binary log at `path`,
+    /// rotated once past `max_size_bytes`. See [`event_log`]. Disabled by
+    /// default.
+    ///
+    /// The file is opened when the [`Carrier`] starts running.
+    #[must_use]
+    pub fn event_log(mut self, path: impl Into<PathBuf>, max_size_bytes: u64) -> Self {
+        self.event_log = Some((path.into(), max_size_bytes));
+        self
+    }
+
+    /// Calls `hook` with the [`RequestTiming`] of every request sent with the
+    /// [`Outgoing`] channels of the carrier, once it completed, failed or was
+    /// abandoned. Replaces the previous hook.
+    ///
+    /// The hook is called on the task awaiting the response, so it must be
+    /// cheap, like recording into a histogram.
+    #[must_use]
+    pub fn on_request_complete(self, hook: impl Fn(RequestTiming) + Send + Sync + 'static) -> Self {
+        *self.request_hook.write().unwrap() = Some(Arc::new(hook));
+        self
+    }
+
+    /// Sets the maximum number of the incoming connection handshakes in
+    /// progress. The connections accepted past the limit wait for their turn,
+    /// while the established connections are not counted. Defaults to 16.
+    ///
+    /// # Panics
+    ///
+    /// If `limit` is zero.
+    #[must_use]
+    pub fn max_concurrent_handshakes(mut self, limit: usize) -> Self {
+        assert!(limit > 0, "handshake limit must be positive");
+        self.max_handshakes = limit;
+        self
+    }
+
+    /// Reports the requests in flight for longer than `threshold`, unset by
+    /// default.
+    ///
+    /// A request to a node awaiting its response, or a request from a node
+    /// awaiting the answer of the application, is logged as a warning and
+    /// streamed as a [`CarrierEvent::SlowRequest`] once older than the
+    /// threshold, without failing it. Its completion follows with its final
+    /// latency, as a [`CarrierEvent::SlowRequestFinished`].
+    /// The requests are swept by a single task, every quarter of the
+    /// threshold and at least every second.
+    ///
+    /// # Panics
+    ///
+    /// If `threshold` is zero.
+    #[must_use]
+    pub fn slow_request_threshold(mut self, threshold: Duration) -> Self {
+        assert!(
+            !threshold.is_zero(),
+            "slow request threshold must be positive"
+        );
+        self.slow_request_threshold = Some(threshold);
+        self
+    }
+
+    /// Reports a node as flapping once its connections go up and down more
+    /// than `transitions` times within `window`. Defaults to
+    /// [`churn::DEFAULT_FLAP_TRANSITIONS`] within
+    /// [`churn::DEFAULT_FLAP_WINDOW`].
+    ///
+    /// A node starting flapping is logged as a warning, streamed as a
+    /// [`CarrierEvent::FlappingStarted`] and marked in its
+    /// [`NodeState::flapping`](stats::NodeState::flapping), until the window
+    /// holds no more than `transitions`, streamed as a
+    /// [`CarrierEvent::FlappingStopped`]. See [`churn`].
+    ///
+    /// # Panics
+    ///
+    /// If `transitions` is not below [`churn::MAX_TRANSITIONS`], or `window`
+    /// is zero.
+    #[must_use]
+    pub fn flap_threshold(mut self, transitions: usize, window: Duration) -> Self {
+        assert!(
+            transitions < churn::MAX_TRANSITIONS,
+            "flap transitions must be below {}",
+            churn::MAX_TRANSITIONS
+        );
+        assert!(!window.is_zero(), "flap window must be positive");
+        self.flap_threshold = FlapThreshold {
+            transitions,
+            window,
+        };
+        self
+    }
+
+    /// Logs a summary of the state of every node every `interval`, such as a
+    /// minute, while the carrier runs: whether it is connected, its queues,
+    /// the frames exchanged since the previous summary and its errors, one
+    /// info line per node followed by one for the carrier. Disabled by
+    /// default.
+    ///
+    /// The lines are made of `key=value` pairs in a fixed order, to be
+    /// grepped from the log:
+    ///
+    /// ```text
+    /// Status node=node-b connected=yes connection=Connected incoming_queue=0 outgoing_queue=2 inflight=1 frames_sent=40 frames_received=38 errors=0
+    /// Status nodes=2 connected=1 incoming_queue=0 outgoing_queue=2 inflight=1 frames_sent=40 frames_received=38 errors=3
+    /// ```
+    ///
+    /// The errors are counted since the carrier was created, the last line
+    /// adding the ones without a node, as in [`Carrier::errors`].
+    ///
+    /// # Panics
+    ///
+    /// If `interval` is zero.
+    #[must_use]
+    pub fn status_summary(mut self, interval: Duration) -> Self {
+        assert!(
+            !interval.is_zero(),
+            "status summary interval must be positive"
+        );
+        self.status_summary = Some(interval);
+        self
+    }
+
+    /// Registers the instruments of the carrier on `meter`, owned by the
+    /// OpenTelemetry pipeline of the application. See [`otel`] for the
+    /// instruments.
+    #[cfg(feature = "otel")]
+    #[must_use]
+    pub fn otel_meter(mut self, meter: &opentelemetry::metrics::Meter) -> Self {
+        self.otel = Some(otel::Instruments::register(
+            meter,
+            &self.stats,
+            &self.errors,
+        ));
+        self
+    }
+
+    /// Exports the spans to the OTLP gRPC endpoint at `endpoint`, such as
+    /// `http://jaeger:4317`, with the `service.name` resource attribute set
+    /// to `mpc-carrier` and `mpc.node_count` to the number of the nodes.
+    /// Disabled by default.
+    ///
+    /// The exporter starts with the first run of the carrier, and installs the
+    /// [`tracing-opentelemetry`](tracing_opentelemetry) layer feeding it as
+    /// the global subscriber: the run fails with [`Error::Otlp`] if the
+    /// application has set one already, in which case it installs the layer
+    /// itself instead. The spans left are exported when the carrier is
+    /// dropped, blocking until then. See [`otel`] for the spans.
+    #[cfg(feature = "otlp")]
+    #[must_use]
+    pub fn otlp_endpoint(mut self, endpoint: impl Into<String>) -> Self {
+        self.otlp.endpoint = Some(endpoint.into());
+        self
+    }
+
+    /// Wraps the [`EventBus`] dispatching the incoming requests into a
+    /// middleware, such as [`middleware::Sniffer`]. The first registered
+    /// middleware is the innermost one.
+    #[must_use]
+    pub fn wrap_bus<B: EventBus>(
+        mut self,
+        wrap: impl FnOnce(Box<dyn EventBus>) -> B + Send + 'static,
+    ) -> Self {
+        self.bus_layers.push(Box::new(|bus| Box::new(wrap(bus))));
+        self
+    }
+
+    /// Chains the carrier as the upstream side of a gateway to `downstream`, a
+    /// carrier of another network. See [`ChainedCarrier`].
+    #[must_use]
+    pub fn chain(self, downstream: Carrier) -> ChainedCarrier {
+        ChainedCarrier::new(self, downstream)
+    }
+
+    /// Sets the supervision [`Policy`] for the incoming connections listener.
+    /// Defaults to restarting the listener.
+    #[must_use]
+    pub fn listener_policy(mut self, policy: Policy) -> Self {
+        self.listener_policy = policy;
+        self
+    }
+
+    /// Sets the supervision [`Policy`] for the outgoing connection loops.
+    /// Defaults to escalating, as the loops retry the connections by
+    /// themselves.
+    #[must_use]
+    pub fn outgoing_policy(mut self, policy: Policy) -> Self {
+        self.outgoing_policy = policy;
+        self
+    }
+
+    /// Makes [`Carrier::run`] return [`Error::HandlesDropped`] once both the
+    /// [`Incoming`] and the [`Outgoing`] handles are dropped, instead of
+    /// serving the connections with no one to consume them. Disabled by
+    /// default.
+    #[must_use]
+    pub fn shutdown_on_handles_dropped(mut self, enabled: bool) -> Self {
+        self.shutdown_on_handles_dropped = enabled;
+        self
+    }
+
+    /// Runs the communication over TCP, secured according to `tls_mode`.
+    ///
+    /// Each sub-task (the listener and every outgoing connection loop) is
+    /// supervised according to its [`Policy`]. The method returns when a
+    /// sub-task failure is escalated.
+    ///
+    /// The carrier is borrowed for the run, and can be run again once the
+    /// method returns, such as after a transient bind failure, with the same
+    /// [`Incoming`] and [`Outgoing`] channels and [`CarrierHandle`]s. The
+    /// nodes added and removed at runtime stay so. Meanwhile, the handles
+    /// report the carrier as not running.
+    ///
+    /// With the `cert-expiry-check` feature, the certificates of `tls_mode` are
+    /// checked with [`tls::verify_cert`] before binding the listener, for the
+    /// keys matching them and their validity periods, whatever their CA. With
+    /// [`TlsMode::Disabled`](tls::TlsMode::Disabled), the node names are
+    /// resolved once at startup to map the incoming connections to the nodes.
     pub async fn run(
-        self,
+        &mut self,
         bind: &str,
         node_port: u16,
-        cert_chain: &Path,
-        cert_priv_key: &Path,
+        tls_mode: TlsMode,
     ) -> Result<(), Error> {
-        let Self {
-            nodes,
-            incoming,
-            mut outgoing,
-        } = self;
-        let mut futures = Vec::new();
-        let (server_config, client_config) = tls::init(cert_chain, cert_priv_key)?;
-
-        let acceptor = TlsAcceptor::from(server_config);
-        futures.push(listen(bind, node_port, acceptor, (incoming,), node::incoming).boxed());
-
-        for (node, port) in nodes {
-            let connector = TlsConnector::from(Arc::clone(&client_config));
-            let dnsname = ServerName::try_from(node.clone()).unwrap();
-            let outgoing = outgoing.remove(&node).unwrap();
-            futures.push(node::outgoing(node, port, connector, dnsname, outgoing).boxed());
+        self.run_until_shutdown(bind, node_port, tls_mode, future::pending::<()>())
+            .await
+    }
+
+    /// Runs the communication as [`Carrier::run`], until `shutdown`
+    /// completes, such as `tokio::signal::ctrl_c()`.
+    ///
+    /// Once `shutdown` completes, the carrier drains: the queues of the
+    /// outgoing messages are closed, the messages already queued are written,
+    /// and the method returns `Ok(())` once the requests in flight are
+    /// answered. The requests to the nodes without a connection are dropped.
+    /// The output of `shutdown` is ignored.
+    ///
+    /// The queues stay closed, so a carrier run again after a shutdown no
+    /// longer sends messages to the nodes.
+    pub async fn run_until_shutdown<F>(
+        &mut self,
+        bind: &str,
+        node_port: u16,
+        tls_mode: TlsMode,
+        shutdown: F,
+    ) -> Result<(), Error>
+    where
+        F: Future + Send,
+    {
+        match tls_mode {
+            TlsMode::Required {
+                cert_chain,
+                cert_priv_key,
+            } => {
+                #[cfg(feature = "cert-expiry-check")]
+                tls::verify_cert(&cert_chain, &cert_priv_key, &[])?;
+                let (server_config, client_config) =
+                    tls::init_with_config(&cert_chain, &cert_priv_key, self.tls_config)?;
+                let acceptor = TlsAcceptor::from(server_config);
+                self.run_with_tls_until_shutdown(bind, node_port, acceptor, client_config, shutdown)
+                    .await
+            }
+            #[cfg(feature = "multi-cert")]
+            TlsMode::MultiCert { certs } => {
+                #[cfg(feature = "cert-expiry-check")]
+                for (cert_chain, cert_priv_key) in &certs {
+                    tls::verify_cert(cert_chain, cert_priv_key, &[])?;
+                }
+                let (server_config, client_config) =
+                    tls::init_multi_cert_files(&certs, self.tls_config)?;
+                let acceptor = TlsAcceptor::from(server_config);
+                self.run_with_tls_until_shutdown(bind, node_port, acceptor, client_config, shutdown)
+                    .await
+            }
+            #[cfg(feature = "no-tls")]
+            TlsMode::Disabled => {
+                let peers = resolve_peers(&self.nodes).await?;
+                let transport = PlainTcpTransport::new(bind, node_port, peers);
+                self.run_with_transport_until_shutdown(transport, shutdown)
+                    .await
+            }
         }
+    }
+
+    /// Runs the communication over TCP, secured with TLS by `acceptor` and
+    /// `client_config` as they are, such as obtained from a secrets manager,
+    /// instead of the certificate files of [`Carrier::run`]. The
+    /// [`TlsConfig`] of the carrier does not apply, and the certificates are
+    /// not checked before binding the listener.
+    ///
+    /// See [`Carrier::run`] for the details.
+    pub async fn run_with_tls(
+        &mut self,
+        bind: &str,
+        node_port: u16,
+        acceptor: TlsAcceptor,
+        client_config: Arc<ClientConfig>,
+    ) -> Result<(), Error> {
+        self.run_with_tls_until_shutdown(
+            bind,
+            node_port,
+            acceptor,
+            client_config,
+            future::pending::<()>(),
+        )
+        .await
+    }
+
+    /// Runs the communication as [`Carrier::run_with_tls`], until `shutdown`
+    /// completes.
+    ///
+    /// See [`Carrier::run_until_shutdown`] for the details.
+    pub async fn run_with_tls_until_shutdown<F>(
+        &mut self,
+        bind: &str,
+        node_port: u16,
+        acceptor: TlsAcceptor,
+        client_config: Arc<ClientConfig>,
+        shutdown: F,
+    ) -> Result<(), Error>
+    where
+        F: Future + Send,
+    {
+        let connector = TlsConnector::from(client_config);
+        let transport = TlsTcpTransport::from_parts(bind, node_port, acceptor, connector);
+        self.run_with_transport_until_shutdown(transport, shutdown)
+            .await
+    }
+
+    /// Runs the communication over a custom [`Transport`].
+    ///
+    /// See [`Carrier::run`] for the details.
+    pub async fn run_with_transport<T: Transport>(&mut self, transport: T) -> Result<(), Error> {
+        self.run_with_transport_until_shutdown(transport, future::pending::<()>())
+            .await
+    }
+
+    /// Runs the communication over a custom [`Transport`], until `shutdown`
+    /// completes.
+    ///
+    /// See [`Carrier::run_until_shutdown`] for the details.
+    pub async fn run_with_transport_until_shutdown<T, F>(
+        &mut self,
+        transport: T,
+        shutdown: F,
+    ) -> Result<(), Error>
+    where
+        T: Transport,
+        F: Future + Send,
+    {
+        runtime::run(self, transport, shutdown).await
+    }
+}
 
-        let (result, _, _) = future::select_all(futures).await;
-        result
+#[cfg(feature = "no-tls")]
+async fn resolve_peers(
+    nodes: &HashMap<NodeId, NodeAddr>,
+) -> Result<HashMap<std::net::IpAddr, String>, Error> {
+    let mut peers = HashMap::new();
+    for (node, addr) in nodes {
+        let addrs = tokio::net::lookup_host((addr.host.as_str(), addr.port))
+            .await
+            .map_err(|source| Error::Resolve {
+                node: node.clone(),
+                source,
+            })?;
+        for addr in addrs {
+            peers.insert(addr.ip().to_canonical(), node.to_string());
+        }
     }
+    Ok(peers)
+}
+
+/// Serves the incoming connections of `transport` until the listener fails.
+/// The connections are served by tasks of their own, outliving a restart of
+/// the listener, until `connections` is cancelled.
+async fn listen<T, A, F, R>(
+    transport: Arc<T>,
+    args: A,
+    lifecycle: &Lifecycle,
+    connections: &CancellationToken,
+    serve: F,
+) -> Result<(), Error>
+where
+    T: Transport,
+    A: Clone,
+    F: FnMut(T::Accepted, Arc<T>, A) -> R,
+    R: Future<Output = Result<(), Error>> + Send + 'static,
+{
+    let addr = transport.listen_addr();
+    let listener = transport.bind().await.map_err(|source| Error::Bind {
+        addr: addr.clone(),
+        source,
+    })?;
+    info!("Listening for incoming connections to {addr}");
+    lifecycle.emit(|at| CarrierEvent::ListenerStarted {
+        at,
+        addr: addr.clone(),
+    });
+    let result = serve_listener(&addr, listener, transport, args, connections, serve).await;
+    lifecycle.emit(|at| CarrierEvent::ListenerStopped {
+        at,
+        addr,
+        error: result.as_ref().err().map(ToString::to_string),
+    });
+    result
 }
 
-async fn listen<A, F, T>(
-    ip: &str,
-    port: u16,
-    acceptor: TlsAcceptor,
+async fn serve_listener<T, S, A, F, R>(
+    addr: &str,
+    listener: S,
+    transport: Arc<T>,
     args: A,
+    connections: &CancellationToken,
     mut serve: F,
 ) -> Result<(), Error>
 where
+    T: Transport,
+    S: Stream<Item = io::Result<T::Accepted>>,
     A: Clone,
-    F: FnMut(TcpStream, TlsAcceptor, A) -> T,
-    T: Future<Output = Result<(), Error>> + Send + 'static,
+    F: FnMut(T::Accepted, Arc<T>, A) -> R,
+    R: Future<Output = Result<(), Error>> + Send + 'static,
 {
-    let listener = TcpListener::bind((ip, port)).await.map_err(Error::Socket)?;
-    let listener = TcpListenerStream::new(listener);
-    info!("Listening for incoming connections to {ip}:{port}");
+    let failures = &LogLimiter::default();
     listener
-        .map_err(Error::Socket)
+        .filter_map(|sock| async move {
+            match sock {
+                Ok(sock) => Some(Ok(sock)),
+                Err(err) if is_transient_accept_error(&err) => {
+                    log_limited!(
+                        failures,
+                        err.kind(),
+                        warn,
+                        "Transient accept failure: {err}"
+                    );
+                    sleep(ACCEPT_RETRY_INTERVAL).await;
+                    None
+                }
+                Err(source) => Some(Err(Error::Listener {
+                    addr: addr.to_string(),
+                    source,
+                })),
+            }
+        })
         .try_for_each_concurrent(None, |sock| {
-            task::spawn(serve(sock, acceptor.clone(), args.clone()))
-                .map(|r| r.expect("connection task failure"))
+            let name = format!("carrier-in:{}", transport.peer_addr(&sock));
+            let connection = serve(sock, Arc::clone(&transport), args.clone());
+            let cancelled = connections.clone().cancelled_owned();
+            let connection = async move {
+                match future::select(pin!(connection), pin!(cancelled)).await {
+                    Either::Left((result, _)) => result,
+                    Either::Right(((), _)) => Ok(()),
+                }
+            };
+            // A connection panicking before naming its node, caught by
+            // `node::incoming` otherwise, is closed alone like any failed
+            // one, while a cancelled one went with the runtime.
+            spawn_named(&name, connection).map(move |r| {
+                if let Err(err) = r {
+                    if let Ok(payload) = err.try_into_panic() {
+                        error!("{}", supervisor::panicked(&name, &*payload));
+                    }
+                }
+                Ok(())
+            })
         })
         .await?;
     Ok(())
 }
+
+/// Spawns a task, named for tokio-console when built with `--cfg
+/// tokio_unstable`.
+fn spawn_named<F>(name: &str, future: F) -> JoinHandle<F::Output>
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    #[cfg(tokio_unstable)]
+    return task::Builder::new()
+        .name(name)
+        .spawn(future)
+        .expect("task spawn failure");
+    #[cfg(not(tokio_unstable))]
+    {
+        let _ = name;
+        task::spawn(future)
+    }
+}
+
+/// Spawns a task into `tasks`, named as in [`spawn_named`].
+fn spawn_named_in<T, F>(tasks: &mut JoinSet<T>, name: &str, future: F)
+where
+    F: Future<Output = T> + Send + 'static,
+    T: Send + 'static,
+{
+    #[cfg(tokio_unstable)]
+    tasks
+        .build_task()
+        .name(name)
+        .spawn(future)
+        .expect("task spawn failure");
+    #[cfg(not(tokio_unstable))]
+    {
+        let _ = name;
+        tasks.spawn(future);
+    }
+}
+
+/// Returns `true` if the accept error is caused by a temporary condition (file
+/// descriptor exhaustion, an aborted connection, an interrupted system call)
+/// and the listener is still usable.
+fn is_transient_accept_error(err: &io::Error) -> bool {
+    #[cfg(unix)]
+    if let Some(code) = err.raw_os_error() {
+        if [libc::EMFILE, libc::ENFILE, libc::ENOBUFS, libc::ENOMEM].contains(&code) {
+            return true;
+        }
+    }
+    matches!(
+        err.kind(),
+        io::ErrorKind::ConnectionAborted
+            | io::ErrorKind::ConnectionReset
+            | io::ErrorKind::Interrupted
+            | io::ErrorKind::WouldBlock
+    )
+}
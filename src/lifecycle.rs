@@ -0,0 +1,353 @@
+//! Lifecycle events of the listener and the connections, for an audit trail.
+//!
+//! [`Carrier::events`](crate::Carrier::events) streams a [`CarrierEvent`] for
+//! the listener starting and stopping, every incoming connection accepted or
+//! rejected, every outgoing connection attempted, every connection
+//! established, failed or closed, every handshake failure, every slow
+//! request with [`Carrier::slow_request_threshold`](crate::Carrier::slow_request_threshold)
+//! set, and every node starting and stopping
+//! [flapping](crate::Carrier::flap_threshold). The events serialize with serde, to be shipped to a log pipeline as
+//! they are.
+//!
+//! The channel is bounded: the events past its capacity are dropped and
+//! counted by [`CarrierHandle::dropped_events`](crate::control::CarrierHandle::dropped_events),
+//! so a slow consumer never slows the connections down.
+
+use crate::middleware::Direction;
+use crate::node::Error;
+use crate::tls::TlsDetails;
+use serde::Serialize;
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use tokio::sync::mpsc;
+
+/// Events kept for a consumer lagging behind.
+pub(crate) const CAPACITY: usize = 1024;
+
+/// Lifecycle event of the carrier, stamped with the time it happened.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+#[non_exhaustive]
+pub enum CarrierEvent {
+    /// Listener bound to `addr`, accepting the incoming connections.
+    ListenerStarted {
+        /// Time of the event.
+        at: SystemTime,
+        /// Address listened on.
+        addr: String,
+    },
+    /// Listener stopped, to be restarted according to its
+    /// [`Policy`](crate::supervisor::Policy).
+    ListenerStopped {
+        /// Time of the event.
+        at: SystemTime,
+        /// Address listened on.
+        addr: String,
+        /// Why the listener stopped, if it failed.
+        error: Option<String>,
+    },
+    /// Incoming connection from `peer` identified as from `node`.
+    IncomingAccepted {
+        /// Time of the event.
+        at: SystemTime,
+        /// Address of the remote end.
+        peer: String,
+        /// Node of the connection.
+        node: String,
+    },
+    /// Incoming connection from `peer` closed before it was identified.
+    IncomingRejected {
+        /// Time of the event.
+        at: SystemTime,
+        /// Address of the remote end.
+        peer: String,
+        /// Class of the failure.
+        reason: FailureReason,
+        /// Failure.
+        error: String,
+    },
+    /// Outgoing connection attempted to `node` at `addr`.
+    OutgoingAttempt {
+        /// Time of the event.
+        at: SystemTime,
+        /// Node of the connection.
+        node: String,
+        /// Address connected to, as `host:port`.
+        addr: String,
+    },
+    /// Connection with `node` through its version handshake and hooks.
+    Established {
+        /// Time of the event.
+        at: SystemTime,
+        /// Node of the connection.
+        node: String,
+        /// End that opened the connection.
+        side: Side,
+        /// Parameters of the TLS handshake, [`None`] over a transport without
+        /// TLS.
+        tls: Option<TlsDetails>,
+    },
+    /// Connection with `node` failed during its TLS or version handshake, or
+    /// its hooks.
+    HandshakeFailed {
+        /// Time of the event.
+        at: SystemTime,
+        /// Node of the connection.
+        node: String,
+        /// End that opened the connection.
+        side: Side,
+        /// Class of the failure.
+        reason: FailureReason,
+        /// Failure.
+        error: String,
+    },
+    /// Connection with `node` failed to connect, or once established.
+    ConnectionFailed {
+        /// Time of the event.
+        at: SystemTime,
+        /// Node of the connection.
+        node: String,
+        /// End that opened the connection.
+        side: Side,
+        /// Class of the failure.
+        reason: FailureReason,
+        /// Failure.
+        error: String,
+    },
+    /// Established connection with `node` closed, following its failure if
+    /// any.
+    ConnectionClosed {
+        /// Time of the event.
+        at: SystemTime,
+        /// Node of the connection.
+        node: String,
+        /// End that opened the connection.
+        side: Side,
+        /// Time since the connection was established.
+        duration: Duration,
+        /// Bytes written to the connection.
+        bytes_sent: u64,
+        /// Bytes read from the connection.
+        bytes_received: u64,
+    },
+    /// Request exchanged with `node` in flight for longer than the
+    /// [`slow_request_threshold`](crate::Carrier::slow_request_threshold).
+    SlowRequest {
+        /// Time of the event.
+        at: SystemTime,
+        /// Node of the request.
+        node: String,
+        /// Direction of the request: a request from the node is awaiting the
+        /// answer of the application if [`Direction::Incoming`], and its
+        /// response from the node if [`Direction::Outgoing`].
+        direction: Direction,
+        /// Time since the request was dispatched or written.
+        age: Duration,
+        /// Hash of the `request_id`, as in the
+        /// [`RequestTiming`](crate::stats::RequestTiming)s.
+        request_id_hash: u64,
+    },
+    /// Request reported by a [`CarrierEvent::SlowRequest`] finished.
+    SlowRequestFinished {
+        /// Time of the event.
+        at: SystemTime,
+        /// Node of the request.
+        node: String,
+        /// Direction of the request.
+        direction: Direction,
+        /// Time from the request dispatched or written until finished.
+        latency: Duration,
+        /// Hash of the `request_id`.
+        request_id_hash: u64,
+        /// How the request finished.
+        outcome: SlowOutcome,
+    },
+    /// Connections with `node` went up and down more than the
+    /// [`flap_threshold`](crate::Carrier::flap_threshold).
+    FlappingStarted {
+        /// Time of the event.
+        at: SystemTime,
+        /// Node of the connections.
+        node: String,
+        /// Number of the transitions within the window.
+        transitions: usize,
+        /// Window of the threshold.
+        window: Duration,
+    },
+    /// Node reported by a [`CarrierEvent::FlappingStarted`] back within the
+    /// threshold.
+    FlappingStopped {
+        /// Time of the event.
+        at: SystemTime,
+        /// Node of the connections.
+        node: String,
+        /// Time since the node started flapping.
+        duration: Duration,
+    },
+}
+
+/// End that opened a connection.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Side {
+    /// Opened by the node, accepted by this one.
+    Incoming,
+    /// Opened by this node.
+    Outgoing,
+}
+
+/// Class of a connection failure.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+#[non_exhaustive]
+pub enum FailureReason {
+    /// The node could not be reached.
+    Connect,
+    /// TLS handshake failure.
+    Tls,
+    /// The remote end could not be mapped to a node.
+    Identity,
+    /// Version handshake failure.
+    Handshake,
+    /// Pre-connect hook failure.
+    Hook,
+    /// Malformed or unexpected message, or the connection lost.
+    Protocol,
+    /// The application stopped consuming the requests.
+    Bus,
+    /// The task serving the connection panicked.
+    Panic,
+}
+
+/// How a slow request finished, in a [`CarrierEvent::SlowRequestFinished`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+#[non_exhaustive]
+pub enum SlowOutcome {
+    /// Response received from the node, or sent to it.
+    Completed,
+    /// Request to the node given up by its caller, such as on a timeout.
+    Abandoned,
+    /// Connection closed before the response.
+    Closed,
+}
+
+/// Sender of the [`CarrierEvent`]s, if subscribed.
+#[derive(Clone, Default)]
+pub(crate) struct Lifecycle {
+    tx: Option<mpsc::Sender<CarrierEvent>>,
+    /// Events dropped on a full channel.
+    dropped: Arc<AtomicU64>,
+}
+
+impl Side {
+    /// Returns the side as logged.
+    pub(crate) fn as_str(self) -> &'static str {
+        match self {
+            Self::Incoming => "incoming",
+            Self::Outgoing => "outgoing",
+        }
+    }
+}
+
+impl fmt::Display for Side {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl SlowOutcome {
+    /// Returns the outcome as logged.
+    pub(crate) fn as_str(self) -> &'static str {
+        match self {
+            Self::Completed => "completed",
+            Self::Abandoned => "abandoned",
+            Self::Closed => "closed",
+        }
+    }
+}
+
+impl fmt::Display for SlowOutcome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FailureReason {
+    /// Classifies a connection failure.
+    pub(crate) fn of(err: &Error) -> Self {
+        match err {
+            Error::Socket(_) => Self::Connect,
+            Error::Tls(_) => Self::Tls,
+            Error::Sni | Error::UnknownServerName => Self::Identity,
+            Error::Hello(_) => Self::Handshake,
+            Error::Hook(_) => Self::Hook,
+            Error::Bus(_) => Self::Bus,
+            Error::ProtocolRead { .. }
+            | Error::ProtocolWrite { .. }
+            | Error::UnexpectedResponse(_)
+            | Error::UnexpectedResponseSeq(_)
+            | Error::PeerDisconnected => Self::Protocol,
+            Error::Panicked(_) => Self::Panic,
+        }
+    }
+}
+
+impl Lifecycle {
+    /// Subscribes to the events, replacing the previous subscriber.
+    pub(crate) fn subscribe(&mut self) -> mpsc::Receiver<CarrierEvent> {
+        let (tx, rx) = mpsc::channel(CAPACITY);
+        self.tx = Some(tx);
+        rx
+    }
+
+    /// Returns the counter of the events dropped on a full channel.
+    pub(crate) fn dropped(&self) -> Arc<AtomicU64> {
+        Arc::clone(&self.dropped)
+    }
+
+    /// Sends the event built by `event`, if subscribed, or counts it dropped
+    /// if the channel is full.
+    pub(crate) fn emit(&self, event: impl FnOnce(SystemTime) -> CarrierEvent) {
+        let Some(tx) = &self.tx else {
+            return;
+        };
+        if let Err(mpsc::error::TrySendError::Full(_)) = tx.try_send(event(SystemTime::now())) {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Sends the failure of a connection with `node`, as a
+    /// [`CarrierEvent::HandshakeFailed`] if not `established`, or a
+    /// [`CarrierEvent::ConnectionFailed`].
+    pub(crate) fn failed(&self, node: &str, side: Side, established: bool, err: &Error) {
+        let reason = FailureReason::of(err);
+        let handshake = !established
+            && matches!(
+                reason,
+                FailureReason::Tls | FailureReason::Handshake | FailureReason::Hook
+            );
+        self.emit(|at| {
+            let (node, error) = (node.to_string(), err.to_string());
+            if handshake {
+                CarrierEvent::HandshakeFailed {
+                    at,
+                    node,
+                    side,
+                    reason,
+                    error,
+                }
+            } else {
+                CarrierEvent::ConnectionFailed {
+                    at,
+                    node,
+                    side,
+                    reason,
+                    error,
+                }
+            }
+        });
+    }
+}
@@ -0,0 +1,159 @@
+//! Rate limiting of repetitive logs.
+//!
+//! A peer down makes the reconnect loop fail several times a second, and a
+//! scanner probing the listener fails a handshake on every probe. A
+//! [`LogLimiter`] lets the first event of every key through at its level,
+//! then at most one every [`LOG_INTERVAL`], and demotes the others to trace.
+//! Every [`SUMMARY_INTERVAL`], the next event is logged at its level with the
+//! number of the events demoted since.
+
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Shortest interval between two events of a key logged at their level.
+pub(crate) const LOG_INTERVAL: Duration = Duration::from_secs(10);
+/// Interval of the summaries of the demoted events of a key.
+pub(crate) const SUMMARY_INTERVAL: Duration = Duration::from_secs(6 * LOG_INTERVAL.as_secs());
+/// Number of the keys above which the idle ones are forgotten.
+const MAX_KEYS: usize = 1024;
+
+/// Logs an event at `$level` if `$limiter` lets the event of `$key` through,
+/// and at trace level otherwise. A summary of the demoted events, when due,
+/// follows the event logged at its level.
+macro_rules! log_limited {
+    ($limiter:expr, $key:expr, $level:ident, $($arg:tt)+) => {
+        let verdict = $limiter.check($key);
+        if verdict.log {
+            tracing::$level!($($arg)+);
+        } else {
+            tracing::trace!($($arg)+);
+        }
+        if let Some((suppressed, window)) = verdict.summary {
+            tracing::$level!(
+                "Suppressed {suppressed} similar events in the last {}s",
+                window.as_secs()
+            );
+        }
+    };
+}
+
+pub(crate) use log_limited;
+
+/// Token buckets of the events by key.
+pub(crate) struct LogLimiter<K> {
+    buckets: Mutex<HashMap<K, Bucket>>,
+}
+
+/// Outcome of [`LogLimiter::check`].
+pub(crate) struct Verdict {
+    /// Whether the event is logged at its level.
+    pub(crate) log: bool,
+    /// Number of the events demoted since the last summary, and the time
+    /// since, when a summary is due.
+    pub(crate) summary: Option<(u64, Duration)>,
+}
+
+struct Bucket {
+    /// Time of the last event logged at its level.
+    logged: Instant,
+    /// Start of the window of the next summary.
+    window: Instant,
+    /// Number of the events demoted in the window.
+    suppressed: u64,
+}
+
+impl<K> Default for LogLimiter<K> {
+    fn default() -> Self {
+        Self {
+            buckets: Mutex::default(),
+        }
+    }
+}
+
+impl<K: Eq + Hash> LogLimiter<K> {
+    /// Counts an event of `key`, and returns whether it is logged at its level.
+    pub(crate) fn check(&self, key: K) -> Verdict {
+        let now = Instant::now();
+        let mut buckets = self.buckets.lock().unwrap();
+        if buckets.len() >= MAX_KEYS && !buckets.contains_key(&key) {
+            buckets.retain(|_, bucket| now.duration_since(bucket.logged) < SUMMARY_INTERVAL);
+        }
+        let Some(bucket) = buckets.get_mut(&key) else {
+            let bucket = Bucket {
+                logged: now,
+                window: now,
+                suppressed: 0,
+            };
+            buckets.insert(key, bucket);
+            return Verdict {
+                log: true,
+                summary: None,
+            };
+        };
+        let window = now.duration_since(bucket.window);
+        let summary_due = window >= SUMMARY_INTERVAL;
+        let log = summary_due || now.duration_since(bucket.logged) >= LOG_INTERVAL;
+        if log {
+            bucket.logged = now;
+        } else {
+            bucket.suppressed += 1;
+        }
+        let summary = summary_due.then(|| {
+            bucket.window = now;
+            (std::mem::take(&mut bucket.suppressed), window)
+        });
+        Verdict {
+            log,
+            summary: summary.filter(|&(suppressed, _)| suppressed > 0),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn storm_demoted_after_first() {
+        let limiter = LogLimiter::default();
+        let verdicts = (0..100).map(|_| limiter.check("a")).collect::<Vec<_>>();
+        assert!(verdicts[0].log);
+        assert_eq!(verdicts.iter().filter(|verdict| verdict.log).count(), 1);
+        assert!(verdicts.iter().all(|verdict| verdict.summary.is_none()));
+    }
+
+    #[test]
+    fn keys_limited_apart() {
+        let limiter = LogLimiter::default();
+        assert!(limiter.check("a").log);
+        assert!(!limiter.check("a").log);
+        assert!(limiter.check("b").log);
+        assert!(!limiter.check("b").log);
+    }
+
+    #[test]
+    fn summary_counts_demoted() {
+        let limiter = LogLimiter::default();
+        limiter.check("a");
+        for _ in 0..5 {
+            limiter.check("a");
+        }
+        // Backdates the window, as if the storm lasted a summary interval.
+        let start = Instant::now().checked_sub(SUMMARY_INTERVAL).unwrap();
+        let mut buckets = limiter.buckets.lock().unwrap();
+        let bucket = buckets.get_mut("a").unwrap();
+        bucket.window = start;
+        bucket.logged = start;
+        drop(buckets);
+        let verdict = limiter.check("a");
+        assert!(verdict.log);
+        let (suppressed, window) = verdict.summary.unwrap();
+        assert_eq!(suppressed, 5);
+        assert!(window >= SUMMARY_INTERVAL);
+        let verdict = limiter.check("a");
+        assert!(!verdict.log);
+        assert!(verdict.summary.is_none());
+    }
+}
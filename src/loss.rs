@@ -0,0 +1,260 @@
+//! Accounting of the messages discarded by the carrier.
+//!
+//! Every message the carrier drops on purpose, or answers with a status
+//! rejecting it, is counted by [`LossReason`], node and [`Direction`], for
+//! "did we lose anything?" to have an answer. The counts are read from the
+//! [`LossAccounting`] of [`Carrier::losses`](crate::Carrier::losses) or
+//! [`CarrierHandle::losses`](crate::control::CarrierHandle::losses).
+//!
+//! Every loss is also logged as a warning with the target
+//! `mpc_carrier::loss`, with the `request_id` of the message if it has one, at
+//! most once per [`WARN_INTERVAL`] for every node and reason. Filter the
+//! target out of the subscriber to silence them.
+
+use crate::config::NodeId;
+use crate::messages::NodeResponse;
+use crate::middleware::{self, Direction};
+use crate::stats::Stats;
+use crate::status;
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tracing::warn;
+
+/// Shortest interval between two warnings of the losses of a node for the
+/// same reason. The losses in between are counted in the next one.
+pub const WARN_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Why a message was discarded.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[non_exhaustive]
+pub enum LossReason {
+    /// A message past the frame limit of the node, not written.
+    Oversized,
+    /// A request colliding with a request in flight with the same key, not
+    /// written.
+    Colliding,
+    /// A notification needing a feature not negotiated with the node, not
+    /// written.
+    NotNegotiated,
+    /// A duplicate of a request in flight or of a notification delivered,
+    /// dropped with the [`deduplication`](crate::Carrier::deduplication).
+    Duplicate,
+    /// An envelope of a kind unknown to this node, sent by a newer peer.
+    UnknownKind,
+    /// A request or notification on a connection without inbound requests.
+    NoInbound,
+    /// A notification received once the [`Incoming`](crate::channels::Incoming)
+    /// channels are gone.
+    NoConsumer,
+    /// A response to a request given up by its caller.
+    Abandoned,
+    /// A message failing the [`auth`](crate::auth)entication, answered with
+    /// [`status::AUTH_FAILED`] if a request.
+    Unauthenticated,
+    /// A malformed message, answered with [`status::INVALID`] if a request.
+    Invalid,
+    /// A request answered with [`status::HANDLER_DROPPED`].
+    HandlerDropped,
+    /// A request answered with [`status::OVERLOADED`].
+    Overloaded,
+    /// A request answered with [`status::UNSUPPORTED`].
+    Unsupported,
+    /// A request answered with [`status::EPOCH_MISMATCH`].
+    EpochMismatch,
+    /// A request answered with [`status::REJECTED`].
+    Rejected,
+    /// A request whose connection closed before its response was written.
+    ConnectionClosed,
+}
+
+/// Number of the messages lost for a reason, exchanged with a node in a
+/// direction.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Loss {
+    /// Node the message was exchanged with.
+    pub node: NodeId,
+    /// Direction of the message discarded: a request, notification or
+    /// response received from the node is [`Direction::Incoming`].
+    pub direction: Direction,
+    /// Why the messages were discarded.
+    pub reason: LossReason,
+    /// Number of the messages discarded.
+    pub count: u64,
+}
+
+/// Handle reading the losses of a [`Carrier`](crate::Carrier), live.
+#[derive(Clone)]
+pub struct LossAccounting {
+    stats: Stats,
+}
+
+/// Losses of a node, in its [`NodeStats`](crate::stats::NodeStats).
+#[derive(Default)]
+pub(crate) struct NodeLosses {
+    counts: Mutex<HashMap<(LossReason, Direction), u64>>,
+    /// Time of the last warning by reason, with the losses since.
+    warned: Mutex<HashMap<LossReason, (Instant, u64)>>,
+}
+
+impl LossReason {
+    /// Returns the reason of a request answered with `status`, if the status
+    /// rejects it.
+    pub(crate) fn of_status(status: u32) -> Option<Self> {
+        match status {
+            status::HANDLER_DROPPED => Some(Self::HandlerDropped),
+            status::OVERLOADED => Some(Self::Overloaded),
+            status::UNSUPPORTED => Some(Self::Unsupported),
+            status::INVALID => Some(Self::Invalid),
+            status::EPOCH_MISMATCH => Some(Self::EpochMismatch),
+            status::AUTH_FAILED => Some(Self::Unauthenticated),
+            status::REJECTED => Some(Self::Rejected),
+            _ => None,
+        }
+    }
+
+    /// Returns the reason as logged.
+    #[must_use]
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Oversized => "oversized",
+            Self::Colliding => "colliding",
+            Self::NotNegotiated => "not_negotiated",
+            Self::Duplicate => "duplicate",
+            Self::UnknownKind => "unknown_kind",
+            Self::NoInbound => "no_inbound",
+            Self::NoConsumer => "no_consumer",
+            Self::Abandoned => "abandoned",
+            Self::Unauthenticated => "unauthenticated",
+            Self::Invalid => "invalid",
+            Self::HandlerDropped => "handler_dropped",
+            Self::Overloaded => "overloaded",
+            Self::Unsupported => "unsupported",
+            Self::EpochMismatch => "epoch_mismatch",
+            Self::Rejected => "rejected",
+            Self::ConnectionClosed => "connection_closed",
+        }
+    }
+}
+
+impl fmt::Display for LossReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl fmt::Display for Loss {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let direction = match self.direction {
+            Direction::Incoming => "from",
+            Direction::Outgoing => "to",
+        };
+        write!(
+            f,
+            "{} {} {direction} {}",
+            self.count, self.reason, self.node
+        )
+    }
+}
+
+impl LossAccounting {
+    pub(crate) fn new(stats: Stats) -> Self {
+        Self { stats }
+    }
+
+    /// Returns the losses of every node, sorted by node, direction and
+    /// reason. The nodes without losses are left out.
+    #[must_use]
+    pub fn snapshot(&self) -> Vec<Loss> {
+        let stats = self.stats.read().unwrap();
+        let mut losses = stats
+            .iter()
+            .flat_map(|(node, stats)| {
+                let counts = stats.losses.counts.lock().unwrap();
+                counts
+                    .iter()
+                    .map(|(&(reason, direction), &count)| Loss {
+                        node: node.clone(),
+                        direction,
+                        reason,
+                        count,
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+        losses.sort_by(|a, b| {
+            (&a.node, a.direction, a.reason).cmp(&(&b.node, b.direction, b.reason))
+        });
+        losses
+    }
+
+    /// Returns the number of the messages lost.
+    #[must_use]
+    pub fn total(&self) -> u64 {
+        self.snapshot().iter().map(|loss| loss.count).sum()
+    }
+
+    /// Panics listing the losses, if any.
+    ///
+    /// # Panics
+    ///
+    /// If a message was lost.
+    #[track_caller]
+    pub fn assert_no_losses(&self) {
+        let losses = self.snapshot();
+        if !losses.is_empty() {
+            let losses = losses.iter().map(Loss::to_string).collect::<Vec<_>>();
+            panic!("Messages lost: {}", losses.join(", "));
+        }
+    }
+}
+
+impl NodeLosses {
+    /// Counts a message exchanged with `node` discarded for `reason`, and
+    /// warns of it unless warned of recently.
+    pub(crate) fn record(
+        &self,
+        node: &NodeId,
+        direction: Direction,
+        reason: LossReason,
+        request_id: Option<&[u8]>,
+    ) {
+        *self
+            .counts
+            .lock()
+            .unwrap()
+            .entry((reason, direction))
+            .or_default() += 1;
+        let mut warned = self.warned.lock().unwrap();
+        let now = Instant::now();
+        if let Some((at, suppressed)) = warned.get_mut(&reason) {
+            if now.duration_since(*at) < WARN_INTERVAL {
+                *suppressed += 1;
+                return;
+            }
+        }
+        let suppressed = warned
+            .insert(reason, (now, 0))
+            .map_or(0, |(_, suppressed)| suppressed);
+        let request_id = request_id.map(middleware::hex);
+        warn!(
+            target: "mpc_carrier::loss",
+            node = %node,
+            ?direction,
+            %reason,
+            request_id,
+            suppressed,
+            "Message discarded"
+        );
+    }
+
+    /// Counts a request from `node` answered with `response`, if its status
+    /// rejects it.
+    pub(crate) fn rejected(&self, node: &NodeId, response: &NodeResponse) {
+        if let Some(reason) = LossReason::of_status(response.status) {
+            let request_id = Some(response.request_id.as_slice());
+            self.record(node, Direction::Incoming, reason, request_id);
+        }
+    }
+}
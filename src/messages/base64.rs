@@ -0,0 +1,56 @@
+//! Base64 encoding of the `bytes` fields for the `serde` feature, keeping the
+//! JSON transcripts readable. The binary formats, such as the one of the
+//! `bincode` feature, keep the raw bytes.
+
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use serde::de::{SeqAccess, Visitor};
+use serde::{Deserialize, Deserializer, Serializer};
+use std::fmt;
+
+/// Visitor of raw bytes.
+struct Bytes;
+
+/// Serializes `bytes` as a base64 string, or as raw bytes in a binary format.
+pub fn serialize<S: Serializer>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+    if serializer.is_human_readable() {
+        serializer.serialize_str(&STANDARD.encode(bytes))
+    } else {
+        serializer.serialize_bytes(bytes)
+    }
+}
+
+/// Deserializes bytes from a base64 string, or from raw bytes in a binary
+/// format.
+pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+    if deserializer.is_human_readable() {
+        let encoded = String::deserialize(deserializer)?;
+        STANDARD.decode(encoded).map_err(serde::de::Error::custom)
+    } else {
+        deserializer.deserialize_byte_buf(Bytes)
+    }
+}
+
+impl<'de> Visitor<'de> for Bytes {
+    type Value = Vec<u8>;
+
+    fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("bytes")
+    }
+
+    fn visit_bytes<E>(self, bytes: &[u8]) -> Result<Vec<u8>, E> {
+        Ok(bytes.to_vec())
+    }
+
+    fn visit_byte_buf<E>(self, bytes: Vec<u8>) -> Result<Vec<u8>, E> {
+        Ok(bytes)
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Vec<u8>, A::Error> {
+        let mut bytes = Vec::with_capacity(seq.size_hint().unwrap_or_default());
+        while let Some(byte) = seq.next_element()? {
+            bytes.push(byte);
+        }
+        Ok(bytes)
+    }
+}
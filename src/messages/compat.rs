@@ -0,0 +1,202 @@
+//! Wire compatibility of the schema across versions.
+//!
+//! Golden encodings of every message are checked in under
+//! `src/messages/compat/`. They are never regenerated: a renumbered or retyped
+//! field makes its golden encoding decode differently, which shows when
+//! encoded again. A new message needs a new fixture, and a new field or kind
+//! may get one, listed in [`FIXTURES`].
+//!
+//! The [`v0`] schema of the first release stands for the peers not upgraded
+//! yet during a rolling deploy.
+
+use super::{
+    Ack, Backpressure, Cancel, CompressionCapabilities, CompressionSelection, Envelope, Heartbeat,
+    Hello, NodeNotification, NodeRequest, NodeResponse,
+};
+use crate::config;
+use crate::node::MAX_LEN;
+use crate::protobuf_tcp;
+use crate::transport::memory::MemoryNetwork;
+use crate::transport::Transport;
+use crate::Carrier;
+use futures::future::{self, Either};
+use futures::prelude::*;
+use prost::Message;
+use std::pin::pin;
+use std::time::Duration;
+use tokio::time::sleep;
+
+/// Messages of the first release.
+#[allow(missing_docs, clippy::pedantic)]
+pub mod v0 {
+    include!(concat!(env!("OUT_DIR"), "/v0.rs"));
+}
+
+// Top-level messages of the current schema, listed by the build script.
+include!(concat!(env!("OUT_DIR"), "/message_names.rs"));
+
+/// Golden encoding of a message.
+struct Fixture {
+    message: &'static str,
+    file: &'static str,
+    encoded: &'static [u8],
+    reencode: fn(&[u8]) -> Result<Vec<u8>, prost::DecodeError>,
+}
+
+macro_rules! fixture {
+    ($message:ident, $file:literal) => {
+        Fixture {
+            message: stringify!($message),
+            file: $file,
+            encoded: include_bytes!(concat!("compat/", $file)),
+            reencode: reencode::<$message>,
+        }
+    };
+}
+
+/// Every golden encoding, with the message it encodes.
+const FIXTURES: &[Fixture] = &[
+    fixture!(NodeRequest, "node_request.bin"),
+    fixture!(NodeResponse, "node_response.bin"),
+    fixture!(NodeNotification, "node_notification.bin"),
+    fixture!(NodeNotification, "node_notification_acked.bin"),
+    fixture!(Envelope, "envelope_request.bin"),
+    fixture!(Envelope, "envelope_response.bin"),
+    fixture!(Envelope, "envelope_notification.bin"),
+    fixture!(Envelope, "envelope_heartbeat.bin"),
+    fixture!(Envelope, "envelope_cancel.bin"),
+    fixture!(Envelope, "envelope_ack.bin"),
+    fixture!(Envelope, "envelope_backpressure.bin"),
+    fixture!(Heartbeat, "heartbeat.bin"),
+    fixture!(Cancel, "cancel.bin"),
+    fixture!(Ack, "ack.bin"),
+    fixture!(Backpressure, "backpressure.bin"),
+    fixture!(CompressionCapabilities, "compression_capabilities.bin"),
+    fixture!(CompressionSelection, "compression_selection.bin"),
+    fixture!(Hello, "hello.bin"),
+];
+
+/// Checks the current schema against the golden encodings and the [`v0`]
+/// schema.
+///
+/// # Panics
+///
+/// If a message has no golden encoding, if a golden encoding no longer
+/// encodes the same once decoded, or if a field exchanged with a [`v0`] peer
+/// is lost.
+pub fn assert_compatible() {
+    for message in MESSAGE_NAMES {
+        assert!(
+            FIXTURES.iter().any(|fixture| fixture.message == *message),
+            "no golden encoding of `{message}` in src/messages/compat/"
+        );
+    }
+    for fixture in FIXTURES {
+        let reencoded = (fixture.reencode)(fixture.encoded)
+            .unwrap_or_else(|err| panic!("{} no longer decodes: {err}", fixture.file));
+        assert!(
+            reencoded == fixture.encoded,
+            "{} encodes differently once decoded",
+            fixture.file
+        );
+    }
+
+    let old = old_request(1);
+    let new = NodeRequest::decode(old.encode_to_vec().as_slice()).expect("v0 request to decode");
+    assert_eq!(new.request_id, old.request_id, "v0 request_id lost");
+    assert_eq!(new.payload, old.distance_list, "v0 distance_list lost");
+    let new = NodeRequest::decode(FIXTURES[0].encoded).expect("fixture to decode");
+    let old = v0::NodeRequest::decode(FIXTURES[0].encoded).expect("request to decode as v0");
+    assert_eq!(old.request_id, new.request_id, "request_id lost by v0");
+    assert_eq!(old.distance_list, new.payload, "payload lost by v0");
+    let new = NodeResponse::decode(FIXTURES[1].encoded).expect("fixture to decode");
+    let old = v0::NodeResponse::decode(FIXTURES[1].encoded).expect("response to decode as v0");
+    assert_eq!(old.request_id, new.request_id, "request_id lost by v0");
+}
+
+/// Exchanges requests in both directions between a [`Carrier`] with the
+/// default configuration and a simulated [`v0`] peer, over a
+/// [`MemoryNetwork`].
+///
+/// # Panics
+///
+/// If an exchange fails or a field is lost, or if called outside of a Tokio
+/// runtime.
+pub async fn assert_old_peer_interop() {
+    let network = MemoryNetwork::new();
+    let old_peer = network.transport("old");
+    let mut listener = old_peer.bind().await.expect("memory listener");
+    let (mut carrier, mut incoming, mut outgoing) =
+        Carrier::from_node_strs(["old:1"], None).expect("valid node");
+    let carrier = carrier.run_with_transport(network.transport("new"));
+
+    let to_carrier = async {
+        let addr = config::default_addr("new", 1).expect("valid node");
+        let conn = loop {
+            match old_peer.connect(&addr).await {
+                Ok((conn, _)) => break conn,
+                // Until the carrier listens.
+                Err(_) => sleep(Duration::from_millis(10)).await,
+            }
+        };
+        let (mut reader, mut writer) = protobuf_tcp::new(conn, MAX_LEN);
+        let request = old_request(2);
+        writer
+            .write(request.clone())
+            .await
+            .expect("v0 request written");
+        writer.flush().await.expect("v0 request flushed");
+        let response = reader.read::<v0::NodeResponse>().await.expect("response");
+        assert_eq!(response.request_id, request.request_id, "request_id lost");
+    };
+    let handle = async {
+        let (node, callback) = incoming.recv().await.expect("v0 request");
+        assert_eq!(node.as_str(), "old");
+        assert_eq!(callback.message.payload, old_request(2).distance_list);
+        let response = NodeResponse {
+            request_id: callback.message.request_id.clone(),
+            ..Default::default()
+        };
+        callback.respond(response).expect("requester to wait");
+    };
+    let from_carrier = async {
+        let request = NodeRequest {
+            request_id: b"from-carrier".to_vec(),
+            payload: old_request(3).distance_list,
+            ..Default::default()
+        };
+        let response = outgoing.send("old", request.clone()).await;
+        let response = response.expect("v0 response");
+        assert_eq!(response.request_id, request.request_id, "request_id lost");
+    };
+    let serve_carrier = async {
+        let accepted = listener.next().await.expect("listener open");
+        let accepted = accepted.expect("accepted");
+        let (conn, _) = old_peer.accept(accepted).await.expect("accepted");
+        let (mut reader, mut writer) = protobuf_tcp::new(conn, MAX_LEN);
+        let request = reader.read::<v0::NodeRequest>().await.expect("request");
+        assert_eq!(request.distance_list, old_request(3).distance_list);
+        let response = v0::NodeResponse {
+            request_id: request.request_id,
+        };
+        writer.write(response).await.expect("v0 response written");
+        writer.flush().await.expect("v0 response flushed");
+    };
+
+    let exchanges = future::join4(to_carrier, handle, from_carrier, serve_carrier);
+    let (carrier, exchanges) = (pin!(carrier), pin!(exchanges));
+    if let Either::Left((result, _)) = future::select(carrier, exchanges).await {
+        panic!("carrier stopped: {result:?}");
+    }
+}
+
+fn reencode<M: Message + Default>(encoded: &[u8]) -> Result<Vec<u8>, prost::DecodeError> {
+    Ok(M::decode(encoded)?.encode_to_vec())
+}
+
+fn old_request(seed: u8) -> v0::NodeRequest {
+    v0::NodeRequest {
+        request_id: vec![seed; 8],
+        distance_list: (0..32).map(|byte| byte ^ seed).collect(),
+    }
+}
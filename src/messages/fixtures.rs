@@ -0,0 +1,97 @@
+//! Deterministic test data.
+//!
+//! Every function produces the same output for the same arguments, which keeps
+//! the tests built on top of them reproducible.
+
+use super::{NodeRequest, NodeResponse};
+
+const PAYLOAD_LEN: usize = 64;
+
+/// Creates a [`NodeRequest`] derived from `seed`. The `request_id` is the
+/// big-endian encoding of `seed`.
+#[must_use]
+pub fn node_request(seed: u64) -> NodeRequest {
+    NodeRequest {
+        request_id: seed.to_be_bytes().to_vec(),
+        payload: bytes(seed, PAYLOAD_LEN),
+        ..Default::default()
+    }
+}
+
+/// Creates a [`NodeResponse`] matching `req`.
+#[must_use]
+pub fn node_response(req: &NodeRequest) -> NodeResponse {
+    NodeResponse {
+        request_id: req.request_id.clone(),
+        ..Default::default()
+    }
+}
+
+/// Creates a [`NodeRequest`] with a `payload` of `size_bytes` bytes.
+#[must_use]
+pub fn large_request(size_bytes: usize) -> NodeRequest {
+    let seed = size_bytes as u64;
+    NodeRequest {
+        request_id: seed.to_be_bytes().to_vec(),
+        payload: bytes(seed, size_bytes),
+        ..Default::default()
+    }
+}
+
+/// Creates `count` [`NodeRequest`]s with distinct `request_id`s derived from
+/// `seed`.
+#[must_use]
+pub fn random_requests(count: usize, seed: u64) -> Vec<NodeRequest> {
+    let mut state = seed;
+    (0..count)
+        .map(|_| node_request(splitmix64(&mut state)))
+        .collect()
+}
+
+fn bytes(seed: u64, len: usize) -> Vec<u8> {
+    let mut state = seed;
+    let mut bytes = Vec::with_capacity(len + 8);
+    while bytes.len() < len {
+        bytes.extend_from_slice(&splitmix64(&mut state).to_le_bytes());
+    }
+    bytes.truncate(len);
+    bytes
+}
+
+fn splitmix64(state: &mut u64) -> u64 {
+    *state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    let mut z = *state;
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    #[test]
+    fn same_seed_same_data() {
+        assert_eq!(node_request(7), node_request(7));
+        assert_ne!(node_request(7).payload, node_request(8).payload);
+        assert_eq!(random_requests(16, 3), random_requests(16, 3));
+    }
+
+    #[test]
+    fn random_request_ids_are_distinct() {
+        let requests = random_requests(1000, 0);
+        let ids = requests
+            .iter()
+            .map(|request| &request.request_id)
+            .collect::<HashSet<_>>();
+        assert_eq!(ids.len(), requests.len());
+    }
+
+    #[test]
+    fn response_and_large_request_sizes() {
+        let request = large_request(1000);
+        assert_eq!(request.payload.len(), 1000);
+        assert_eq!(node_response(&request).request_id, request.request_id);
+    }
+}
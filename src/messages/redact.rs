@@ -0,0 +1,160 @@
+//! Log rendering of the messages without their payloads.
+//!
+//! The payloads carry secret shares, so `{message:?}` in a log leaks them, and
+//! bloats the log. [`redacted`] renders a message with its payload replaced
+//! by the length and a truncated SHA-256, enough to tell the payloads apart:
+//!
+//! ```text
+//! NodeRequest { request_id: 0a0b, payload: 1024 bytes sha256:3f2a9c01d4e5b6a7 }
+//! ```
+//!
+//! Setting the `MPC_CARRIER_WIRE_TRACE` environment variable to anything but
+//! `0` renders the messages in full instead, for debugging. It is read once.
+
+use super::{envelope, Envelope, NodeNotification, NodeRequest, NodeResponse};
+use ring::digest;
+use std::env;
+use std::fmt;
+use std::sync::OnceLock;
+
+/// Environment variable rendering the messages in full.
+pub const WIRE_TRACE_ENV: &str = "MPC_CARRIER_WIRE_TRACE";
+
+/// Bytes of the SHA-256 of a payload rendered.
+const HASH_LEN: usize = 8;
+
+/// Message rendered by [`redacted`].
+pub trait Redact: fmt::Debug {
+    /// Formats the message without its payload.
+    fn fmt_redacted(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result;
+}
+
+/// [`Display`](fmt::Display) of a message without its payload, returned by
+/// [`redacted`].
+pub struct Redacted<'a, M>(&'a M);
+
+/// Length and truncated hash of a payload.
+struct Payload<'a>(&'a [u8]);
+
+/// Hexadecimal rendering of an identifier.
+struct Hex<'a>(&'a [u8]);
+
+/// Renders `message` without its payload, or in full with the
+/// `MPC_CARRIER_WIRE_TRACE` environment variable set.
+pub fn redacted<M: Redact>(message: &M) -> Redacted<'_, M> {
+    Redacted(message)
+}
+
+fn wire_trace() -> bool {
+    static WIRE_TRACE: OnceLock<bool> = OnceLock::new();
+    *WIRE_TRACE.get_or_init(|| env::var_os(WIRE_TRACE_ENV).is_some_and(|value| value != "0"))
+}
+
+impl<M: Redact> fmt::Display for Redacted<'_, M> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if wire_trace() {
+            fmt::Debug::fmt(self.0, f)
+        } else {
+            self.0.fmt_redacted(f)
+        }
+    }
+}
+
+impl<M: Redact> fmt::Debug for Redacted<'_, M> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+impl Redact for NodeRequest {
+    fn fmt_redacted(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "NodeRequest {{ request_id: {}", Hex(&self.request_id))?;
+        if self.request_seq != 0 {
+            write!(f, ", request_seq: {}", self.request_seq)?;
+        }
+        if let Some(session_id) = self.session_id {
+            write!(f, ", session_id: {session_id}")?;
+        }
+        if let Some(epoch) = self.epoch {
+            write!(f, ", epoch: {epoch}")?;
+        }
+        if self.priority != 0 {
+            write!(f, ", priority: {}", self.priority)?;
+        }
+        if !self.service.is_empty() {
+            write!(f, ", service: {:?}", self.service)?;
+        }
+        write!(f, ", payload: {} }}", Payload(&self.payload))
+    }
+}
+
+impl Redact for NodeResponse {
+    fn fmt_redacted(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "NodeResponse {{ request_id: {}", Hex(&self.request_id))?;
+        if self.request_seq != 0 {
+            write!(f, ", request_seq: {}", self.request_seq)?;
+        }
+        write!(f, ", status: {}", self.status)?;
+        if !self.error_detail.is_empty() {
+            write!(f, ", error_detail: {:?}", self.error_detail)?;
+        }
+        if !self.payload.is_empty() {
+            write!(f, ", payload: {}", Payload(&self.payload))?;
+        }
+        f.write_str(" }")
+    }
+}
+
+impl Redact for NodeNotification {
+    fn fmt_redacted(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("NodeNotification { ")?;
+        if self.ack_id != 0 {
+            write!(f, "ack_id: {:016x}, ", self.ack_id)?;
+        }
+        write!(f, "payload: {} }}", Payload(&self.payload))
+    }
+}
+
+impl Redact for Envelope {
+    fn fmt_redacted(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Envelope { ")?;
+        if self.seq != 0 {
+            write!(f, "seq: {}, ", self.seq)?;
+        }
+        match &self.kind {
+            Some(envelope::Kind::Request(request)) => request.fmt_redacted(f)?,
+            Some(envelope::Kind::Response(response)) => response.fmt_redacted(f)?,
+            Some(envelope::Kind::Notification(notification)) => notification.fmt_redacted(f)?,
+            Some(
+                kind @ (envelope::Kind::Heartbeat(_)
+                | envelope::Kind::Cancel(_)
+                | envelope::Kind::Ack(_)
+                | envelope::Kind::Backpressure(_)),
+            ) => {
+                write!(f, "{kind:?}")?;
+            }
+            None => f.write_str("unknown kind")?,
+        }
+        write!(f, ", compression: {:?} }}", self.compression())
+    }
+}
+
+impl fmt::Display for Payload<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} bytes", self.0.len())?;
+        if !self.0.is_empty() {
+            let hash = digest::digest(&digest::SHA256, self.0);
+            write!(f, " sha256:{}", Hex(&hash.as_ref()[..HASH_LEN]))?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Hex<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.0.is_empty() {
+            return f.write_str("\"\"");
+        }
+        self.0.iter().try_for_each(|byte| write!(f, "{byte:02x}"))
+    }
+}
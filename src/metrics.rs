@@ -0,0 +1,141 @@
+//! Export of the statistics through the [`metrics`](::metrics) facade, with
+//! the `metrics` feature.
+//!
+//! A running carrier publishes the statistics of
+//! [`Carrier::debug_state`](crate::Carrier::debug_state) and
+//! [`Carrier::losses`](crate::Carrier::losses) every [`PUBLISH_INTERVAL`], and
+//! records the response latencies as they are measured, to the recorder
+//! installed by the application. Every series is labeled with the `node`:
+//!
+//! - `mpc_carrier_connections`, gauge: open connections with the node.
+//! - `mpc_carrier_queue_length`, gauge, by `direction`: requests from the
+//!   node not yet taken from [`Incoming`](crate::channels::Incoming)
+//!   (`incoming`), and messages to the node not yet written (`outgoing`).
+//! - `mpc_carrier_inflight_requests`, gauge: requests awaiting a response.
+//! - `mpc_carrier_unacked_notifications`, gauge: acknowledged notifications
+//!   awaiting their acknowledgements.
+//! - `mpc_carrier_bytes_total`, counter, by `direction`: bytes read from
+//!   (`incoming`) and written to (`outgoing`) the connections.
+//! - `mpc_carrier_auth_failures_total`, counter: messages failing the
+//!   authentication.
+//! - `mpc_carrier_sequence_gaps_total`, counter: gaps in the sequence
+//!   numbers of the received envelopes.
+//! - `mpc_carrier_losses_total`, counter, by `direction` and `reason`:
+//!   messages discarded. See [`loss`](crate::loss).
+//! - `mpc_carrier_rtt_seconds`, gauge: estimated round-trip time, once
+//!   sampled.
+//! - `mpc_carrier_network_rtt_seconds`, gauge: estimated round-trip time of
+//!   the network, without the handling of the requests, once sampled on the
+//!   current connection. See
+//!   [`CarrierHandle::rtt`](crate::control::CarrierHandle::rtt).
+//! - `mpc_carrier_request_duration_seconds`, histogram: time from writing a
+//!   request until receiving its response.
+//! - `mpc_carrier_reconnects_total`, counter: connections established after
+//!   the first, the node having gone down meanwhile. See
+//!   [`churn`](crate::churn).
+//! - `mpc_carrier_downtime_seconds`, gauge: time without a connection in the
+//!   last hour.
+//! - `mpc_carrier_flapping`, gauge: 1 while the node is flapping, 0 otherwise.
+//! - `mpc_carrier_connection_lifetime_seconds`, histogram: time from
+//!   establishing a connection until closing it.
+//! - `mpc_carrier_errors_total`, counter, by `kind`: errors, labeled with the
+//!   `node` only if they have one. See [`errors`](crate::errors).
+
+use crate::config::NodeId;
+use crate::errors::ErrorAccounting;
+use crate::loss::LossAccounting;
+use crate::middleware::Direction;
+use crate::stats::{DebugState, Stats};
+use ::metrics::{counter, gauge, histogram};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::time;
+
+/// Interval between two publications of the statistics.
+pub const PUBLISH_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Publishes the statistics every [`PUBLISH_INTERVAL`], forever.
+pub(crate) async fn publish(stats: Stats, errors: ErrorAccounting) {
+    let mut interval = time::interval(PUBLISH_INTERVAL);
+    interval.set_missed_tick_behavior(time::MissedTickBehavior::Delay);
+    loop {
+        interval.tick().await;
+        publish_once(&stats, &errors);
+    }
+}
+
+/// Records the response latency of a request to `node`.
+pub(crate) fn record_latency(node: &NodeId, latency: Duration) {
+    histogram!("mpc_carrier_request_duration_seconds", "node" => node.to_string()).record(latency);
+}
+
+/// Records the lifetime of a connection with `node`, on its closing.
+pub(crate) fn record_lifetime(node: &NodeId, lifetime: Duration) {
+    histogram!("mpc_carrier_connection_lifetime_seconds", "node" => node.to_string())
+        .record(lifetime);
+}
+
+#[allow(clippy::cast_precision_loss)]
+fn publish_once(stats: &Stats, errors: &ErrorAccounting) {
+    for (node, state) in DebugState::new(stats).nodes {
+        gauge!("mpc_carrier_connections", "node" => node.clone()).set(state.connections as f64);
+        let queues = [
+            (Direction::Incoming, state.incoming_queue),
+            (Direction::Outgoing, state.outgoing_queue),
+        ];
+        for (direction, len) in queues {
+            let labels = [
+                ("node", node.clone()),
+                ("direction", direction.as_str().to_string()),
+            ];
+            gauge!("mpc_carrier_queue_length", &labels).set(len as f64);
+        }
+        gauge!("mpc_carrier_inflight_requests", "node" => node.clone()).set(state.inflight as f64);
+        gauge!("mpc_carrier_unacked_notifications", "node" => node.clone())
+            .set(state.unacked_notifications as f64);
+        let bytes = [
+            (Direction::Incoming, state.bytes_received),
+            (Direction::Outgoing, state.bytes_sent),
+        ];
+        for (direction, bytes) in bytes {
+            let labels = [
+                ("node", node.clone()),
+                ("direction", direction.as_str().to_string()),
+            ];
+            counter!("mpc_carrier_bytes_total", &labels).absolute(bytes);
+        }
+        counter!("mpc_carrier_auth_failures_total", "node" => node.clone())
+            .absolute(state.auth_failures);
+        counter!("mpc_carrier_sequence_gaps_total", "node" => node.clone())
+            .absolute(state.sequence_gaps);
+        counter!("mpc_carrier_reconnects_total", "node" => node.clone()).absolute(state.reconnects);
+        gauge!("mpc_carrier_downtime_seconds", "node" => node.clone())
+            .set(state.downtime_last_hour.as_secs_f64());
+        gauge!("mpc_carrier_flapping", "node" => node.clone())
+            .set(f64::from(u8::from(state.flapping)));
+        if let Some(rtt) = state.network_rtt {
+            gauge!("mpc_carrier_network_rtt_seconds", "node" => node.clone()).set(rtt.smoothed);
+        }
+        if let Some(rtt) = state.rtt {
+            gauge!("mpc_carrier_rtt_seconds", "node" => node).set(rtt);
+        }
+    }
+    for loss in LossAccounting::new(Arc::clone(stats)).snapshot() {
+        let labels = [
+            ("node", loss.node.to_string()),
+            ("direction", loss.direction.as_str().to_string()),
+            ("reason", loss.reason.as_str().to_string()),
+        ];
+        counter!("mpc_carrier_losses_total", &labels).absolute(loss.count);
+    }
+    for count in errors.snapshot() {
+        let kind = ("kind", count.kind.as_str().to_string());
+        match count.node {
+            Some(node) => {
+                let labels = [("node", node.to_string()), kind];
+                counter!("mpc_carrier_errors_total", &labels).absolute(count.count);
+            }
+            None => counter!("mpc_carrier_errors_total", &[kind]).absolute(count.count),
+        }
+    }
+}
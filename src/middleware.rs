@@ -0,0 +1,856 @@
+//! [`EventBus`] middleware.
+//!
+//! Middleware is installed with [`Carrier::wrap_bus`](crate::Carrier::wrap_bus).
+
+use crate::bus::{BusError, EventBus};
+use crate::channels::{Outgoing, SendError};
+use crate::config::NodeId;
+use crate::messages::{NodeRequest, NodeResponse};
+use crate::node::MAX_LEN;
+use crate::status;
+use crate::{spawn_named, NodeCallback};
+use futures::channel::{mpsc, oneshot};
+use futures::future::BoxFuture;
+use futures::prelude::*;
+use rand::rngs::SmallRng;
+use rand::{Rng, SeedableRng};
+use serde::Serialize;
+use std::collections::{HashMap, VecDeque};
+use std::fmt::{self, Write};
+use std::mem;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use thiserror::Error;
+use tokio::time::{sleep, timeout};
+use tracing::debug;
+
+/// Window over which a [`LogSampler`] reservoir logs its minimum of requests.
+const RESERVOIR_WINDOW: Duration = Duration::from_secs(1);
+
+/// Direction of a [`SniffedMessage`] relative to the local node.
+#[allow(missing_docs)]
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Debug, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Direction {
+    Incoming,
+    Outgoing,
+}
+
+impl Direction {
+    /// Returns the direction as serialized and labeled in the metrics.
+    #[must_use]
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Incoming => "incoming",
+            Self::Outgoing => "outgoing",
+        }
+    }
+}
+
+/// A request observed by a [`Sniffer`].
+#[derive(Clone, Debug)]
+pub struct SniffedMessage {
+    /// Remote node.
+    pub node: NodeId,
+    /// Time the request was observed.
+    pub timestamp: Instant,
+    /// Direction of the request.
+    pub direction: Direction,
+    /// The request itself.
+    pub request: NodeRequest,
+}
+
+/// Middleware copying every dispatched request to a channel, for protocol
+/// debugging.
+///
+/// The copies are sent with [`mpsc::Sender::try_send`], so a slow consumer
+/// never delays the requests. Copies which don't fit into the channel are
+/// dropped.
+#[derive(Clone)]
+pub struct Sniffer {
+    inner: Box<dyn EventBus>,
+    tx: mpsc::Sender<SniffedMessage>,
+}
+
+impl Sniffer {
+    /// Creates a new [`Sniffer`] forwarding the requests to `inner`, and their
+    /// copies to `tx`.
+    #[must_use]
+    pub fn new(inner: Box<dyn EventBus>, tx: mpsc::Sender<SniffedMessage>) -> Self {
+        Self { inner, tx }
+    }
+
+    /// Records a copy of `request`. The incoming requests are recorded
+    /// automatically, this method allows adding the outgoing ones to the same
+    /// stream through a clone of the sniffer.
+    pub fn record(&mut self, node: &NodeId, direction: Direction, request: &NodeRequest) {
+        if self.tx.is_closed() {
+            return;
+        }
+        let message = SniffedMessage {
+            node: node.clone(),
+            timestamp: Instant::now(),
+            direction,
+            request: request.clone(),
+        };
+        let _ = self.tx.try_send(message);
+    }
+}
+
+impl EventBus for Sniffer {
+    fn dispatch<'a>(
+        &'a mut self,
+        node: &'a NodeId,
+        callback: NodeCallback,
+    ) -> BoxFuture<'a, Result<(), BusError>> {
+        self.record(node, Direction::Incoming, &callback.message);
+        self.inner.dispatch(node, callback)
+    }
+}
+
+/// Middleware logging a sample of the requests and their responses at the
+/// `DEBUG` level, to keep the log readable at high throughput.
+///
+/// Every request is logged with the probability `rate`, drawn independently
+/// for each node. With a [`reservoir`](Self::reservoir), the requests of each
+/// node not picked by the rate are also sampled uniformly into a reservoir
+/// over one-second windows, and enough of them are logged at the end of a
+/// window to reach the reservoir size. Only the requests picked by the rate
+/// have their responses logged. The payloads are
+/// [redacted](crate::messages::redacted).
+#[derive(Clone)]
+pub struct LogSampler {
+    inner: Box<dyn EventBus>,
+    rate: f64,
+    state: Arc<Mutex<SamplerState>>,
+}
+
+/// Random source and windows of a [`LogSampler`], shared by its clones.
+struct SamplerState {
+    rng: SmallRng,
+    reservoir_size: usize,
+    windows: HashMap<NodeId, Window>,
+}
+
+/// One-second window of the requests of a node.
+struct Window {
+    start: Instant,
+    /// Number of the requests logged by the rate.
+    logged: usize,
+    /// Number of the requests offered to the reservoir.
+    seen: usize,
+    reservoir: Vec<String>,
+}
+
+impl LogSampler {
+    /// Creates a new [`LogSampler`] forwarding the requests to `inner`, and
+    /// logging each with the probability `rate`, from 0 to 1.
+    #[must_use]
+    pub fn new(inner: Box<dyn EventBus>, rate: f64) -> Self {
+        Self {
+            inner,
+            rate: rate.clamp(0.0, 1.0),
+            state: Arc::new(Mutex::new(SamplerState {
+                rng: SmallRng::from_entropy(),
+                reservoir_size: 0,
+                windows: HashMap::new(),
+            })),
+        }
+    }
+
+    /// Logs at least `n` requests per second of every node with traffic,
+    /// whatever the rate. The requests sampled into the reservoir of a window
+    /// are logged once the next request of the node starts a new window.
+    #[must_use]
+    pub fn reservoir(self, n: usize) -> Self {
+        self.state.lock().unwrap().reservoir_size = n;
+        self
+    }
+}
+
+impl SamplerState {
+    /// Returns whether a request of `node` is logged right away, and offers
+    /// it to the reservoir otherwise.
+    fn sample(&mut self, node: &NodeId, rate: f64, request: &NodeRequest) -> bool {
+        let Self {
+            rng,
+            reservoir_size,
+            windows,
+        } = self;
+        let sampled = rng.gen_bool(rate);
+        if *reservoir_size == 0 {
+            return sampled;
+        }
+        let window = windows.entry(node.clone()).or_insert_with(Window::new);
+        if window.start.elapsed() >= RESERVOIR_WINDOW {
+            window.flush(node, *reservoir_size);
+            *window = Window::new();
+        }
+        if sampled {
+            window.logged += 1;
+            return true;
+        }
+        window.seen += 1;
+        let rendered = || crate::messages::redacted(request).to_string();
+        if window.reservoir.len() < *reservoir_size {
+            window.reservoir.push(rendered());
+        } else {
+            let index = rng.gen_range(0..window.seen);
+            if index < *reservoir_size {
+                window.reservoir[index] = rendered();
+            }
+        }
+        false
+    }
+}
+
+impl Window {
+    fn new() -> Self {
+        Self {
+            start: Instant::now(),
+            logged: 0,
+            seen: 0,
+            reservoir: Vec::new(),
+        }
+    }
+
+    /// Logs the reservoir requests missing to reach `reservoir_size`.
+    fn flush(&mut self, node: &NodeId, reservoir_size: usize) {
+        let missing = reservoir_size.saturating_sub(self.logged);
+        for request in self.reservoir.drain(..).take(missing) {
+            debug!(node = %node, "Sampled request: {request}");
+        }
+    }
+}
+
+impl EventBus for LogSampler {
+    fn dispatch<'a>(
+        &'a mut self,
+        node: &'a NodeId,
+        mut callback: NodeCallback,
+    ) -> BoxFuture<'a, Result<(), BusError>> {
+        let sampled = self
+            .state
+            .lock()
+            .unwrap()
+            .sample(node, self.rate, &callback.message);
+        if sampled {
+            let redacted = crate::messages::redacted(&callback.message);
+            debug!(node = %node, "Sampled request: {redacted}");
+            let (tx, rx) = oneshot::channel();
+            let responder = mem::replace(&mut callback.callback, tx);
+            spawn_named(
+                "carrier-log-sampler",
+                log_response(node.clone(), rx, responder),
+            );
+        }
+        self.inner.dispatch(node, callback)
+    }
+}
+
+/// Logs the response of a sampled request on its way to `responder`, and
+/// drops it if `responder` is canceled.
+async fn log_response(
+    node: NodeId,
+    rx: oneshot::Receiver<NodeResponse>,
+    mut responder: oneshot::Sender<NodeResponse>,
+) {
+    let response = match future::select(rx, responder.cancellation()).await {
+        future::Either::Left((Ok(response), _)) => response,
+        future::Either::Left((Err(oneshot::Canceled), _)) | future::Either::Right(_) => return,
+    };
+    let redacted = crate::messages::redacted(&response);
+    debug!(node = %node, "Sampled response: {redacted}");
+    let _ = responder.send(response);
+}
+
+/// Which request a [`LoadShedder`] drops when its queue is full.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum ShedPolicy {
+    /// Drop the request being dispatched.
+    #[default]
+    DropNewest,
+    /// Drop the request which waited in the queue for the longest.
+    DropOldest,
+}
+
+/// Middleware bounding the number of requests waiting to be consumed from the
+/// [`Incoming`](crate::channels::Incoming) channels.
+///
+/// The requests are queued by the shedder and forwarded to the inner bus by a
+/// background task. When the queue holds `max_queue_depth` requests, a request
+/// is dropped according to the [`ShedPolicy`], and answered with the
+/// [`status::OVERLOADED`] status.
+#[derive(Clone)]
+pub struct LoadShedder {
+    queue: Arc<Mutex<VecDeque<(NodeId, NodeCallback)>>>,
+    wake: mpsc::UnboundedSender<()>,
+    max_queue_depth: usize,
+    policy: ShedPolicy,
+    shed_count: Arc<AtomicU64>,
+}
+
+impl LoadShedder {
+    /// Creates a new [`LoadShedder`] forwarding the requests to `inner`.
+    ///
+    /// # Panics
+    ///
+    /// If called outside of a Tokio runtime.
+    #[must_use]
+    pub fn new(inner: Box<dyn EventBus>, max_queue_depth: usize, policy: ShedPolicy) -> Self {
+        let queue = Arc::new(Mutex::new(VecDeque::new()));
+        let (wake, woken) = mpsc::unbounded();
+        spawn_named(
+            "carrier-load-shedder",
+            forward(Arc::clone(&queue), woken, inner),
+        );
+        Self {
+            queue,
+            wake,
+            max_queue_depth,
+            policy,
+            shed_count: Arc::new(AtomicU64::new(0)),
+        }
+    }
+
+    /// Counts the dropped requests into `counter`, for reading the metric
+    /// after the shedder is moved into the carrier.
+    #[must_use]
+    pub fn shed_counter(mut self, counter: Arc<AtomicU64>) -> Self {
+        self.shed_count = counter;
+        self
+    }
+
+    /// Returns the number of the dropped requests.
+    #[must_use]
+    pub fn shed_count(&self) -> u64 {
+        self.shed_count.load(Ordering::Relaxed)
+    }
+}
+
+impl EventBus for LoadShedder {
+    fn dispatch<'a>(
+        &'a mut self,
+        node: &'a NodeId,
+        callback: NodeCallback,
+    ) -> BoxFuture<'a, Result<(), BusError>> {
+        let request = (node.clone(), callback);
+        let mut queue = self.queue.lock().unwrap();
+        let shed = if queue.len() < self.max_queue_depth {
+            queue.push_back(request);
+            None
+        } else if self.policy == ShedPolicy::DropOldest && !queue.is_empty() {
+            let oldest = queue.pop_front();
+            queue.push_back(request);
+            oldest
+        } else {
+            Some(request)
+        };
+        drop(queue);
+        if let Some((node, callback)) = shed {
+            self.shed_count.fetch_add(1, Ordering::Relaxed);
+            debug!("Shed a request from {node}");
+            let _ = callback.respond_err(status::OVERLOADED, "request shed under load");
+        }
+        let _ = self.wake.unbounded_send(());
+        future::ready(Ok(())).boxed()
+    }
+}
+
+/// Forwards the queued requests to `inner` until every clone of the shedder is
+/// dropped.
+async fn forward(
+    queue: Arc<Mutex<VecDeque<(NodeId, NodeCallback)>>>,
+    mut woken: mpsc::UnboundedReceiver<()>,
+    mut inner: Box<dyn EventBus>,
+) {
+    while woken.next().await.is_some() {
+        loop {
+            let Some((node, callback)) = queue.lock().unwrap().pop_front() else {
+                break;
+            };
+            if let Err(err) = inner.dispatch(&node, callback).await {
+                debug!("Request from {node} not forwarded: {err}");
+            }
+        }
+    }
+}
+
+/// Rules checked by a [`Validator`] and by
+/// [`Outgoing::set_validator`](crate::channels::Outgoing::set_validator).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ValidatorConfig {
+    /// Maximum length of `request_id`. Defaults to 64 bytes.
+    pub max_request_id_len: usize,
+    /// Whether an empty `payload` is rejected. Defaults to `true`.
+    pub require_payload: bool,
+    /// Maximum encoded size of the request. Defaults to the maximum frame
+    /// size of the connections.
+    pub max_message_len: usize,
+}
+
+/// A [`ValidatorConfig`] rule violated by a request.
+#[allow(missing_docs)]
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ValidationError {
+    #[error("empty request_id")]
+    EmptyRequestId,
+    #[error("request_id of {0} bytes is too long")]
+    RequestIdTooLong(usize),
+    #[error("empty payload")]
+    EmptyPayload,
+    #[error("request of {0} bytes is too large")]
+    TooLarge(usize),
+}
+
+/// Middleware rejecting the malformed requests before they reach the
+/// [`Incoming`](crate::channels::Incoming) channels.
+///
+/// A rejected request is answered right away with the [`status::INVALID`]
+/// status, and an `error_detail` describing the violated rule.
+#[derive(Clone)]
+pub struct Validator {
+    inner: Box<dyn EventBus>,
+    config: ValidatorConfig,
+}
+
+impl Default for ValidatorConfig {
+    fn default() -> Self {
+        Self {
+            max_request_id_len: 64,
+            require_payload: true,
+            max_message_len: MAX_LEN,
+        }
+    }
+}
+
+impl ValidatorConfig {
+    /// Checks `request` against the rules.
+    pub fn validate(&self, request: &NodeRequest) -> Result<(), ValidationError> {
+        let request_id_len = request.request_id.len();
+        if request_id_len == 0 {
+            return Err(ValidationError::EmptyRequestId);
+        }
+        if request_id_len > self.max_request_id_len {
+            return Err(ValidationError::RequestIdTooLong(request_id_len));
+        }
+        if self.require_payload && request.payload.is_empty() {
+            return Err(ValidationError::EmptyPayload);
+        }
+        let len = prost::Message::encoded_len(request);
+        if len > self.max_message_len {
+            return Err(ValidationError::TooLarge(len));
+        }
+        Ok(())
+    }
+}
+
+impl Validator {
+    /// Creates a new [`Validator`] forwarding the valid requests to `inner`.
+    #[must_use]
+    pub fn new(inner: Box<dyn EventBus>, config: ValidatorConfig) -> Self {
+        Self { inner, config }
+    }
+}
+
+impl EventBus for Validator {
+    fn dispatch<'a>(
+        &'a mut self,
+        node: &'a NodeId,
+        callback: NodeCallback,
+    ) -> BoxFuture<'a, Result<(), BusError>> {
+        let Err(err) = self.config.validate(&callback.message) else {
+            return self.inner.dispatch(node, callback);
+        };
+        debug!("Rejected a request from {node}: {err}");
+        let _ = callback.respond_err(status::INVALID, err.to_string());
+        future::ready(Ok(())).boxed()
+    }
+}
+
+/// Current epoch of the protocol, shared by its clones.
+///
+/// Set on an [`Outgoing`] with [`Outgoing::set_epoch`] to stamp the sent
+/// requests, and on an [`EpochFilter`] to accept the received requests of the
+/// same epoch only.
+#[derive(Clone, Debug, Default)]
+pub struct Epoch(Arc<AtomicU64>);
+
+/// Middleware rejecting the requests of other sessions or epochs before they
+/// reach the [`Incoming`](crate::channels::Incoming) channels.
+///
+/// A rejected request is answered right away with the
+/// [`status::EPOCH_MISMATCH`] status. A request is checked once it is received,
+/// so the requests sent before the epoch is bumped on both ends are rejected as
+/// a whole.
+#[derive(Clone)]
+pub struct EpochFilter {
+    inner: Box<dyn EventBus>,
+    accept: Arc<dyn Fn(Option<u64>, Option<u64>) -> bool + Send + Sync>,
+}
+
+impl Epoch {
+    /// Creates a new [`Epoch`] starting at `epoch`.
+    #[must_use]
+    pub fn new(epoch: u64) -> Self {
+        Self(Arc::new(AtomicU64::new(epoch)))
+    }
+
+    /// Returns the current epoch.
+    #[must_use]
+    pub fn get(&self) -> u64 {
+        self.0.load(Ordering::Acquire)
+    }
+
+    /// Sets the current epoch.
+    pub fn set(&self, epoch: u64) {
+        self.0.store(epoch, Ordering::Release);
+    }
+
+    /// Moves to the next epoch, and returns it.
+    #[allow(clippy::must_use_candidate)]
+    pub fn bump(&self) -> u64 {
+        self.0.fetch_add(1, Ordering::AcqRel) + 1
+    }
+}
+
+impl EpochFilter {
+    /// Creates a new [`EpochFilter`] forwarding to `inner` the requests of the
+    /// current `epoch`. The requests without an epoch are rejected.
+    #[must_use]
+    pub fn new(inner: Box<dyn EventBus>, epoch: Epoch) -> Self {
+        Self::with_predicate(inner, move |_, request_epoch| {
+            request_epoch == Some(epoch.get())
+        })
+    }
+
+    /// Creates a new [`EpochFilter`] forwarding to `inner` the requests for
+    /// which `accept` returns `true`, given their `session_id` and `epoch`.
+    #[must_use]
+    pub fn with_predicate(
+        inner: Box<dyn EventBus>,
+        accept: impl Fn(Option<u64>, Option<u64>) -> bool + Send + Sync + 'static,
+    ) -> Self {
+        Self {
+            inner,
+            accept: Arc::new(accept),
+        }
+    }
+}
+
+impl EventBus for EpochFilter {
+    fn dispatch<'a>(
+        &'a mut self,
+        node: &'a NodeId,
+        callback: NodeCallback,
+    ) -> BoxFuture<'a, Result<(), BusError>> {
+        let NodeRequest {
+            session_id, epoch, ..
+        } = callback.message;
+        if (self.accept)(session_id, epoch) {
+            return self.inner.dispatch(node, callback);
+        }
+        let show = |value: Option<u64>| value.map_or_else(|| "none".to_string(), |v| v.to_string());
+        let detail = format!(
+            "session {} epoch {} not accepted",
+            show(session_id),
+            show(epoch)
+        );
+        debug!("Rejected a request from {node}: {detail}");
+        let _ = callback.respond_err(status::EPOCH_MISMATCH, detail);
+        future::ready(Ok(())).boxed()
+    }
+}
+
+/// Exponential backoff between the attempts of a [`RetryOutgoing`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BackoffConfig {
+    /// Delay before the first retry. Defaults to 100 milliseconds.
+    pub initial: Duration,
+    /// Upper bound of the delay. Defaults to 5 seconds.
+    pub max: Duration,
+    /// Factor the delay grows by after each retry. Defaults to 2.
+    pub multiplier: u32,
+}
+
+/// Statistics of a [`RetryOutgoing`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct RetryStats {
+    /// Retries made across all the calls.
+    pub total_retries: u64,
+    /// Most retries made within a single call.
+    pub max_retries: u64,
+    /// Calls which succeeded after at least one retry.
+    pub success_after_retry: u64,
+}
+
+/// Wrapper of [`Outgoing`] which transparently retries the failed sends.
+///
+/// A send is retried on [`SendError::ForwardClosed`], and on
+/// [`SendError::Timeout`] when an attempt timeout is set. The request may
+/// thus be delivered more than once, so it has to be idempotent.
+pub struct RetryOutgoing {
+    outgoing: Outgoing,
+    max_attempts: usize,
+    backoff: BackoffConfig,
+    attempt_timeout: Option<Duration>,
+    stats: RetryStats,
+}
+
+impl Default for BackoffConfig {
+    fn default() -> Self {
+        Self {
+            initial: Duration::from_millis(100),
+            max: Duration::from_secs(5),
+            multiplier: 2,
+        }
+    }
+}
+
+impl RetryOutgoing {
+    /// Creates a new [`RetryOutgoing`] making at most `max_attempts` attempts
+    /// per call, the first one included.
+    ///
+    /// # Panics
+    ///
+    /// If `max_attempts` is zero.
+    #[must_use]
+    pub fn new(outgoing: Outgoing, max_attempts: usize, backoff: BackoffConfig) -> Self {
+        assert!(max_attempts > 0, "at least one attempt is required");
+        Self {
+            outgoing,
+            max_attempts,
+            backoff,
+            attempt_timeout: None,
+            stats: RetryStats::default(),
+        }
+    }
+
+    /// Fails an attempt with [`SendError::Timeout`] if the response doesn't
+    /// arrive within `attempt_timeout`. Unset by default.
+    #[must_use]
+    pub fn attempt_timeout(mut self, attempt_timeout: Duration) -> Self {
+        self.attempt_timeout = Some(attempt_timeout);
+        self
+    }
+
+    /// Sends a request `message` to `node` and awaits for the response,
+    /// retrying the failed attempts.
+    ///
+    /// # Panics
+    ///
+    /// If `node` was not configured in [`Carrier::new`](crate::Carrier::new).
+    pub async fn send(
+        &mut self,
+        node: impl Into<NodeId>,
+        message: NodeRequest,
+    ) -> Result<NodeResponse, SendError> {
+        let node = node.into();
+        let mut delay = self.backoff.initial;
+        let mut retries = 0;
+        loop {
+            let result = self.attempt(&node, message.clone()).await;
+            let retry = matches!(
+                result,
+                Err(SendError::ForwardClosed(_) | SendError::Timeout)
+            );
+            if !retry || retries + 1 >= self.max_attempts as u64 {
+                if result.is_ok() && retries > 0 {
+                    self.stats.success_after_retry += 1;
+                }
+                return result;
+            }
+            debug!("Retrying a request to {node}: {}", result.unwrap_err());
+            sleep(delay).await;
+            delay = (delay * self.backoff.multiplier).min(self.backoff.max);
+            retries += 1;
+            self.stats.total_retries += 1;
+            self.stats.max_retries = self.stats.max_retries.max(retries);
+        }
+    }
+
+    /// Returns the retry statistics.
+    #[must_use]
+    pub fn stats(&self) -> RetryStats {
+        self.stats
+    }
+
+    /// Returns the wrapped [`Outgoing`].
+    #[must_use]
+    pub fn into_inner(self) -> Outgoing {
+        self.outgoing
+    }
+
+    async fn attempt(
+        &mut self,
+        node: &NodeId,
+        message: NodeRequest,
+    ) -> Result<NodeResponse, SendError> {
+        let send = self.outgoing.send(node, message);
+        match self.attempt_timeout {
+            Some(attempt_timeout) => timeout(attempt_timeout, send)
+                .await
+                .map_err(|_| SendError::Timeout)?,
+            None => send.await,
+        }
+    }
+}
+
+impl SniffedMessage {
+    /// Renders `resp` in the same format as the requests are displayed, for
+    /// matching the responses with the sniffed requests.
+    #[must_use]
+    pub fn decode_response(resp: &NodeResponse) -> String {
+        format!("response request_id={}", hex(&resp.request_id))
+    }
+}
+
+impl fmt::Display for SniffedMessage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let direction = match self.direction {
+            Direction::Incoming => "from",
+            Direction::Outgoing => "to",
+        };
+        write!(
+            f,
+            "request {direction} {} request_id={} payload={} bytes",
+            self.node,
+            hex(&self.request.request_id),
+            self.request.payload.len(),
+        )
+    }
+}
+
+pub(crate) fn hex(bytes: &[u8]) -> String {
+    bytes.iter().fold(String::new(), |mut s, byte| {
+        let _ = write!(s, "{byte:02x}");
+        s
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::messages::fixtures;
+
+    const DEPTH: usize = 10;
+
+    /// Bus keeping the requests without answering them.
+    #[derive(Clone, Default)]
+    struct Stalled(Arc<Mutex<Vec<NodeCallback>>>);
+
+    impl EventBus for Stalled {
+        fn dispatch<'a>(
+            &'a mut self,
+            _node: &'a NodeId,
+            callback: NodeCallback,
+        ) -> BoxFuture<'a, Result<(), BusError>> {
+            self.0.lock().unwrap().push(callback);
+            future::ready(Ok(())).boxed()
+        }
+    }
+
+    fn callback(message: NodeRequest) -> (NodeCallback, oneshot::Receiver<NodeResponse>) {
+        let (callback, response) = oneshot::channel();
+        (NodeCallback { message, callback }, response)
+    }
+
+    /// Dispatches twice `DEPTH` requests before the shedder forwards any, and
+    /// returns whether each one was shed.
+    async fn flood(policy: ShedPolicy) -> (LoadShedder, Vec<bool>) {
+        let mut shedder = LoadShedder::new(Box::new(Stalled::default()), DEPTH, policy);
+        let node = NodeId::from("a");
+        let mut responses = Vec::new();
+        for seed in 0..2 * DEPTH as u64 {
+            let (callback, response) = callback(fixtures::node_request(seed));
+            shedder.dispatch(&node, callback).await.unwrap();
+            responses.push(response);
+        }
+        let shed = responses
+            .into_iter()
+            .map(|mut response| match response.try_recv().unwrap() {
+                Some(response) => response.status == status::OVERLOADED,
+                None => false,
+            })
+            .collect();
+        (shedder, shed)
+    }
+
+    #[tokio::test]
+    async fn drop_newest_sheds_overflow() {
+        let (shedder, shed) = flood(ShedPolicy::DropNewest).await;
+        assert_eq!(shedder.shed_count(), DEPTH as u64);
+        assert!(shed[..DEPTH].iter().all(|shed| !shed));
+        assert!(shed[DEPTH..].iter().all(|shed| *shed));
+    }
+
+    #[tokio::test]
+    async fn drop_oldest_sheds_first_queued() {
+        let (shedder, shed) = flood(ShedPolicy::DropOldest).await;
+        assert_eq!(shedder.shed_count(), DEPTH as u64);
+        assert!(shed[..DEPTH].iter().all(|shed| *shed));
+        assert!(shed[DEPTH..].iter().all(|shed| !shed));
+    }
+
+    #[tokio::test]
+    async fn validator_rejects_malformed_requests() {
+        let config = ValidatorConfig {
+            max_message_len: 1024,
+            ..ValidatorConfig::default()
+        };
+        let inner = Stalled::default();
+        let mut validator = Validator::new(Box::new(inner.clone()), config);
+        let node = NodeId::from("a");
+        let valid = (0..5).map(fixtures::node_request);
+        let invalid = [
+            (Vec::new(), vec![1]),
+            (vec![1; 65], vec![1]),
+            (vec![1], Vec::new()),
+            (vec![1], vec![0; 2048]),
+            (Vec::new(), Vec::new()),
+        ]
+        .into_iter()
+        .map(|(request_id, payload)| NodeRequest {
+            request_id,
+            payload,
+            ..NodeRequest::default()
+        });
+        let mut rejected = 0;
+        for request in valid.zip(invalid).flat_map(|(a, b)| [a, b]) {
+            let (callback, mut response) = callback(request);
+            validator.dispatch(&node, callback).await.unwrap();
+            if let Ok(Some(response)) = response.try_recv() {
+                assert_eq!(response.status, status::INVALID);
+                rejected += 1;
+            }
+        }
+        assert_eq!(rejected, 5);
+        let forwarded = inner.0.lock().unwrap();
+        assert_eq!(forwarded.len(), 5);
+        assert!(forwarded
+            .iter()
+            .all(|callback| config.validate(&callback.message).is_ok()));
+    }
+
+    #[test]
+    fn validation_errors_name_rule() {
+        let config = ValidatorConfig::default();
+        let request = NodeRequest {
+            request_id: vec![1; 65],
+            payload: vec![1],
+            ..NodeRequest::default()
+        };
+        let err = config.validate(&request).unwrap_err();
+        assert_eq!(err, ValidationError::RequestIdTooLong(65));
+        let request = NodeRequest {
+            request_id: vec![1],
+            ..NodeRequest::default()
+        };
+        assert_eq!(
+            config.validate(&request),
+            Err(ValidationError::EmptyPayload)
+        );
+    }
+}
@@ -1,176 +1,2213 @@
 //! Node-to-node communication.
 
-use crate::channels::Callback;
-use crate::{messages, protobuf_tcp, NodeCallback};
+use crate::auth::{Authenticated, KeyProvider};
+use crate::bus::{BusError, EventBus};
+use crate::channels::{Callback, Notifications};
+use crate::codec::{CodecKind, Frame};
+use crate::compression::PayloadCompression;
+use crate::config::{NodeId, Registry};
+use crate::dedup::{DeduplicationCache, DeduplicationConfig, Seen};
+use crate::errors::ErrorCounts;
+use crate::event_log::{EventLog, EventType};
+use crate::events::{BackpressureSignal, ConnectionEvent};
+use crate::hello::{self, Feature, HelloConfig, HelloMode};
+use crate::hook::{self, Hooks};
+use crate::lifecycle::{CarrierEvent, FailureReason, Lifecycle, Side, SlowOutcome};
+use crate::log_limit::{log_limited, LogLimiter};
+use crate::loss::LossReason;
+use crate::messages::{envelope, CompressionAlgorithm};
+use crate::middleware::Direction;
+use crate::slow::Watch;
+use crate::stats::{self, Callbacks, InFlight, NodeStats, Stats};
+use crate::status;
+use crate::supervisor;
+use crate::sync::TracingMutex;
+use crate::tls::TlsDetails;
+use crate::transport::{self, NodeAddr, Transport};
+use crate::{messages, protobuf_tcp, OutgoingMessage, CHANNEL_CAPACITY};
 use async_stream::try_stream;
 use futures::channel::{mpsc, oneshot};
 use futures::future::{self, Either};
 use futures::prelude::*;
-use futures::stream::FuturesUnordered;
-use rustls::pki_types::ServerName;
-use std::pin::pin;
-use std::time::Duration;
-use std::{collections::HashMap, io};
+use futures::stream::{FusedStream, FuturesOrdered, FuturesUnordered};
+use std::any;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::io;
+use std::mem::{self, Discriminant};
+use std::panic::AssertUnwindSafe;
+use std::pin::{pin, Pin};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, OnceLock, RwLock};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant, SystemTime};
 use thiserror::Error;
-use tokio::net::TcpStream;
-use tokio::time::sleep;
-use tokio_rustls::{TlsAcceptor, TlsConnector};
-use tracing::{debug, error, instrument, trace};
+use tokio::sync::{broadcast, Notify, Semaphore};
+use tokio::time::{self, sleep, sleep_until, Interval};
+use tracing::{debug, error, field, info, info_span, instrument, trace, warn, Instrument, Span};
 
-const MAX_LEN: usize = 8 * 1024 * 1024;
+pub(crate) const MAX_LEN: usize = 8 * 1024 * 1024;
 const OUTGOING_CONNECTION_RETRY_INTERVAL: Duration = Duration::from_millis(200);
+/// Longest pause of the requests to a node following a report of its queue
+/// congested, in case the report of the queue drained never comes.
+const BACKPRESSURE_MAX_PAUSE: Duration = Duration::from_secs(1);
+/// Interval between the round-trip probes written by the end of a connection
+/// writing the requests, with [`Feature::Envelope`] negotiated. See
+/// [`CarrierHandle::rtt`](crate::control::CarrierHandle::rtt).
+pub const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(10);
 
 /// Node-to-node communication error.
 #[allow(missing_docs)]
 #[derive(Error, Debug)]
+#[non_exhaustive]
 pub enum Error {
-    #[error("TLS: {0}")]
+    #[error("TLS handshake: {0}")]
     Tls(io::Error),
-    #[error("Socket: {0}")]
+    #[error("Connect: {0}")]
     Socket(io::Error),
     #[error("SNI failure")]
     Sni,
     #[error("Unknown server name")]
     UnknownServerName,
-    #[error("Protocol: {0}")]
-    Protocol(#[from] protobuf_tcp::Error),
+    #[error("Protocol reading {message_type}: {inner}")]
+    ProtocolRead {
+        inner: protobuf_tcp::Error,
+        message_type: &'static str,
+    },
+    #[error("Protocol writing {message_type}: {inner}")]
+    ProtocolWrite {
+        inner: protobuf_tcp::Error,
+        message_type: &'static str,
+    },
+    #[error("Event bus: {0}")]
+    Bus(#[from] BusError),
+    #[error("Version handshake: {0}")]
+    Hello(#[from] hello::Error),
+    #[error("Pre-connect hook: {0}")]
+    Hook(#[from] hook::Error),
     #[error("Unexpected response with request_id: {0:?}")]
     UnexpectedResponse(Vec<u8>),
+    #[error("Unexpected response with request_seq: {0}")]
+    UnexpectedResponseSeq(u64),
+    /// The peer closed the connection between two messages, as on a restart
+    /// or a removal, rather than losing it.
+    #[error("Peer disconnected")]
+    PeerDisconnected,
+    /// The task serving the connection panicked, closing the connection
+    /// alone.
+    #[error("Connection panicked: {0}")]
+    Panicked(String),
+}
+
+impl From<transport::Error> for Error {
+    fn from(err: transport::Error) -> Self {
+        match err {
+            transport::Error::Connect(err) => Self::Socket(err),
+            transport::Error::Handshake(err) => Self::Tls(err),
+        }
+    }
+}
+
+/// Outgoing requests to a node with
+/// [`Direction::Accept`](crate::config::Direction::Accept), shared by the
+/// connections accepted from it.
+pub(crate) type SharedOutgoing = Arc<TracingMutex<OutgoingQueue>>;
+
+/// Queue of the messages to a node, written by its connections in the order
+/// of the priority of the requests, and of sending among equal ones.
+pub(crate) struct OutgoingQueue {
+    rx: mpsc::Receiver<OutgoingMessage>,
+    /// Messages taken from `rx` ahead of the next write, up to
+    /// [`CHANNEL_CAPACITY`], in order. Kept across reconnects.
+    ready: VecDeque<OutgoingMessage>,
+}
+
+/// State shared by all incoming connections.
+#[derive(Clone)]
+pub struct IncomingContext {
+    pub(crate) nodes: Arc<RwLock<HashSet<NodeId>>>,
+    pub(crate) registry: Registry,
+    pub(crate) accept_only: Arc<HashMap<NodeId, SharedOutgoing>>,
+    pub(crate) inbound: Inbound,
+    pub(crate) shared: Arc<Shared>,
+    pub(crate) handshakes: Arc<Handshakes>,
+}
+
+/// Consumers of the messages received from the nodes.
+#[derive(Clone)]
+pub(crate) struct Inbound {
+    pub(crate) bus: Box<dyn EventBus>,
+    pub(crate) notifications: Notifications,
+    /// Whether the requests awaiting the answers of the application are
+    /// watched for the slow ones.
+    pub(crate) watch_slow: bool,
+}
+
+/// State shared by all connections.
+pub(crate) struct Shared {
+    /// Version handshake run on every new connection.
+    pub(crate) hello: HelloConfig,
+    /// Hooks run on every new connection, after the version handshake.
+    pub(crate) hooks: Hooks,
+    /// Payload compression of the connections with
+    /// [`Feature::Compression`] negotiated.
+    pub(crate) compression: PayloadCompression,
+    /// Keys authenticating the requests and responses.
+    pub(crate) auth: Option<KeyProvider>,
+    /// Deduplication of the requests received on every connection.
+    pub(crate) deduplication: Option<DeduplicationConfig>,
+    /// Largest frame accepted from the nodes, in bytes.
+    pub(crate) max_frame_len: usize,
+    /// Codec of the node messages, the same on both ends.
+    pub(crate) codec: CodecKind,
+    /// Log of the connection events.
+    pub(crate) event_log: Option<Arc<EventLog>>,
+    /// Subscribers of the [`ConnectionEvent`]s.
+    pub(crate) events: broadcast::Sender<ConnectionEvent>,
+    /// Nodes answered in the order of their requests.
+    pub(crate) ordered: HashSet<NodeId>,
+    /// Subscriber of the [`CarrierEvent`]s.
+    pub(crate) lifecycle: Lifecycle,
+    /// Acknowledged notifications received from every node, kept across the
+    /// connections with the deduplication configured.
+    pub(crate) notification_dedup: Mutex<HashMap<NodeId, Arc<Mutex<DeduplicationCache>>>>,
+    /// Instruments registered on the meter of the application.
+    #[cfg(feature = "otel")]
+    pub(crate) otel: Option<crate::otel::Instruments>,
+    pub(crate) stats: Stats,
+    /// Counts of the errors without a node: of the carrier, and of the
+    /// incoming connections failing before naming their node.
+    pub(crate) errors: Arc<ErrorCounts>,
+    /// Rate limiting of the logs of the failed outgoing connections, by node
+    /// and error.
+    pub(crate) outgoing_failures: LogLimiter<(NodeId, Discriminant<Error>)>,
+    /// Rate limiting of the logs of the failed incoming connections, by peer
+    /// host and error.
+    pub(crate) incoming_failures: LogLimiter<(String, Discriminant<Error>)>,
+}
+
+/// Payload compression of a connection.
+#[derive(Clone, Copy)]
+struct Compression {
+    config: PayloadCompression,
+    /// Whether the sent payloads are compressed: not on the connections
+    /// compressed whole, where they would be twice.
+    negotiated: bool,
+}
+
+/// Message authentication of a connection.
+struct Auth {
+    keys: Option<KeyProvider>,
+    node: NodeId,
+    stats: Arc<NodeStats>,
+}
+
+/// Deduplication of the requests received on a connection, and of the
+/// acknowledged notifications received from the node, if configured.
+#[derive(Clone)]
+struct Dedup {
+    requests: Option<Arc<Mutex<DeduplicationCache>>>,
+    notifications: Option<Arc<Mutex<DeduplicationCache>>>,
+}
+
+/// Acknowledged notifications sent on a connection, kept in the
+/// [`NodeStats`] of the node until acknowledged.
+struct Acks {
+    stats: Arc<NodeStats>,
+    /// Whether the connection carries the acknowledgements.
+    negotiated: bool,
+}
+
+/// Fill of the queue of the requests received from a node, reported to the
+/// node on a connection serving its requests.
+struct Backpressure {
+    stats: Arc<NodeStats>,
+    node: NodeId,
+    /// Whether the connection carries the reports, its responses being
+    /// enveloped.
+    negotiated: bool,
+    /// Whether the queue was last reported congested.
+    congested: bool,
+}
+
+/// Responses of a connection awaiting to be written, as soon as answered, or
+/// in the order of their requests for the [`Shared::ordered`] nodes.
+enum Responses<F: Future> {
+    Unordered(FuturesUnordered<F>),
+    Ordered(FuturesOrdered<F>),
+}
+
+/// Pause of the requests written to a node on a connection, while the node
+/// reports its queue congested.
+struct Throttle {
+    node: NodeId,
+    events: broadcast::Sender<ConnectionEvent>,
+    /// End of the pause, [`BACKPRESSURE_MAX_PAUSE`] after the report at most.
+    paused_until: Mutex<Option<Instant>>,
+    /// Notified as the node reports its queue drained.
+    relieved: Notify,
+}
+
+/// Bound on the incoming handshakes in progress.
+pub(crate) struct Handshakes {
+    pub(crate) semaphore: Semaphore,
+    /// Gauge of the handshakes in progress, shared with the
+    /// [`CarrierHandle`](crate::control::CarrierHandle).
+    pub(crate) in_progress: Arc<AtomicUsize>,
+}
+
+/// Decrements the handshakes gauge when dropped.
+struct InProgress<'a>(&'a AtomicUsize);
+
+/// Counts a request dispatched to the application as awaiting its response
+/// until dropped, and as lost if dropped before answered as its connection
+/// closed.
+struct Unanswered {
+    stats: Arc<NodeStats>,
+    node: NodeId,
+    /// `request_id` of the request, until answered.
+    request_id: Option<Vec<u8>>,
+    /// Token of the request in the [`SlowRequests`](crate::slow::SlowRequests),
+    /// if watched.
+    slow_token: Option<u64>,
+}
+
+/// Counts an open connection with a node until dropped, and logs its opening
+/// and closing.
+struct Connected {
+    stats: Arc<NodeStats>,
+    node: NodeId,
+    side: Side,
+    /// Parameters of the TLS handshake, if any.
+    tls: Option<TlsDetails>,
+    /// Log of the closing.
+    event_log: Option<Arc<EventLog>>,
+    lifecycle: Lifecycle,
+    #[cfg(feature = "otel")]
+    otel: Option<crate::otel::Instruments>,
+    /// Time the connection went through its handshake and hooks.
+    established: OnceLock<Instant>,
+    /// Bytes written to the connection.
+    bytes_sent: Arc<AtomicU64>,
+    /// Bytes read from the connection.
+    bytes_received: Arc<AtomicU64>,
 }
 
 /// Handles a new incoming node-to-node connection.
-#[instrument(name = "node-incoming", level = "error", skip_all)]
-pub async fn incoming(
-    sock: TcpStream,
-    acceptor: TlsAcceptor,
-    (incoming,): (HashMap<String, mpsc::Sender<NodeCallback>>,),
+#[instrument(
+    name = "node-incoming",
+    level = "error",
+    skip_all,
+    fields(peer = %transport.peer_addr(&accepted), node = field::Empty),
+)]
+pub async fn incoming<T: Transport>(
+    accepted: T::Accepted,
+    transport: Arc<T>,
+    context: IncomingContext,
 ) -> Result<(), crate::Error> {
-    match serve_incoming(sock, acceptor, incoming).await {
-        Ok(()) => Ok(()),
-        Err(err) => {
-            debug!("Connection terminated: {err}");
-            Ok(())
+    let peer = transport.peer_addr(&accepted);
+    let shared = Arc::clone(&context.shared);
+    match serve_incoming(accepted, &*transport, context).await {
+        Ok(()) => {}
+        Err(Error::PeerDisconnected) => debug!("Peer disconnected"),
+        Err(source) => {
+            // Keyed by host, as a scanner probes from ever new ports.
+            let host = peer
+                .rsplit_once(':')
+                .map_or(peer.as_str(), |(host, _)| host);
+            let key = (host.to_string(), mem::discriminant(&source));
+            let err = crate::Error::Incoming { peer, source };
+            log_limited!(
+                shared.incoming_failures,
+                key,
+                debug,
+                "Connection terminated: {err}"
+            );
         }
     }
+    Ok(())
 }
 
 /// Handles an outgoing node-to-node connection.
-#[instrument(name = "node-outgoing", level = "error", skip_all)]
-pub async fn outgoing(
-    node: String,
-    port: u16,
-    connector: TlsConnector,
-    dnsname: ServerName<'static>,
-    mut outgoing: mpsc::Receiver<NodeCallback>,
+#[instrument(name = "node-outgoing", level = "error", skip_all, fields(node = %node))]
+pub(crate) async fn outgoing<T: Transport>(
+    node: NodeId,
+    registry: Registry,
+    transport: Arc<T>,
+    outgoing: &mut OutgoingQueue,
+    removed: &AtomicBool,
+    shared: Arc<Shared>,
+    mut inbound: Option<Inbound>,
 ) -> Result<(), crate::Error> {
     loop {
-        if let Err(err) =
-            serve_outgoing(node.clone(), port, &connector, &dnsname, &mut outgoing).await
-        {
-            debug!("Connection failure: {err}");
+        // Looked up on every attempt to follow the node when it is re-pointed.
+        let Some(addr) = registry.read().unwrap().get(&node).cloned() else {
+            return Ok(());
+        };
+        let stats = shared.stats(&node);
+        stats.dialing.store(true, Ordering::Relaxed);
+        let result = serve_outgoing(
+            &node,
+            &addr,
+            &*transport,
+            outgoing,
+            &shared,
+            inbound.as_mut(),
+        )
+        .await;
+        stats.dialing.store(false, Ordering::Relaxed);
+        if removed.load(Ordering::Acquire) {
+            return Ok(());
+        }
+        if let Err(err) = &result {
+            stats.errors.record(err);
         }
+        match result {
+            Ok(()) => {}
+            // Expected on the restarts of the peer, neither counted nor kept
+            // as the last error.
+            Err(Error::PeerDisconnected) => debug!(node = %node, "Peer disconnected"),
+            Err(err) => {
+                stats.set_last_error(&err);
+                shared.record(EventType::Failed, &node, err.to_string().as_bytes());
+                let key = (node.clone(), mem::discriminant(&err));
+                let err = crate::Error::Node {
+                    node: node.clone(),
+                    addr: format!("{}:{}", addr.host, addr.port),
+                    source: err,
+                };
+                log_limited!(shared.outgoing_failures, key, debug, node = %node, "Connection failure: {err}");
+            }
+        }
+        *stats.retry_at.lock().unwrap() = Some(Instant::now() + OUTGOING_CONNECTION_RETRY_INTERVAL);
         sleep(OUTGOING_CONNECTION_RETRY_INTERVAL).await;
+        *stats.retry_at.lock().unwrap() = None;
     }
 }
 
-async fn serve_incoming(
-    sock: TcpStream,
-    acceptor: TlsAcceptor,
-    mut incoming: HashMap<String, mpsc::Sender<NodeCallback>>,
+async fn serve_incoming<T: Transport>(
+    accepted: T::Accepted,
+    transport: &T,
+    context: IncomingContext,
 ) -> Result<(), Error> {
-    let stream = acceptor.accept(sock).await.map_err(Error::Tls)?;
-    let server_name = stream.get_ref().1.server_name().ok_or(Error::Sni)?;
-    trace!("Accepted a new connection from {server_name}");
-    let incoming = incoming
-        .get_mut(server_name)
-        .ok_or(Error::UnknownServerName)?;
-    let (reader, mut writer) = protobuf_tcp::new(stream.into(), MAX_LEN);
-
-    let mut callbacks = FuturesUnordered::new();
-    let mut incoming_requests = pin!(incoming_requests(reader, incoming));
+    let IncomingContext {
+        nodes,
+        registry,
+        accept_only,
+        mut inbound,
+        shared,
+        handshakes,
+    } = context;
+    let peer = transport.peer_addr(&accepted);
+    let (stream, identity) = handshakes
+        .run(transport.accept(accepted))
+        .await
+        .map_err(|err| shared.rejected(&peer, err.into()))?;
+    let tls = transport.tls_details(&stream);
+    let name = identity
+        .name
+        .ok_or_else(|| shared.rejected(&peer, Error::Sni))?;
+    // Names the node in the events of the connection, including its failure.
+    Span::current().record("node", field::display(&name));
+    trace!(node = %name, "Accepted a new connection");
+    let node = identify(&nodes.read().unwrap(), &registry.read().unwrap(), &name)
+        .ok_or_else(|| shared.rejected(&peer, Error::UnknownServerName))?;
+    shared.lifecycle.emit(|at| CarrierEvent::IncomingAccepted {
+        at,
+        peer,
+        node: node.to_string(),
+    });
+    if let Some(tls) = &tls {
+        log_tls(&node, Side::Incoming, tls);
+    }
+    let stats = shared.stats(&node);
+    let serve = serve_accepted(
+        &node,
+        stream,
+        tls,
+        &accept_only,
+        &mut inbound,
+        &shared,
+        &stats,
+    );
+    // A panic closes this connection, while the listener and the other
+    // connections keep serving.
+    let result = AssertUnwindSafe(serve)
+        .catch_unwind()
+        .await
+        .unwrap_or_else(|payload| {
+            let err = Error::Panicked(supervisor::panic_message(&*payload));
+            error!(node = %node, "{err}");
+            Err(err)
+        });
+    if let Err(err) = &result {
+        stats.errors.record(err);
+    }
+    match &result {
+        Ok(()) | Err(Error::PeerDisconnected) => {}
+        Err(err) => {
+            stats.set_last_error(err);
+            shared.record(EventType::Failed, &node, err.to_string().as_bytes());
+        }
+    }
+    result
+}
+
+/// Serves an incoming connection from an identified `node`.
+async fn serve_accepted(
+    node: &NodeId,
+    stream: impl tokio::io::AsyncRead + tokio::io::AsyncWrite + Send + 'static,
+    tls: Option<TlsDetails>,
+    accept_only: &HashMap<NodeId, SharedOutgoing>,
+    inbound: &mut Inbound,
+    shared: &Shared,
+    stats: &Arc<NodeStats>,
+) -> Result<(), Error> {
+    let (mut reader, mut writer) = protobuf_tcp::new(stream, shared.max_frame_len);
+    let connected = Connected::new(
+        stats,
+        shared,
+        node,
+        Side::Incoming,
+        tls,
+        &mut reader,
+        &mut writer,
+    );
+    let result = serve_accepted_connection(
+        node,
+        reader,
+        writer,
+        &connected,
+        accept_only,
+        inbound,
+        shared,
+    )
+    .await;
+    connected.close(&result);
+    result
+}
+
+/// Serves an incoming connection from `node`, from its version handshake.
+async fn serve_accepted_connection(
+    node: &NodeId,
+    mut reader: protobuf_tcp::Reader,
+    mut writer: protobuf_tcp::Writer,
+    connected: &Connected,
+    accept_only: &HashMap<NodeId, SharedOutgoing>,
+    inbound: &mut Inbound,
+    shared: &Shared,
+) -> Result<(), Error> {
+    let stats = &connected.stats;
+    let negotiated = hello::accept(
+        &shared.hello,
+        &mut reader,
+        &mut writer,
+        shared.max_frame_len,
+        shared.codec,
+    )
+    .await?;
+    debug!(node = %node, ?negotiated, "Incoming connection negotiated");
+    shared.limit_frames(stats, &mut writer, &negotiated);
+    frames(&mut reader, &mut writer, &negotiated);
+    hook::run_accept(&shared.hooks, &mut reader, &mut writer).await?;
+    connected.established();
+    let auth = Auth::new(shared, node, stats);
+    let dedup = Dedup::new(shared, node);
+    if let Some(outgoing) = accept_only.get(node) {
+        // A reconnecting node is served once its previous connection fails.
+        let mut outgoing = outgoing.lock().await;
+        let compression = Compression::new(shared, &negotiated, &writer);
+        return serve_bidirectional(
+            node,
+            reader,
+            writer,
+            &mut outgoing,
+            inbound,
+            Pending::new(shared, Arc::clone(stats), &negotiated),
+            Acks::new(Arc::clone(stats), &negotiated),
+            compression,
+            &auth,
+            &dedup,
+            &Throttle::new(shared, node),
+            shared.ordered.contains(node),
+        )
+        .await;
+    }
+
+    let compression = Compression::new(shared, &negotiated, &writer);
+    let enveloped = compression.envelopes_requests(&negotiated);
+    let mut backpressure = Backpressure::new(stats, node, negotiated.supports(Feature::Envelope));
+    let mut responses = Responses::new(shared.ordered.contains(node));
+    let mut incoming_requests = pin!(incoming_requests(
+        reader,
+        node,
+        stats,
+        inbound,
+        enveloped,
+        compression,
+        &auth,
+        &dedup
+    ));
     loop {
-        match future::select(incoming_requests.next(), callbacks.next()).await {
-            Either::Left((Some(rx), _)) => {
-                callbacks.push(rx?);
+        // An empty `Responses` resolves immediately, so don't poll it
+        // until there is a pending response.
+        let response = if responses.is_empty() {
+            future::pending().left_future()
+        } else {
+            responses.next().right_future()
+        };
+        match future::select(incoming_requests.next(), response).await {
+            Either::Left((Some(response), _)) => {
+                responses.push(response?);
+                backpressure.report(&mut writer, compression).await?;
             }
             Either::Left((None, _)) => return Ok(()),
-            Either::Right((Some(callback), _)) => {
-                if let Ok(response) = callback {
-                    writer.write::<messages::NodeResponse>(response).await?;
-                    writer.flush().await?;
+            Either::Right((Some(mut kind), _)) => {
+                if let envelope::Kind::Response(response) = &mut kind {
+                    stats.losses.rejected(node, response);
+                    auth.sign(response);
+                }
+                match kind {
+                    envelope::Kind::Response(response)
+                        if !negotiated.supports(Feature::Envelope) =>
+                    {
+                        write::<messages::NodeResponse>(&mut writer, response).await?;
+                    }
+                    // Acknowledgements and probes are negotiated together
+                    // with the envelope.
+                    kind => {
+                        let envelope = compression.envelope(kind, stats);
+                        write_envelope(&mut writer, stats, envelope).await?;
+                    }
                 }
+                backpressure.report(&mut writer, compression).await?;
             }
             Either::Right((None, _)) => {}
         }
     }
 }
 
-async fn serve_outgoing(
-    node: String,
-    port: u16,
-    connector: &TlsConnector,
-    dnsname: &ServerName<'static>,
-    outgoing: &mut mpsc::Receiver<NodeCallback>,
+/// Logs the parameters of the TLS handshake of a connection with `node`, as
+/// fields of a single event.
+fn log_tls(node: &NodeId, side: Side, tls: &TlsDetails) {
+    info!(
+        node = %node,
+        direction = side.as_str(),
+        tls.version = %tls.version,
+        tls.cipher_suite = %tls.cipher_suite,
+        tls.alpn = tls.alpn.as_deref(),
+        tls.peer_cert_sha256 = tls.peer_cert_sha256.as_deref(),
+        tls.resumed = tls.resumed,
+        "TLS handshake completed"
+    );
+}
+
+async fn serve_outgoing<T: Transport>(
+    node: &NodeId,
+    addr: &NodeAddr,
+    transport: &T,
+    outgoing: &mut OutgoingQueue,
+    shared: &Shared,
+    inbound: Option<&mut Inbound>,
 ) -> Result<(), Error> {
-    let stream = TcpStream::connect((node.clone(), port))
-        .await
-        .map_err(Error::Socket)?;
-    let stream = connector
-        .connect(dnsname.clone(), stream)
-        .await
-        .map_err(Error::Tls)?;
-    trace!("Established a connection to {node}:{port}");
-    let (reader, mut writer) = protobuf_tcp::new(stream.into(), MAX_LEN);
+    shared.lifecycle.emit(|at| CarrierEvent::OutgoingAttempt {
+        at,
+        node: node.to_string(),
+        addr: format!("{}:{}", addr.host, addr.port),
+    });
+    let (stream, _) = transport.connect(addr).await.map_err(|err| {
+        let err = err.into();
+        shared.lifecycle.failed(node, Side::Outgoing, false, &err);
+        err
+    })?;
+    trace!(
+        node = %node,
+        "Established a connection to {}:{}",
+        addr.host,
+        addr.port
+    );
+    let tls = transport.tls_details(&stream);
+    if let Some(tls) = &tls {
+        log_tls(node, Side::Outgoing, tls);
+    }
+    let early_data = transport.early_data(&stream);
+    let (mut reader, mut writer) = protobuf_tcp::new(stream, shared.max_frame_len);
+    if let Some(early_data) = early_data {
+        writer.set_early_data(early_data);
+    }
+    let stats = shared.stats(node);
+    let connected = Connected::new(
+        &stats,
+        shared,
+        node,
+        Side::Outgoing,
+        tls,
+        &mut reader,
+        &mut writer,
+    );
+    let result =
+        serve_outgoing_connection(node, reader, writer, &connected, outgoing, shared, inbound)
+            .await;
+    connected.close(&result);
+    result
+}
+
+/// Serves an outgoing connection to `node`, from its version handshake.
+#[allow(clippy::too_many_lines)]
+async fn serve_outgoing_connection(
+    node: &NodeId,
+    mut reader: protobuf_tcp::Reader,
+    mut writer: protobuf_tcp::Writer,
+    connected: &Connected,
+    outgoing: &mut OutgoingQueue,
+    shared: &Shared,
+    inbound: Option<&mut Inbound>,
+) -> Result<(), Error> {
+    let stats = Arc::clone(&connected.stats);
+    // The Hello is safe to replay, and goes as early data if supported.
+    writer.set_early(shared.hello.mode == HelloMode::Required);
+    let negotiated = hello::connect(
+        &shared.hello,
+        &mut reader,
+        &mut writer,
+        shared.max_frame_len,
+        shared.codec,
+    )
+    .await?;
+    debug!(node = %node, ?negotiated, "Outgoing connection negotiated");
+    shared.limit_frames(&stats, &mut writer, &negotiated);
+    frames(&mut reader, &mut writer, &negotiated);
+    hook::run_connect(&shared.hooks, &mut reader, &mut writer).await?;
+    connected.established();
+    let compression = Compression::new(shared, &negotiated, &writer);
+    let auth = Auth::new(shared, node, &stats);
+    let throttle = Throttle::new(shared, node);
+    if let Some(inbound) = inbound {
+        return serve_bidirectional(
+            node,
+            reader,
+            writer,
+            outgoing,
+            inbound,
+            Pending::new(shared, Arc::clone(&stats), &negotiated),
+            Acks::new(Arc::clone(&stats), &negotiated),
+            compression,
+            &auth,
+            &Dedup::new(shared, node),
+            &throttle,
+            shared.ordered.contains(node),
+        )
+        .await;
+    }
 
-    let mut callbacks = HashMap::new();
-    let mut incoming_responses = pin!(incoming_responses(reader));
+    let enveloped = compression.envelopes_requests(&negotiated);
+    let mut pending = Pending::new(shared, Arc::clone(&stats), &negotiated);
+    let acks = Acks::new(Arc::clone(&stats), &negotiated);
+    acks.resend(&mut writer, compression, node).await?;
+    // The probes are answered in the enveloped responses.
+    let probing = negotiated.supports(Feature::Envelope);
+    let mut heartbeats = heartbeats();
+    let mut incoming_responses = pin!(incoming_responses(
+        reader,
+        node,
+        &stats,
+        negotiated.supports(Feature::Envelope),
+        &acks,
+        &throttle,
+    )
+    .fuse());
     loop {
-        match future::select(outgoing.next(), incoming_responses.next()).await {
-            Either::Left((None, _)) | Either::Right((None, _)) => return Ok(()),
-            Either::Left((Some(Callback { message, callback }), _)) => {
-                if callbacks
-                    .insert(message.request_id.clone(), callback)
-                    .is_none()
-                {
-                    writer.write::<messages::NodeRequest>(message).await?;
-                    writer.flush().await?;
-                } else {
-                    error!("Colliding request_id: {:?}", message.request_id);
+        // The queue of a removed node terminates, and the connection stays
+        // open until the requests in flight are answered.
+        if outgoing.is_terminated() && pending.is_empty() {
+            return Ok(());
+        }
+        futures::select! {
+            message = throttle.next(outgoing).fuse() => match message {
+                None => {}
+                Some(OutgoingMessage::Request { callback, written }) => {
+                    stats.outgoing_queue.fetch_sub(1, Ordering::Relaxed);
+                    let Callback {
+                        mut message,
+                        callback,
+                    } = callback;
+                    let key = pending.key(&mut message);
+                    if pending.contains(&key) {
+                        colliding(node, &stats, &message);
+                    } else {
+                        auth.sign(&mut message);
+                        writer.set_early(pending.early(&message));
+                        let (request_id, request_seq) =
+                            (message.request_id.clone(), message.request_seq);
+                        let result = if enveloped {
+                            let kind = envelope::Kind::Request(message);
+                            let envelope = compression.envelope(kind, &stats);
+                            write_envelope(&mut writer, &stats, envelope).await
+                        } else {
+                            compression.compress(&mut message.payload, &stats);
+                            write::<messages::NodeRequest>(&mut writer, message).await
+                        };
+                        match oversized(result, node, &stats, &request_id, request_seq)? {
+                            Some(response) => {
+                                let _ = callback.send(response);
+                            }
+                            None => pending.insert(key, callback, written, &request_id),
+                        }
+                    }
+                }
+                Some(OutgoingMessage::Notification {
+                    notification,
+                    acked,
+                }) => {
+                    stats.outgoing_queue.fetch_sub(1, Ordering::Relaxed);
+                    if !enveloped {
+                        warn!(node = %node, "Notifications not negotiated, dropping one");
+                        stats
+                            .losses
+                            .record(node, Direction::Outgoing, LossReason::NotNegotiated, None);
+                    } else if let Some(notification) = acks.register(node, notification, acked) {
+                        acks.write(&mut writer, compression, node, notification)
+                            .await?;
+                    }
+                }
+            },
+            message = incoming_responses.next() => match message {
+                None => return Ok(()),
+                Some(message) => pending.complete(node, auth.verified(message?))?,
+            },
+            _ = heartbeats.tick().fuse() => {
+                if probing {
+                    probe(&mut writer, &stats, compression).await?;
                 }
+            },
+        }
+    }
+}
+
+/// Reads the responses to the requests written to `node`.
+fn incoming_responses<'a>(
+    mut reader: protobuf_tcp::Reader,
+    node: &'a NodeId,
+    stats: &'a NodeStats,
+    enveloped: bool,
+    acks: &'a Acks,
+    throttle: &'a Throttle,
+) -> impl Stream<Item = Result<messages::NodeResponse, Error>> + 'a {
+    try_stream! {
+        loop {
+            if !enveloped {
+                yield read::<messages::NodeResponse>(&mut reader).await?;
+                continue;
             }
-            Either::Right((Some(message), _)) => {
-                let message = message?;
-                if let Some(callback) = callbacks.remove(&message.request_id) {
-                    let _ = callback.send(message);
-                } else {
-                    Err(Error::UnexpectedResponse(message.request_id))?;
+            match read_envelope(&mut reader, node, stats).await?.kind {
+                Some(envelope::Kind::Response(message)) => yield message,
+                Some(envelope::Kind::Request(_) | envelope::Kind::Notification(_)) => {
+                    warn!(node = %node, "Ignoring a message on a connection without inbound requests");
+                    stats.losses.record(node, Direction::Incoming, LossReason::NoInbound, None);
                 }
+                Some(envelope::Kind::Ack(ack)) => acks.complete(ack.ack_id),
+                Some(envelope::Kind::Backpressure(report)) => throttle.report(&report),
+                Some(envelope::Kind::Heartbeat(_) | envelope::Kind::Cancel(_)) => {}
+                None => skip_unknown(node, stats),
             }
         }
     }
 }
 
-fn incoming_requests(
+#[allow(clippy::too_many_arguments)]
+fn incoming_requests<'a>(
     mut reader: protobuf_tcp::Reader,
-    incoming: &mut mpsc::Sender<NodeCallback>,
-) -> impl Stream<Item = Result<oneshot::Receiver<messages::NodeResponse>, Error>> + '_ {
+    node: &'a NodeId,
+    stats: &'a Arc<NodeStats>,
+    inbound: &'a mut Inbound,
+    enveloped: bool,
+    compression: Compression,
+    auth: &'a Auth,
+    dedup: &'a Dedup,
+) -> impl Stream<Item = Result<impl Future<Output = envelope::Kind>, Error>> + 'a {
     try_stream! {
         loop {
-            let message = reader.read::<messages::NodeRequest>().await?;
-            let (message, rx) = Callback::new(message);
-            incoming.send(message).await.expect("to be alive");
-            yield rx;
+            // Echoed in the response, zero for a bare request.
+            let mut sent_at_us = 0;
+            let (mut message, read_at) = if enveloped {
+                let mut envelope = read_envelope(&mut reader, node, stats).await?;
+                let read_at = Instant::now();
+                sent_at_us = envelope.sent_at_us;
+                let decompressed = compression.decompress(&mut envelope);
+                match envelope.kind {
+                    Some(envelope::Kind::Request(message)) => match decompressed {
+                        Ok(()) => (message, read_at),
+                        Err(err) => {
+                            let response = invalid(message.request_id, message.request_seq, &err);
+                            let response = echo(response, sent_at_us, read_at);
+                            yield future::ready(envelope::Kind::Response(response)).left_future();
+                            continue;
+                        }
+                    },
+                    Some(envelope::Kind::Notification(notification)) => {
+                        match decompressed {
+                            Ok(()) => {
+                                if let Some(ack) = dedup.notify(node, stats, notification, inbound).await {
+                                    yield future::ready(envelope::Kind::Ack(ack)).left_future();
+                                }
+                            }
+                            Err(err) => {
+                                warn!(node = %node, "Dropping a notification: {err}");
+                                stats.losses.record(node, Direction::Incoming, LossReason::Invalid, None);
+                            }
+                        }
+                        continue;
+                    }
+                    Some(envelope::Kind::Response(message)) => {
+                        Err(Error::UnexpectedResponse(message.request_id))?
+                    }
+                    Some(envelope::Kind::Heartbeat(heartbeat)) => {
+                        if heartbeat.probe_sent_at_us == 0 && sent_at_us != 0 {
+                            yield future::ready(answer_probe(sent_at_us)).left_future();
+                        }
+                        continue;
+                    }
+                    Some(
+                        envelope::Kind::Cancel(_)
+                        | envelope::Kind::Ack(_)
+                        | envelope::Kind::Backpressure(_),
+                    ) => continue,
+                    None => {
+                        skip_unknown(node, stats);
+                        continue;
+                    }
+                }
+            } else {
+                (read::<messages::NodeRequest>(&mut reader).await?, Instant::now())
+            };
+            if !auth.verify(&mut message) {
+                let response = unauthenticated(message.request_id, message.request_seq);
+                let response = echo(response, sent_at_us, read_at);
+                yield future::ready(envelope::Kind::Response(response)).left_future();
+                continue;
+            }
+            match dedup.check(node, stats, &message) {
+                Seen::New => {
+                    let answer = dedup.answer(&message);
+                    let response = inbound.dispatch(node, stats, message).await?;
+                    yield response
+                        .map(answer)
+                        .map(move |response| {
+                            envelope::Kind::Response(echo(response, sent_at_us, read_at))
+                        })
+                        .right_future();
+                }
+                Seen::InFlight => {}
+                Seen::Answered(response) => {
+                    let response = echo(response, sent_at_us, read_at);
+                    yield future::ready(envelope::Kind::Response(response)).left_future();
+                }
+            }
         }
     }
 }
 
-fn incoming_responses(
+/// Serves the requests in both directions over a single connection, for the
+/// nodes not configured with [`Direction::Both`](crate::config::Direction::Both).
+#[allow(clippy::too_many_arguments, clippy::too_many_lines)]
+async fn serve_bidirectional(
+    node: &NodeId,
+    reader: protobuf_tcp::Reader,
+    mut writer: protobuf_tcp::Writer,
+    outgoing: &mut OutgoingQueue,
+    inbound: &mut Inbound,
+    mut pending: Pending,
+    acks: Acks,
+    compression: Compression,
+    auth: &Auth,
+    dedup: &Dedup,
+    throttle: &Throttle,
+    ordered: bool,
+) -> Result<(), Error> {
+    let stats = &Arc::clone(&pending.stats);
+    let mut backpressure = Backpressure::new(stats, node, true);
+    acks.resend(&mut writer, compression, node).await?;
+    let mut responses = Responses::new(ordered);
+    let mut envelopes = pin!(incoming_envelopes(reader, node, stats).fuse());
+    let mut heartbeats = heartbeats();
+    loop {
+        if outgoing.is_terminated() && pending.is_empty() && responses.is_empty() {
+            return Ok(());
+        }
+        futures::select! {
+            // The queue of a removed node terminates, and the connection
+            // stays open until the requests in flight are answered.
+            message = throttle.next(outgoing).fuse() => match message {
+                None => {}
+                Some(OutgoingMessage::Request { callback, written }) => {
+                    stats.outgoing_queue.fetch_sub(1, Ordering::Relaxed);
+                    let Callback { mut message, callback } = callback;
+                    let key = pending.key(&mut message);
+                    if pending.contains(&key) {
+                        colliding(node, stats, &message);
+                    } else {
+                        auth.sign(&mut message);
+                        writer.set_early(pending.early(&message));
+                        let (request_id, request_seq) =
+                            (message.request_id.clone(), message.request_seq);
+                        let kind = envelope::Kind::Request(message);
+                        let result = write_envelope(&mut writer, stats, compression.envelope(kind, stats)).await;
+                        match oversized(result, node, stats, &request_id, request_seq)? {
+                            Some(response) => {
+                                let _ = callback.send(response);
+                            }
+                            None => pending.insert(key, callback, written, &request_id),
+                        }
+                    }
+                }
+                Some(OutgoingMessage::Notification { notification, acked }) => {
+                    stats.outgoing_queue.fetch_sub(1, Ordering::Relaxed);
+                    if let Some(notification) = acks.register(node, notification, acked) {
+                        acks.write(&mut writer, compression, node, notification).await?;
+                    }
+                }
+            },
+            envelope = envelopes.next() => {
+                let Some(mut envelope) = envelope.transpose()? else {
+                    return Ok(());
+                };
+                let (sent_at_us, read_at) = (envelope.sent_at_us, Instant::now());
+                let decompressed = compression.decompress(&mut envelope);
+                match (envelope.kind, decompressed) {
+                    (Some(envelope::Kind::Request(mut message)), Ok(())) => {
+                        if !auth.verify(&mut message) {
+                            let response =
+                                unauthenticated(message.request_id, message.request_seq);
+                            let response = echo(response, sent_at_us, read_at);
+                            responses.push(future::ready(response).left_future());
+                            continue;
+                        }
+                        match dedup.check(node, stats, &message) {
+                            Seen::New => {
+                                let answer = dedup.answer(&message);
+                                let response = inbound.dispatch(node, stats, message).await?;
+                                let response = response
+                                    .map(answer)
+                                    .map(move |response| echo(response, sent_at_us, read_at));
+                                responses.push(response.right_future());
+                                backpressure.report(&mut writer, compression).await?;
+                            }
+                            Seen::InFlight => {}
+                            Seen::Answered(response) => {
+                                let response = echo(response, sent_at_us, read_at);
+                                responses.push(future::ready(response).left_future());
+                            }
+                        }
+                    }
+                    (Some(envelope::Kind::Request(message)), Err(err)) => {
+                        let response = invalid(message.request_id, message.request_seq, &err);
+                        let response = echo(response, sent_at_us, read_at);
+                        responses.push(future::ready(response).left_future());
+                    }
+                    (Some(envelope::Kind::Notification(notification)), Ok(())) => {
+                        if let Some(ack) = dedup.notify(node, stats, notification, inbound).await {
+                            let kind = envelope::Kind::Ack(ack);
+                            write_envelope(&mut writer, stats, compression.envelope(kind, stats)).await?;
+                        }
+                    }
+                    (Some(envelope::Kind::Notification(_)), Err(err)) => {
+                        warn!(node = %node, "Dropping a notification: {err}");
+                        stats.losses.record(node, Direction::Incoming, LossReason::Invalid, None);
+                    }
+                    (Some(envelope::Kind::Response(message)), _) => {
+                        pending.complete(node, auth.verified(message))?;
+                    }
+                    (Some(envelope::Kind::Ack(ack)), _) => acks.complete(ack.ack_id),
+                    (Some(envelope::Kind::Backpressure(report)), _) => throttle.report(&report),
+                    (Some(envelope::Kind::Heartbeat(heartbeat)), _) => {
+                        if heartbeat.probe_sent_at_us == 0 && sent_at_us != 0 {
+                            let kind = answer_probe(sent_at_us);
+                            write_envelope(&mut writer, stats, compression.envelope(kind, stats)).await?;
+                        }
+                    }
+                    (Some(envelope::Kind::Cancel(_)), _) => {}
+                    (None, _) => skip_unknown(node, stats),
+                }
+            },
+            _ = heartbeats.tick().fuse() => probe(&mut writer, stats, compression).await?,
+            response = responses.select_next_some() => {
+                let mut response = response;
+                stats.losses.rejected(node, &response);
+                auth.sign(&mut response);
+                let kind = envelope::Kind::Response(response);
+                write_envelope(&mut writer, stats, compression.envelope(kind, stats)).await?;
+                backpressure.report(&mut writer, compression).await?;
+            },
+        }
+    }
+}
+
+/// Requests written to a connection and awaiting their responses, kept in the
+/// [`NodeStats`] of the node to be inspected by
+/// [`Carrier::debug_state`](crate::Carrier::debug_state).
+struct Pending {
+    stats: Arc<NodeStats>,
+    /// `request_seq` of the next request, with [`Feature::RequestSeq`]
+    /// negotiated.
+    next_seq: Option<u64>,
+    /// Whether no request was written yet.
+    first: bool,
+    #[cfg(feature = "otel")]
+    otel: Option<crate::otel::Instruments>,
+}
+
+/// Key correlating a request with its response.
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub(crate) enum RequestKey {
+    /// `request_seq`, with [`Feature::RequestSeq`] negotiated.
+    Seq(u64),
+    /// `request_id` otherwise.
+    Id(Vec<u8>),
+}
+
+impl RequestKey {
+    /// Returns the key of a received request or response, by `request_seq` if
+    /// set.
+    fn received(request_id: &[u8], request_seq: u64) -> Self {
+        if request_seq == 0 {
+            Self::Id(request_id.to_vec())
+        } else {
+            Self::Seq(request_seq)
+        }
+    }
+}
+
+impl Pending {
+    #[cfg_attr(not(feature = "otel"), allow(unused_variables))]
+    fn new(shared: &Shared, stats: Arc<NodeStats>, negotiated: &hello::Negotiated) -> Self {
+        Self {
+            stats,
+            next_seq: negotiated.supports(Feature::RequestSeq).then_some(1),
+            first: true,
+            #[cfg(feature = "otel")]
+            otel: shared.otel.clone(),
+        }
+    }
+
+    /// Returns whether a request to be written may go as early data: only the
+    /// first one on the connection, if idempotent, as early data may be
+    /// replayed.
+    fn early(&mut self, request: &messages::NodeRequest) -> bool {
+        mem::take(&mut self.first) && request.is_idempotent
+    }
+
+    /// Returns the key of a request to be written, numbering it with
+    /// [`Feature::RequestSeq`] negotiated.
+    fn key(&mut self, request: &mut messages::NodeRequest) -> RequestKey {
+        if let Some(seq) = &mut self.next_seq {
+            request.request_seq = *seq;
+            *seq += 1;
+            RequestKey::Seq(request.request_seq)
+        } else {
+            RequestKey::Id(request.request_id.clone())
+        }
+    }
+
+    fn callbacks(&self) -> MutexGuard<'_, Callbacks> {
+        self.stats.pending.lock().unwrap()
+    }
+
+    fn is_empty(&self) -> bool {
+        self.callbacks().is_empty()
+    }
+
+    fn contains(&self, key: &RequestKey) -> bool {
+        self.callbacks().contains_key(key)
+    }
+
+    /// Registers a request just written to the connection.
+    fn insert(
+        &mut self,
+        key: RequestKey,
+        callback: oneshot::Sender<messages::NodeResponse>,
+        written: Option<oneshot::Sender<Instant>>,
+        request_id: &[u8],
+    ) {
+        let now = Instant::now();
+        if let Some(written) = written {
+            let _ = written.send(now);
+        }
+        let request = InFlight {
+            callback,
+            written: now,
+            id_hash: stats::request_id_hash(request_id),
+            watch: Watch::Timely,
+        };
+        self.callbacks().insert(key, request);
+    }
+
+    /// Delivers a response to its request from `node`.
+    fn complete(
+        &mut self,
+        node: &NodeId,
+        mut response: messages::NodeResponse,
+    ) -> Result<(), Error> {
+        let key = if self.next_seq.is_some() {
+            RequestKey::Seq(response.request_seq)
+        } else {
+            RequestKey::Id(mem::take(&mut response.request_id))
+        };
+        let request = self.callbacks().remove(&key);
+        let Some(request) = request else {
+            return Err(match key {
+                RequestKey::Seq(request_seq) => Error::UnexpectedResponseSeq(request_seq),
+                RequestKey::Id(request_id) => Error::UnexpectedResponse(request_id),
+            });
+        };
+        if let RequestKey::Id(request_id) = key {
+            response.request_id = request_id;
+        }
+        let latency = request.written.elapsed();
+        self.stats.latency.record(latency);
+        #[cfg(feature = "metrics")]
+        crate::metrics::record_latency(node, latency);
+        #[cfg(feature = "otel")]
+        if let Some(otel) = &self.otel {
+            otel.record_latency(node, latency);
+        }
+        let (watch, id_hash) = (request.watch, request.id_hash);
+        self.stats
+            .slow
+            .completed(watch, id_hash, request.written, SlowOutcome::Completed);
+        if let Err(response) = request.callback.send(response) {
+            let request_id = Some(response.request_id.as_slice());
+            self.stats
+                .losses
+                .record(node, Direction::Incoming, LossReason::Abandoned, request_id);
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Pending {
+    fn drop(&mut self) {
+        // Fails the requests in flight on a closed connection.
+        let requests = mem::take(&mut *self.callbacks());
+        for request in requests.into_values() {
+            let outcome = SlowOutcome::Closed;
+            self.stats
+                .slow
+                .completed(request.watch, request.id_hash, request.written, outcome);
+        }
+    }
+}
+
+impl Acks {
+    fn new(stats: Arc<NodeStats>, negotiated: &hello::Negotiated) -> Self {
+        Self {
+            stats,
+            negotiated: negotiated.supports(Feature::AckedNotifications)
+                && negotiated.supports(Feature::Envelope),
+        }
+    }
+
+    /// Writes again the notifications not acknowledged on the previous
+    /// connections, forgetting the ones no longer awaited.
+    async fn resend(
+        &self,
+        writer: &mut protobuf_tcp::Writer,
+        compression: Compression,
+        node: &NodeId,
+    ) -> Result<(), Error> {
+        if !self.negotiated {
+            return Ok(());
+        }
+        let unacked = {
+            let mut unacked = self.stats.unacked.lock().unwrap();
+            unacked.retain(|(_, acked)| !acked.is_canceled());
+            unacked
+                .iter()
+                .map(|(notification, _)| notification.clone())
+                .collect::<Vec<_>>()
+        };
+        if unacked.is_empty() {
+            return Ok(());
+        }
+        debug!(node = %node, "Resending {} unacknowledged notifications", unacked.len());
+        for notification in unacked {
+            self.write(writer, compression, node, notification).await?;
+        }
+        Ok(())
+    }
+
+    /// Writes a notification, dropping it if its frame exceeds the limit of
+    /// `node`, which leaves the connection untouched.
+    async fn write(
+        &self,
+        writer: &mut protobuf_tcp::Writer,
+        compression: Compression,
+        node: &NodeId,
+        notification: messages::NodeNotification,
+    ) -> Result<(), Error> {
+        let ack_id = notification.ack_id;
+        let kind = envelope::Kind::Notification(notification);
+        match write_envelope(writer, &self.stats, compression.envelope(kind, &self.stats)).await {
+            Err(Error::ProtocolWrite {
+                inner: protobuf_tcp::Error::MessageTooLarge { actual, max },
+                ..
+            }) => {
+                warn!(node = %node, "Dropping a notification of {actual} bytes, past the frame limit of {max} bytes");
+                self.stats
+                    .losses
+                    .record(node, Direction::Outgoing, LossReason::Oversized, None);
+                // Fails the sender awaiting its acknowledgement.
+                let mut unacked = self.stats.unacked.lock().unwrap();
+                unacked.retain(|(notification, _)| ack_id == 0 || notification.ack_id != ack_id);
+                Ok(())
+            }
+            result => result,
+        }
+    }
+
+    /// Registers a notification to be written, returning it unless it awaits
+    /// an acknowledgement not negotiated.
+    fn register(
+        &self,
+        node: &NodeId,
+        notification: messages::NodeNotification,
+        acked: Option<oneshot::Sender<()>>,
+    ) -> Option<messages::NodeNotification> {
+        let Some(acked) = acked else {
+            return Some(notification);
+        };
+        if !self.negotiated {
+            warn!(node = %node, "Acknowledged notifications not negotiated, dropping one");
+            self.stats
+                .losses
+                .record(node, Direction::Outgoing, LossReason::NotNegotiated, None);
+            return None;
+        }
+        let mut unacked = self.stats.unacked.lock().unwrap();
+        unacked.push_back((notification.clone(), acked));
+        Some(notification)
+    }
+
+    /// Completes the notification acknowledged with `ack_id`.
+    fn complete(&self, ack_id: u64) {
+        let mut unacked = self.stats.unacked.lock().unwrap();
+        let index = unacked
+            .iter()
+            .position(|(notification, _)| notification.ack_id == ack_id);
+        // Already completed when acknowledged again after a resend.
+        if let Some((_, acked)) = index.and_then(|index| unacked.remove(index)) {
+            let _ = acked.send(());
+        }
+    }
+}
+
+impl Backpressure {
+    fn new(stats: &Arc<NodeStats>, node: &NodeId, negotiated: bool) -> Self {
+        Self {
+            stats: Arc::clone(stats),
+            node: node.clone(),
+            negotiated,
+            congested: false,
+        }
+    }
+
+    /// Reports the queue to the node as it passes 80% of its capacity, or
+    /// drains below half once reported congested.
+    async fn report(
+        &mut self,
+        writer: &mut protobuf_tcp::Writer,
+        compression: Compression,
+    ) -> Result<(), Error> {
+        if !self.negotiated {
+            return Ok(());
+        }
+        let depth = self.stats.incoming_queue.load(Ordering::Relaxed);
+        let changed = if self.congested {
+            depth * 2 <= CHANNEL_CAPACITY
+        } else {
+            depth * 5 > CHANNEL_CAPACITY * 4
+        };
+        if !changed {
+            return Ok(());
+        }
+        self.congested = !self.congested;
+        debug!(node = %self.node, depth, congested = self.congested, "Reporting backpressure");
+        let kind = envelope::Kind::Backpressure(messages::Backpressure {
+            depth: u32::try_from(depth).unwrap_or(u32::MAX),
+            capacity: u32::try_from(CHANNEL_CAPACITY).unwrap_or(u32::MAX),
+        });
+        write_envelope(writer, &self.stats, compression.envelope(kind, &self.stats)).await
+    }
+}
+
+impl<F: Future> Responses<F> {
+    fn new(ordered: bool) -> Self {
+        if ordered {
+            Self::Ordered(FuturesOrdered::new())
+        } else {
+            Self::Unordered(FuturesUnordered::new())
+        }
+    }
+
+    fn push(&mut self, response: F) {
+        match self {
+            Self::Unordered(responses) => responses.push(response),
+            Self::Ordered(responses) => responses.push_back(response),
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        match self {
+            Self::Unordered(responses) => responses.is_empty(),
+            Self::Ordered(responses) => responses.is_empty(),
+        }
+    }
+}
+
+impl<F: Future> Stream for Responses<F>
+where
+    F::Output: Unpin,
+{
+    type Item = F::Output;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        match &mut *self {
+            Self::Unordered(responses) => responses.poll_next_unpin(cx),
+            Self::Ordered(responses) => responses.poll_next_unpin(cx),
+        }
+    }
+}
+
+impl<F: Future> FusedStream for Responses<F>
+where
+    F::Output: Unpin,
+{
+    fn is_terminated(&self) -> bool {
+        match self {
+            Self::Unordered(responses) => responses.is_terminated(),
+            Self::Ordered(responses) => responses.is_terminated(),
+        }
+    }
+}
+
+impl OutgoingQueue {
+    pub(crate) fn new(rx: mpsc::Receiver<OutgoingMessage>) -> Self {
+        Self {
+            rx,
+            ready: VecDeque::new(),
+        }
+    }
+
+    /// Returns whether the queue of a removed node terminated and drained.
+    fn is_terminated(&self) -> bool {
+        self.ready.is_empty() && self.rx.is_terminated()
+    }
+
+    /// Takes the message of the highest priority among the queued ones,
+    /// waiting for one if none. The notifications have the lowest, so a
+    /// prioritized request overtakes them.
+    async fn next(&mut self) -> Option<OutgoingMessage> {
+        if self.ready.is_empty() {
+            let message = self.rx.next().await?;
+            self.ready.push_back(message);
+        }
+        while self.ready.len() < CHANNEL_CAPACITY {
+            let Ok(message) = self.rx.try_recv() else {
+                break;
+            };
+            self.ready.push_back(message);
+        }
+        let priority = |message: &OutgoingMessage| match message {
+            OutgoingMessage::Request { callback, .. } => callback.message.priority,
+            OutgoingMessage::Notification { .. } => 0,
+        };
+        let highest = self.ready.iter().map(priority).max()?;
+        let index = self.ready.iter().position(|m| priority(m) == highest)?;
+        self.ready.remove(index)
+    }
+}
+
+impl Throttle {
+    fn new(shared: &Shared, node: &NodeId) -> Self {
+        Self {
+            node: node.clone(),
+            events: shared.events.clone(),
+            paused_until: Mutex::new(None),
+            relieved: Notify::new(),
+        }
+    }
+
+    /// Handles a report of the queue of the node, pausing the requests while
+    /// congested, and streams it as a [`ConnectionEvent`].
+    fn report(&self, report: &messages::Backpressure) {
+        let signal = BackpressureSignal {
+            node: self.node.to_string(),
+            depth: report.depth as usize,
+            capacity: report.capacity as usize,
+        };
+        let congested = signal.is_congested();
+        debug!(node = %self.node, depth = signal.depth, congested, "Backpressure reported");
+        *self.paused_until.lock().unwrap() =
+            congested.then(|| Instant::now() + BACKPRESSURE_MAX_PAUSE);
+        if !congested {
+            self.relieved.notify_one();
+        }
+        // Fails without subscribers.
+        let _ = self.events.send(ConnectionEvent::Backpressure(signal));
+    }
+
+    /// Returns the end of the pause, if still paused.
+    fn paused_until(&self) -> Option<Instant> {
+        let mut paused_until = self.paused_until.lock().unwrap();
+        *paused_until = paused_until.filter(|until| *until > Instant::now());
+        *paused_until
+    }
+
+    /// Takes the next message to write to the node once the pause ended. Never
+    /// completes once the queue of a removed node terminated.
+    async fn next(&self, outgoing: &mut OutgoingQueue) -> Option<OutgoingMessage> {
+        if outgoing.is_terminated() {
+            return future::pending().await;
+        }
+        while let Some(until) = self.paused_until() {
+            let relieved = pin!(self.relieved.notified());
+            future::select(pin!(sleep_until(until.into())), relieved).await;
+        }
+        outgoing.next().await
+    }
+}
+
+/// Applies the negotiated codec and numbering to the frames of a connection.
+fn frames(
+    reader: &mut protobuf_tcp::Reader,
+    writer: &mut protobuf_tcp::Writer,
+    negotiated: &hello::Negotiated,
+) {
+    reader.set_codec(negotiated.codec);
+    writer.set_codec(negotiated.codec);
+    if negotiated.supports(Feature::FrameSeq) && negotiated.supports(Feature::Envelope) {
+        reader.sequence();
+        writer.sequence();
+    }
+}
+
+impl Shared {
+    /// Limits the frames written to a connection to the largest accepted by
+    /// the node, and records the limits for the [`Outgoing`] channels to fail
+    /// the messages past it.
+    ///
+    /// [`Outgoing`]: crate::channels::Outgoing
+    fn limit_frames(
+        &self,
+        stats: &NodeStats,
+        writer: &mut protobuf_tcp::Writer,
+        negotiated: &hello::Negotiated,
+    ) {
+        writer.set_max_len(negotiated.max_frame_len);
+        *stats.frame_limits.lock().unwrap() = Some((self.max_frame_len, negotiated.max_frame_len));
+    }
+
+    /// Returns the statistics of `node`, or detached ones if it was removed.
+    pub(crate) fn stats(&self, node: &NodeId) -> Arc<NodeStats> {
+        self.stats
+            .read()
+            .unwrap()
+            .get(node)
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    /// Logs a connection event, if enabled.
+    fn record(&self, event_type: EventType, node: &NodeId, data: &[u8]) {
+        if let Some(event_log) = &self.event_log {
+            event_log.record(event_type, node, data);
+        }
+    }
+
+    /// Counts and sends the rejection of an incoming connection from `peer`
+    /// before it was identified, and returns the failure.
+    fn rejected(&self, peer: &str, err: Error) -> Error {
+        self.errors.record(&err);
+        self.lifecycle.emit(|at| CarrierEvent::IncomingRejected {
+            at,
+            peer: peer.to_string(),
+            reason: FailureReason::of(&err),
+            error: err.to_string(),
+        });
+        err
+    }
+}
+
+impl Compression {
+    fn new(shared: &Shared, negotiated: &hello::Negotiated, writer: &protobuf_tcp::Writer) -> Self {
+        Self {
+            config: shared.compression,
+            negotiated: negotiated.supports(Feature::Compression)
+                && writer.compression() == CompressionAlgorithm::None,
+        }
+    }
+
+    /// Returns whether the requests to a node are enveloped, to tell them
+    /// apart from notifications, and to carry the compression of their
+    /// payloads. The peers predating the envelope get bare requests.
+    fn envelopes_requests(self, negotiated: &hello::Negotiated) -> bool {
+        self.negotiated
+            || negotiated.supports(Feature::Notifications)
+            || negotiated.supports(Feature::Envelope)
+    }
+
+    /// Compresses the `payload` of a request or notification if negotiated,
+    /// and returns the algorithm used.
+    fn compress(self, payload: &mut Vec<u8>, stats: &NodeStats) -> CompressionAlgorithm {
+        self.config.compress(payload, self.negotiated, stats)
+    }
+
+    /// Wraps a message sent to a node, compressing the payload of a request or
+    /// notification if negotiated.
+    fn envelope(self, kind: envelope::Kind, stats: &NodeStats) -> messages::Envelope {
+        let mut envelope = messages::Envelope {
+            kind: Some(kind),
+            ..Default::default()
+        };
+        let payload = match &mut envelope.kind {
+            Some(envelope::Kind::Request(request)) => &mut request.payload,
+            Some(envelope::Kind::Notification(notification)) => &mut notification.payload,
+            _ => return envelope,
+        };
+        envelope.compression = self.compress(payload, stats).into();
+        envelope
+    }
+
+    /// Decompresses the payload of a request or notification received from a
+    /// node. Compressed payloads are accepted even if not negotiated.
+    fn decompress(self, envelope: &mut messages::Envelope) -> io::Result<()> {
+        let payload = match &mut envelope.kind {
+            Some(envelope::Kind::Request(request)) => &mut request.payload,
+            Some(envelope::Kind::Notification(notification)) => &mut notification.payload,
+            _ => return Ok(()),
+        };
+        self.config.decompress(payload, envelope.compression)
+    }
+}
+
+/// Skips an envelope of a kind added after this version, for the newer peers
+/// to send new kinds without breaking this one.
+fn skip_unknown(node: &NodeId, stats: &NodeStats) {
+    trace!(node = %node, "Ignoring an envelope of an unknown kind");
+    stats.unknown_envelopes.fetch_add(1, Ordering::Relaxed);
+    stats
+        .losses
+        .record(node, Direction::Incoming, LossReason::UnknownKind, None);
+}
+
+/// Echoes the `sent_at_us` of the envelope of a request in its response, for
+/// the requester to estimate the clock offset, with the time since the
+/// request was read at `read_at` for the requester to estimate the
+/// round-trip time.
+fn echo(
+    response: messages::NodeResponse,
+    sent_at_us: u64,
+    read_at: Instant,
+) -> messages::NodeResponse {
+    let handling_us = u64::try_from(read_at.elapsed().as_micros()).unwrap_or(u64::MAX);
+    messages::NodeResponse {
+        request_sent_at_us: sent_at_us,
+        handling_us: Some(handling_us),
+        ..response
+    }
+}
+
+/// Returns the answer to the round-trip probe of an envelope written at
+/// `sent_at_us`.
+fn answer_probe(sent_at_us: u64) -> envelope::Kind {
+    envelope::Kind::Heartbeat(messages::Heartbeat {
+        probe_sent_at_us: sent_at_us,
+    })
+}
+
+/// Response to a request which could not be decompressed.
+fn invalid(request_id: Vec<u8>, request_seq: u64, err: &io::Error) -> messages::NodeResponse {
+    messages::NodeResponse {
+        request_id,
+        request_seq,
+        error_detail: format!("payload decompression: {err}"),
+        status: status::INVALID,
+        ..Default::default()
+    }
+}
+
+/// Returns the response failing a request whose frame exceeded the limit of
+/// `node`, counted lost, which leaves the connection untouched, or passes the
+/// result of the write.
+fn oversized(
+    result: Result<(), Error>,
+    node: &NodeId,
+    stats: &NodeStats,
+    request_id: &[u8],
+    request_seq: u64,
+) -> Result<Option<messages::NodeResponse>, Error> {
+    match result {
+        Err(Error::ProtocolWrite {
+            inner: protobuf_tcp::Error::MessageTooLarge { actual, max },
+            ..
+        }) => {
+            let reason = LossReason::Oversized;
+            stats
+                .losses
+                .record(node, Direction::Outgoing, reason, Some(request_id));
+            Ok(Some(messages::NodeResponse {
+                request_id: request_id.to_vec(),
+                request_seq,
+                error_detail: format!(
+                    "request of {actual} bytes past the frame limit of {node} of {max} bytes"
+                ),
+                status: status::INVALID,
+                ..Default::default()
+            }))
+        }
+        result => result.map(|()| None),
+    }
+}
+
+/// Logs a request colliding with a request in flight to `node`, dropped.
+fn colliding(node: &NodeId, stats: &NodeStats, request: &messages::NodeRequest) {
+    error!(node = %node, "Colliding request: {}", messages::redacted(request));
+    let request_id = Some(request.request_id.as_slice());
+    stats
+        .losses
+        .record(node, Direction::Outgoing, LossReason::Colliding, request_id);
+}
+
+/// Response standing for a request or response which failed the
+/// authentication.
+fn unauthenticated(request_id: Vec<u8>, request_seq: u64) -> messages::NodeResponse {
+    messages::NodeResponse {
+        request_id,
+        request_seq,
+        error_detail: "message authentication failed".to_string(),
+        status: status::AUTH_FAILED,
+        ..Default::default()
+    }
+}
+
+impl Auth {
+    fn new(shared: &Shared, node: &NodeId, stats: &Arc<NodeStats>) -> Self {
+        Self {
+            keys: shared.auth.clone(),
+            node: node.clone(),
+            stats: Arc::clone(stats),
+        }
+    }
+
+    /// Tags a message sent to the node, if it has a key. The key is looked up
+    /// for every message to follow the rotations.
+    fn sign<M: Authenticated>(&self, message: &mut M) {
+        if let Some(key) = self.keys.as_ref().and_then(|keys| keys(&self.node)) {
+            key.sign(message);
+        }
+    }
+
+    /// Verifies a message received from the node, if it has a key, and counts
+    /// the failures. The tag is cleared either way.
+    fn verify<M: Authenticated>(&self, message: &mut M) -> bool {
+        let Some(key) = self.keys.as_ref().and_then(|keys| keys(&self.node)) else {
+            message.auth_tag().clear();
+            return true;
+        };
+        if key.verify(message) {
+            return true;
+        }
+        self.stats.auth_failures.fetch_add(1, Ordering::Relaxed);
+        warn!(node = %self.node, "Message authentication failed");
+        false
+    }
+
+    /// Verifies a response received from the node, replacing it if it fails.
+    fn verified(&self, mut response: messages::NodeResponse) -> messages::NodeResponse {
+        if self.verify(&mut response) {
+            response
+        } else {
+            let request_id = Some(response.request_id.as_slice());
+            self.stats.losses.record(
+                &self.node,
+                Direction::Incoming,
+                LossReason::Unauthenticated,
+                request_id,
+            );
+            unauthenticated(response.request_id, response.request_seq)
+        }
+    }
+}
+
+impl Dedup {
+    fn new(shared: &Shared, node: &NodeId) -> Self {
+        let cache = |config| Arc::new(Mutex::new(DeduplicationCache::new(config)));
+        let notifications = shared.deduplication.map(|config| {
+            let mut notifications = shared.notification_dedup.lock().unwrap();
+            let notifications = notifications.entry(node.clone());
+            Arc::clone(notifications.or_insert_with(|| cache(config)))
+        });
+        Self {
+            requests: shared.deduplication.map(cache),
+            notifications,
+        }
+    }
+
+    /// Looks up a request received from `node`, counting a duplicate of a
+    /// request in flight lost. Every request is new without the deduplication.
+    fn check(&self, node: &NodeId, stats: &NodeStats, request: &messages::NodeRequest) -> Seen {
+        let Some(cache) = &self.requests else {
+            return Seen::New;
+        };
+        let key = RequestKey::received(&request.request_id, request.request_seq);
+        let seen = cache.lock().unwrap().check(key);
+        match &seen {
+            Seen::New => {}
+            Seen::InFlight => {
+                debug!(node = %node, "Dropping a duplicate of a request in flight");
+                let request_id = Some(request.request_id.as_slice());
+                stats
+                    .losses
+                    .record(node, Direction::Incoming, LossReason::Duplicate, request_id);
+            }
+            Seen::Answered(_) => debug!(node = %node, "Answering a duplicate of a request again"),
+        }
+        seen
+    }
+
+    /// Returns a function remembering the response to a new `request`, to
+    /// answer its duplicates.
+    fn answer(
+        &self,
+        request: &messages::NodeRequest,
+    ) -> impl FnOnce(messages::NodeResponse) -> messages::NodeResponse {
+        let cache = self.requests.clone().map(|cache| {
+            let key = RequestKey::received(&request.request_id, request.request_seq);
+            (cache, key)
+        });
+        move |response| {
+            if let Some((cache, key)) = cache {
+                cache.lock().unwrap().answer(&key, &response);
+            }
+            response
+        }
+    }
+
+    /// Delivers a notification received from `node`, returning the
+    /// acknowledgement it awaits. A duplicate of an acknowledged notification
+    /// is dropped with the deduplication, and acknowledged again.
+    async fn notify(
+        &self,
+        node: &NodeId,
+        stats: &NodeStats,
+        notification: messages::NodeNotification,
+        inbound: &mut Inbound,
+    ) -> Option<messages::Ack> {
+        let ack_id = notification.ack_id;
+        if ack_id == 0 {
+            inbound.notify(node, stats, notification).await;
+            return None;
+        }
+        let key = RequestKey::Seq(ack_id);
+        let cache = self.notifications.as_ref();
+        if cache.is_some_and(|cache| cache.lock().unwrap().contains(&key)) {
+            debug!(node = %node, "Dropping a duplicate of a notification");
+            stats
+                .losses
+                .record(node, Direction::Incoming, LossReason::Duplicate, None);
+        } else {
+            inbound.notify(node, stats, notification).await;
+            // Remembered once delivered, as a connection closed meanwhile
+            // gets it resent.
+            if let Some(cache) = cache {
+                cache.lock().unwrap().check(key);
+            }
+        }
+        Some(messages::Ack { ack_id })
+    }
+}
+
+impl Connected {
+    /// Counts a new connection with a node, and the bytes transferred over it.
+    fn new(
+        stats: &Arc<NodeStats>,
+        shared: &Shared,
+        node: &NodeId,
+        side: Side,
+        tls: Option<TlsDetails>,
+        reader: &mut protobuf_tcp::Reader,
+        writer: &mut protobuf_tcp::Writer,
+    ) -> Self {
+        let bytes_sent = Arc::default();
+        let bytes_received = Arc::default();
+        reader.count_bytes(Arc::clone(&stats.bytes_received));
+        reader.count_bytes(Arc::clone(&bytes_received));
+        writer.count_bytes(Arc::clone(&stats.bytes_sent));
+        writer.count_bytes(Arc::clone(&bytes_sent));
+        reader.count_frames(Arc::clone(&stats.frames_received));
+        writer.count_frames(Arc::clone(&stats.frames_sent));
+        stats.connections.fetch_add(1, Ordering::Relaxed);
+        shared.record(EventType::Connected, node, side.as_str().as_bytes());
+        Self {
+            stats: Arc::clone(stats),
+            node: node.clone(),
+            side,
+            tls,
+            event_log: shared.event_log.clone(),
+            lifecycle: shared.lifecycle.clone(),
+            #[cfg(feature = "otel")]
+            otel: shared.otel.clone(),
+            established: OnceLock::new(),
+            bytes_sent,
+            bytes_received,
+        }
+    }
+
+    /// Marks the connection through its handshake and hooks.
+    fn established(&self) {
+        let established = *self.established.get_or_init(Instant::now);
+        let mut open = self.stats.established.lock().unwrap();
+        if open.is_empty() {
+            self.stats.churn.up();
+        }
+        open.push(established);
+        drop(open);
+        self.stats.rtt.lock().unwrap().reset();
+        self.lifecycle.emit(|at| CarrierEvent::Established {
+            at,
+            node: self.node.to_string(),
+            side: self.side,
+            tls: self.tls.clone(),
+        });
+    }
+
+    /// Closes the connection served with `result`.
+    fn close(self, result: &Result<(), Error>) {
+        match result {
+            // Reported as the connection closed, on drop.
+            Ok(()) | Err(Error::PeerDisconnected) => {}
+            Err(err) => {
+                let established = self.established.get().is_some();
+                self.lifecycle
+                    .failed(&self.node, self.side, established, err);
+            }
+        }
+    }
+}
+
+impl Drop for Connected {
+    fn drop(&mut self) {
+        self.stats.connections.fetch_sub(1, Ordering::Relaxed);
+        if let Some(event_log) = &self.event_log {
+            event_log.record(
+                EventType::Disconnected,
+                &self.node,
+                self.side.as_str().as_bytes(),
+            );
+        }
+        if let Some(established) = self.established.get() {
+            let mut open = self.stats.established.lock().unwrap();
+            if let Some(i) = open.iter().position(|open| open == established) {
+                open.swap_remove(i);
+            }
+            let lifetime = established.elapsed();
+            if open.is_empty() {
+                self.stats.churn.down(lifetime);
+            } else {
+                self.stats.churn.closed(lifetime);
+            }
+            drop(open);
+            #[cfg(feature = "metrics")]
+            crate::metrics::record_lifetime(&self.node, lifetime);
+            #[cfg(feature = "otel")]
+            if let Some(otel) = &self.otel {
+                otel.record_lifetime(&self.node, lifetime);
+            }
+            self.lifecycle.emit(|at| CarrierEvent::ConnectionClosed {
+                at,
+                node: self.node.to_string(),
+                side: self.side,
+                duration: lifetime,
+                bytes_sent: self.bytes_sent.load(Ordering::Relaxed),
+                bytes_received: self.bytes_received.load(Ordering::Relaxed),
+            });
+        }
+    }
+}
+
+impl Inbound {
+    /// Dispatches a received request, and returns the future of its response.
+    /// A request dropped without a response is answered with the
+    /// [`status::HANDLER_DROPPED`] status.
+    async fn dispatch(
+        &mut self,
+        node: &NodeId,
+        stats: &Arc<NodeStats>,
+        mut request: messages::NodeRequest,
+    ) -> Result<impl Future<Output = messages::NodeResponse>, Error> {
+        request.migrate_distance_list();
+        let request_id = request.request_id.clone();
+        let request_seq = request.request_seq;
+        #[cfg(not(feature = "otel"))]
+        let span = info_span!("request", %node);
+        #[cfg(feature = "otel")]
+        let span = info_span!("request", %node, otel.name = "mpc.rpc", otel.kind = "server");
+        #[cfg(feature = "otel")]
+        crate::trace_context::set_parent(&span, &request);
+        let (callback, rx) = Callback::new(request);
+        self.bus
+            .dispatch(node, callback)
+            .instrument(span.clone())
+            .await?;
+        let unanswered = Unanswered::new(stats, node, request_id, self.watch_slow);
+        let response = rx.map(move |response| {
+            let request_id = unanswered.answer();
+            let response = response.unwrap_or_else(|_| messages::NodeResponse {
+                request_id,
+                error_detail: "request dropped without a response".to_string(),
+                status: status::HANDLER_DROPPED,
+                ..Default::default()
+            });
+            // Echoed by the carrier, as the handlers answer by `request_id`.
+            messages::NodeResponse {
+                request_seq,
+                ..response
+            }
+        });
+        Ok(response.instrument(span))
+    }
+
+    /// Delivers a received notification, dropped if the [`Incoming`] channels
+    /// are gone.
+    ///
+    /// [`Incoming`]: crate::channels::Incoming
+    async fn notify(
+        &mut self,
+        node: &NodeId,
+        stats: &NodeStats,
+        notification: messages::NodeNotification,
+    ) {
+        let delivered = self.notifications.send((node.clone(), notification)).await;
+        if delivered.is_err() {
+            stats
+                .losses
+                .record(node, Direction::Incoming, LossReason::NoConsumer, None);
+        }
+    }
+}
+
+impl Handshakes {
+    /// Runs the `handshake` once fewer than the limit are in progress.
+    async fn run<F: Future>(&self, handshake: F) -> F::Output {
+        let _permit = self.semaphore.acquire().await.expect("never closed");
+        self.in_progress.fetch_add(1, Ordering::Relaxed);
+        let _in_progress = InProgress(&self.in_progress);
+        handshake.await
+    }
+}
+
+impl Drop for InProgress<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+impl Unanswered {
+    fn new(stats: &Arc<NodeStats>, node: &NodeId, request_id: Vec<u8>, watch_slow: bool) -> Self {
+        stats.awaiting_response.fetch_add(1, Ordering::Relaxed);
+        let slow_token =
+            watch_slow.then(|| stats.slow.handling(stats::request_id_hash(&request_id)));
+        Self {
+            stats: Arc::clone(stats),
+            node: node.clone(),
+            request_id: Some(request_id),
+            slow_token,
+        }
+    }
+
+    /// Returns the `request_id` of the request answered.
+    fn answer(mut self) -> Vec<u8> {
+        self.request_id.take().unwrap_or_default()
+    }
+}
+
+impl Drop for Unanswered {
+    fn drop(&mut self) {
+        self.stats.awaiting_response.fetch_sub(1, Ordering::Relaxed);
+        if let Some(token) = self.slow_token {
+            let answered = self.request_id.is_none();
+            self.stats.slow.handled(token, answered);
+        }
+        if let Some(request_id) = &self.request_id {
+            let reason = LossReason::ConnectionClosed;
+            let request_id = Some(request_id.as_slice());
+            self.stats
+                .losses
+                .record(&self.node, Direction::Incoming, reason, request_id);
+        }
+    }
+}
+
+/// Looks up the accepted node with the identity `name`, or with the TLS name
+/// of its address equal to `name`.
+fn identify(
+    nodes: &HashSet<NodeId>,
+    registry: &HashMap<NodeId, NodeAddr>,
+    name: &str,
+) -> Option<NodeId> {
+    if let Some(node) = nodes.get(name) {
+        return Some(node.clone());
+    }
+    nodes
+        .iter()
+        .find(|node| {
+            registry
+                .get(*node)
+                .is_some_and(|addr| addr.tls_name.to_str() == name)
+        })
+        .cloned()
+}
+
+/// Reads a message, attributing a failure to its type.
+async fn read<M: Frame>(reader: &mut protobuf_tcp::Reader) -> Result<M, Error> {
+    reader.read_frame().await.map_err(|inner| match inner {
+        protobuf_tcp::Error::Closed => Error::PeerDisconnected,
+        inner => Error::ProtocolRead {
+            inner,
+            message_type: message_type::<M>(),
+        },
+    })
+}
+
+/// Writes and flushes a message, attributing a failure to its type.
+async fn write<M: Frame>(writer: &mut protobuf_tcp::Writer, message: M) -> Result<(), Error> {
+    let protocol = |inner| Error::ProtocolWrite {
+        inner,
+        message_type: message_type::<M>(),
+    };
+    writer.write_frame(message).await.map_err(protocol)?;
+    writer.flush().await.map_err(protocol)
+}
+
+/// Writes and flushes an envelope, numbered on a sequenced connection.
+async fn write_envelope(
+    writer: &mut protobuf_tcp::Writer,
+    stats: &NodeStats,
+    mut envelope: messages::Envelope,
+) -> Result<(), Error> {
+    let seq = writer.next_seq();
+    envelope.seq = seq.unwrap_or_default();
+    envelope.sent_at_us = unix_micros();
+    write(writer, envelope).await?;
+    if let Some(seq) = seq {
+        stats.last_sent_seq.store(seq, Ordering::Relaxed);
+    }
+    Ok(())
+}
+
+/// Reads an envelope, reporting a gap in the numbering of a sequenced
+/// connection. Over TCP, a gap is a bug of the framing or buffering of either
+/// end.
+async fn read_envelope(
+    reader: &mut protobuf_tcp::Reader,
+    node: &NodeId,
+    stats: &NodeStats,
+) -> Result<messages::Envelope, Error> {
+    let envelope = read::<messages::Envelope>(reader).await?;
+    let got = envelope.seq;
+    if let Some(expected) = reader.check_seq(got) {
+        warn!(node = %node, expected, got, "SequenceGap");
+        stats.sequence_gaps.fetch_add(1, Ordering::Relaxed);
+    }
+    // Zero on the connections without the numbering.
+    if got != 0 {
+        stats.last_received_seq.store(got, Ordering::Relaxed);
+    }
+    // Zero from the peers predating the timestamps.
+    match &envelope.kind {
+        Some(envelope::Kind::Response(response)) if response.request_sent_at_us != 0 => {
+            if envelope.sent_at_us != 0 {
+                let mut clock = stats.clock.lock().unwrap();
+                if clock.observe(
+                    response.request_sent_at_us,
+                    envelope.sent_at_us,
+                    unix_micros(),
+                ) {
+                    let (rtt, clock_offset_us) = (clock.rtt(), clock.offset_us());
+                    debug!(node = %node, ?rtt, clock_offset_us, "Clock estimate");
+                }
+            }
+            if let Some(handling_us) = response.handling_us {
+                observe_rtt(stats, response.request_sent_at_us, handling_us);
+            }
+        }
+        Some(envelope::Kind::Heartbeat(heartbeat)) if heartbeat.probe_sent_at_us != 0 => {
+            observe_rtt(stats, heartbeat.probe_sent_at_us, 0);
+        }
+        _ => {}
+    }
+    Ok(envelope)
+}
+
+/// Samples the round-trip time of a probe or request written at `sent_us`,
+/// held for `held_us` by the node, both in microseconds.
+fn observe_rtt(stats: &NodeStats, sent_us: u64, held_us: u64) {
+    // The local clock stepped back meanwhile.
+    if let Some(rtt) = unix_micros()
+        .checked_sub(sent_us)
+        .and_then(|elapsed| elapsed.checked_sub(held_us))
+    {
+        stats
+            .rtt
+            .lock()
+            .unwrap()
+            .observe(Duration::from_micros(rtt));
+    }
+}
+
+/// Returns the timer of the round-trip probes of a connection, firing right
+/// away to seed the estimate.
+fn heartbeats() -> Interval {
+    let mut heartbeats = time::interval(HEARTBEAT_INTERVAL);
+    heartbeats.set_missed_tick_behavior(time::MissedTickBehavior::Delay);
+    heartbeats
+}
+
+/// Writes a round-trip probe.
+async fn probe(
+    writer: &mut protobuf_tcp::Writer,
+    stats: &NodeStats,
+    compression: Compression,
+) -> Result<(), Error> {
+    // Safe to replay, the first probe goes as early data if supported, so as
+    // not to hold back the first request.
+    writer.set_early(true);
+    let kind = envelope::Kind::Heartbeat(messages::Heartbeat::default());
+    write_envelope(writer, stats, compression.envelope(kind, stats)).await
+}
+
+/// Returns the current time in microseconds since the Unix epoch.
+fn unix_micros() -> u64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map_or(0, |since| {
+            u64::try_from(since.as_micros()).unwrap_or(u64::MAX)
+        })
+}
+
+/// Returns the protobuf name of a message type, such as `NodeRequest`.
+fn message_type<M>() -> &'static str {
+    let name = any::type_name::<M>();
+    name.rsplit("::").next().unwrap_or(name)
+}
+
+fn incoming_envelopes<'a>(
     mut reader: protobuf_tcp::Reader,
-) -> impl Stream<Item = Result<messages::NodeResponse, Error>> {
+    node: &'a NodeId,
+    stats: &'a NodeStats,
+) -> impl Stream<Item = Result<messages::Envelope, Error>> + 'a {
     try_stream! {
         loop {
-            let message = reader.read::<messages::NodeResponse>().await?;
-            yield message;
+            let envelope = read_envelope(&mut reader, node, stats).await?;
+            yield envelope;
         }
     }
 }
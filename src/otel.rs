@@ -0,0 +1,301 @@
+//! OpenTelemetry spans and metrics, with the `otel` feature.
+//!
+//! The carrier exports nothing by itself: the spans go to the
+//! [`tracing-opentelemetry`](tracing_opentelemetry) layer installed by the
+//! application, and the metrics to the [`Meter`] passed to
+//! [`Carrier::otel_meter`](crate::Carrier::otel_meter), both part of the
+//! pipeline of the application. With the `otlp` feature,
+//! [`Carrier::otlp_endpoint`](crate::Carrier::otlp_endpoint) installs a
+//! pipeline exporting the spans instead.
+//!
+//! Every request sent with [`Outgoing`](crate::channels::Outgoing) is sent
+//! from a `request` span of the client kind, covering the request until its
+//! response, and every request received is handled in a `request` span of
+//! the server kind, parented to the client span of the requester. Both are
+//! exported as `mpc.rpc`. See
+//! [`NodeCallback::trace_context`](crate::channels::NodeCallback::trace_context).
+//!
+//! The instruments read the statistics of
+//! [`Carrier::debug_state`](crate::Carrier::debug_state) and
+//! [`Carrier::losses`](crate::Carrier::losses) on every collection, except
+//! the histograms, recorded as the latencies and lifetimes are measured. Every series has
+//! the `node` attribute:
+//!
+//! - `mpc_carrier.connections`, gauge: open connections with the node.
+//! - `mpc_carrier.queue.length`, gauge, by `direction`: requests from the
+//!   node not yet taken from [`Incoming`](crate::channels::Incoming)
+//!   (`incoming`), and messages to the node not yet written (`outgoing`).
+//! - `mpc_carrier.inflight_requests`, gauge: requests awaiting a response.
+//! - `mpc_carrier.unacked_notifications`, gauge: acknowledged notifications
+//!   awaiting their acknowledgements.
+//! - `mpc_carrier.bytes`, counter, by `direction`: bytes read from
+//!   (`incoming`) and written to (`outgoing`) the connections.
+//! - `mpc_carrier.auth_failures`, counter: messages failing the
+//!   authentication.
+//! - `mpc_carrier.sequence_gaps`, counter: gaps in the sequence numbers of the
+//!   received envelopes.
+//! - `mpc_carrier.losses`, counter, by `direction` and `reason`: messages
+//!   discarded. See [`loss`](crate::loss).
+//! - `mpc_carrier.rtt`, gauge, in seconds: estimated round-trip time, once
+//!   sampled.
+//! - `mpc_carrier.network_rtt`, gauge, in seconds: estimated round-trip time
+//!   of the network, once sampled on the current connection. See
+//!   [`CarrierHandle::rtt`](crate::control::CarrierHandle::rtt).
+//! - `mpc_carrier.request.duration`, histogram, in seconds: time from writing
+//!   a request until receiving its response, in the buckets of
+//!   [`DEFAULT_BUCKETS`].
+//! - `mpc_carrier.reconnects`, counter: connections established after the
+//!   first, the node having gone down meanwhile. See [`churn`](crate::churn).
+//! - `mpc_carrier.downtime`, gauge, in seconds: time without a connection in
+//!   the last hour.
+//! - `mpc_carrier.flapping`, gauge: 1 while the node is flapping, 0
+//!   otherwise.
+//! - `mpc_carrier.connection.lifetime`, histogram, in seconds: time from
+//!   establishing a connection until closing it, in the buckets of
+//!   [`LIFETIME_BUCKETS`].
+//! - `mpc_carrier.errors`, counter, by `kind`: errors, with the `node`
+//!   attribute only if they have one. See [`errors`](crate::errors).
+
+use crate::churn::LIFETIME_BUCKETS;
+use crate::config::NodeId;
+use crate::errors::{ErrorAccounting, ErrorCounts};
+use crate::loss::LossAccounting;
+use crate::middleware::Direction;
+use crate::stats::{DebugState, NodeState, NodeStats, Stats, DEFAULT_BUCKETS};
+use opentelemetry::metrics::{AsyncInstrument, Histogram, Meter};
+use opentelemetry::KeyValue;
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock, Weak};
+use std::time::Duration;
+
+/// Instruments of a carrier, registered on the [`Meter`] of the application.
+#[derive(Clone)]
+pub(crate) struct Instruments {
+    request_duration: Histogram<f64>,
+    connection_lifetime: Histogram<f64>,
+}
+
+impl Instruments {
+    /// Registers the instruments of the carrier of `stats` and `errors` on
+    /// `meter`. The observations stop once the statistics are dropped.
+    pub(crate) fn register(meter: &Meter, stats: &Stats, errors: &Arc<ErrorCounts>) -> Self {
+        let stats = Arc::downgrade(stats);
+        gauge(meter, "mpc_carrier.connections", &stats, |state, record| {
+            record(state.connections as u64, None);
+        });
+        gauge(
+            meter,
+            "mpc_carrier.queue.length",
+            &stats,
+            |state, record| {
+                record(state.incoming_queue as u64, Some(Direction::Incoming));
+                record(state.outgoing_queue as u64, Some(Direction::Outgoing));
+            },
+        );
+        gauge(
+            meter,
+            "mpc_carrier.inflight_requests",
+            &stats,
+            |state, record| {
+                record(state.inflight as u64, None);
+            },
+        );
+        gauge(
+            meter,
+            "mpc_carrier.unacked_notifications",
+            &stats,
+            |state, record| {
+                record(state.unacked_notifications as u64, None);
+            },
+        );
+        counter(meter, "mpc_carrier.bytes", "By", &stats, |state, record| {
+            record(state.bytes_received, Some(Direction::Incoming));
+            record(state.bytes_sent, Some(Direction::Outgoing));
+        });
+        counter(
+            meter,
+            "mpc_carrier.auth_failures",
+            "{message}",
+            &stats,
+            |state, record| {
+                record(state.auth_failures, None);
+            },
+        );
+        counter(
+            meter,
+            "mpc_carrier.sequence_gaps",
+            "{gap}",
+            &stats,
+            |state, record| {
+                record(state.sequence_gaps, None);
+            },
+        );
+        let losses = stats.clone();
+        meter
+            .u64_observable_counter("mpc_carrier.losses")
+            .with_unit("{message}")
+            .with_callback(move |observer| {
+                let Some(stats) = losses.upgrade() else {
+                    return;
+                };
+                for loss in LossAccounting::new(stats).snapshot() {
+                    let attributes = [
+                        KeyValue::new("node", loss.node.to_string()),
+                        KeyValue::new("direction", loss.direction.as_str()),
+                        KeyValue::new("reason", loss.reason.as_str()),
+                    ];
+                    observer.observe(loss.count, &attributes);
+                }
+            })
+            .build();
+        churn(meter, &stats);
+        error_counter(meter, &stats, Arc::downgrade(errors));
+        seconds(meter, "mpc_carrier.rtt", &stats, |state| {
+            state.rtt.map(|rtt| rtt.as_secs_f64())
+        });
+        seconds(meter, "mpc_carrier.network_rtt", &stats, |state| {
+            state.network_rtt.map(|rtt| rtt.smoothed.as_secs_f64())
+        });
+        let request_duration = meter
+            .f64_histogram("mpc_carrier.request.duration")
+            .with_unit("s")
+            .with_boundaries(DEFAULT_BUCKETS.to_vec())
+            .build();
+        let connection_lifetime = meter
+            .f64_histogram("mpc_carrier.connection.lifetime")
+            .with_unit("s")
+            .with_boundaries(LIFETIME_BUCKETS.to_vec())
+            .build();
+        Self {
+            request_duration,
+            connection_lifetime,
+        }
+    }
+
+    /// Records the response latency of a request to `node`.
+    pub(crate) fn record_latency(&self, node: &NodeId, latency: Duration) {
+        let attributes = [KeyValue::new("node", node.to_string())];
+        self.request_duration
+            .record(latency.as_secs_f64(), &attributes);
+    }
+
+    /// Records the lifetime of a connection with `node`, on its closing.
+    pub(crate) fn record_lifetime(&self, node: &NodeId, lifetime: Duration) {
+        let attributes = [KeyValue::new("node", node.to_string())];
+        self.connection_lifetime
+            .record(lifetime.as_secs_f64(), &attributes);
+    }
+}
+
+/// [`Stats`] of a carrier, not kept alive by its instruments.
+type WeakStats = Weak<RwLock<HashMap<NodeId, Arc<NodeStats>>>>;
+
+/// Recorder of an observation of a node, by direction if any.
+type Record<'a> = &'a dyn Fn(u64, Option<Direction>);
+
+fn gauge<F>(meter: &Meter, name: &'static str, stats: &WeakStats, observe: F)
+where
+    F: Fn(&NodeState, Record) + Send + Sync + 'static,
+{
+    let stats = stats.clone();
+    meter
+        .u64_observable_gauge(name)
+        .with_callback(move |observer| observe_nodes(&stats, observer, &observe))
+        .build();
+}
+
+fn counter<F>(meter: &Meter, name: &'static str, unit: &'static str, stats: &WeakStats, observe: F)
+where
+    F: Fn(&NodeState, Record) + Send + Sync + 'static,
+{
+    let stats = stats.clone();
+    meter
+        .u64_observable_counter(name)
+        .with_unit(unit)
+        .with_callback(move |observer| observe_nodes(&stats, observer, &observe))
+        .build();
+}
+
+fn churn(meter: &Meter, stats: &WeakStats) {
+    counter(
+        meter,
+        "mpc_carrier.reconnects",
+        "{connection}",
+        stats,
+        |state, record| {
+            record(state.reconnects, None);
+        },
+    );
+    seconds(meter, "mpc_carrier.downtime", stats, |state| {
+        Some(state.downtime_last_hour.as_secs_f64())
+    });
+    gauge(meter, "mpc_carrier.flapping", stats, |state, record| {
+        record(u64::from(state.flapping), None);
+    });
+}
+
+fn error_counter(meter: &Meter, stats: &WeakStats, errors: Weak<ErrorCounts>) {
+    let stats = stats.clone();
+    meter
+        .u64_observable_counter("mpc_carrier.errors")
+        .with_unit("{error}")
+        .with_callback(move |observer| {
+            let (Some(stats), Some(errors)) = (stats.upgrade(), errors.upgrade()) else {
+                return;
+            };
+            for count in ErrorAccounting::new(stats, errors).snapshot() {
+                let kind = KeyValue::new("kind", count.kind.as_str());
+                match count.node {
+                    Some(node) => {
+                        let node = KeyValue::new("node", node.to_string());
+                        observer.observe(count.count, &[node, kind]);
+                    }
+                    None => observer.observe(count.count, &[kind]),
+                }
+            }
+        })
+        .build();
+}
+
+fn seconds<F>(meter: &Meter, name: &'static str, stats: &WeakStats, value: F)
+where
+    F: Fn(&NodeState) -> Option<f64> + Send + Sync + 'static,
+{
+    let stats = stats.clone();
+    meter
+        .f64_observable_gauge(name)
+        .with_unit("s")
+        .with_callback(move |observer| {
+            let Some(stats) = stats.upgrade() else {
+                return;
+            };
+            for (node, state) in DebugState::new(&stats).nodes {
+                if let Some(value) = value(&state) {
+                    observer.observe(value, &[KeyValue::new("node", node)]);
+                }
+            }
+        })
+        .build();
+}
+
+fn observe_nodes<F>(stats: &WeakStats, observer: &dyn AsyncInstrument<u64>, observe: &F)
+where
+    F: Fn(&NodeState, Record),
+{
+    let Some(stats) = stats.upgrade() else {
+        return;
+    };
+    for (node, state) in DebugState::new(&stats).nodes {
+        let record = |value, direction: Option<Direction>| {
+            let node = KeyValue::new("node", node.clone());
+            match direction {
+                Some(direction) => {
+                    let direction = KeyValue::new("direction", direction.as_str());
+                    observer.observe(value, &[node, direction]);
+                }
+                None => observer.observe(value, &[node]),
+            }
+        };
+        observe(&state, &record);
+    }
+}
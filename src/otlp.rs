@@ -0,0 +1,86 @@
+//! Export of the spans to an OTLP endpoint, with the `otlp` feature.
+//!
+//! With [`Carrier::otlp_endpoint`](crate::Carrier::otlp_endpoint) set, the
+//! first run of the carrier starts a pipeline exporting the spans over gRPC in
+//! batches, and installs the [`tracing-opentelemetry`](tracing_opentelemetry)
+//! layer feeding it as the global subscriber. The pipeline lives as long as
+//! the carrier, and flushes the spans left when dropped.
+
+use crate::Error;
+use opentelemetry::trace::TracerProvider as _;
+use opentelemetry::KeyValue;
+use opentelemetry_otlp::{SpanExporter, WithExportConfig};
+use opentelemetry_sdk::trace::SdkTracerProvider;
+use opentelemetry_sdk::Resource;
+use tracing::warn;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+
+/// `service.name` resource attribute of the exported spans.
+const SERVICE_NAME: &str = "mpc-carrier";
+
+/// Resource attribute of the exported spans with the number of the nodes of
+/// the carrier.
+const NODE_COUNT: &str = "mpc.node_count";
+
+/// Export of the spans of a carrier.
+#[derive(Default)]
+pub(crate) struct Otlp {
+    /// Endpoint to export the spans to, if any.
+    pub(crate) endpoint: Option<String>,
+    /// Pipeline exporting the spans, started by the first run.
+    pipeline: Option<Pipeline>,
+}
+
+/// Pipeline exporting the spans, shut down when dropped.
+struct Pipeline(SdkTracerProvider);
+
+impl Otlp {
+    /// Starts the pipeline for a carrier of `node_count` nodes, unless
+    /// started already or without an endpoint.
+    pub(crate) fn start(&mut self, node_count: usize) -> Result<(), Error> {
+        if let (None, Some(endpoint)) = (&self.pipeline, &self.endpoint) {
+            self.pipeline = Some(Pipeline::start(endpoint, node_count)?);
+        }
+        Ok(())
+    }
+}
+
+impl Pipeline {
+    /// Starts exporting the spans to `endpoint`, for a carrier of
+    /// `node_count` nodes. Fails if the global subscriber is already set.
+    fn start(endpoint: &str, node_count: usize) -> Result<Self, Error> {
+        let exporter = SpanExporter::builder()
+            .with_tonic()
+            .with_endpoint(endpoint)
+            .build()
+            .map_err(|err| Error::Otlp(err.to_string()))?;
+        let resource = Resource::builder()
+            .with_service_name(SERVICE_NAME)
+            .with_attribute(KeyValue::new(
+                NODE_COUNT,
+                i64::try_from(node_count).unwrap_or(i64::MAX),
+            ))
+            .build();
+        let provider = SdkTracerProvider::builder()
+            .with_batch_exporter(exporter)
+            .with_resource(resource)
+            .build();
+        let layer = tracing_opentelemetry::layer().with_tracer(provider.tracer(SERVICE_NAME));
+        // Dropped on failure, shutting the provider down.
+        let pipeline = Self(provider);
+        tracing_subscriber::registry()
+            .with(layer)
+            .try_init()
+            .map_err(|err| Error::Otlp(err.to_string()))?;
+        Ok(pipeline)
+    }
+}
+
+impl Drop for Pipeline {
+    fn drop(&mut self) {
+        if let Err(err) = self.0.shutdown() {
+            warn!("OTLP exporter shutdown: {err}");
+        }
+    }
+}
@@ -0,0 +1,152 @@
+//! Middleware pipelines around [`Incoming`] and [`Outgoing`].
+//!
+//! Unlike the [`EventBus`](crate::bus::EventBus) middleware, each wrapping the
+//! next one, a [`Pipeline`] holds a flat list of [`Middleware`] applied in
+//! order to every request, so validation, logging and rate limiting are mixed
+//! without nesting wrappers. A middleware may be a closure taking the
+//! [`MessageContext`] and returning a [`MiddlewareAction`].
+
+use crate::channels::{Incoming, Outgoing, SendError};
+use crate::config::NodeId;
+use crate::messages::{NodeRequest, NodeResponse};
+use crate::middleware::Direction;
+use crate::status;
+use crate::NodeCallback;
+use futures::future::{self, BoxFuture};
+use std::mem;
+use tracing::debug;
+
+/// Step of a [`Pipeline`].
+pub trait Middleware: Send {
+    /// Processes a request, which the middleware may modify in place.
+    fn process<'a>(&'a mut self, ctx: &'a mut MessageContext) -> BoxFuture<'a, MiddlewareAction>;
+}
+
+/// Request going through a [`Pipeline`].
+#[derive(Clone, Debug)]
+pub struct MessageContext {
+    /// Remote node.
+    pub node: NodeId,
+    /// Direction of the request.
+    pub direction: Direction,
+    /// The request itself.
+    pub request: NodeRequest,
+}
+
+/// Outcome of a [`Middleware`].
+#[allow(clippy::large_enum_variant)]
+#[derive(Clone, Debug, PartialEq)]
+pub enum MiddlewareAction {
+    /// Passes the request to the next middleware.
+    Continue,
+    /// Drops the request. A received request is answered with the
+    /// [`status::REJECTED`] status, and a sent one fails with
+    /// [`SendError::Aborted`].
+    Abort,
+    /// Passes another request to the next middleware instead.
+    Replace(NodeRequest),
+}
+
+/// Middleware applied in order to the requests of the wrapped [`Incoming`] or
+/// [`Outgoing`].
+pub struct Pipeline<T> {
+    inner: T,
+    middleware: Vec<Box<dyn Middleware>>,
+}
+
+/// [`Pipeline`] applied to the received requests.
+pub type IncomingPipeline = Pipeline<Incoming>;
+
+/// [`Pipeline`] applied to the sent requests.
+pub type OutgoingPipeline = Pipeline<Outgoing>;
+
+impl<F> Middleware for F
+where
+    F: FnMut(&mut MessageContext) -> MiddlewareAction + Send,
+{
+    fn process<'a>(&'a mut self, ctx: &'a mut MessageContext) -> BoxFuture<'a, MiddlewareAction> {
+        Box::pin(future::ready(self(ctx)))
+    }
+}
+
+impl<T> Pipeline<T> {
+    /// Creates a new [`Pipeline`] without middleware around `inner`.
+    #[must_use]
+    pub fn new(inner: T) -> Self {
+        Self {
+            inner,
+            middleware: Vec::new(),
+        }
+    }
+
+    /// Appends `middleware`, applied after the ones already in the pipeline.
+    pub fn push(&mut self, middleware: impl Middleware + 'static) {
+        self.middleware.push(Box::new(middleware));
+    }
+
+    /// Returns the wrapped [`Incoming`] or [`Outgoing`].
+    #[must_use]
+    pub fn into_inner(self) -> T {
+        self.inner
+    }
+
+    /// Applies the middleware to `ctx` in order, returning whether the request
+    /// goes through.
+    async fn apply(&mut self, ctx: &mut MessageContext) -> bool {
+        for middleware in &mut self.middleware {
+            match middleware.process(ctx).await {
+                MiddlewareAction::Continue => {}
+                MiddlewareAction::Abort => return false,
+                MiddlewareAction::Replace(request) => ctx.request = request,
+            }
+        }
+        true
+    }
+}
+
+impl Pipeline<Incoming> {
+    /// Receives the next request which went through the middleware, as
+    /// [`Incoming::recv`]. The aborted requests are answered and skipped.
+    pub async fn recv(&mut self) -> Option<(NodeId, NodeCallback)> {
+        loop {
+            let (node, mut callback) = self.inner.recv().await?;
+            let mut ctx = MessageContext {
+                node,
+                direction: Direction::Incoming,
+                request: mem::take(&mut callback.message),
+            };
+            let passed = self.apply(&mut ctx).await;
+            callback.message = ctx.request;
+            if passed {
+                return Some((ctx.node, callback));
+            }
+            debug!("Request from {} aborted by a middleware", ctx.node);
+            let _ = callback.respond_err(status::REJECTED, "aborted by a middleware");
+        }
+    }
+}
+
+impl Pipeline<Outgoing> {
+    /// Sends a request `message` which went through the middleware to `node`,
+    /// as [`Outgoing::send`]. Fails with [`SendError::Aborted`] if a
+    /// middleware aborted the request.
+    ///
+    /// # Panics
+    ///
+    /// If `node` was not configured in [`Carrier::new`](crate::Carrier::new).
+    pub async fn send(
+        &mut self,
+        node: impl Into<NodeId>,
+        message: NodeRequest,
+    ) -> Result<NodeResponse, SendError> {
+        let mut ctx = MessageContext {
+            node: node.into(),
+            direction: Direction::Outgoing,
+            request: message,
+        };
+        if !self.apply(&mut ctx).await {
+            return Err(SendError::Aborted);
+        }
+        self.inner.send(ctx.node, ctx.request).await
+    }
+}
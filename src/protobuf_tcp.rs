@@ -1,10 +1,29 @@
 //! Protobuf over TCP.
+//!
+//! The handshake messages are always encoded with Protobuf, by [`Reader::read`]
+//! and [`Writer::write`], and the node messages with the negotiated
+//! [`codec`](crate::codec), by [`Reader::read_frame`] and
+//! [`Writer::write_frame`].
+//!
+//! [`ProtobufLenDelimCodec`] implements the same framing for
+//! [`tokio_util::codec`], to compose with its other layers: the frames it
+//! yields and takes are the raw payloads, without the compression, codec and
+//! byte counting of the [`Reader`] and [`Writer`].
 
+use crate::codec::{CodecKind, Frame};
+use crate::messages::CompressionAlgorithm;
+use crate::transport::EarlyData;
+use bytes::{Buf, BufMut, Bytes, BytesMut};
 use std::io;
+use std::mem;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
 use thiserror::Error;
-use tokio::io::{split, AsyncReadExt, AsyncWriteExt, BufReader, BufWriter, ReadHalf, WriteHalf};
-use tokio::net::TcpStream;
-use tokio_rustls::TlsStream;
+use tokio::io::{
+    split, AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader,
+    BufWriter, ReadHalf, WriteHalf,
+};
+use tokio_util::codec::{Decoder, Encoder, FramedRead, FramedWrite};
 
 /// Protobuf over TCP error.
 #[allow(missing_docs)]
@@ -16,71 +35,401 @@ pub enum Error {
     Decode(#[from] prost::DecodeError),
     #[error("Protobuf encode: {0}")]
     Encode(#[from] prost::EncodeError),
-    #[error("The length preceding the protobuf value is not valid")]
-    InvalidLen,
+    #[error("message of {actual} bytes exceeds maximum of {max} bytes")]
+    MessageTooLarge { actual: usize, max: usize },
+    #[error("Compression: {0}")]
+    Compression(io::Error),
+    #[error("connection closed by the peer")]
+    Closed,
+    #[cfg(feature = "bincode")]
+    #[error("Bincode: {0}")]
+    Bincode(bincode::Error),
 }
 
+const ZSTD_LEVEL: i32 = 3;
+
+/// Length of the prefix of a frame.
+pub(crate) const LEN_PREFIX: usize = mem::size_of::<u32>();
+
 /// Protobuf over TCP reader.
+#[allow(clippy::struct_field_names)]
 pub struct Reader {
-    reader: BufReader<ReadHalf<TlsStream<TcpStream>>>,
+    reader: BufReader<Box<dyn AsyncRead + Send + Unpin>>,
     buffer: Vec<u8>,
     max_len: usize,
+    compression: CompressionAlgorithm,
+    codec: CodecKind,
+    replay: bool,
+    counters: Vec<Arc<AtomicU64>>,
+    frames: Option<Arc<AtomicU64>>,
+    /// Sequence number of the last frame read, if sequenced.
+    last_seq: Option<u64>,
 }
 
 /// Protobuf over TCP writer.
+#[allow(clippy::struct_field_names)]
 pub struct Writer {
-    writer: BufWriter<WriteHalf<TlsStream<TcpStream>>>,
+    writer: BufWriter<Box<dyn AsyncWrite + Send + Unpin>>,
     buffer: Vec<u8>,
+    compressed: Vec<u8>,
+    max_len: usize,
+    compression: CompressionAlgorithm,
+    codec: CodecKind,
+    counters: Vec<Arc<AtomicU64>>,
+    frames: Option<Arc<AtomicU64>>,
+    /// Sequence number of the next frame written, if sequenced.
+    next_seq: Option<u64>,
+    /// Gate of the early data of the connection, if supported.
+    early_data: Option<EarlyData>,
+    /// Whether the next frame may be written as early data.
+    early: bool,
+    /// Number of the bytes written to the socket.
+    offset: u64,
+}
+
+/// Codec of the frames of [`Reader`] and [`Writer`]: a payload prefixed with
+/// its length as a big-endian `u32`.
+#[derive(Clone, Copy, Debug)]
+pub struct ProtobufLenDelimCodec {
     max_len: usize,
 }
 
 /// Creates a new pair of [`Reader`] and [`Writer`].
-pub fn new(sock: TlsStream<TcpStream>, max_len: usize) -> (Reader, Writer) {
+pub fn new<S>(sock: S, max_len: usize) -> (Reader, Writer)
+where
+    S: AsyncRead + AsyncWrite + Send + 'static,
+{
     let (reader, writer) = split(sock);
     let reader = Reader {
-        reader: BufReader::new(reader),
+        reader: BufReader::new(Box::new(reader)),
         buffer: Vec::new(),
         max_len,
+        compression: CompressionAlgorithm::None,
+        codec: CodecKind::Prost,
+        replay: false,
+        counters: Vec::new(),
+        frames: None,
+        last_seq: None,
     };
     let writer = Writer {
-        writer: BufWriter::new(writer),
+        writer: BufWriter::new(Box::new(writer)),
         buffer: Vec::new(),
+        compressed: Vec::new(),
         max_len,
+        compression: CompressionAlgorithm::None,
+        codec: CodecKind::Prost,
+        counters: Vec::new(),
+        frames: None,
+        next_seq: None,
+        early_data: None,
+        early: false,
+        offset: 0,
     };
     (reader, writer)
 }
 
+/// Creates a new pair of [`FramedRead`] and [`FramedWrite`] with the
+/// [`ProtobufLenDelimCodec`], reading and writing the payloads of the frames
+/// of up to `max_len` bytes.
+pub fn new_framed<T>(
+    stream: T,
+    max_len: usize,
+) -> (
+    FramedRead<ReadHalf<T>, ProtobufLenDelimCodec>,
+    FramedWrite<WriteHalf<T>, ProtobufLenDelimCodec>,
+)
+where
+    T: AsyncRead + AsyncWrite,
+{
+    let (reader, writer) = split(stream);
+    let codec = ProtobufLenDelimCodec::new(max_len);
+    (
+        FramedRead::new(reader, codec),
+        FramedWrite::new(writer, codec),
+    )
+}
+
+impl ProtobufLenDelimCodec {
+    /// Creates a new [`ProtobufLenDelimCodec`] for the frames of up to
+    /// `max_len` bytes.
+    #[must_use]
+    pub fn new(max_len: usize) -> Self {
+        Self { max_len }
+    }
+
+    /// Returns the maximum length of the frames.
+    #[must_use]
+    pub fn max_len(&self) -> usize {
+        self.max_len
+    }
+}
+
+impl Decoder for ProtobufLenDelimCodec {
+    type Item = BytesMut;
+    type Error = Error;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<BytesMut>, Error> {
+        let Some(prefix) = src.get(..LEN_PREFIX) else {
+            return Ok(None);
+        };
+        let length = u32::from_be_bytes(prefix.try_into().unwrap()) as usize;
+        if length > self.max_len {
+            return Err(Error::MessageTooLarge {
+                actual: length,
+                max: self.max_len,
+            });
+        }
+        if src.len() < LEN_PREFIX + length {
+            src.reserve(LEN_PREFIX + length - src.len());
+            return Ok(None);
+        }
+        src.advance(LEN_PREFIX);
+        Ok(Some(src.split_to(length)))
+    }
+}
+
+impl Encoder<Bytes> for ProtobufLenDelimCodec {
+    type Error = Error;
+
+    fn encode(&mut self, frame: Bytes, dst: &mut BytesMut) -> Result<(), Error> {
+        if frame.len() > self.max_len {
+            return Err(Error::MessageTooLarge {
+                actual: frame.len(),
+                max: self.max_len,
+            });
+        }
+        dst.reserve(LEN_PREFIX + frame.len());
+        dst.put_u32(frame.len().try_into().unwrap());
+        dst.put(frame);
+        Ok(())
+    }
+}
+
 impl Reader {
-    /// Reads and decodes the next message from the socket.
+    /// Reads and decodes the next message from the socket with Protobuf.
     pub async fn read<T: prost::Message + Default>(&mut self) -> Result<T, Error> {
+        self.next(|buffer| Ok(T::decode(buffer)?)).await
+    }
+
+    /// Reads and decodes the next message from the socket with the codec set
+    /// by [`set_codec`](Self::set_codec).
+    pub async fn read_frame<T: Frame>(&mut self) -> Result<T, Error> {
+        let codec = self.codec;
+        self.next(|buffer| codec.decode(buffer)).await
+    }
+
+    async fn next<T>(
+        &mut self,
+        decode: impl FnOnce(&[u8]) -> Result<T, Error>,
+    ) -> Result<T, Error> {
+        if self.replay {
+            self.replay = false;
+            return self.decode(decode);
+        }
+        // Closed between two frames, by a peer shutting down rather than
+        // failing, which would end a frame early or skip the TLS close_notify.
+        if self.reader.fill_buf().await?.is_empty() {
+            return Err(Error::Closed);
+        }
         let length = self.reader.read_u32().await? as usize;
         if length > self.max_len {
-            return Err(Error::InvalidLen);
+            return Err(Error::MessageTooLarge {
+                actual: length,
+                max: self.max_len,
+            });
         }
         self.buffer.clear();
         self.buffer.resize(length, 0);
         self.reader.read_exact(&mut self.buffer).await?;
-        Ok(T::decode(self.buffer.as_slice())?)
+        count(&self.counters, self.frames.as_ref(), length);
+        self.decode(decode)
+    }
+
+    /// Makes the next read return the last read message again, decoded anew.
+    pub(crate) fn unread(&mut self) {
+        self.replay = true;
+    }
+
+    fn decode<T>(&self, decode: impl FnOnce(&[u8]) -> Result<T, Error>) -> Result<T, Error> {
+        match self.compression {
+            CompressionAlgorithm::None => decode(&self.buffer),
+            CompressionAlgorithm::Zstd => {
+                let buffer = zstd::bulk::decompress(&self.buffer, self.max_len)
+                    .map_err(Error::Compression)?;
+                decode(&buffer)
+            }
+        }
+    }
+
+    /// Sets the compression algorithm for the subsequent reads.
+    pub fn set_compression(&mut self, compression: CompressionAlgorithm) {
+        self.compression = compression;
+    }
+
+    /// Sets the codec for the subsequent [`read_frame`](Self::read_frame)s.
+    pub fn set_codec(&mut self, codec: CodecKind) {
+        self.codec = codec;
+    }
+
+    /// Adds the bytes of the subsequently read frames to `counter`, along
+    /// with the previous counters.
+    pub(crate) fn count_bytes(&mut self, counter: Arc<AtomicU64>) {
+        self.counters.push(counter);
+    }
+
+    /// Counts the subsequently read frames in `counter`.
+    pub(crate) fn count_frames(&mut self, counter: Arc<AtomicU64>) {
+        self.frames = Some(counter);
+    }
+
+    /// Expects the subsequent frames to carry sequence numbers from 1.
+    pub(crate) fn sequence(&mut self) {
+        self.last_seq = Some(0);
+    }
+
+    /// Records the sequence number carried by the last read frame, returning
+    /// the expected one if it does not follow the previous frame. The
+    /// sequence resumes from `seq` either way.
+    pub(crate) fn check_seq(&mut self, seq: u64) -> Option<u64> {
+        let last_seq = self.last_seq.as_mut()?;
+        let expected = last_seq.wrapping_add(1);
+        *last_seq = seq;
+        (seq != expected).then_some(expected)
     }
 }
 
 impl Writer {
-    /// Encodes and sends a message over the socket.
+    /// Encodes with Protobuf and sends a message over the socket.
     pub async fn write<T: prost::Message>(&mut self, message: T) -> Result<(), Error> {
+        let early = mem::take(&mut self.early);
         let length = message.encoded_len();
         if length > self.max_len {
-            return Err(Error::InvalidLen);
+            return Err(Error::MessageTooLarge {
+                actual: length,
+                max: self.max_len,
+            });
         }
-        self.writer.write_u32(length.try_into().unwrap()).await?;
         self.buffer.clear();
         message.encode(&mut self.buffer)?;
-        self.writer.write_all(&self.buffer).await?;
+        self.send(early).await
+    }
+
+    /// Encodes with the codec set by [`set_codec`](Self::set_codec) and sends
+    /// a message over the socket.
+    pub async fn write_frame<T: Frame>(&mut self, message: T) -> Result<(), Error> {
+        let early = mem::take(&mut self.early);
+        self.buffer.clear();
+        self.codec.encode(&message, &mut self.buffer)?;
+        if self.buffer.len() > self.max_len {
+            return Err(Error::MessageTooLarge {
+                actual: self.buffer.len(),
+                max: self.max_len,
+            });
+        }
+        self.send(early).await?;
+        if let Some(next_seq) = &mut self.next_seq {
+            *next_seq += 1;
+        }
+        Ok(())
+    }
+
+    /// Sends the encoded message in the buffer, as early data if `early`.
+    async fn send(&mut self, early: bool) -> Result<(), Error> {
+        let frame = match self.compression {
+            CompressionAlgorithm::None => &self.buffer,
+            CompressionAlgorithm::Zstd => {
+                self.compressed =
+                    zstd::bulk::compress(&self.buffer, ZSTD_LEVEL).map_err(Error::Compression)?;
+                &self.compressed
+            }
+        };
+        if frame.len() > self.max_len {
+            return Err(Error::MessageTooLarge {
+                actual: frame.len(),
+                max: self.max_len,
+            });
+        }
+        let len = (LEN_PREFIX + frame.len()) as u64;
+        if let Some(early_data) = self.early_data.as_ref().filter(|_| early) {
+            early_data.allow(self.offset, len);
+        }
+        self.offset += len;
+        self.writer
+            .write_u32(frame.len().try_into().unwrap())
+            .await?;
+        self.writer.write_all(frame).await?;
+        count(&self.counters, self.frames.as_ref(), frame.len());
         Ok(())
     }
 
+    /// Sets the compression algorithm for the subsequent writes.
+    pub fn set_compression(&mut self, compression: CompressionAlgorithm) {
+        self.compression = compression;
+    }
+
+    /// Returns the compression algorithm of the subsequent writes.
+    pub(crate) fn compression(&self) -> CompressionAlgorithm {
+        self.compression
+    }
+
+    /// Sets the codec for the subsequent [`write_frame`](Self::write_frame)s.
+    pub fn set_codec(&mut self, codec: CodecKind) {
+        self.codec = codec;
+    }
+
+    /// Adds the bytes of the subsequently written frames to `counter`, along
+    /// with the previous counters.
+    pub(crate) fn count_bytes(&mut self, counter: Arc<AtomicU64>) {
+        self.counters.push(counter);
+    }
+
+    /// Counts the subsequently written frames in `counter`.
+    pub(crate) fn count_frames(&mut self, counter: Arc<AtomicU64>) {
+        self.frames = Some(counter);
+    }
+
+    /// Numbers the subsequent frames from 1.
+    pub(crate) fn sequence(&mut self) {
+        self.next_seq = Some(1);
+    }
+
+    /// Returns the sequence number of the next frame written by
+    /// [`write_frame`](Self::write_frame), if sequenced.
+    pub(crate) fn next_seq(&self) -> Option<u64> {
+        self.next_seq
+    }
+
+    /// Sets the gate of the early data of the connection.
+    pub(crate) fn set_early_data(&mut self, early_data: EarlyData) {
+        self.early_data = Some(early_data);
+    }
+
+    /// Sets whether the next frame may be written as early data, before the
+    /// handshake of the connection completes. It is only if all the frames
+    /// before it were.
+    pub(crate) fn set_early(&mut self, early: bool) {
+        self.early = early;
+    }
+
+    /// Sets the maximum length of the subsequently written messages.
+    pub(crate) fn set_max_len(&mut self, max_len: usize) {
+        self.max_len = max_len;
+    }
+
     /// Flushes the socket.
     pub async fn flush(&mut self) -> Result<(), Error> {
         self.writer.flush().await?;
         Ok(())
     }
 }
+
+/// Adds a frame of `len` bytes and its length prefix to `counters`, and the
+/// frame to `frames`.
+fn count(counters: &[Arc<AtomicU64>], frames: Option<&Arc<AtomicU64>>, len: usize) {
+    for counter in counters {
+        counter.fetch_add((LEN_PREFIX + len) as u64, Ordering::Relaxed);
+    }
+    if let Some(frames) = frames {
+        frames.fetch_add(1, Ordering::Relaxed);
+    }
+}
@@ -0,0 +1,138 @@
+//! Helpers for the common patterns of the MPC protocols, on top of the
+//! [`Outgoing`] channels.
+//!
+//! [`CommitReveal`] runs the sending side of a commit-then-reveal exchange: a
+//! node binds itself to a secret by broadcasting a commitment to it, such as
+//! its hash, and reveals the secret only once every other node acknowledged
+//! the commitment, so no node can pick its own secret after seeing another
+//! one. When every node runs the exchange, the commitments and secrets of the
+//! others arrive through the [`Incoming`] channels stamped with the session,
+//! received apart from the other sessions with [`Incoming::recv_session`],
+//! and the responses are mere acknowledgements. The `coin_flip` example runs
+//! such an exchange between three nodes.
+//!
+//! [`Incoming`]: crate::channels::Incoming
+//! [`Incoming::recv_session`]: crate::channels::Incoming::recv_session
+
+use crate::channels::{Outgoing, SendError};
+use crate::config::NodeId;
+use crate::messages::{NodeRequest, NodeResponse};
+use std::collections::HashMap;
+use std::fmt;
+use thiserror::Error;
+
+/// Error returned by [`CommitReveal`].
+#[allow(missing_docs)]
+#[derive(Error, Debug)]
+#[non_exhaustive]
+pub enum Error {
+    #[error("{phase} broadcast to {node}: {source}")]
+    Send {
+        phase: Phase,
+        node: NodeId,
+        source: SendError,
+    },
+    #[error("{0} broadcast out of order")]
+    OutOfOrder(Phase),
+}
+
+/// Phase of a [`CommitReveal`] exchange.
+#[allow(missing_docs)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Phase {
+    Commit,
+    Reveal,
+}
+
+/// Commit-then-reveal exchange of a node with all the others, within a
+/// session.
+///
+/// The phases run once each and in order. A failed broadcast aborts the
+/// exchange, as the nodes that did not acknowledge the commitment must not
+/// see the secret, and the following broadcasts fail with
+/// [`Error::OutOfOrder`].
+pub struct CommitReveal<'a> {
+    outgoing: &'a mut Outgoing,
+    session_id: u64,
+    /// Next phase to run, or [`None`] once done or aborted.
+    next: Option<Phase>,
+}
+
+impl<'a> CommitReveal<'a> {
+    /// Starts an exchange over `outgoing`, with every request stamped with
+    /// `session_id`.
+    #[must_use]
+    pub fn new(outgoing: &'a mut Outgoing, session_id: u64) -> Self {
+        Self {
+            outgoing,
+            session_id,
+            next: Some(Phase::Commit),
+        }
+    }
+
+    /// Returns the session of the exchange.
+    #[must_use]
+    pub fn session_id(&self) -> u64 {
+        self.session_id
+    }
+
+    /// Broadcasts `commitment` to every node, and returns their responses
+    /// once all of them acknowledged it.
+    pub async fn broadcast_commit(
+        &mut self,
+        commitment: NodeRequest,
+    ) -> Result<HashMap<NodeId, NodeResponse>, Error> {
+        self.broadcast(Phase::Commit, commitment).await
+    }
+
+    /// Broadcasts `secret` to every node, once they all acknowledged the
+    /// commitment, and returns their responses.
+    pub async fn broadcast_reveal(
+        &mut self,
+        secret: NodeRequest,
+    ) -> Result<HashMap<NodeId, NodeResponse>, Error> {
+        self.broadcast(Phase::Reveal, secret).await
+    }
+
+    async fn broadcast(
+        &mut self,
+        phase: Phase,
+        request: NodeRequest,
+    ) -> Result<HashMap<NodeId, NodeResponse>, Error> {
+        if self.next != Some(phase) {
+            return Err(Error::OutOfOrder(phase));
+        }
+        self.next = None;
+        let request = NodeRequest {
+            session_id: Some(self.session_id),
+            ..request
+        };
+        let responses = self
+            .outgoing
+            .broadcast(request)
+            .await
+            .into_iter()
+            .map(|(node, response)| match response {
+                Ok(response) => Ok((node, response)),
+                Err(source) => Err(Error::Send {
+                    phase,
+                    node,
+                    source,
+                }),
+            })
+            .collect::<Result<HashMap<_, _>, _>>()?;
+        if phase == Phase::Commit {
+            self.next = Some(Phase::Reveal);
+        }
+        Ok(responses)
+    }
+}
+
+impl fmt::Display for Phase {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Commit => f.write_str("commit"),
+            Self::Reveal => f.write_str("reveal"),
+        }
+    }
+}
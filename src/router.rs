@@ -0,0 +1,270 @@
+//! Dispatch of the incoming requests to typed handlers.
+//!
+//! A [`Router`] decodes the payloads of the requests into the Protobuf types
+//! of their handlers, and encodes the responses of the handlers into the
+//! payloads of the [`NodeResponse`]s, instead of the application matching on
+//! the raw payloads taken from [`Incoming`]. Every handler serves the
+//! [service](Incoming::route) named after the full Protobuf name of its
+//! request type, which the requesters set with [`request`]:
+//!
+//! - a payload failing to decode is answered with [`status::DECODE_FAILED`];
+//! - a [`HandlerError`] is answered with its status and detail;
+//! - a panicking handler is answered with [`status::HANDLER_PANICKED`].
+//!
+//! A [`Handler`] registered with [`Router::register_handler`] instead answers
+//! the raw requests of its [`SERVICE`](Handler::SERVICE) one at a time, with
+//! mutable state kept across them, such as the rounds of an MPC protocol.
+//!
+//! The requests without a service are still delivered to [`Incoming`], or
+//! answered with [`status::UNSUPPORTED`] by [`Router::run`].
+
+use crate::channels::{Incoming, ServiceIncoming};
+use crate::config::NodeId;
+use crate::messages::{NodeRequest, NodeResponse};
+use crate::{spawn_named, status, NodeCallback};
+use futures::future::{self, BoxFuture};
+use futures::prelude::*;
+use futures::stream;
+use prost::{Message, Name};
+use std::collections::HashMap;
+use std::mem;
+use std::panic::AssertUnwindSafe;
+use std::sync::Arc;
+use thiserror::Error;
+use tracing::{debug, warn};
+
+/// Typed handlers of the incoming requests, by service.
+#[derive(Default)]
+pub struct Router {
+    handlers: HashMap<String, ErasedHandler>,
+    /// Loops of the [`Handler`]s, by service.
+    stateful: HashMap<String, StatefulHandler>,
+}
+
+/// Handler of the raw requests to a service, keeping state across them.
+///
+/// The requests are handled one at a time, in their order of arrival. A
+/// panicking handler is answered with [`status::HANDLER_PANICKED`] and serves
+/// no further request, as its state may be inconsistent: the requests to its
+/// service are then answered with [`status::UNSUPPORTED`].
+pub trait Handler: Send + 'static {
+    /// Service served by the handler, set by the requesters in
+    /// [`NodeRequest::service`].
+    const SERVICE: &'static str;
+
+    /// Answers a `request` from `node`. The `request_id` of the response is
+    /// set by the router.
+    fn handle(
+        &mut self,
+        node: &str,
+        request: NodeRequest,
+    ) -> impl Future<Output = NodeResponse> + Send;
+}
+
+/// Failure of a handler, answered with its `status` and `detail`.
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+#[error("status {status}: {detail}")]
+pub struct HandlerError {
+    /// Status of the response, one of the application codes from
+    /// [`status::FIRST_APPLICATION`].
+    pub status: u32,
+    /// Why the request failed.
+    pub detail: String,
+}
+
+/// Handler taking and returning encoded payloads.
+type ErasedHandler = Arc<dyn Fn(NodeId, Vec<u8>) -> BoxFuture<'static, Reply> + Send + Sync>;
+
+/// Encoded response of a handler, or its failure.
+type Reply = Result<Vec<u8>, HandlerError>;
+
+/// Loop of a [`Handler`] over the requests to its service.
+type StatefulHandler = Box<dyn FnOnce(ServiceIncoming) -> BoxFuture<'static, ()> + Send>;
+
+impl Router {
+    /// Creates a new [`Router`] without handlers.
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers the `handler` of the requests of type `Req`, answered with a
+    /// `Resp`.
+    ///
+    /// # Panics
+    ///
+    /// If a handler of `Req` is already registered.
+    #[must_use]
+    pub fn handler_for<Req, Resp, F, Fut>(mut self, handler: F) -> Self
+    where
+        Req: Name + Default + 'static,
+        Resp: Message,
+        F: Fn(NodeId, Req) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<Resp, HandlerError>> + Send + 'static,
+    {
+        let service = Req::full_name();
+        self.assert_unregistered(&service);
+        let erased: ErasedHandler = Arc::new(move |node, payload: Vec<u8>| {
+            let request = match Req::decode(payload.as_slice()) {
+                Ok(request) => request,
+                Err(err) => {
+                    let err = HandlerError::new(status::DECODE_FAILED, err.to_string());
+                    return future::ready(Err(err)).boxed();
+                }
+            };
+            handler(node, request)
+                .map_ok(|response| response.encode_to_vec())
+                .boxed()
+        });
+        self.handlers.insert(service, erased);
+        self
+    }
+
+    /// Registers a stateful `handler` of the requests to
+    /// [`T::SERVICE`](Handler::SERVICE).
+    ///
+    /// # Panics
+    ///
+    /// If a handler of the service is already registered.
+    #[must_use]
+    pub fn register_handler<T: Handler>(mut self, mut handler: T) -> Self {
+        self.assert_unregistered(T::SERVICE);
+        let serve: StatefulHandler = Box::new(move |mut rx: ServiceIncoming| {
+            async move {
+                while let Some((node, mut callback)) = rx.recv().await {
+                    let request = callback.message.clone();
+                    let response = AssertUnwindSafe(handler.handle(&node, request))
+                        .catch_unwind()
+                        .await;
+                    let Ok(mut response) = response else {
+                        warn!(node = %node, service = T::SERVICE, "Request handler panicked");
+                        let _ = callback.respond_err(status::HANDLER_PANICKED, "handler panicked");
+                        return;
+                    };
+                    response.request_id = mem::take(&mut callback.message.request_id);
+                    let _ = callback.respond(response);
+                }
+            }
+            .boxed()
+        });
+        self.stateful.insert(T::SERVICE.to_string(), serve);
+        self
+    }
+
+    fn assert_unregistered(&self, service: &str) {
+        assert!(
+            !self.handlers.contains_key(service) && !self.stateful.contains_key(service),
+            "handler of `{service}` already registered"
+        );
+    }
+
+    /// Routes the services of the handlers from `incoming`, and returns the
+    /// future serving their requests, to be spawned. Every request is handled
+    /// in a task of its own.
+    ///
+    /// The services are routed before returning, so the router must be served
+    /// before [`Carrier::run`](crate::Carrier::run) for no request to be
+    /// missed.
+    ///
+    /// # Panics
+    ///
+    /// If a service of the handlers is already routed.
+    pub fn serve(self, incoming: &Incoming) -> impl Future<Output = ()> + Send + 'static {
+        let services = self.handlers.into_iter().map(|(service, handler)| {
+            let rx = incoming.route(service);
+            stream::unfold(rx, |mut rx: ServiceIncoming| async move {
+                let (node, callback) = rx.recv().await?;
+                Some(((node, callback, rx.service().to_string()), rx))
+            })
+            .map(move |request| (request, Arc::clone(&handler)))
+            .boxed()
+        });
+        let mut requests = stream::select_all(services);
+        let stateful = self
+            .stateful
+            .into_iter()
+            .map(|(service, serve)| serve(incoming.route(service)))
+            .collect::<Vec<_>>();
+        let typed = async move {
+            while let Some(((node, callback, service), handler)) = requests.next().await {
+                spawn_named("router-handler", handle(node, callback, service, handler));
+            }
+        };
+        future::join(typed, future::join_all(stateful)).map(|_| ())
+    }
+
+    /// Serves the handlers, and answers the requests without a service from
+    /// `incoming` with [`status::UNSUPPORTED`], until the carrier stops.
+    ///
+    /// As with [`serve`](Self::serve), the services are routed once called,
+    /// so the router must be run before [`Carrier::run`](crate::Carrier::run)
+    /// for no request to be missed.
+    ///
+    /// # Panics
+    ///
+    /// If a service of the handlers is already routed.
+    pub fn run(self, mut incoming: Incoming) -> impl Future<Output = ()> + Send + 'static {
+        let serve = self.serve(&incoming);
+        let unrouted = async move {
+            while let Some((node, callback)) = incoming.recv().await {
+                debug!(node = %node, "Request without a service");
+                let _ = callback.respond_err(status::UNSUPPORTED, "request without a service");
+            }
+        };
+        future::join(serve, unrouted).map(|_| ())
+    }
+}
+
+impl HandlerError {
+    /// Creates a new [`HandlerError`].
+    #[must_use]
+    pub fn new(status: u32, detail: impl Into<String>) -> Self {
+        Self {
+            status,
+            detail: detail.into(),
+        }
+    }
+}
+
+/// Returns a request of `request` to its [`Router`] handler, to be sent with
+/// [`Outgoing::send`](crate::channels::Outgoing::send).
+pub fn request<Req: Name>(request: &Req) -> NodeRequest {
+    NodeRequest {
+        payload: request.encode_to_vec(),
+        service: Req::full_name(),
+        ..Default::default()
+    }
+}
+
+/// Decodes the payload of a response of a [`Router`] handler.
+pub fn response<Resp: Message + Default>(
+    response: &NodeResponse,
+) -> Result<Resp, prost::DecodeError> {
+    Resp::decode(response.payload.as_slice())
+}
+
+/// Answers a request with its `handler`.
+async fn handle(node: NodeId, mut callback: NodeCallback, service: String, handler: ErasedHandler) {
+    let payload = mem::take(&mut callback.message.payload);
+    let reply = AssertUnwindSafe(async { handler(node.clone(), payload).await })
+        .catch_unwind()
+        .await;
+    let _ = match reply {
+        Ok(Ok(payload)) => {
+            let request_id = mem::take(&mut callback.message.request_id);
+            callback.respond(NodeResponse {
+                request_id,
+                payload,
+                ..Default::default()
+            })
+        }
+        Ok(Err(err)) => {
+            debug!(node = %node, service, "Request failed: {err}");
+            callback.respond_err(err.status, err.detail)
+        }
+        Err(_) => {
+            warn!(node = %node, service, "Request handler panicked");
+            callback.respond_err(status::HANDLER_PANICKED, "handler panicked")
+        }
+    };
+}
@@ -0,0 +1,499 @@
+//! Run loop of a [`Carrier`].
+
+use crate::bus::SharedChannels;
+use crate::channels::{NodeCallback, Outgoing, OutgoingMessage};
+use crate::config::{Direction, NodeId, Registry};
+use crate::control::{AddError, Command, RemoveError};
+use crate::event_log::EventLog;
+use crate::log_limit::LogLimiter;
+use crate::stats::SharedRequestHook;
+use crate::supervisor::{self, Component, Policy};
+use crate::sync::TracingMutex;
+use crate::transport::{NodeAddr, Transport};
+use crate::{listen, node, spawn_named_in, Carrier, Error, CHANNEL_CAPACITY};
+use futures::channel::{mpsc, oneshot};
+use futures::prelude::*;
+use std::collections::{HashMap, HashSet};
+use std::mem;
+use std::ops::ControlFlow;
+use std::panic::AssertUnwindSafe;
+use std::pin::pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use tokio::sync::Semaphore;
+use tokio::task::JoinSet;
+use tokio_util::sync::CancellationToken;
+use tracing::{error, info};
+
+/// Result of a carrier task, with the node name for the outgoing loops.
+type TaskExit = (Option<NodeId>, Result<(), Error>);
+
+/// A node known to the running carrier.
+struct Registered {
+    /// Sender side of the outgoing queue, kept to close the queue on removal.
+    queue: mpsc::Sender<OutgoingMessage>,
+    /// Receiver side of the outgoing queue, kept for the next run.
+    outgoing: node::SharedOutgoing,
+    /// Stop flag of the outgoing loop, if the node is dialed.
+    removed: Option<Arc<AtomicBool>>,
+}
+
+struct Runtime<T> {
+    transport: Arc<T>,
+    shared: Arc<node::Shared>,
+    inbound: node::Inbound,
+    bus_channels: SharedChannels,
+    accepted: Arc<RwLock<HashSet<NodeId>>>,
+    registry: Registry,
+    incoming_added: mpsc::UnboundedSender<(NodeId, mpsc::Receiver<NodeCallback>)>,
+    request_hook: SharedRequestHook,
+    outgoing_policy: Policy,
+    tasks: JoinSet<TaskExit>,
+    registered: HashMap<NodeId, Registered>,
+    removals: HashMap<NodeId, oneshot::Sender<Result<(), RemoveError>>>,
+}
+
+/// Marks a carrier running until dropped, as its run returns or is dropped.
+struct Running(Arc<AtomicBool>);
+
+pub(crate) async fn run<T, F>(carrier: &mut Carrier, transport: T, shutdown: F) -> Result<(), Error>
+where
+    T: Transport,
+    F: Future + Send,
+{
+    let running = Running::start(&carrier.running);
+    let mut runtime = Runtime::start(carrier, transport)?;
+    let result = runtime.run(&mut carrier.commands, shutdown).await;
+    runtime.tasks.shutdown().await;
+    runtime.stop(carrier);
+    drop(running);
+    // Fails the commands sent as the carrier stopped, by dropping their
+    // replies.
+    while carrier.commands.try_recv().is_ok() {}
+    result
+}
+
+impl Running {
+    fn start(running: &Arc<AtomicBool>) -> Self {
+        running.store(true, Ordering::Release);
+        Self(Arc::clone(running))
+    }
+}
+
+impl Drop for Running {
+    fn drop(&mut self) {
+        self.0.store(false, Ordering::Release);
+    }
+}
+
+impl<T: Transport> Runtime<T> {
+    /// Spawns the tasks of a run of `carrier`.
+    #[allow(clippy::too_many_lines)]
+    fn start(carrier: &mut Carrier, transport: T) -> Result<Self, Error> {
+        let event_log = carrier
+            .event_log
+            .clone()
+            .map(|(path, max_size_bytes)| EventLog::open(path, max_size_bytes).map(Arc::new))
+            .transpose();
+        let event_log = match event_log {
+            Ok(event_log) => event_log,
+            Err(err) => {
+                carrier.errors.record(&err);
+                return Err(err);
+            }
+        };
+        #[cfg(feature = "otlp")]
+        if let Err(err) = carrier.otlp.start(carrier.nodes.len()) {
+            carrier.errors.record(&err);
+            return Err(err);
+        }
+        // The middlewares registered since the last run wrap the bus of the
+        // previous ones.
+        let layers = mem::take(&mut carrier.bus_layers);
+        carrier.bus = layers
+            .into_iter()
+            .fold(carrier.bus.clone(), |bus, layer| layer(bus));
+        let carrier = &*carrier;
+        let direction = |node: &str| carrier.directions.get(node).copied().unwrap_or_default();
+        let accepted = carrier
+            .nodes
+            .keys()
+            .filter(|node| direction(node) != Direction::Dial)
+            .cloned()
+            .collect::<HashSet<_>>();
+        let mut runtime = Runtime {
+            transport: Arc::new(transport),
+            shared: Arc::new(node::Shared {
+                hello: carrier.hello.clone(),
+                hooks: Arc::new(carrier.hooks.clone()),
+                compression: carrier.payload_compression,
+                auth: carrier.auth.clone(),
+                deduplication: carrier.deduplication,
+                max_frame_len: carrier.max_frame_len,
+                codec: carrier.codec,
+                event_log,
+                events: carrier.events.clone(),
+                ordered: carrier.ordered.clone(),
+                lifecycle: carrier.lifecycle.clone(),
+                notification_dedup: Mutex::default(),
+                #[cfg(feature = "otel")]
+                otel: carrier.otel.clone(),
+                stats: Arc::clone(&carrier.stats),
+                errors: Arc::clone(&carrier.errors),
+                outgoing_failures: LogLimiter::default(),
+                incoming_failures: LogLimiter::default(),
+            }),
+            inbound: node::Inbound {
+                bus: carrier.bus.clone(),
+                notifications: carrier.notifications.clone(),
+                watch_slow: carrier.slow_request_threshold.is_some(),
+            },
+            bus_channels: Arc::clone(&carrier.incoming),
+            accepted: Arc::new(RwLock::new(accepted)),
+            registry: Arc::new(RwLock::new(carrier.nodes.clone())),
+            incoming_added: carrier.incoming_added.clone(),
+            request_hook: Arc::clone(&carrier.request_hook),
+            outgoing_policy: carrier.outgoing_policy,
+            tasks: JoinSet::new(),
+            registered: HashMap::new(),
+            removals: HashMap::new(),
+        };
+
+        let mut accept_only = HashMap::new();
+        for (node, queue) in &carrier.queues {
+            let outgoing = Arc::clone(&carrier.outgoing[node]);
+            let removed = if direction(node) == Direction::Accept {
+                accept_only.insert(node.clone(), Arc::clone(&outgoing));
+                None
+            } else {
+                Some(Arc::new(AtomicBool::new(false)))
+            };
+            let registered = Registered {
+                queue: queue.clone(),
+                outgoing,
+                removed,
+            };
+            runtime.registered.insert(node.clone(), registered);
+        }
+        let listening = !runtime.accepted.read().unwrap().is_empty();
+        if listening || !carrier.skip_unused_listener {
+            let handshakes = node::Handshakes {
+                semaphore: Semaphore::new(carrier.max_handshakes),
+                in_progress: Arc::clone(&carrier.handshakes),
+            };
+            runtime.spawn_listener(accept_only, handshakes, carrier.listener_policy);
+        }
+        for (node, outgoing) in &carrier.outgoing {
+            if direction(node) == Direction::Accept {
+                continue;
+            }
+            let inbound = (direction(node) == Direction::Dial).then(|| runtime.inbound.clone());
+            runtime.spawn_outgoing(node.clone(), Arc::clone(outgoing), inbound);
+        }
+        #[cfg(feature = "metrics")]
+        {
+            let stats = Arc::clone(&runtime.shared.stats);
+            let errors = crate::errors::ErrorAccounting::new(
+                Arc::clone(&stats),
+                Arc::clone(&runtime.shared.errors),
+            );
+            runtime.spawn("carrier-metrics", async move {
+                crate::metrics::publish(stats, errors).await;
+                (None, Ok(()))
+            });
+        }
+        if let Some(threshold) = carrier.slow_request_threshold {
+            let stats = Arc::clone(&runtime.shared.stats);
+            let lifecycle = runtime.shared.lifecycle.clone();
+            runtime.spawn("carrier-slow-requests", async move {
+                crate::slow::watch(stats, threshold, lifecycle).await;
+                (None, Ok(()))
+            });
+        }
+        {
+            let stats = Arc::clone(&runtime.shared.stats);
+            let lifecycle = runtime.shared.lifecycle.clone();
+            let threshold = carrier.flap_threshold;
+            runtime.spawn("carrier-churn", async move {
+                crate::churn::watch(stats, threshold, lifecycle).await;
+                (None, Ok(()))
+            });
+        }
+        if let Some(interval) = carrier.status_summary {
+            let stats = Arc::clone(&runtime.shared.stats);
+            let errors = Arc::clone(&runtime.shared.errors);
+            runtime.spawn("carrier-summary", async move {
+                crate::summary::watch(stats, errors, interval).await;
+                (None, Ok(()))
+            });
+        }
+        if carrier.shutdown_on_handles_dropped {
+            let handles = carrier.handles.clone();
+            let errors = Arc::clone(&runtime.shared.errors);
+            runtime.spawn("carrier-handles", async move {
+                handles.await;
+                info!("Incoming and Outgoing handles dropped, shutting down");
+                errors.record(&Error::HandlesDropped);
+                (None, Err(Error::HandlesDropped))
+            });
+        }
+        Ok(runtime)
+    }
+
+    /// Keeps the nodes added and removed during the run in `carrier`, for its
+    /// next run.
+    fn stop(self, carrier: &mut Carrier) {
+        carrier.nodes.clone_from(&self.registry.read().unwrap());
+        carrier.queues = self
+            .registered
+            .iter()
+            .map(|(node, registered)| (node.clone(), registered.queue.clone()))
+            .collect();
+        carrier.outgoing = self
+            .registered
+            .into_iter()
+            .map(|(node, registered)| (node, registered.outgoing))
+            .collect();
+    }
+
+    async fn run(
+        &mut self,
+        commands: &mut mpsc::UnboundedReceiver<Command>,
+        shutdown: impl Future + Send,
+    ) -> Result<(), Error> {
+        let mut shutdown = pin!(shutdown.fuse());
+        // Nodes whose outgoing loops are yet to drain, once shutting down.
+        let mut draining: Option<HashSet<NodeId>> = None;
+        // The set aborts the remaining tasks if this future is dropped.
+        loop {
+            futures::select! {
+                exit = self.tasks.join_next().fuse() => {
+                    let (node, result) = match exit {
+                        Some(Ok(exit)) => exit,
+                        // The tasks catch their panics, and are only
+                        // cancelled as the set is dropped.
+                        Some(Err(_)) => continue,
+                        None => return Ok(()),
+                    };
+                    let drained = match (&mut draining, &node) {
+                        (Some(draining), Some(node)) => draining.remove(node),
+                        _ => false,
+                    };
+                    if let Some(reply) = node.and_then(|node| self.removals.remove(&node)) {
+                        let _ = reply.send(Ok(()));
+                    } else if !drained || result.is_err() {
+                        return result;
+                    }
+                    if draining.as_ref().is_some_and(HashSet::is_empty) {
+                        return Ok(());
+                    }
+                }
+                _ = shutdown => {
+                    info!("Shutting down, draining the outgoing connections");
+                    let nodes = self.drain();
+                    if nodes.is_empty() {
+                        return Ok(());
+                    }
+                    draining = Some(nodes);
+                }
+                command = commands.select_next_some() => match command {
+                    Command::AddNode { reply, .. } if draining.is_some() => {
+                        let _ = reply.send(Err(AddError::Stopped));
+                    }
+                    Command::AddNode { node, addr, reply } => {
+                        let _ = reply.send(self.add_node(node, addr));
+                    }
+                    Command::RemoveNode { node, reply } => self.remove_node(node, reply),
+                    Command::SetAddr { node, addr, reply } => {
+                        let _ = reply.send(self.set_addr(node, addr));
+                    }
+                },
+            }
+        }
+    }
+
+    fn spawn_listener(
+        &mut self,
+        accept_only: HashMap<NodeId, node::SharedOutgoing>,
+        handshakes: node::Handshakes,
+        policy: Policy,
+    ) {
+        let context = node::IncomingContext {
+            nodes: Arc::clone(&self.accepted),
+            registry: Arc::clone(&self.registry),
+            accept_only: Arc::new(accept_only),
+            inbound: self.inbound.clone(),
+            shared: Arc::clone(&self.shared),
+            handshakes: Arc::new(handshakes),
+        };
+        let transport = Arc::clone(&self.transport);
+        self.spawn("carrier-accept", async move {
+            // Closes the incoming connections once the run ends, as the task
+            // is aborted, whether the run returns or is dropped.
+            let connections = CancellationToken::new();
+            let _connections = connections.clone().drop_guard();
+            loop {
+                let lifecycle = &context.shared.lifecycle;
+                let listen = listen(
+                    Arc::clone(&transport),
+                    context.clone(),
+                    lifecycle,
+                    &connections,
+                    node::incoming,
+                );
+                let result = supervisor::catch_panic("carrier-accept", listen).await;
+                let errors = &context.shared.errors;
+                let exit = supervisor::handle_exit(&Component::Listener, policy, errors, result);
+                if let ControlFlow::Break(result) = exit.await {
+                    return (None, result);
+                }
+            }
+        });
+    }
+
+    fn spawn_outgoing(
+        &mut self,
+        node: NodeId,
+        outgoing: node::SharedOutgoing,
+        inbound: Option<node::Inbound>,
+    ) {
+        let removed = self.registered[&node].removed.clone().unwrap();
+        let registry = Arc::clone(&self.registry);
+        let transport = Arc::clone(&self.transport);
+        let shared = Arc::clone(&self.shared);
+        let policy = self.outgoing_policy;
+        let component = Component::Outgoing(node.clone());
+        let name = format!("carrier-out:{node}");
+        let task = name.clone();
+        self.spawn(&name, async move {
+            let mut outgoing = outgoing.lock().await;
+            loop {
+                let outgoing = node::outgoing(
+                    node.clone(),
+                    Arc::clone(&registry),
+                    Arc::clone(&transport),
+                    &mut outgoing,
+                    &removed,
+                    Arc::clone(&shared),
+                    inbound.clone(),
+                );
+                let result = supervisor::catch_panic(&task, outgoing).await;
+                let errors = &shared.stats(&node).errors;
+                let exit = supervisor::handle_exit(&component, policy, errors, result);
+                if let ControlFlow::Break(result) = exit.await {
+                    return (Some(node), result);
+                }
+            }
+        });
+    }
+
+    /// Spawns a task of the run, named as in [`spawn_named_in`]. A panic of
+    /// the task is logged and fails the run with an [`Error::Panicked`].
+    fn spawn<F>(&mut self, name: &str, task: F)
+    where
+        F: Future<Output = TaskExit> + Send + 'static,
+    {
+        let errors = Arc::clone(&self.shared.errors);
+        let task_name = name.to_string();
+        let task = AssertUnwindSafe(task).catch_unwind().map(move |exit| {
+            exit.unwrap_or_else(|payload| {
+                let err = supervisor::panicked(&task_name, &*payload);
+                error!("{err}");
+                errors.record(&err);
+                (None, Err(err))
+            })
+        });
+        spawn_named_in(&mut self.tasks, name, task);
+    }
+
+    fn add_node(&mut self, node: NodeId, addr: NodeAddr) -> Result<Outgoing, AddError> {
+        if self.registered.contains_key(&node) {
+            return Err(AddError::Exists(node));
+        }
+        let (incoming_tx, incoming_rx) = mpsc::channel(CHANNEL_CAPACITY);
+        let (outgoing_tx, outgoing_rx) = mpsc::channel(CHANNEL_CAPACITY);
+        self.bus_channels
+            .write()
+            .unwrap()
+            .insert(node.clone(), incoming_tx);
+        self.accepted.write().unwrap().insert(node.clone());
+        self.registry.write().unwrap().insert(node.clone(), addr);
+        self.shared
+            .stats
+            .write()
+            .unwrap()
+            .insert(node.clone(), Arc::default());
+        let _ = self
+            .incoming_added
+            .unbounded_send((node.clone(), incoming_rx));
+        let outgoing_rx = node::OutgoingQueue::new(outgoing_rx);
+        let outgoing_rx = Arc::new(TracingMutex::new("outgoing", outgoing_rx));
+        let registered = Registered {
+            queue: outgoing_tx.clone(),
+            outgoing: Arc::clone(&outgoing_rx),
+            removed: Some(Arc::new(AtomicBool::new(false))),
+        };
+        self.registered.insert(node.clone(), registered);
+        self.spawn_outgoing(node.clone(), outgoing_rx, None);
+        info!("Added node {node}");
+        // The guard receiver is dropped, as the handle of a single node
+        // doesn't count for `shutdown_on_handles_dropped`.
+        let (guard, _) = oneshot::channel();
+        let channels = HashMap::from([(node, outgoing_tx)]);
+        Ok(Outgoing::new(
+            channels,
+            &self.shared.stats,
+            Arc::clone(&self.request_hook),
+            guard,
+        ))
+    }
+
+    fn remove_node(&mut self, node: NodeId, reply: oneshot::Sender<Result<(), RemoveError>>) {
+        let Some(mut registered) = self.registered.remove(&node) else {
+            let _ = reply.send(Err(RemoveError::NotFound(node)));
+            return;
+        };
+        self.accepted.write().unwrap().remove(&node);
+        self.registry.write().unwrap().remove(&node);
+        self.shared.stats.write().unwrap().remove(&node);
+        if let Some(mut channel) = self.bus_channels.write().unwrap().remove(&node) {
+            channel.close_channel();
+        }
+        registered.queue.close_channel();
+        info!("Removing node {node}");
+        if let Some(removed) = registered.removed {
+            removed.store(true, Ordering::Release);
+            self.removals.insert(node, reply);
+        } else {
+            let _ = reply.send(Ok(()));
+        }
+    }
+
+    /// Closes the outgoing queues of every node, for the outgoing loops to
+    /// return once the messages queued are written and the requests in flight
+    /// answered. Returns the nodes whose loops are still running.
+    fn drain(&mut self) -> HashSet<NodeId> {
+        let mut draining = HashSet::new();
+        for (node, registered) in &mut self.registered {
+            registered.queue.close_channel();
+            if let Some(removed) = &registered.removed {
+                removed.store(true, Ordering::Release);
+                draining.insert(node.clone());
+            }
+        }
+        draining
+    }
+
+    fn set_addr(&mut self, node: NodeId, addr: NodeAddr) -> Result<(), RemoveError> {
+        let mut registry = self.registry.write().unwrap();
+        let Some(current) = registry.get_mut(&node) else {
+            return Err(RemoveError::NotFound(node));
+        };
+        info!(
+            "Re-pointing node {node} from {}:{} to {}:{}",
+            current.host, current.port, addr.host, addr.port
+        );
+        *current = addr;
+        Ok(())
+    }
+}
@@ -0,0 +1,205 @@
+//! Detection of the slow requests.
+//!
+//! With [`Carrier::slow_request_threshold`](crate::Carrier::slow_request_threshold)
+//! set, a single task sweeps the requests in flight of every node: the
+//! requests written to a connection and awaiting their responses, and the
+//! requests dispatched to the application and awaiting its answers. A request
+//! found older than the threshold is reported once, as a warning and a
+//! [`CarrierEvent::SlowRequest`], without failing it. Once it completes, is
+//! given up by its caller or loses its connection, it is reported again with
+//! its final latency, as a [`CarrierEvent::SlowRequestFinished`].
+
+use crate::config::NodeId;
+use crate::lifecycle::{CarrierEvent, Lifecycle, SlowOutcome};
+use crate::middleware::Direction;
+use crate::stats::{NodeStats, Stats};
+use std::collections::HashMap;
+use std::mem;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::time;
+use tracing::{info, warn};
+
+/// Longest interval between two sweeps, whatever the threshold.
+const MAX_SWEEP_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Reporting of a request in flight.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub(crate) enum Watch {
+    /// Younger than the threshold when last swept.
+    #[default]
+    Timely,
+    /// Reported slow, its completion yet to be reported.
+    Slow,
+    /// Reported slow and given up, its completion no longer reported.
+    Done,
+}
+
+/// Slow requests of a node, in its [`NodeStats`].
+#[derive(Default)]
+pub(crate) struct SlowRequests {
+    /// Requests from the node awaiting the answers of the application, by
+    /// token, with the threshold set.
+    handling: Mutex<HashMap<u64, Handling>>,
+    next_token: AtomicU64,
+    /// Slow requests finished since the last sweep.
+    finished: Mutex<Vec<Finished>>,
+}
+
+/// Request from a node awaiting the answer of the application.
+struct Handling {
+    received: Instant,
+    id_hash: u64,
+    watch: Watch,
+}
+
+/// Slow request finished, to be reported by the next sweep.
+struct Finished {
+    direction: Direction,
+    id_hash: u64,
+    latency: Duration,
+    outcome: SlowOutcome,
+}
+
+impl SlowRequests {
+    /// Registers a request from the node dispatched to the application, and
+    /// returns its token.
+    pub(crate) fn handling(&self, id_hash: u64) -> u64 {
+        let token = self.next_token.fetch_add(1, Ordering::Relaxed);
+        let handling = Handling {
+            received: Instant::now(),
+            id_hash,
+            watch: Watch::Timely,
+        };
+        self.handling.lock().unwrap().insert(token, handling);
+        token
+    }
+
+    /// Unregisters the request of `token`, `answered` by the application or
+    /// not.
+    pub(crate) fn handled(&self, token: u64, answered: bool) {
+        let Some(handling) = self.handling.lock().unwrap().remove(&token) else {
+            return;
+        };
+        if handling.watch == Watch::Slow {
+            let outcome = if answered {
+                SlowOutcome::Completed
+            } else {
+                SlowOutcome::Closed
+            };
+            let latency = handling.received.elapsed();
+            self.finish(Direction::Incoming, handling.id_hash, latency, outcome);
+        }
+    }
+
+    /// Records the completion of a request to the node written at `written`,
+    /// if reported slow.
+    pub(crate) fn completed(
+        &self,
+        watch: Watch,
+        id_hash: u64,
+        written: Instant,
+        outcome: SlowOutcome,
+    ) {
+        if watch == Watch::Slow {
+            self.finish(Direction::Outgoing, id_hash, written.elapsed(), outcome);
+        }
+    }
+
+    fn finish(&self, direction: Direction, id_hash: u64, latency: Duration, outcome: SlowOutcome) {
+        self.finished.lock().unwrap().push(Finished {
+            direction,
+            id_hash,
+            latency,
+            outcome,
+        });
+    }
+}
+
+/// Sweeps the requests in flight of every node for the ones older than
+/// `threshold`, forever.
+pub(crate) async fn watch(stats: Stats, threshold: Duration, lifecycle: Lifecycle) {
+    let mut interval = time::interval((threshold / 4).min(MAX_SWEEP_INTERVAL));
+    interval.set_missed_tick_behavior(time::MissedTickBehavior::Delay);
+    loop {
+        interval.tick().await;
+        let nodes = stats
+            .read()
+            .unwrap()
+            .iter()
+            .map(|(node, stats)| (node.clone(), Arc::clone(stats)))
+            .collect::<Vec<_>>();
+        for (node, stats) in nodes {
+            sweep(&node, &stats, threshold, &lifecycle);
+        }
+    }
+}
+
+fn sweep(node: &NodeId, stats: &NodeStats, threshold: Duration, lifecycle: &Lifecycle) {
+    let now = Instant::now();
+    let mut slow = Vec::new();
+    for request in stats.pending.lock().unwrap().values_mut() {
+        let age = now.duration_since(request.written);
+        match request.watch {
+            Watch::Timely if age >= threshold => {
+                request.watch = Watch::Slow;
+                slow.push((Direction::Outgoing, request.id_hash, age));
+            }
+            Watch::Slow if request.callback.is_canceled() => {
+                request.watch = Watch::Done;
+                let outcome = SlowOutcome::Abandoned;
+                stats
+                    .slow
+                    .finish(Direction::Outgoing, request.id_hash, age, outcome);
+            }
+            _ => {}
+        }
+    }
+    for handling in stats.slow.handling.lock().unwrap().values_mut() {
+        let age = now.duration_since(handling.received);
+        if handling.watch == Watch::Timely && age >= threshold {
+            handling.watch = Watch::Slow;
+            slow.push((Direction::Incoming, handling.id_hash, age));
+        }
+    }
+    for (direction, request_id_hash, age) in slow {
+        warn!(
+            node = %node,
+            ?direction,
+            request_id_hash = format_args!("{request_id_hash:x}"),
+            "Slow request in flight for {age:?}"
+        );
+        lifecycle.emit(|at| CarrierEvent::SlowRequest {
+            at,
+            node: node.to_string(),
+            direction,
+            age,
+            request_id_hash,
+        });
+    }
+    let finished = mem::take(&mut *stats.slow.finished.lock().unwrap());
+    for finished in finished {
+        let Finished {
+            direction,
+            id_hash: request_id_hash,
+            latency,
+            outcome,
+        } = finished;
+        info!(
+            node = %node,
+            ?direction,
+            request_id_hash = format_args!("{request_id_hash:x}"),
+            %outcome,
+            "Slow request finished after {latency:?}"
+        );
+        lifecycle.emit(|at| CarrierEvent::SlowRequestFinished {
+            at,
+            node: node.to_string(),
+            direction,
+            latency,
+            request_id_hash,
+            outcome,
+        });
+    }
+}
@@ -0,0 +1,785 @@
+//! Communication statistics.
+
+use crate::churn::Churn;
+use crate::config::NodeId;
+use crate::errors::ErrorCounts;
+use crate::loss::NodeLosses;
+use crate::messages::{NodeNotification, NodeResponse};
+use crate::node::RequestKey;
+use crate::slow::{SlowRequests, Watch};
+use futures::channel::oneshot;
+use serde::{Serialize, Serializer};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
+
+/// Default upper bounds of the [`LatencyHistogram`] buckets, in seconds: from
+/// 100 microseconds to 1 second.
+pub const DEFAULT_BUCKETS: &[f64] = &[0.000_1, 0.000_5, 0.001, 0.005, 0.01, 0.05, 0.1, 0.5, 1.0];
+
+/// Prometheus-style histogram of the response latencies of a node, measured
+/// from writing a request to the connection until receiving its response.
+#[derive(Debug)]
+pub struct LatencyHistogram {
+    buckets: &'static [f64],
+    /// Count of every bucket, followed by the count above the last bound.
+    counts: Vec<AtomicU64>,
+    sum_us: AtomicU64,
+}
+
+/// Weight of a new sample in the rolling [`ClockEstimate`], as the smoothing
+/// of the TCP round-trip time.
+const CLOCK_GAIN: f64 = 0.125;
+
+/// Interval between the reports of a [`ClockEstimate`] in the log.
+const CLOCK_REPORT_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Weight of a new sample in the [`RttEstimate::smoothed`] round-trip time, as
+/// in RFC 6298.
+const RTT_GAIN: f64 = 0.125;
+
+/// Weight of a new sample in the [`RttEstimate::var`]iation, as in RFC 6298.
+const RTT_VAR_GAIN: f64 = 0.25;
+
+/// Time without a sample after which an [`RttEstimate`] is stale: three
+/// missed [heartbeats](crate::node::HEARTBEAT_INTERVAL).
+pub const RTT_STALE_AFTER: Duration =
+    Duration::from_secs(3 * crate::node::HEARTBEAT_INTERVAL.as_secs());
+
+/// Statistics of the nodes, shared with the connections and the channels.
+pub(crate) type Stats = Arc<RwLock<HashMap<NodeId, Arc<NodeStats>>>>;
+
+/// Requests written to the connection, by request key.
+pub(crate) type Callbacks = HashMap<RequestKey, InFlight>;
+
+/// Request written to the connection, awaiting its response.
+pub(crate) struct InFlight {
+    pub(crate) callback: oneshot::Sender<NodeResponse>,
+    /// Time the request was written.
+    pub(crate) written: Instant,
+    /// Hash of the `request_id`.
+    pub(crate) id_hash: u64,
+    pub(crate) watch: Watch,
+}
+
+/// Notifications sent with
+/// [`Outgoing::notify_acked`](crate::channels::Outgoing::notify_acked) and not
+/// yet acknowledged, in the order of sending, with their callbacks.
+pub(crate) type Unacked = VecDeque<(NodeNotification, oneshot::Sender<()>)>;
+
+/// Live statistics of a node, updated by its connections and channels.
+#[derive(Default)]
+pub(crate) struct NodeStats {
+    pub(crate) latency: Arc<LatencyHistogram>,
+    /// Number of the open connections with the node.
+    pub(crate) connections: AtomicUsize,
+    /// Number of the requests from the node not yet taken from
+    /// [`Incoming`](crate::channels::Incoming).
+    pub(crate) incoming_queue: AtomicUsize,
+    /// Number of the messages to the node not yet written to a connection.
+    pub(crate) outgoing_queue: AtomicUsize,
+    /// Number of the requests from the node dispatched to the application and
+    /// not yet answered.
+    pub(crate) awaiting_response: AtomicUsize,
+    /// Callbacks of the requests written to the connection.
+    pub(crate) pending: Mutex<Callbacks>,
+    /// Acknowledged notifications awaiting their acknowledgements, kept
+    /// across the connections to be written again on every new one.
+    pub(crate) unacked: Mutex<Unacked>,
+    /// Largest frames accepted by this node and by the node, as negotiated
+    /// by the last connection.
+    pub(crate) frame_limits: Mutex<Option<(usize, usize)>>,
+    pub(crate) bytes_sent: Arc<AtomicU64>,
+    pub(crate) bytes_received: Arc<AtomicU64>,
+    pub(crate) frames_sent: Arc<AtomicU64>,
+    pub(crate) frames_received: Arc<AtomicU64>,
+    /// Number of the request and notification frames sent with a compressed
+    /// payload.
+    pub(crate) compressed_frames: AtomicU64,
+    /// Number of the request and notification frames sent with the payload
+    /// as is.
+    pub(crate) uncompressed_frames: AtomicU64,
+    /// Total size of the compressed payloads before compression.
+    pub(crate) bytes_before_compression: AtomicU64,
+    /// Total size of the compressed payloads after compression.
+    pub(crate) bytes_after_compression: AtomicU64,
+    /// Number of the received requests and responses which failed the
+    /// authentication.
+    pub(crate) auth_failures: AtomicU64,
+    /// Number of the received envelopes of a kind unknown to this node.
+    pub(crate) unknown_envelopes: AtomicU64,
+    /// Sequence number of the last envelope written to a connection with the
+    /// node.
+    pub(crate) last_sent_seq: AtomicU64,
+    /// Sequence number of the last envelope read from a connection with the
+    /// node.
+    pub(crate) last_received_seq: AtomicU64,
+    /// Number of the gaps in the sequence numbers of the received envelopes.
+    pub(crate) sequence_gaps: AtomicU64,
+    pub(crate) clock: Mutex<ClockEstimate>,
+    /// Round-trip time of the network, since the last connection was
+    /// established.
+    pub(crate) rtt: Mutex<RttEstimator>,
+    /// Time every open connection with the node was established.
+    pub(crate) established: Mutex<Vec<Instant>>,
+    /// Whether a connection to the node is being dialed or served.
+    pub(crate) dialing: AtomicBool,
+    /// Time of the next attempt to connect to the node, while waiting for it.
+    pub(crate) retry_at: Mutex<Option<Instant>>,
+    pub(crate) churn: Churn,
+    pub(crate) losses: NodeLosses,
+    pub(crate) slow: SlowRequests,
+    pub(crate) errors: ErrorCounts,
+    pub(crate) last_error: Mutex<Option<String>>,
+}
+
+/// Rolling estimate of the round-trip time and clock offset of a node, from
+/// the timestamps of the envelopes of the requests and their responses.
+#[derive(Debug, Default)]
+pub(crate) struct ClockEstimate {
+    /// Round-trip time and clock offset, in microseconds, once sampled.
+    estimate: Option<(f64, f64)>,
+    /// Time the estimate was last reported.
+    reported: Option<Instant>,
+}
+
+/// Rolling estimate of the round-trip time of the network to a node, seeded
+/// from the heartbeats of the connections and refined from the requests
+/// answered by nodes reporting their handling time.
+#[derive(Debug, Default)]
+pub(crate) struct RttEstimator {
+    /// Smoothed round-trip time and its variation, in microseconds, once
+    /// sampled.
+    estimate: Option<(f64, f64)>,
+    samples: u64,
+    /// Time of the last sample, on the clock of tokio to be paused in tests.
+    sampled: Option<tokio::time::Instant>,
+}
+
+/// Estimate of the round-trip time of the network to a node, without the
+/// handling of the requests by the node, returned by
+/// [`CarrierHandle::rtt`](crate::control::CarrierHandle::rtt).
+///
+/// The estimate restarts with every connection established with the node.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+pub struct RttEstimate {
+    /// Smoothed round-trip time, as the SRTT of RFC 6298, serialized in
+    /// seconds.
+    #[serde(serialize_with = "serialize_duration")]
+    pub smoothed: Duration,
+    /// Smoothed mean deviation of the samples from the
+    /// [`smoothed`](Self::smoothed) round-trip time, as the RTTVAR of RFC
+    /// 6298, serialized in seconds.
+    #[serde(serialize_with = "serialize_duration")]
+    pub var: Duration,
+    /// Number of the samples since the connection was established.
+    pub samples: u64,
+    /// Whether no sample arrived for [`RTT_STALE_AFTER`], as the node stopped
+    /// answering the heartbeats.
+    pub stale: bool,
+}
+
+/// Timing of a request sent with [`Outgoing`](crate::channels::Outgoing),
+/// passed to the hook of
+/// [`Carrier::on_request_complete`](crate::Carrier::on_request_complete).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RequestTiming {
+    /// Node the request was sent to.
+    pub node: NodeId,
+    /// Hash of the `request_id`, to correlate the timings with the requests
+    /// without keeping their ids.
+    pub request_id_hash: u64,
+    /// Time from the request queued until written to the connection, or
+    /// until completed if never written.
+    pub queued: Duration,
+    /// Time from the request written to the connection until completed, zero
+    /// if never written.
+    pub on_wire: Duration,
+    /// Time from the request queued until completed.
+    pub total: Duration,
+    /// How the request completed.
+    pub outcome: RequestOutcome,
+}
+
+/// Completion of a request, in a [`RequestTiming`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum RequestOutcome {
+    /// Answered with the [`OK`](crate::status::OK) status.
+    Ok,
+    /// Answered with another [`status`](crate::status).
+    Remote(u32),
+    /// Failed without a response, such as on a closed connection.
+    Failed,
+    /// Given up by the caller before the response, such as on the attempt
+    /// timeout of a [`RetryOutgoing`](crate::middleware::RetryOutgoing).
+    Abandoned,
+}
+
+/// State of the connections with a node, in a [`NodeState`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+#[non_exhaustive]
+pub enum ConnectionState {
+    /// No connection, and none attempted, such as for a node only accepted
+    /// which has not connected yet.
+    #[default]
+    Disconnected,
+    /// A connection dialed, or open and not yet through its handshakes.
+    Connecting,
+    /// A connection through its handshakes.
+    Connected,
+    /// Waiting to connect again after a failure.
+    Backoff,
+}
+
+/// Hook of [`Carrier::on_request_complete`](crate::Carrier::on_request_complete).
+pub(crate) type RequestHook = Arc<dyn Fn(RequestTiming) + Send + Sync>;
+
+/// [`RequestHook`] of a carrier, set after its [`Outgoing`] channels are
+/// created.
+///
+/// [`Outgoing`]: crate::channels::Outgoing
+pub(crate) type SharedRequestHook = Arc<RwLock<Option<RequestHook>>>;
+
+/// Snapshot of the state of a [`Carrier`](crate::Carrier) for diagnostics,
+/// returned by [`Carrier::debug_state`](crate::Carrier::debug_state).
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct DebugState {
+    /// State of every configured node, by name.
+    pub nodes: BTreeMap<String, NodeState>,
+}
+
+/// Snapshot of the state of a node.
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct NodeState {
+    /// State of the connections with the node.
+    pub connection: ConnectionState,
+    /// Time since the oldest open connection with the node was established,
+    /// serialized in seconds.
+    #[serde(serialize_with = "serialize_seconds")]
+    pub connection_age: Option<Duration>,
+    /// Time until the next attempt to connect to the node, while waiting for
+    /// it after a failure, serialized in seconds.
+    #[serde(serialize_with = "serialize_seconds")]
+    pub retry_in: Option<Duration>,
+    /// Number of the open connections with the node.
+    pub connections: usize,
+    /// Number of the requests from the node not yet taken from
+    /// [`Incoming`](crate::channels::Incoming).
+    pub incoming_queue: usize,
+    /// Number of the messages to the node not yet written to a connection.
+    pub outgoing_queue: usize,
+    /// Number of the requests written to the node and awaiting a response.
+    pub inflight: usize,
+    /// Number of the acknowledged notifications sent to the node and awaiting
+    /// their acknowledgements.
+    pub unacked_notifications: usize,
+    /// Time since the oldest of the [`inflight`](Self::inflight) requests was
+    /// written, serialized in seconds.
+    #[serde(serialize_with = "serialize_seconds")]
+    pub oldest_inflight: Option<Duration>,
+    /// Hash of the `request_id` of the oldest of the
+    /// [`inflight`](Self::inflight) requests, as in the
+    /// [`RequestTiming`]s.
+    pub oldest_inflight_id_hash: Option<u64>,
+    /// Number of the requests from the node dispatched to the application and
+    /// not yet answered, including the [`incoming_queue`](Self::incoming_queue).
+    pub awaiting_response: usize,
+    /// Total bytes written to the connections with the node.
+    pub bytes_sent: u64,
+    /// Total bytes read from the connections with the node.
+    pub bytes_received: u64,
+    /// Number of the frames written to the connections with the node,
+    /// including the handshakes and the heartbeats.
+    pub frames_sent: u64,
+    /// Number of the frames read from the connections with the node.
+    pub frames_received: u64,
+    /// Number of the request and notification frames sent with a compressed
+    /// payload. See [`PayloadCompression`](crate::compression::PayloadCompression).
+    pub compressed_frames: u64,
+    /// Number of the request and notification frames sent with the payload
+    /// as is.
+    pub uncompressed_frames: u64,
+    /// Total size of the compressed payloads before compression.
+    pub bytes_before_compression: u64,
+    /// Total size of the compressed payloads after compression.
+    pub bytes_after_compression: u64,
+    /// Number of the received requests and responses which failed the
+    /// authentication. See [`auth`](crate::auth).
+    pub auth_failures: u64,
+    /// Number of the received envelopes of a kind unknown to this node, sent
+    /// by a newer peer and skipped.
+    pub unknown_envelopes: u64,
+    /// Sequence number of the last envelope written to a connection with the
+    /// node, with [`Feature::FrameSeq`](crate::hello::Feature::FrameSeq)
+    /// negotiated. The numbering restarts from 1 on every connection.
+    pub last_sent_seq: u64,
+    /// Sequence number of the last envelope read from a connection with the
+    /// node.
+    pub last_received_seq: u64,
+    /// Number of the received envelopes whose sequence number did not follow
+    /// the previous one on their connection, which indicates a framing bug.
+    pub sequence_gaps: u64,
+    /// Rolling estimate of the round-trip time of the requests to the node,
+    /// from the timestamps of their envelopes, serialized in seconds. `None`
+    /// until a response to an enveloped request carries them.
+    #[serde(serialize_with = "serialize_seconds")]
+    pub rtt: Option<Duration>,
+    /// Estimate of the round-trip time of the network to the node, without
+    /// the handling of the requests. `None` until sampled on the current
+    /// connection.
+    pub network_rtt: Option<RttEstimate>,
+    /// Rolling estimate of the clock of the node minus the local clock, in
+    /// microseconds, by the midpoint of the round trips. `None` as
+    /// [`rtt`](Self::rtt).
+    pub clock_offset_us: Option<i64>,
+    /// Number of the connections established with the node after the first,
+    /// the node having gone down meanwhile. See [`churn`](crate::churn).
+    pub reconnects: u64,
+    /// Time without a connection with the node in the last
+    /// [`DOWNTIME_WINDOW`](crate::churn::DOWNTIME_WINDOW), serialized in
+    /// seconds.
+    #[serde(serialize_with = "serialize_duration")]
+    pub downtime_last_hour: Duration,
+    /// Whether the connections with the node go up and down more than
+    /// [`Carrier::flap_threshold`](crate::Carrier::flap_threshold).
+    pub flapping: bool,
+    /// Last error of a connection with the node.
+    pub last_error: Option<String>,
+}
+
+impl LatencyHistogram {
+    /// Creates an empty [`LatencyHistogram`] with the upper bounds of the
+    /// `buckets` in seconds, in increasing order.
+    #[must_use]
+    pub fn new(buckets: &'static [f64]) -> Self {
+        Self {
+            buckets,
+            counts: (0..=buckets.len()).map(|_| AtomicU64::new(0)).collect(),
+            sum_us: AtomicU64::new(0),
+        }
+    }
+
+    /// Records a response received after `latency`.
+    pub fn record(&self, latency: Duration) {
+        let seconds = latency.as_secs_f64();
+        let bucket = self
+            .buckets
+            .iter()
+            .position(|bound| seconds <= *bound)
+            .unwrap_or(self.buckets.len());
+        self.counts[bucket].fetch_add(1, Ordering::Relaxed);
+        let us = u64::try_from(latency.as_micros()).unwrap_or(u64::MAX);
+        self.sum_us.fetch_add(us, Ordering::Relaxed);
+    }
+
+    /// Returns the upper bounds of the buckets in seconds.
+    #[must_use]
+    pub fn buckets(&self) -> &'static [f64] {
+        self.buckets
+    }
+
+    /// Returns the number of the recorded latencies in every bucket, followed
+    /// by the number above the last bound.
+    #[must_use]
+    pub fn counts(&self) -> Vec<u64> {
+        self.counts
+            .iter()
+            .map(|count| count.load(Ordering::Relaxed))
+            .collect()
+    }
+
+    /// Returns the number of the recorded latencies.
+    #[must_use]
+    pub fn count(&self) -> u64 {
+        self.counts().iter().sum()
+    }
+
+    /// Returns the sum of the recorded latencies.
+    #[must_use]
+    pub fn sum(&self) -> Duration {
+        Duration::from_micros(self.sum_us.load(Ordering::Relaxed))
+    }
+
+    /// Returns the upper bound, in seconds, of the bucket holding the `p`-th
+    /// percentile of the recorded latencies, with `p` from 0 to 100. The
+    /// percentiles above the last bound are infinite.
+    ///
+    /// Returns [`None`] if nothing was recorded or `p` is out of range.
+    #[must_use]
+    pub fn percentile(&self, p: f64) -> Option<f64> {
+        if !(0.0..=100.0).contains(&p) {
+            return None;
+        }
+        let counts = self.counts();
+        let total = counts.iter().sum::<u64>();
+        if total == 0 {
+            return None;
+        }
+        #[allow(
+            clippy::cast_possible_truncation,
+            clippy::cast_precision_loss,
+            clippy::cast_sign_loss
+        )]
+        let rank = ((p / 100.0 * total as f64).ceil() as u64).max(1);
+        let mut cumulative = 0;
+        for (bucket, count) in counts.iter().enumerate() {
+            cumulative += count;
+            if cumulative >= rank {
+                return Some(self.buckets.get(bucket).copied().unwrap_or(f64::INFINITY));
+            }
+        }
+        Some(f64::INFINITY)
+    }
+}
+
+impl Default for LatencyHistogram {
+    fn default() -> Self {
+        Self::new(DEFAULT_BUCKETS)
+    }
+}
+
+impl NodeStats {
+    /// Records the failure of a connection with the node.
+    pub(crate) fn set_last_error(&self, err: &impl fmt::Display) {
+        *self.last_error.lock().unwrap() = Some(err.to_string());
+    }
+
+    pub(crate) fn state(&self) -> NodeState {
+        let (inflight, oldest_inflight) = {
+            let pending = self.pending.lock().unwrap();
+            let oldest = pending
+                .values()
+                .map(|request| (request.written, request.id_hash))
+                .min();
+            (pending.len(), oldest)
+        };
+        let established = self.established.lock().unwrap().iter().min().copied();
+        let retry_at = *self.retry_at.lock().unwrap();
+        let connections = self.connections.load(Ordering::Relaxed);
+        let connection = if established.is_some() {
+            ConnectionState::Connected
+        } else if retry_at.is_some() {
+            ConnectionState::Backoff
+        } else if connections > 0 || self.dialing.load(Ordering::Relaxed) {
+            ConnectionState::Connecting
+        } else {
+            ConnectionState::Disconnected
+        };
+        let clock = self.clock.lock().unwrap();
+        NodeState {
+            connection,
+            connection_age: established.map(|established| established.elapsed()),
+            retry_in: retry_at.map(|retry_at| retry_at.saturating_duration_since(Instant::now())),
+            connections,
+            incoming_queue: self.incoming_queue.load(Ordering::Relaxed),
+            outgoing_queue: self.outgoing_queue.load(Ordering::Relaxed),
+            inflight,
+            unacked_notifications: self.unacked.lock().unwrap().len(),
+            oldest_inflight: oldest_inflight.map(|(written, _)| written.elapsed()),
+            oldest_inflight_id_hash: oldest_inflight.map(|(_, id_hash)| id_hash),
+            awaiting_response: self.awaiting_response.load(Ordering::Relaxed),
+            bytes_sent: self.bytes_sent.load(Ordering::Relaxed),
+            bytes_received: self.bytes_received.load(Ordering::Relaxed),
+            frames_sent: self.frames_sent.load(Ordering::Relaxed),
+            frames_received: self.frames_received.load(Ordering::Relaxed),
+            compressed_frames: self.compressed_frames.load(Ordering::Relaxed),
+            uncompressed_frames: self.uncompressed_frames.load(Ordering::Relaxed),
+            bytes_before_compression: self.bytes_before_compression.load(Ordering::Relaxed),
+            bytes_after_compression: self.bytes_after_compression.load(Ordering::Relaxed),
+            auth_failures: self.auth_failures.load(Ordering::Relaxed),
+            unknown_envelopes: self.unknown_envelopes.load(Ordering::Relaxed),
+            last_sent_seq: self.last_sent_seq.load(Ordering::Relaxed),
+            last_received_seq: self.last_received_seq.load(Ordering::Relaxed),
+            sequence_gaps: self.sequence_gaps.load(Ordering::Relaxed),
+            rtt: clock.rtt(),
+            network_rtt: self.rtt.lock().unwrap().estimate(),
+            clock_offset_us: clock.offset_us(),
+            reconnects: self.churn.reconnects(),
+            downtime_last_hour: self.churn.downtime(),
+            flapping: self.churn.flapping(),
+            last_error: self.last_error.lock().unwrap().clone(),
+        }
+    }
+}
+
+impl ClockEstimate {
+    /// Adds the sample of a request sent at `sent_us` and answered at
+    /// `received_us` by the local clock, whose response was sent at `peer_us`
+    /// by the clock of the node, all in microseconds since the Unix epoch.
+    /// Returns whether the estimate is due to be reported.
+    pub(crate) fn observe(&mut self, sent_us: u64, peer_us: u64, received_us: u64) -> bool {
+        // The local clock stepped back meanwhile.
+        let Some(rtt) = received_us.checked_sub(sent_us) else {
+            return false;
+        };
+        #[allow(clippy::cast_precision_loss)]
+        let rtt = rtt as f64;
+        // The response was sent at the midpoint of the round trip.
+        #[allow(clippy::cast_precision_loss)]
+        let offset = peer_us as f64 - sent_us as f64 - rtt / 2.0;
+        self.estimate = Some(match self.estimate {
+            None => (rtt, offset),
+            Some((old_rtt, old_offset)) => (
+                old_rtt + (rtt - old_rtt) * CLOCK_GAIN,
+                old_offset + (offset - old_offset) * CLOCK_GAIN,
+            ),
+        });
+        if let Some(reported) = self.reported {
+            if reported.elapsed() < CLOCK_REPORT_INTERVAL {
+                return false;
+            }
+        }
+        self.reported = Some(Instant::now());
+        true
+    }
+
+    /// Returns the estimated round-trip time.
+    pub(crate) fn rtt(&self) -> Option<Duration> {
+        self.estimate
+            .map(|(rtt, _)| Duration::from_secs_f64(rtt / 1_000_000.0))
+    }
+
+    /// Returns the estimated clock offset of the node, in microseconds.
+    #[allow(clippy::cast_possible_truncation)]
+    pub(crate) fn offset_us(&self) -> Option<i64> {
+        self.estimate.map(|(_, offset)| offset.round() as i64)
+    }
+}
+
+impl RttEstimator {
+    /// Adds a sample of the round-trip time.
+    pub(crate) fn observe(&mut self, rtt: Duration) {
+        let rtt = rtt.as_secs_f64() * 1_000_000.0;
+        self.estimate = Some(match self.estimate {
+            None => (rtt, rtt / 2.0),
+            Some((smoothed, var)) => (
+                smoothed + (rtt - smoothed) * RTT_GAIN,
+                var + ((smoothed - rtt).abs() - var) * RTT_VAR_GAIN,
+            ),
+        });
+        self.samples += 1;
+        self.sampled = Some(tokio::time::Instant::now());
+    }
+
+    /// Forgets the samples, of a previous connection.
+    pub(crate) fn reset(&mut self) {
+        *self = Self::default();
+    }
+
+    pub(crate) fn estimate(&self) -> Option<RttEstimate> {
+        let (smoothed, var) = self.estimate?;
+        Some(RttEstimate {
+            smoothed: Duration::from_secs_f64(smoothed / 1_000_000.0),
+            var: Duration::from_secs_f64(var / 1_000_000.0),
+            samples: self.samples,
+            stale: self
+                .sampled
+                .is_some_and(|sampled| sampled.elapsed() >= RTT_STALE_AFTER),
+        })
+    }
+}
+
+/// Returns the hash of a `request_id` in a [`RequestTiming`] or a
+/// [`NodeState`].
+pub(crate) fn request_id_hash(request_id: &[u8]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    request_id.hash(&mut hasher);
+    hasher.finish()
+}
+
+impl DebugState {
+    pub(crate) fn new(stats: &Stats) -> Self {
+        let nodes = stats
+            .read()
+            .unwrap()
+            .iter()
+            .map(|(node, stats)| (node.to_string(), stats.state()))
+            .collect();
+        Self { nodes }
+    }
+}
+
+impl fmt::Display for DebugState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (node, state) in &self.nodes {
+            writeln!(f, "{node}: {state}")?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for NodeState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "connection={:?}", self.connection)?;
+        if let Some(age) = self.connection_age {
+            write!(f, " connection_age={age:?}")?;
+        }
+        if let Some(retry_in) = self.retry_in {
+            write!(f, " retry_in={retry_in:?}")?;
+        }
+        write!(
+            f,
+            " connections={} incoming_queue={} outgoing_queue={} inflight={}",
+            self.connections, self.incoming_queue, self.outgoing_queue, self.inflight
+        )?;
+        if let (Some(oldest), Some(id_hash)) = (self.oldest_inflight, self.oldest_inflight_id_hash)
+        {
+            write!(
+                f,
+                " oldest_inflight={oldest:?} oldest_inflight_id_hash={id_hash:x}"
+            )?;
+        }
+        if self.awaiting_response > 0 {
+            write!(f, " awaiting_response={}", self.awaiting_response)?;
+        }
+        if self.unacked_notifications > 0 {
+            write!(f, " unacked_notifications={}", self.unacked_notifications)?;
+        }
+        write!(
+            f,
+            " bytes_sent={} bytes_received={} frames_sent={} frames_received={}",
+            self.bytes_sent, self.bytes_received, self.frames_sent, self.frames_received
+        )?;
+        write!(
+            f,
+            " compressed_frames={} uncompressed_frames={}",
+            self.compressed_frames, self.uncompressed_frames
+        )?;
+        if self.compressed_frames > 0 {
+            write!(
+                f,
+                " bytes_before_compression={} bytes_after_compression={}",
+                self.bytes_before_compression, self.bytes_after_compression
+            )?;
+        }
+        if self.auth_failures > 0 {
+            write!(f, " auth_failures={}", self.auth_failures)?;
+        }
+        if self.unknown_envelopes > 0 {
+            write!(f, " unknown_envelopes={}", self.unknown_envelopes)?;
+        }
+        if let Some(rtt) = &self.network_rtt {
+            write!(f, " network_rtt={:?}±{:?}", rtt.smoothed, rtt.var)?;
+            if rtt.stale {
+                write!(f, " (stale)")?;
+            }
+        }
+        if self.reconnects > 0 {
+            write!(f, " reconnects={}", self.reconnects)?;
+        }
+        if !self.downtime_last_hour.is_zero() {
+            write!(f, " downtime_last_hour={:?}", self.downtime_last_hour)?;
+        }
+        if self.flapping {
+            write!(f, " flapping")?;
+        }
+        if let Some(err) = &self.last_error {
+            write!(f, " last_error={err:?}")?;
+        }
+        Ok(())
+    }
+}
+
+fn serialize_duration<S: Serializer>(
+    duration: &Duration,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    duration.as_secs_f64().serialize(serializer)
+}
+
+#[allow(clippy::ref_option)]
+fn serialize_seconds<S: Serializer>(
+    duration: &Option<Duration>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    duration
+        .map(|duration| duration.as_secs_f64())
+        .serialize(serializer)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn percentile_returns_bucket_bound() {
+        let histogram = LatencyHistogram::default();
+        assert_eq!(histogram.percentile(50.0), None);
+        for _ in 0..90 {
+            histogram.record(Duration::from_millis(3));
+        }
+        for _ in 0..10 {
+            histogram.record(Duration::from_secs(2));
+        }
+        assert_eq!(histogram.count(), 100);
+        assert_eq!(histogram.percentile(0.0), Some(0.005));
+        assert_eq!(histogram.percentile(90.0), Some(0.005));
+        assert_eq!(histogram.percentile(91.0), Some(f64::INFINITY));
+        assert_eq!(histogram.percentile(100.1), None);
+        assert_eq!(histogram.sum(), Duration::from_millis(90 * 3 + 10 * 2000));
+    }
+
+    #[test]
+    fn bound_is_inclusive() {
+        let histogram = LatencyHistogram::default();
+        histogram.record(Duration::from_millis(1));
+        assert_eq!(histogram.counts()[2], 1);
+    }
+
+    #[test]
+    fn clock_estimate_recovers_offset() {
+        let mut clock = ClockEstimate::default();
+        assert_eq!(clock.offset_us(), None);
+        // A node 300 ms ahead and 1 ms away, answering within 0.8 ms.
+        let mut sent_us = 1_700_000_000_000_000;
+        for sample in 0..100 {
+            let handling_us = sample % 5 * 200;
+            let peer_us = sent_us + 1_000 + handling_us + 300_000;
+            let received_us = sent_us + 2_000 + handling_us;
+            let reported = clock.observe(sent_us, peer_us, received_us);
+            assert_eq!(reported, sample == 0);
+            sent_us += 10_000;
+        }
+        let offset_us = clock.offset_us().unwrap();
+        assert!((300_000..=300_400).contains(&offset_us), "{offset_us}");
+        let rtt = clock.rtt().unwrap();
+        assert!(rtt >= Duration::from_millis(2) && rtt <= Duration::from_micros(2_800));
+    }
+
+    #[test]
+    fn rtt_estimate_follows_samples() {
+        let mut rtt = RttEstimator::default();
+        assert_eq!(rtt.estimate(), None);
+        rtt.observe(Duration::from_millis(10));
+        let seeded = rtt.estimate().unwrap();
+        assert_eq!(seeded.smoothed, Duration::from_millis(10));
+        assert_eq!(seeded.var, Duration::from_millis(5));
+        // A path 30 ms longer from then on.
+        for _ in 0..50 {
+            rtt.observe(Duration::from_millis(40));
+        }
+        let estimate = rtt.estimate().unwrap();
+        assert_eq!(estimate.samples, 51);
+        assert!(
+            estimate.smoothed > Duration::from_micros(39_900),
+            "{estimate:?}"
+        );
+        assert!(estimate.var < Duration::from_millis(1), "{estimate:?}");
+        assert!(!estimate.stale);
+        rtt.reset();
+        assert_eq!(rtt.estimate(), None);
+    }
+
+    #[test]
+    fn clock_stepping_back_ignored() {
+        let mut clock = ClockEstimate::default();
+        assert!(!clock.observe(2_000, 1_500, 1_000));
+        assert_eq!(clock.offset_us(), None);
+    }
+}
@@ -0,0 +1,45 @@
+//! Status codes of a [`NodeResponse`](crate::messages::NodeResponse).
+//!
+//! Zero means success. The codes below [`FIRST_APPLICATION`] are reserved for
+//! the carrier, and the rest are free for the applications. Peers built before
+//! the status codes ignore the field, so they take every response for a
+//! success.
+
+/// The request succeeded.
+pub const OK: u32 = 0;
+/// The request was dropped without a response, for example because its
+/// callback was dropped or the [`Incoming`](crate::channels::Incoming) channels
+/// are gone. Set by the carrier of the receiving node.
+pub const HANDLER_DROPPED: u32 = 1;
+/// The receiving node was overloaded and shed the request. Set by the
+/// [`LoadShedder`](crate::middleware::LoadShedder).
+pub const OVERLOADED: u32 = 2;
+/// The request was not handled in time. Reserved, not set by the carrier.
+pub const DEADLINE_EXCEEDED: u32 = 3;
+/// The receiving node does not support the request. Set by the carrier for a
+/// request to a service not routed with
+/// [`Incoming::route`](crate::channels::Incoming::route).
+pub const UNSUPPORTED: u32 = 4;
+/// The request is malformed. Set by the
+/// [`Validator`](crate::middleware::Validator), and by the carrier of the
+/// sending node for a request past the frame limit of the receiving node
+/// found when written, before the limit is known to the
+/// [`Outgoing`](crate::channels::Outgoing) channels.
+pub const INVALID: u32 = 5;
+/// The request belongs to another session or epoch than the receiving node
+/// accepts. Set by the [`EpochFilter`](crate::middleware::EpochFilter).
+pub const EPOCH_MISMATCH: u32 = 6;
+/// The message failed the authentication with the pre-shared key of the node.
+/// Set by the carrier with [`auth`](crate::auth) configured.
+pub const AUTH_FAILED: u32 = 7;
+/// The request was aborted by a middleware of the receiving node. Set by the
+/// [`IncomingPipeline`](crate::pipeline::IncomingPipeline).
+pub const REJECTED: u32 = 8;
+/// The payload of the request failed to decode into the type of its handler.
+/// Set by the [`Router`](crate::router::Router).
+pub const DECODE_FAILED: u32 = 9;
+/// The handler of the request panicked. Set by the
+/// [`Router`](crate::router::Router).
+pub const HANDLER_PANICKED: u32 = 10;
+/// First status code free for the applications.
+pub const FIRST_APPLICATION: u32 = 100;
@@ -0,0 +1,105 @@
+//! Periodic status summary in the log.
+//!
+//! With [`Carrier::status_summary`](crate::Carrier::status_summary) set, a
+//! single task logs the state of every node at the interval, for the
+//! operators without a metrics stack. It reads the same state as
+//! [`Carrier::debug_state`](crate::Carrier::debug_state) and
+//! [`Carrier::errors`](crate::Carrier::errors), whatever the features, and
+//! keeps the frame counts of the previous summary to log the differences.
+
+use crate::config::NodeId;
+use crate::errors::ErrorCounts;
+use crate::stats::{ConnectionState, NodeState, Stats};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::time::{self, Instant};
+use tracing::info;
+
+/// Totals of the summary of the carrier.
+#[derive(Default)]
+struct Totals {
+    nodes: usize,
+    connected: usize,
+    incoming_queue: usize,
+    outgoing_queue: usize,
+    inflight: usize,
+    frames_sent: u64,
+    frames_received: u64,
+    errors: u64,
+}
+
+/// Logs the summary of the nodes every `interval`, forever.
+pub(crate) async fn watch(stats: Stats, errors: Arc<ErrorCounts>, interval: Duration) {
+    let mut interval = time::interval_at(Instant::now() + interval, interval);
+    interval.set_missed_tick_behavior(time::MissedTickBehavior::Delay);
+    // Frames sent and received by node as of the previous summary.
+    let mut frames = HashMap::<NodeId, (u64, u64)>::new();
+    loop {
+        interval.tick().await;
+        let mut nodes = stats
+            .read()
+            .unwrap()
+            .iter()
+            .map(|(node, stats)| (node.clone(), stats.state(), stats.errors.total()))
+            .collect::<Vec<_>>();
+        nodes.sort_by(|(a, ..), (b, ..)| a.cmp(b));
+        let mut totals = Totals {
+            errors: errors.total(),
+            ..Totals::default()
+        };
+        let mut previous = HashMap::with_capacity(nodes.len());
+        for (node, state, errors) in &nodes {
+            let (sent, received) = frames.get(node).copied().unwrap_or_default();
+            let sent = state.frames_sent.saturating_sub(sent);
+            let received = state.frames_received.saturating_sub(received);
+            report(node, state, sent, received, *errors);
+            totals.add(state, sent, received, *errors);
+            previous.insert(node.clone(), (state.frames_sent, state.frames_received));
+        }
+        frames = previous;
+        info!(
+            "Status nodes={} connected={} incoming_queue={} outgoing_queue={} inflight={} \
+             frames_sent={} frames_received={} errors={}",
+            totals.nodes,
+            totals.connected,
+            totals.incoming_queue,
+            totals.outgoing_queue,
+            totals.inflight,
+            totals.frames_sent,
+            totals.frames_received,
+            totals.errors,
+        );
+    }
+}
+
+/// Logs the summary of `node`, which sent and received `sent` and `received`
+/// frames since the previous one.
+fn report(node: &NodeId, state: &NodeState, sent: u64, received: u64, errors: u64) {
+    let connected = if state.connection == ConnectionState::Connected {
+        "yes"
+    } else {
+        "no"
+    };
+    info!(
+        "Status node={node} connected={connected} connection={:?} incoming_queue={} \
+         outgoing_queue={} inflight={} frames_sent={sent} frames_received={received} \
+         errors={errors}",
+        state.connection, state.incoming_queue, state.outgoing_queue, state.inflight,
+    );
+}
+
+impl Totals {
+    fn add(&mut self, state: &NodeState, sent: u64, received: u64, errors: u64) {
+        self.nodes += 1;
+        if state.connection == ConnectionState::Connected {
+            self.connected += 1;
+        }
+        self.incoming_queue += state.incoming_queue;
+        self.outgoing_queue += state.outgoing_queue;
+        self.inflight += state.inflight;
+        self.frames_sent += sent;
+        self.frames_received += received;
+        self.errors += errors;
+    }
+}
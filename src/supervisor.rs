@@ -0,0 +1,144 @@
+//! Supervision of the carrier sub-tasks.
+
+use crate::config::NodeId;
+use crate::errors::ErrorCounts;
+use crate::Error;
+use futures::FutureExt;
+use std::any::Any;
+use std::fmt;
+use std::future::Future;
+use std::ops::ControlFlow;
+use std::panic::AssertUnwindSafe;
+use std::time::Duration;
+use tokio::time::sleep;
+use tracing::error;
+
+/// A supervised sub-task of a [`Carrier`](crate::Carrier).
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Component {
+    /// Listener for incoming connections.
+    Listener,
+    /// Outgoing connection loop to the node.
+    Outgoing(NodeId),
+}
+
+/// What to do when a supervised sub-task fails.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Policy {
+    /// Re-create the sub-task after the `backoff` interval.
+    Restart {
+        /// Delay before the restart.
+        backoff: Duration,
+    },
+    /// Stop the carrier and return the sub-task error.
+    Escalate,
+}
+
+impl fmt::Display for Component {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Listener => write!(f, "listener"),
+            Self::Outgoing(node) => write!(f, "outgoing to {node}"),
+        }
+    }
+}
+
+/// Runs `future`, turning its panic into an [`Error::Panicked`] of `task`, for
+/// the policy of its component to apply as to any other failure.
+pub(crate) async fn catch_panic<F>(task: &str, future: F) -> Result<(), Error>
+where
+    F: Future<Output = Result<(), Error>>,
+{
+    AssertUnwindSafe(future)
+        .catch_unwind()
+        .await
+        .unwrap_or_else(|payload| Err(panicked(task, &*payload)))
+}
+
+/// Returns the error of `task` panicking with `payload`.
+pub(crate) fn panicked(task: &str, payload: &(dyn Any + Send)) -> Error {
+    Error::Panicked {
+        task: task.to_string(),
+        message: panic_message(payload),
+    }
+}
+
+/// Returns the message of a panic with `payload`.
+pub(crate) fn panic_message(payload: &(dyn Any + Send)) -> String {
+    payload
+        .downcast_ref::<&str>()
+        .map(ToString::to_string)
+        .or_else(|| payload.downcast_ref::<String>().cloned())
+        .unwrap_or_else(|| "non-string payload".to_string())
+}
+
+/// Applies `policy` to the `result` of a terminated `component`, counting its
+/// failure in `errors`. Returns [`ControlFlow::Continue`] if the component
+/// should be restarted.
+pub(crate) async fn handle_exit(
+    component: &Component,
+    policy: Policy,
+    errors: &ErrorCounts,
+    result: Result<(), Error>,
+) -> ControlFlow<Result<(), Error>> {
+    let Err(err) = result else {
+        return ControlFlow::Break(Ok(()));
+    };
+    errors.record(&err);
+    match policy {
+        Policy::Restart { backoff } => {
+            error!("Component {component} failed, restarting in {backoff:?}: {err}");
+            sleep(backoff).await;
+            ControlFlow::Continue(())
+        }
+        Policy::Escalate => {
+            error!("Component {component} failed: {err}");
+            ControlFlow::Break(Err(Error::Component {
+                component: component.clone(),
+                source: Box::new(err),
+            }))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::errors::ErrorKind;
+    use futures::future;
+
+    async fn fail(task: &str) -> Result<(), Error> {
+        catch_panic(task, future::lazy(|_| panic!("boom"))).await
+    }
+
+    #[tokio::test]
+    async fn panic_restarts_component() {
+        let result = fail("carrier-accept").await;
+        let err = result.as_ref().unwrap_err();
+        assert_eq!(err.to_string(), "task carrier-accept panicked: boom");
+        assert_eq!(ErrorKind::from(err), ErrorKind::Panicked);
+        let errors = ErrorCounts::default();
+        let policy = Policy::Restart {
+            backoff: Duration::ZERO,
+        };
+        let exit = handle_exit(&Component::Listener, policy, &errors, result).await;
+        assert!(exit.is_continue());
+        assert_eq!(errors.total(), 1);
+    }
+
+    #[tokio::test]
+    async fn panic_escalates_naming_component() {
+        let result = fail("carrier-out:b").await;
+        let component = Component::Outgoing(NodeId::from("b"));
+        let errors = ErrorCounts::default();
+        let exit = handle_exit(&component, Policy::Escalate, &errors, result).await;
+        let ControlFlow::Break(Err(err)) = exit else {
+            panic!("not escalated");
+        };
+        assert!(matches!(&err, Error::Component { component: c, .. } if *c == component));
+        assert_eq!(
+            err.to_string(),
+            "outgoing to b: task carrier-out:b panicked: boom"
+        );
+    }
+}
@@ -0,0 +1,51 @@
+//! Synchronization primitives.
+
+use futures::lock::{Mutex, MutexGuard};
+use std::ops::{Deref, DerefMut};
+use std::time::Instant;
+use tracing::trace;
+
+/// Async mutex recording the time spent waiting for the lock, to diagnose
+/// lock contention.
+pub(crate) struct TracingMutex<T> {
+    inner: Mutex<T>,
+    name: &'static str,
+}
+
+/// Guard of a locked [`TracingMutex`].
+pub(crate) struct TracingMutexGuard<'a, T> {
+    guard: MutexGuard<'a, T>,
+}
+
+impl<T> TracingMutex<T> {
+    /// Creates a new [`TracingMutex`], identified by `name` in the traces.
+    pub(crate) fn new(name: &'static str, value: T) -> Self {
+        Self {
+            inner: Mutex::new(value),
+            name,
+        }
+    }
+
+    /// Acquires the lock, emitting a trace event with the wait time.
+    pub(crate) async fn lock(&self) -> TracingMutexGuard<'_, T> {
+        let start = Instant::now();
+        let guard = self.inner.lock().await;
+        let wait_us = u64::try_from(start.elapsed().as_micros()).unwrap_or(u64::MAX);
+        trace!(mutex = self.name, wait_us, "Lock acquired");
+        TracingMutexGuard { guard }
+    }
+}
+
+impl<'a, T> Deref for TracingMutexGuard<'a, T> {
+    type Target = MutexGuard<'a, T>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.guard
+    }
+}
+
+impl<T> DerefMut for TracingMutexGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.guard
+    }
+}
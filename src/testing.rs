@@ -0,0 +1,368 @@
+//! In-process cluster of carriers for integration tests, with the `test-util`
+//! feature.
+//!
+//! [`Cluster::start`] runs `n` carriers on the current Tokio runtime, all
+//! connected to each other over TLS on the loopback interface, with the
+//! certificates issued by a CA generated on the fly. The nodes are named
+//! `node0.test`, `node1.test`, and so on, and reached at `127.0.0.1` whatever
+//! their names. Every pair of nodes is identified by a TLS server name of its
+//! own, covered by the certificates of both, for each end to identify the
+//! other one from the name sent by its connections.
+//!
+//! A node can be [killed](Cluster::kill), dropping its carrier as a crashed
+//! process would, and [restarted](Cluster::restart) on the same port, for
+//! resilience tests. The [`faults`] module injects finer faults into the
+//! connections.
+
+pub mod faults;
+
+use crate::channels::{Incoming, Outgoing};
+use crate::config::NodeId;
+use crate::control::CarrierHandle;
+use crate::supervisor::Policy;
+use crate::tls::TlsDetails;
+use crate::transport::{self, NodeAddr, PeerIdentity, TlsTcpTransport, Transport};
+use crate::{Carrier, Error};
+use futures::channel::oneshot;
+use rcgen::{BasicConstraints, CertificateParams, IsCa, KeyPair};
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, PrivatePkcs8KeyDer, ServerName};
+use rustls::{ClientConfig, RootCertStore, ServerConfig};
+use std::mem;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use std::{io, net};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::task::JoinHandle;
+use tokio_stream::wrappers::TcpListenerStream;
+
+/// Address of the nodes.
+const HOST: &str = "127.0.0.1";
+
+/// Backoff of the listener restarts, short for a restarted node to listen
+/// again soon after its killed predecessor released the port.
+const LISTENER_RESTART_BACKOFF: Duration = Duration::from_millis(50);
+
+/// Carriers connected to each other, running on the current Tokio runtime.
+///
+/// The carriers still running are aborted when the cluster is dropped.
+pub struct Cluster {
+    nodes: Vec<ClusterNode>,
+}
+
+/// Node of a [`Cluster`], with the channels and the handle of its carrier.
+pub struct ClusterNode {
+    /// Name of the node, as known to the other nodes.
+    pub name: NodeId,
+    /// Requests from the other nodes.
+    pub incoming: Incoming,
+    /// Requests to the other nodes.
+    pub outgoing: Outgoing,
+    /// Runtime control of the carrier.
+    pub handle: CarrierHandle,
+    port: u16,
+    peers: Vec<(NodeId, NodeAddr)>,
+    server_config: Arc<ServerConfig>,
+    client_config: Arc<ClientConfig>,
+    run: Option<Run>,
+}
+
+/// Run of the carrier of a node.
+struct Run {
+    task: JoinHandle<Result<(), Error>>,
+    stop: oneshot::Sender<()>,
+}
+
+/// [`TlsTcpTransport`] accepting the connections of a listener bound
+/// beforehand, for the port of every node to be known before the others are
+/// configured. The listener is bound again on the same port by a restart of
+/// the listener.
+struct ClusterTransport {
+    inner: TlsTcpTransport,
+    listener: Mutex<Option<net::TcpListener>>,
+}
+
+impl Cluster {
+    /// Starts a cluster of `n` nodes, each listening on a port of its own
+    /// picked by the OS. Must be called within a Tokio runtime.
+    ///
+    /// The carriers connect to each other in the background, and the
+    /// requests sent meanwhile are queued until their connections are up.
+    #[must_use]
+    pub fn start(n: usize) -> Self {
+        let names = (0..n).map(node_name).collect::<Vec<_>>();
+        let listeners = (0..n)
+            .map(|_| {
+                let listener = net::TcpListener::bind((HOST, 0)).unwrap();
+                listener.set_nonblocking(true).unwrap();
+                listener
+            })
+            .collect::<Vec<_>>();
+        let ports = listeners
+            .iter()
+            .map(|listener| listener.local_addr().unwrap().port())
+            .collect::<Vec<_>>();
+        let (server_configs, client_config) = tls_configs(n);
+        let nodes = listeners
+            .into_iter()
+            .zip(server_configs)
+            .enumerate()
+            .map(|(i, (listener, server_config))| {
+                let peers = (0..n)
+                    .filter(|&j| j != i)
+                    .map(|j| {
+                        let addr = NodeAddr {
+                            host: HOST.to_string(),
+                            port: ports[j],
+                            tls_name: ServerName::try_from(pair_name(i, j)).unwrap(),
+                        };
+                        (names[j].clone(), addr)
+                    })
+                    .collect::<Vec<_>>();
+                ClusterNode::start(
+                    names[i].clone(),
+                    ports[i],
+                    peers,
+                    server_config,
+                    Arc::clone(&client_config),
+                    listener,
+                )
+            })
+            .collect();
+        Self { nodes }
+    }
+
+    /// Returns the node `index`.
+    pub fn node(&mut self, index: usize) -> &mut ClusterNode {
+        &mut self.nodes[index]
+    }
+
+    /// Returns all the nodes, in the order of their indices.
+    pub fn nodes(&mut self) -> &mut [ClusterNode] {
+        &mut self.nodes
+    }
+
+    /// Kills the node `index`, dropping its carrier and all its connections
+    /// at once, as a crashed process would. The channels of the node stay in
+    /// place, failing the requests sent through them. Does nothing if the
+    /// node is not running.
+    pub async fn kill(&mut self, index: usize) {
+        if let Some(run) = self.nodes[index].run.take() {
+            run.task.abort();
+            let _ = run.task.await;
+        }
+    }
+
+    /// Restarts the node `index` on the same port, killing it first if it
+    /// runs. The node gets the channels and the handle of a new carrier, and
+    /// the other nodes reconnect to it as they retry.
+    pub async fn restart(&mut self, index: usize) {
+        self.kill(index).await;
+        self.nodes[index].restart();
+    }
+
+    /// Shuts down all the nodes, letting their carriers drain as with
+    /// [`Carrier::run_until_shutdown`], and returns the first error of a
+    /// carrier run, if any.
+    ///
+    /// The channels of the nodes are dropped first, failing the requests
+    /// left unanswered.
+    pub async fn shutdown(mut self) -> Result<(), Error> {
+        let runs = mem::take(&mut self.nodes)
+            .into_iter()
+            .filter_map(|node| node.run)
+            .collect::<Vec<_>>();
+        let mut tasks = Vec::with_capacity(runs.len());
+        for Run { task, stop } in runs {
+            let _ = stop.send(());
+            tasks.push(task);
+        }
+        let mut result = Ok(());
+        for task in tasks {
+            let run = task.await.expect("carrier run panicked");
+            if result.is_ok() {
+                result = run;
+            }
+        }
+        result
+    }
+}
+
+impl Drop for Cluster {
+    fn drop(&mut self) {
+        for node in &self.nodes {
+            if let Some(run) = &node.run {
+                run.task.abort();
+            }
+        }
+    }
+}
+
+impl ClusterNode {
+    fn start(
+        name: NodeId,
+        port: u16,
+        peers: Vec<(NodeId, NodeAddr)>,
+        server_config: Arc<ServerConfig>,
+        client_config: Arc<ClientConfig>,
+        listener: net::TcpListener,
+    ) -> Self {
+        let transport = ClusterTransport::new(
+            port,
+            Arc::clone(&server_config),
+            Arc::clone(&client_config),
+            Some(listener),
+        );
+        let (incoming, outgoing, handle, run) = run(peers.clone(), transport);
+        Self {
+            name,
+            incoming,
+            outgoing,
+            handle,
+            port,
+            peers,
+            server_config,
+            client_config,
+            run: Some(run),
+        }
+    }
+
+    /// Returns the port the node listens on.
+    #[must_use]
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+
+    fn restart(&mut self) {
+        let transport = ClusterTransport::new(
+            self.port,
+            Arc::clone(&self.server_config),
+            Arc::clone(&self.client_config),
+            None,
+        );
+        let (incoming, outgoing, handle, run) = run(self.peers.clone(), transport);
+        self.incoming = incoming;
+        self.outgoing = outgoing;
+        self.handle = handle;
+        self.run = Some(run);
+    }
+}
+
+/// Spawns a carrier connected to `peers` over `transport`.
+fn run(
+    peers: Vec<(NodeId, NodeAddr)>,
+    transport: ClusterTransport,
+) -> (Incoming, Outgoing, CarrierHandle, Run) {
+    let (carrier, incoming, outgoing) = Carrier::with_addrs(peers);
+    let mut carrier = carrier.listener_policy(Policy::Restart {
+        backoff: LISTENER_RESTART_BACKOFF,
+    });
+    let handle = carrier.handle();
+    let (stop, stopped) = oneshot::channel::<()>();
+    let task = tokio::spawn(async move {
+        carrier
+            .run_with_transport_until_shutdown(transport, stopped)
+            .await
+    });
+    (incoming, outgoing, handle, Run { task, stop })
+}
+
+/// Returns the name of the node `index`.
+fn node_name(index: usize) -> NodeId {
+    NodeId::from(format!("node{index}.test"))
+}
+
+/// Returns the TLS server name of the pair of the nodes `a` and `b`, the same
+/// both ways.
+fn pair_name(a: usize, b: usize) -> String {
+    format!("node{}-node{}.test", a.min(b), a.max(b))
+}
+
+/// Issues the certificates of `n` nodes from a new CA, and returns the server
+/// configurations of the nodes with the client configuration trusting the CA.
+fn tls_configs(n: usize) -> (Vec<Arc<ServerConfig>>, Arc<ClientConfig>) {
+    let ca_key = KeyPair::generate().unwrap();
+    let mut ca = CertificateParams::default();
+    ca.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+    let ca = ca.self_signed(&ca_key).unwrap();
+    let server_configs = (0..n)
+        .map(|i| {
+            let names = (0..n)
+                .filter(|&j| j != i)
+                .map(|j| pair_name(i, j))
+                .chain([node_name(i).to_string()])
+                .collect::<Vec<_>>();
+            let key = KeyPair::generate().unwrap();
+            let cert = CertificateParams::new(names)
+                .unwrap()
+                .signed_by(&key, &ca, &ca_key)
+                .unwrap();
+            let chain = vec![cert.der().clone(), ca.der().clone()];
+            let key = PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(key.serialize_der()));
+            let server_config = ServerConfig::builder()
+                .with_no_client_auth()
+                .with_single_cert(chain, key)
+                .unwrap();
+            Arc::new(server_config)
+        })
+        .collect();
+    let mut roots = RootCertStore::empty();
+    roots.add(CertificateDer::clone(ca.der())).unwrap();
+    let client_config = ClientConfig::builder()
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+    (server_configs, Arc::new(client_config))
+}
+
+impl ClusterTransport {
+    fn new(
+        port: u16,
+        server_config: Arc<ServerConfig>,
+        client_config: Arc<ClientConfig>,
+        listener: Option<net::TcpListener>,
+    ) -> Self {
+        Self {
+            inner: TlsTcpTransport::new(HOST, port, server_config, client_config),
+            listener: Mutex::new(listener),
+        }
+    }
+}
+
+impl Transport for ClusterTransport {
+    type Conn = <TlsTcpTransport as Transport>::Conn;
+    type Accepted = TcpStream;
+    type Listener = TcpListenerStream;
+
+    fn listen_addr(&self) -> String {
+        self.inner.listen_addr()
+    }
+
+    fn peer_addr(&self, accepted: &Self::Accepted) -> String {
+        self.inner.peer_addr(accepted)
+    }
+
+    async fn bind(&self) -> io::Result<Self::Listener> {
+        let listener = self.listener.lock().unwrap().take();
+        match listener {
+            Some(listener) => Ok(TcpListenerStream::new(TcpListener::from_std(listener)?)),
+            None => self.inner.bind().await,
+        }
+    }
+
+    async fn accept(
+        &self,
+        accepted: Self::Accepted,
+    ) -> Result<(Self::Conn, PeerIdentity), transport::Error> {
+        self.inner.accept(accepted).await
+    }
+
+    async fn connect<'a>(
+        &'a self,
+        node: &'a NodeAddr,
+    ) -> Result<(Self::Conn, PeerIdentity), transport::Error> {
+        self.inner.connect(node).await
+    }
+
+    fn tls_details(&self, conn: &Self::Conn) -> Option<TlsDetails> {
+        self.inner.tls_details(conn)
+    }
+}
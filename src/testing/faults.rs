@@ -0,0 +1,683 @@
+//! Fault injection into the connections, for testing the recovery from
+//! network failures deterministically.
+//!
+//! A [`FaultPlan`] scripts the faults of a connection, triggered by the number
+//! of bytes or [frames](crate::protobuf_tcp) written to it, and [`FaultyIo`]
+//! plays it over any stream, such as an in-memory duplex pipe or a TCP
+//! socket. [`FaultyTransport`] plays the plans over the connections of
+//! another transport, such as
+//! [`MemoryTransport`](crate::transport::memory::MemoryTransport) or
+//! `PlainTcpTransport`. For example, the plan of a connection breaking once
+//! it wrote 3 frames is `FaultPlan::new().after_frames(3).kill_connection()`.
+//! Its [`ListenerFaults`] fail the accepts of its listeners.
+//!
+//! The frames are counted on the bytes written through [`FaultyIo`], so over
+//! TLS it must wrap the decrypted stream, as [`FaultyTransport`] does.
+
+use crate::protobuf_tcp::LEN_PREFIX;
+use crate::tls::TlsDetails;
+use crate::transport::{EarlyData, Error, NodeAddr, PeerIdentity, Transport};
+use futures::Stream;
+use std::collections::VecDeque;
+use std::future::Future;
+use std::io;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{ready, Context, Poll, Waker};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::time::{sleep, Sleep};
+
+/// Script of the faults of a connection.
+///
+/// Every fault is injected once the connection wrote the number of bytes or
+/// frames of the trigger set before it with [`FaultPlan::after_bytes`] or
+/// [`FaultPlan::after_frames`], counted from the start of the connection. The
+/// faults scripted before any trigger are injected right away.
+#[derive(Clone, Debug, Default)]
+pub struct FaultPlan {
+    steps: Vec<Step>,
+    trigger: Trigger,
+}
+
+/// Gate stalling the reads of the connections until released. See
+/// [`FaultPlan::stall_reads`].
+#[derive(Clone, Debug, Default)]
+pub struct ReadStall(Arc<Mutex<StallState>>);
+
+/// Stream injecting the faults of a [`FaultPlan`] into the reads and writes of
+/// an inner stream.
+pub struct FaultyIo<S> {
+    /// Inner stream, dropped once the connection is killed.
+    inner: Option<S>,
+    /// Faults not triggered yet.
+    pending: Vec<Step>,
+    /// Bytes written so far.
+    written: u64,
+    /// Frames written so far.
+    frames: u64,
+    /// Position of the writes within the frame being written.
+    frame: FrameCursor,
+    /// Delay of the writes, if slowed down.
+    delay: Option<Duration>,
+    /// Delay of the write in progress.
+    sleep: Option<Pin<Box<Sleep>>>,
+    /// Offsets of the written bytes to flip.
+    flips: Vec<u64>,
+    /// Whether to duplicate the next frame.
+    duplicate: bool,
+    /// Bytes of the frame being duplicated, written so far.
+    capture: Option<Vec<u8>>,
+    /// Bytes to write before any more of the caller, with the offset of the
+    /// first one left.
+    extra: (Vec<u8>, usize),
+    /// Stalls of the reads.
+    stalls: Vec<ReadStall>,
+    /// Waker of the pending read, woken as the connection is killed.
+    read_waker: Option<Waker>,
+}
+
+/// Transport injecting faults into the connections of an inner transport.
+///
+/// Every connection dialed, or accepted, is played the next plan queued with
+/// [`FaultyTransport::dialed`], or [`FaultyTransport::accepted`], in order.
+/// The connections beyond the plans queued are left alone, such as the
+/// reconnections after a killed connection.
+pub struct FaultyTransport<T> {
+    inner: T,
+    dialed: Mutex<VecDeque<FaultPlan>>,
+    accepted: Mutex<VecDeque<FaultPlan>>,
+    listener: ListenerFaults,
+}
+
+/// Handle failing the accepts of the listeners of a [`FaultyTransport`], kept
+/// by the test while the carrier owns the transport.
+#[derive(Clone, Default)]
+pub struct ListenerFaults(Arc<Mutex<ListenerState>>);
+
+/// Listener of a [`FaultyTransport`], yielding the errors of its
+/// [`ListenerFaults`] before the connections of the inner listener.
+pub struct FaultyListener<L> {
+    inner: L,
+    faults: ListenerFaults,
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Trigger {
+    Bytes(u64),
+    Frames(u64),
+}
+
+#[derive(Clone, Debug)]
+struct Step {
+    trigger: Trigger,
+    fault: Fault,
+}
+
+#[derive(Clone, Debug)]
+enum Fault {
+    Kill,
+    DelayWrites(Duration),
+    DuplicateFrame,
+    FlipByte(u64),
+    StallReads(ReadStall),
+}
+
+#[derive(Default)]
+struct ListenerState {
+    errors: VecDeque<io::Error>,
+    waker: Option<Waker>,
+}
+
+#[derive(Debug, Default)]
+struct StallState {
+    released: bool,
+    wakers: Vec<Waker>,
+}
+
+/// Position within a frame: the bytes of its length prefix, then the bytes
+/// left of its body.
+#[derive(Default)]
+struct FrameCursor {
+    prefix: [u8; LEN_PREFIX],
+    prefix_len: usize,
+    body_left: u64,
+}
+
+impl FaultPlan {
+    /// Creates a plan without faults.
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates a plan killing the connection once it wrote `frames` frames.
+    #[must_use]
+    pub fn kill_after_frames(frames: u64) -> Self {
+        Self::new().after_frames(frames).kill_connection()
+    }
+
+    /// Creates a plan killing the connection once it wrote `bytes` bytes.
+    #[must_use]
+    pub fn kill_after_bytes(bytes: u64) -> Self {
+        Self::new().after_bytes(bytes).kill_connection()
+    }
+
+    /// Creates a plan delaying every frame written to the connection by
+    /// `delay`.
+    #[must_use]
+    pub fn slow(delay: Duration) -> Self {
+        Self::new().delay_writes(delay)
+    }
+
+    /// Creates a plan duplicating the frame written after the first `frames`
+    /// ones.
+    #[must_use]
+    pub fn duplicate_after_frames(frames: u64) -> Self {
+        Self::new().after_frames(frames).duplicate_frame()
+    }
+
+    /// Creates a plan flipping the first byte of the body of the frame
+    /// written after the first `frames` ones, leaving its length intact.
+    #[must_use]
+    pub fn corrupt_after_frames(frames: u64) -> Self {
+        Self::new()
+            .after_frames(frames)
+            .flip_byte(LEN_PREFIX as u64)
+    }
+
+    /// Triggers the next faults once the connection wrote `bytes` bytes.
+    #[must_use]
+    pub fn after_bytes(mut self, bytes: u64) -> Self {
+        self.trigger = Trigger::Bytes(bytes);
+        self
+    }
+
+    /// Triggers the next faults once the connection wrote `frames` frames.
+    #[must_use]
+    pub fn after_frames(mut self, frames: u64) -> Self {
+        self.trigger = Trigger::Frames(frames);
+        self
+    }
+
+    /// Kills the connection: the inner stream is dropped, and the reads and
+    /// writes fail from then on.
+    #[must_use]
+    pub fn kill_connection(self) -> Self {
+        self.push(Fault::Kill)
+    }
+
+    /// Delays every frame by `delay` from then on, before its first byte.
+    #[must_use]
+    pub fn delay_writes(self, delay: Duration) -> Self {
+        self.push(Fault::DelayWrites(delay))
+    }
+
+    /// Writes the bytes of the next frame started twice.
+    #[must_use]
+    pub fn duplicate_frame(self) -> Self {
+        self.push(Fault::DuplicateFrame)
+    }
+
+    /// Flips all the bits of the byte written `offset` bytes after the
+    /// trigger.
+    #[must_use]
+    pub fn flip_byte(self, offset: u64) -> Self {
+        self.push(Fault::FlipByte(offset))
+    }
+
+    /// Stalls the reads until `stall` is released. The bytes received
+    /// meanwhile are left to the inner stream.
+    #[must_use]
+    pub fn stall_reads(self, stall: &ReadStall) -> Self {
+        self.push(Fault::StallReads(stall.clone()))
+    }
+
+    fn push(mut self, fault: Fault) -> Self {
+        self.steps.push(Step {
+            trigger: self.trigger,
+            fault,
+        });
+        self
+    }
+}
+
+impl Default for Trigger {
+    fn default() -> Self {
+        Self::Bytes(0)
+    }
+}
+
+impl ReadStall {
+    /// Creates a stall not released yet.
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Releases the reads stalled, for good.
+    pub fn release(&self) {
+        let wakers = {
+            let mut state = self.0.lock().unwrap();
+            state.released = true;
+            std::mem::take(&mut state.wakers)
+        };
+        wakers.into_iter().for_each(Waker::wake);
+    }
+
+    fn poll(&self, cx: &mut Context<'_>) -> Poll<()> {
+        let mut state = self.0.lock().unwrap();
+        if state.released {
+            return Poll::Ready(());
+        }
+        state.wakers.push(cx.waker().clone());
+        Poll::Pending
+    }
+}
+
+impl ListenerFaults {
+    /// Fails the next accept of the listener with `error`, such as a transient
+    /// `EMFILE`, waited out by the carrier, or any other error, stopping the
+    /// listener until its restart.
+    pub fn fail_accept(&self, error: io::Error) {
+        let waker = {
+            let mut state = self.0.lock().unwrap();
+            state.errors.push_back(error);
+            state.waker.take()
+        };
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+    }
+}
+
+impl<L, A> Stream for FaultyListener<L>
+where
+    L: Stream<Item = io::Result<A>> + Unpin,
+{
+    type Item = io::Result<A>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        {
+            let mut state = this.faults.0.lock().unwrap();
+            if let Some(error) = state.errors.pop_front() {
+                return Poll::Ready(Some(Err(error)));
+            }
+            state.waker = Some(cx.waker().clone());
+        }
+        Pin::new(&mut this.inner).poll_next(cx)
+    }
+}
+
+impl<S> FaultyIo<S> {
+    /// Wraps `inner`, injecting the faults of `plan`.
+    pub fn new(inner: S, plan: FaultPlan) -> Self {
+        let mut io = Self {
+            inner: Some(inner),
+            pending: plan.steps,
+            written: 0,
+            frames: 0,
+            frame: FrameCursor::default(),
+            delay: None,
+            sleep: None,
+            flips: Vec::new(),
+            duplicate: false,
+            capture: None,
+            extra: (Vec::new(), 0),
+            stalls: Vec::new(),
+            read_waker: None,
+        };
+        io.trigger();
+        io
+    }
+
+    /// Returns the inner stream, or [`None`] once the connection is killed.
+    pub fn get_ref(&self) -> Option<&S> {
+        self.inner.as_ref()
+    }
+
+    /// Injects the faults triggered by the bytes and frames written so far.
+    fn trigger(&mut self) {
+        let (written, frames) = (self.written, self.frames);
+        let (triggered, pending) = self
+            .pending
+            .drain(..)
+            .partition::<Vec<_>, _>(|step| match step.trigger {
+                Trigger::Bytes(bytes) => written >= bytes,
+                Trigger::Frames(count) => frames >= count,
+            });
+        self.pending = pending;
+        for step in triggered {
+            match step.fault {
+                Fault::Kill => {
+                    self.inner = None;
+                    if let Some(waker) = self.read_waker.take() {
+                        waker.wake();
+                    }
+                }
+                Fault::DelayWrites(delay) => self.delay = Some(delay),
+                Fault::DuplicateFrame => self.duplicate = true,
+                Fault::FlipByte(offset) => self.flips.push(written + offset),
+                Fault::StallReads(stall) => self.stalls.push(stall),
+            }
+        }
+    }
+
+    /// Returns the number of the bytes the next write may take, to stop at
+    /// the next boundary of a frame part or of a byte trigger.
+    fn write_limit(&self) -> u64 {
+        let written = self.written;
+        self.pending
+            .iter()
+            .filter_map(|step| match step.trigger {
+                Trigger::Bytes(bytes) if bytes > written => Some(bytes - written),
+                _ => None,
+            })
+            .fold(self.frame.part_left(), u64::min)
+    }
+
+    /// Accounts for `buf` written, within a single frame part.
+    fn advance(&mut self, buf: &[u8]) {
+        if self.duplicate && self.frame.at_start() {
+            self.duplicate = false;
+            self.capture = Some(Vec::new());
+        }
+        if let Some(capture) = &mut self.capture {
+            capture.extend_from_slice(buf);
+        }
+        self.written += buf.len() as u64;
+        self.flips.retain(|&offset| offset >= self.written);
+        if self.frame.advance(buf) {
+            self.frames += 1;
+            if let Some(capture) = self.capture.take() {
+                self.extra = (capture, 0);
+            }
+        }
+        self.trigger();
+    }
+}
+
+impl<S: AsyncWrite + Unpin> FaultyIo<S> {
+    /// Writes the extra bytes left, such as a duplicated frame.
+    fn poll_extra(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let (extra, offset) = &mut self.extra;
+        while *offset < extra.len() {
+            let inner = self.inner.as_mut().ok_or_else(killed)?;
+            let n = ready!(Pin::new(inner).poll_write(cx, &extra[*offset..]))?;
+            if n == 0 {
+                return Poll::Ready(Err(io::ErrorKind::WriteZero.into()));
+            }
+            *offset += n;
+        }
+        *extra = Vec::new();
+        *offset = 0;
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for FaultyIo<S> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        if !this
+            .read_waker
+            .as_ref()
+            .is_some_and(|waker| waker.will_wake(cx.waker()))
+        {
+            this.read_waker = Some(cx.waker().clone());
+        }
+        for stall in &this.stalls {
+            ready!(stall.poll(cx));
+        }
+        let inner = this.inner.as_mut().ok_or_else(killed)?;
+        Pin::new(inner).poll_read(cx, buf)
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for FaultyIo<S> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        ready!(this.poll_extra(cx))?;
+        if let Some(delay) = this.delay.filter(|_| this.frame.at_start()) {
+            let sleep = this.sleep.get_or_insert_with(|| Box::pin(sleep(delay)));
+            ready!(sleep.as_mut().poll(cx));
+        }
+        let limit = usize::try_from(this.write_limit()).unwrap_or(usize::MAX);
+        let mut chunk = buf[..buf.len().min(limit)].to_vec();
+        for &offset in &this.flips {
+            if let Some(byte) = offset
+                .checked_sub(this.written)
+                .and_then(|at| chunk.get_mut(usize::try_from(at).ok()?))
+            {
+                *byte ^= 0xff;
+            }
+        }
+        let inner = this.inner.as_mut().ok_or_else(killed)?;
+        let n = ready!(Pin::new(inner).poll_write(cx, &chunk))?;
+        this.sleep = None;
+        this.advance(&buf[..n]);
+        Poll::Ready(Ok(n))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        ready!(this.poll_extra(cx))?;
+        let inner = this.inner.as_mut().ok_or_else(killed)?;
+        Pin::new(inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let inner = self.get_mut().inner.as_mut().ok_or_else(killed)?;
+        Pin::new(inner).poll_shutdown(cx)
+    }
+}
+
+impl FrameCursor {
+    fn at_start(&self) -> bool {
+        self.prefix_len == 0
+    }
+
+    /// Returns the number of the bytes left of the length prefix, or else of
+    /// the body.
+    fn part_left(&self) -> u64 {
+        if self.prefix_len < LEN_PREFIX {
+            (LEN_PREFIX - self.prefix_len) as u64
+        } else {
+            self.body_left
+        }
+    }
+
+    /// Accounts for `buf` written, within a single frame part. Returns `true`
+    /// if it completes the frame.
+    fn advance(&mut self, buf: &[u8]) -> bool {
+        if self.prefix_len < LEN_PREFIX {
+            self.prefix[self.prefix_len..self.prefix_len + buf.len()].copy_from_slice(buf);
+            self.prefix_len += buf.len();
+            if self.prefix_len < LEN_PREFIX {
+                return false;
+            }
+            self.body_left = u32::from_be_bytes(self.prefix).into();
+        } else {
+            self.body_left -= buf.len() as u64;
+        }
+        if self.body_left == 0 {
+            *self = Self::default();
+            return true;
+        }
+        false
+    }
+}
+
+impl<T> FaultyTransport<T> {
+    /// Wraps `inner`, without any plan queued.
+    #[must_use]
+    pub fn new(inner: T) -> Self {
+        Self {
+            inner,
+            dialed: Mutex::default(),
+            accepted: Mutex::default(),
+            listener: ListenerFaults::default(),
+        }
+    }
+
+    /// Returns the handle failing the accepts of the listeners.
+    #[must_use]
+    pub fn listener_faults(&self) -> ListenerFaults {
+        self.listener.clone()
+    }
+
+    /// Queues `plan` for the next connection dialed without one.
+    #[must_use]
+    pub fn dialed(self, plan: FaultPlan) -> Self {
+        self.dialed.lock().unwrap().push_back(plan);
+        self
+    }
+
+    /// Queues `plan` for the next connection accepted without one.
+    #[must_use]
+    pub fn accepted(self, plan: FaultPlan) -> Self {
+        self.accepted.lock().unwrap().push_back(plan);
+        self
+    }
+}
+
+impl<T: Transport> Transport for FaultyTransport<T> {
+    type Conn = FaultyIo<T::Conn>;
+    type Accepted = T::Accepted;
+    type Listener = FaultyListener<T::Listener>;
+
+    fn listen_addr(&self) -> String {
+        self.inner.listen_addr()
+    }
+
+    fn peer_addr(&self, accepted: &Self::Accepted) -> String {
+        self.inner.peer_addr(accepted)
+    }
+
+    async fn bind(&self) -> io::Result<Self::Listener> {
+        Ok(FaultyListener {
+            inner: self.inner.bind().await?,
+            faults: self.listener.clone(),
+        })
+    }
+
+    async fn accept(&self, accepted: Self::Accepted) -> Result<(Self::Conn, PeerIdentity), Error> {
+        let (conn, peer) = self.inner.accept(accepted).await?;
+        Ok((FaultyIo::new(conn, next_plan(&self.accepted)), peer))
+    }
+
+    async fn connect<'a>(
+        &'a self,
+        node: &'a NodeAddr,
+    ) -> Result<(Self::Conn, PeerIdentity), Error> {
+        let (conn, peer) = self.inner.connect(node).await?;
+        Ok((FaultyIo::new(conn, next_plan(&self.dialed)), peer))
+    }
+
+    fn early_data(&self, conn: &Self::Conn) -> Option<EarlyData> {
+        self.inner.early_data(conn.get_ref()?)
+    }
+
+    fn tls_details(&self, conn: &Self::Conn) -> Option<TlsDetails> {
+        self.inner.tls_details(conn.get_ref()?)
+    }
+}
+
+fn next_plan(plans: &Mutex<VecDeque<FaultPlan>>) -> FaultPlan {
+    plans.lock().unwrap().pop_front().unwrap_or_default()
+}
+
+fn killed() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::ConnectionReset,
+        "connection killed by the fault plan",
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::AsyncWriteExt;
+
+    /// Returns a frame of `body`, with its length prefix.
+    fn frame(body: &[u8]) -> Vec<u8> {
+        let mut frame = u32::try_from(body.len()).unwrap().to_be_bytes().to_vec();
+        frame.extend_from_slice(body);
+        frame
+    }
+
+    /// Writes `frames` through a [`FaultyIo`] playing `plan`, and returns the
+    /// bytes reaching the inner stream.
+    async fn play(plan: FaultPlan, frames: &[&[u8]]) -> Vec<u8> {
+        let mut io = FaultyIo::new(Vec::new(), plan);
+        for body in frames {
+            io.write_all(&frame(body)).await.unwrap();
+        }
+        io.flush().await.unwrap();
+        io.get_ref().unwrap().clone()
+    }
+
+    #[test]
+    fn cursor_follows_split_prefix_and_body() {
+        let mut cursor = FrameCursor::default();
+        assert!(cursor.at_start());
+        assert_eq!(cursor.part_left(), LEN_PREFIX as u64);
+        assert!(!cursor.advance(&[0, 0]));
+        assert_eq!(cursor.part_left(), 2);
+        assert!(!cursor.advance(&[0, 3]));
+        assert_eq!(cursor.part_left(), 3);
+        assert!(!cursor.advance(&[1, 2]));
+        assert_eq!(cursor.part_left(), 1);
+        assert!(cursor.advance(&[3]));
+        assert!(cursor.at_start());
+        // A frame without a body ends with its prefix.
+        assert!(cursor.advance(&[0; LEN_PREFIX]));
+        assert!(cursor.at_start());
+    }
+
+    #[tokio::test]
+    async fn writes_stop_at_part_and_byte_boundaries() {
+        let mut io = FaultyIo::new(Vec::new(), FaultPlan::kill_after_bytes(6));
+        assert_eq!(io.write_limit(), LEN_PREFIX as u64);
+        let frame = frame(&[1, 2, 3, 4, 5]);
+        assert_eq!(io.write(&frame).await.unwrap(), LEN_PREFIX);
+        // The body stops at the trigger, 2 bytes in.
+        assert_eq!(io.write_limit(), 2);
+        assert_eq!(io.write(&frame[LEN_PREFIX..]).await.unwrap(), 2);
+        assert!(io.get_ref().is_none());
+        assert!(io.write(&frame[6..]).await.is_err());
+        assert_eq!(io.frames, 0);
+    }
+
+    #[tokio::test]
+    async fn frames_counted_across_writes() {
+        let mut io = FaultyIo::new(Vec::new(), FaultPlan::new());
+        let frames = [frame(&[1]), frame(&[]), frame(&[2, 3])].concat();
+        io.write_all(&frames).await.unwrap();
+        assert_eq!(io.frames, 3);
+        assert_eq!(io.written, frames.len() as u64);
+        assert!(io.frame.at_start());
+    }
+
+    #[tokio::test]
+    async fn duplicated_frame_written_twice() {
+        let written = play(FaultPlan::duplicate_after_frames(1), &[&[1], &[2, 3], &[4]]).await;
+        let expected = [frame(&[1]), frame(&[2, 3]), frame(&[2, 3]), frame(&[4])].concat();
+        assert_eq!(written, expected);
+    }
+
+    #[tokio::test]
+    async fn corrupted_frame_keeps_its_length() {
+        let written = play(FaultPlan::corrupt_after_frames(1), &[&[1], &[2, 3], &[4]]).await;
+        let expected = [frame(&[1]), frame(&[!2, 3]), frame(&[4])].concat();
+        assert_eq!(written, expected);
+    }
+}
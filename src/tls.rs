@@ -1,14 +1,48 @@
 //! Transport Layer Security.
 
-use rustls::{ClientConfig, RootCertStore, ServerConfig};
+use ring::digest;
+use rustls::client::ResolvesClientCert;
+use rustls::crypto::ring::sign::any_supported_type;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+#[cfg(feature = "multi-cert")]
+use rustls::server::ResolvesServerCertUsingSni;
+use rustls::server::{ClientHello, ResolvesServerCert};
+use rustls::sign::CertifiedKey;
+use rustls::{
+    ClientConfig, CommonState, HandshakeKind, RootCertStore, ServerConfig, SignatureScheme,
+    SupportedProtocolVersion,
+};
 use rustls_pemfile::{certs, private_key};
+use serde::Serialize;
+#[cfg(feature = "multi-cert")]
+use std::collections::HashSet;
+use std::fmt::Write as _;
 use std::fs::File;
 use std::io::{self, BufReader};
-use std::path::Path;
-use std::sync::Arc;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+#[cfg(any(feature = "cert-expiry-check", feature = "cert-watch"))]
+use std::time::Duration;
+#[cfg(feature = "cert-expiry-check")]
+use std::time::SystemTime;
 use thiserror::Error;
+#[cfg(feature = "cert-watch")]
+use tokio::{sync::mpsc, task::JoinHandle, time::timeout};
+use tokio_rustls::TlsAcceptor;
+#[cfg(feature = "cert-watch")]
+use tracing::{info, warn};
 
-/// Error returned by [`init`].
+#[cfg(feature = "cert-expiry-check")]
+const SECS_PER_DAY: u64 = 24 * 60 * 60;
+#[cfg(feature = "cert-expiry-check")]
+const EXPIRY_WARNING_PERIOD: Duration = Duration::from_secs(30 * SECS_PER_DAY);
+#[cfg(feature = "cert-watch")]
+const WATCH_DEBOUNCE: Duration = Duration::from_millis(100);
+
+static TLS13_ONLY: [&SupportedProtocolVersion; 1] = [&rustls::version::TLS13];
+
+/// Error returned by [`init`], [`init_multi_cert`], [`verify_cert`],
+/// [`verify_chain`] and [`ReloadableAcceptor`].
 #[allow(missing_docs)]
 #[derive(Error, Debug)]
 pub enum Error {
@@ -22,6 +56,147 @@ pub enum Error {
     ServerConfig(rustls::Error),
     #[error("TLS client configuration: {0}")]
     ClientConfig(rustls::Error),
+    #[cfg(feature = "cert-watch")]
+    #[error("certificate files watch: {0}")]
+    Watch(notify::Error),
+    #[cfg(any(feature = "cert-expiry-check", feature = "multi-cert"))]
+    #[error("certificate parse: {0}")]
+    CertParse(String),
+    #[cfg(feature = "cert-expiry-check")]
+    #[error("certificate `{subject}` has expired")]
+    CertificateExpired { subject: String, expiry: SystemTime },
+    #[cfg(feature = "cert-expiry-check")]
+    #[error("certificate `{subject}` is not yet valid")]
+    CertificateNotYetValid {
+        subject: String,
+        not_before: SystemTime,
+    },
+    #[cfg(feature = "cert-expiry-check")]
+    #[error("certificate chain file without certificates")]
+    CertChainEmpty,
+    #[cfg(feature = "cert-expiry-check")]
+    #[error("certificate priv key not matching the certificate: {0}")]
+    CertKeyMismatch(rustls::Error),
+    #[cfg(feature = "cert-expiry-check")]
+    #[error("certificate not valid for `{name}`: {reason}")]
+    CertNameMismatch { name: String, reason: String },
+    #[cfg(feature = "cert-expiry-check")]
+    #[error("certificate chain not trusted: {0}")]
+    CertUntrusted(rustls::Error),
+    #[cfg(feature = "multi-cert")]
+    #[error("certificate without DNS names")]
+    CertWithoutNames,
+    #[cfg(feature = "multi-cert")]
+    #[error("server name `{0}` in several certificates")]
+    DuplicateServerName(String),
+}
+
+/// Transport security of the node-to-node connections.
+#[derive(Clone, Debug)]
+pub enum TlsMode {
+    /// TLS on top of TCP, with the node identity established by SNI.
+    Required {
+        /// Certificate chain file.
+        cert_chain: PathBuf,
+        /// Certificate private key file.
+        cert_priv_key: PathBuf,
+    },
+    /// Plain TCP, with the node identity established by the peer IP address.
+    ///
+    /// # Security
+    ///
+    /// The traffic is neither encrypted nor authenticated, and anyone able to
+    /// connect from a node address can impersonate that node. Use only in fully
+    /// trusted networks.
+    #[cfg(feature = "no-tls")]
+    Disabled,
+    /// TLS on top of TCP as with [`TlsMode::Required`], presenting the
+    /// certificate matching the SNI of each incoming connection. See
+    /// [`init_multi_cert`].
+    #[cfg(feature = "multi-cert")]
+    MultiCert {
+        /// Certificate chain and private key files of every certificate.
+        certs: Vec<(PathBuf, PathBuf)>,
+    },
+}
+
+/// TLS protocol versions of the handshakes.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum TlsVersion {
+    /// TLS 1.2 and 1.3.
+    #[default]
+    Tls12AndTls13,
+    /// TLS 1.3 only, failing the handshakes with the peers limited to TLS 1.2.
+    Tls13Only,
+}
+
+/// Configuration of the TLS handshakes, set with
+/// [`Carrier::tls_config`](crate::Carrier::tls_config) or passed to
+/// [`init_with_config`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct TlsConfig {
+    /// Protocol versions accepted, in both the server and client roles.
+    pub version: TlsVersion,
+}
+
+/// TLS configurations with the certificate replaced by
+/// [`ReloadableAcceptor::reload`], without restarting the transports built from
+/// them. The established connections keep the certificate they started with.
+#[derive(Clone)]
+pub struct ReloadableAcceptor {
+    cert_chain: PathBuf,
+    cert_priv_key: PathBuf,
+    cert: Arc<ReloadableCert>,
+    server_config: Arc<ServerConfig>,
+    client_config: Arc<ClientConfig>,
+}
+
+/// Certificate presented by both configurations of a [`ReloadableAcceptor`].
+#[derive(Debug)]
+struct ReloadableCert(RwLock<Arc<CertifiedKey>>);
+
+/// Subject, subject alternative names, validity and key type of a
+/// certificate, returned by [`cert_info`] and [`verify_cert`].
+#[cfg(feature = "cert-expiry-check")]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CertInfo {
+    /// Distinguished name of the subject.
+    pub subject: String,
+    /// Subject alternative names, such as `DNSName(node.example.com)`.
+    pub sans: Vec<String>,
+    /// Start of the validity.
+    pub not_before: SystemTime,
+    /// End of the validity.
+    pub not_after: SystemTime,
+    /// Type of the public key, such as `ECDSA P-256` or `RSA 2048`.
+    pub key_type: String,
+}
+
+/// Parameters of a completed TLS handshake, logged with the connection and
+/// attached to its [`CarrierEvent::Established`](crate::lifecycle::CarrierEvent::Established).
+///
+/// Made of names and of a hash of the public certificate of the peer only: no
+/// key, secret or session ticket of the connection goes in.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct TlsDetails {
+    /// Protocol version, such as `TLSv1_3`.
+    pub version: String,
+    /// Cipher suite, such as `TLS13_AES_256_GCM_SHA384`.
+    pub cipher_suite: String,
+    /// Application protocol negotiated with ALPN, if any.
+    pub alpn: Option<String>,
+    /// SHA-256 of the certificate of the peer, in hex, if it presented one.
+    /// An incoming connection presents none without client authentication.
+    pub peer_cert_sha256: Option<String>,
+    /// Whether the session was resumed rather than fully negotiated.
+    pub resumed: bool,
+}
+
+/// Stops watching the certificate files of [`load_and_watch`] when dropped.
+#[cfg(feature = "cert-watch")]
+pub struct WatchHandle {
+    _watcher: notify::RecommendedWatcher,
+    task: JoinHandle<()>,
 }
 
 /// Initializes [`TlsAcceptor`].
@@ -29,26 +204,522 @@ pub fn init(
     cert_chain: &Path,
     cert_priv_key: &Path,
 ) -> Result<(Arc<ServerConfig>, Arc<ClientConfig>), Error> {
-    let cert_chain = File::open(cert_chain).map_err(Error::CertChainIo)?;
-    let cert_priv_key = File::open(cert_priv_key).map_err(Error::CertPrivKeyIo)?;
-    let cert_chain = certs(&mut BufReader::new(cert_chain))
-        .collect::<Result<Vec<_>, _>>()
-        .map_err(Error::CertChainIo)?;
-    let cert_priv_key = private_key(&mut BufReader::new(cert_priv_key))
-        .map_err(Error::CertPrivKeyIo)?
-        .ok_or(Error::CertPrivKeyMissing)?;
+    init_with_config(cert_chain, cert_priv_key, TlsConfig::default())
+}
 
-    let server_config = ServerConfig::builder()
+/// Initializes [`TlsAcceptor`] as [`init`], with the protocol versions of
+/// `config`.
+pub fn init_with_config(
+    cert_chain: &Path,
+    cert_priv_key: &Path,
+    config: TlsConfig,
+) -> Result<(Arc<ServerConfig>, Arc<ClientConfig>), Error> {
+    let (cert_chain, cert_priv_key) = load(cert_chain, cert_priv_key)?;
+    let server_config = ServerConfig::builder_with_protocol_versions(config.versions())
         .with_no_client_auth()
         .with_single_cert(cert_chain.clone(), cert_priv_key.clone_key())
         .map_err(Error::ServerConfig)?;
 
-    let mut root_cert_store = RootCertStore::empty();
-    root_cert_store.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned());
-    let client_config = ClientConfig::builder()
-        .with_root_certificates(root_cert_store)
+    let client_config = ClientConfig::builder_with_protocol_versions(config.versions())
+        .with_root_certificates(root_cert_store())
         .with_client_auth_cert(cert_chain, cert_priv_key)
         .map_err(Error::ClientConfig)?;
 
     Ok((Arc::new(server_config), Arc::new(client_config)))
 }
+
+/// Initializes a [`ServerConfig`] presenting one of several certificates, for
+/// the peers from several trust domains. Each certificate is presented to the
+/// clients sending one of the DNS names of its subject alternative names in
+/// SNI, wildcard names excepted. The handshakes with other names fail.
+#[cfg(feature = "multi-cert")]
+pub fn init_multi_cert(
+    certs: Vec<(Vec<CertificateDer<'static>>, PrivateKeyDer<'static>)>,
+) -> Result<Arc<ServerConfig>, Error> {
+    multi_cert_server_config(certs, TlsConfig::default())
+}
+
+#[cfg(feature = "multi-cert")]
+fn multi_cert_server_config(
+    certs: Vec<(Vec<CertificateDer<'static>>, PrivateKeyDer<'static>)>,
+    config: TlsConfig,
+) -> Result<Arc<ServerConfig>, Error> {
+    let mut resolver = ResolvesServerCertUsingSni::new();
+    let mut names = HashSet::new();
+    for (cert_chain, cert_priv_key) in certs {
+        let key = any_supported_type(&cert_priv_key).map_err(Error::ServerConfig)?;
+        let cert = CertifiedKey::new(cert_chain, key);
+        let dns_names = dns_names(cert.end_entity_cert().map_err(Error::ServerConfig)?)?;
+        if dns_names.is_empty() {
+            return Err(Error::CertWithoutNames);
+        }
+        for name in dns_names {
+            if !names.insert(name.to_ascii_lowercase()) {
+                return Err(Error::DuplicateServerName(name));
+            }
+            resolver
+                .add(&name, cert.clone())
+                .map_err(Error::ServerConfig)?;
+        }
+    }
+    let server_config = ServerConfig::builder_with_protocol_versions(config.versions())
+        .with_no_client_auth()
+        .with_cert_resolver(Arc::new(resolver));
+    Ok(Arc::new(server_config))
+}
+
+/// Initializes [`init_multi_cert`] from files, with a client configuration
+/// presenting no certificate, as the servers do not ask for one.
+#[cfg(feature = "multi-cert")]
+pub(crate) fn init_multi_cert_files(
+    certs: &[(PathBuf, PathBuf)],
+    config: TlsConfig,
+) -> Result<(Arc<ServerConfig>, Arc<ClientConfig>), Error> {
+    let certs = certs
+        .iter()
+        .map(|(cert_chain, cert_priv_key)| load(cert_chain, cert_priv_key))
+        .collect::<Result<Vec<_>, _>>()?;
+    let client_config = ClientConfig::builder_with_protocol_versions(config.versions())
+        .with_root_certificates(root_cert_store())
+        .with_no_client_auth();
+    let server_config = multi_cert_server_config(certs, config)?;
+    Ok((server_config, Arc::new(client_config)))
+}
+
+/// Initializes a [`ReloadableAcceptor`], reloaded whenever the certificate
+/// files change until the [`WatchHandle`] is dropped. A change is applied once
+/// the files are left alone for 100 milliseconds, so a file written and then
+/// renamed into place is reloaded once. A failed reload is logged, keeping the
+/// current certificate.
+///
+/// # Panics
+///
+/// If called outside of a Tokio runtime.
+#[cfg(feature = "cert-watch")]
+pub fn load_and_watch(
+    cert_chain: &Path,
+    cert_priv_key: &Path,
+) -> Result<(ReloadableAcceptor, WatchHandle), Error> {
+    use notify::{EventKind, RecursiveMode, Watcher};
+
+    let acceptor = ReloadableAcceptor::new(cert_chain, cert_priv_key)?;
+    let names = [cert_chain, cert_priv_key].map(Path::file_name);
+    let names = names.map(|name| name.map(ToOwned::to_owned));
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    // Replacing a file by renaming another one over it leaves a watch on the
+    // file itself dangling, so the directories are watched instead.
+    let mut watcher = notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+        match event {
+            // Reading the files on reload is not a change.
+            Ok(event) if matches!(event.kind, EventKind::Access(_)) => {}
+            Ok(event) => {
+                let changed = event.paths.iter().any(|path| {
+                    let name = path.file_name();
+                    names.iter().any(|watched| watched.as_deref() == name)
+                });
+                if changed {
+                    let _ = tx.send(());
+                }
+            }
+            Err(err) => warn!("Certificate files watch: {err}"),
+        }
+    })
+    .map_err(Error::Watch)?;
+    for path in [cert_chain, cert_priv_key] {
+        let dir = match path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        };
+        watcher
+            .watch(dir, RecursiveMode::NonRecursive)
+            .map_err(Error::Watch)?;
+    }
+    let reloadable = acceptor.clone();
+    let task = tokio::spawn(async move {
+        while rx.recv().await.is_some() {
+            while let Ok(Some(())) = timeout(WATCH_DEBOUNCE, rx.recv()).await {}
+            match reloadable.reload() {
+                Ok(()) => info!("Reloaded the TLS certificate"),
+                Err(err) => warn!("TLS certificate reload failed: {err}"),
+            }
+        }
+    });
+    let handle = WatchHandle {
+        _watcher: watcher,
+        task,
+    };
+    Ok((acceptor, handle))
+}
+
+impl TlsConfig {
+    fn versions(self) -> &'static [&'static SupportedProtocolVersion] {
+        match self.version {
+            TlsVersion::Tls12AndTls13 => rustls::DEFAULT_VERSIONS,
+            TlsVersion::Tls13Only => &TLS13_ONLY,
+        }
+    }
+}
+
+impl ReloadableAcceptor {
+    /// Creates a new [`ReloadableAcceptor`] with the certificate loaded from
+    /// the files, as with [`init`].
+    pub fn new(cert_chain: &Path, cert_priv_key: &Path) -> Result<Self, Error> {
+        let cert = Arc::new(ReloadableCert(RwLock::new(certified_key(
+            cert_chain,
+            cert_priv_key,
+        )?)));
+        let server_config = ServerConfig::builder()
+            .with_no_client_auth()
+            .with_cert_resolver(Arc::clone(&cert) as Arc<dyn ResolvesServerCert>);
+        let client_config = ClientConfig::builder()
+            .with_root_certificates(root_cert_store())
+            .with_client_cert_resolver(Arc::clone(&cert) as Arc<dyn ResolvesClientCert>);
+        Ok(Self {
+            cert_chain: cert_chain.to_path_buf(),
+            cert_priv_key: cert_priv_key.to_path_buf(),
+            cert,
+            server_config: Arc::new(server_config),
+            client_config: Arc::new(client_config),
+        })
+    }
+
+    /// Returns the server configuration, presenting the current certificate
+    /// on every handshake.
+    #[must_use]
+    pub fn server_config(&self) -> Arc<ServerConfig> {
+        Arc::clone(&self.server_config)
+    }
+
+    /// Returns the client configuration, presenting the current certificate
+    /// on every handshake.
+    #[must_use]
+    pub fn client_config(&self) -> Arc<ClientConfig> {
+        Arc::clone(&self.client_config)
+    }
+
+    /// Returns a [`TlsAcceptor`] presenting the current certificate.
+    #[must_use]
+    pub fn acceptor(&self) -> TlsAcceptor {
+        TlsAcceptor::from(self.server_config())
+    }
+
+    /// Loads the certificate from the files again. On failure the current
+    /// certificate is kept.
+    pub fn reload(&self) -> Result<(), Error> {
+        let cert = certified_key(&self.cert_chain, &self.cert_priv_key)?;
+        *self.cert.0.write().unwrap() = cert;
+        Ok(())
+    }
+}
+
+impl ReloadableCert {
+    fn current(&self) -> Arc<CertifiedKey> {
+        Arc::clone(&self.0.read().unwrap())
+    }
+}
+
+impl ResolvesServerCert for ReloadableCert {
+    fn resolve(&self, _: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+        Some(self.current())
+    }
+}
+
+impl ResolvesClientCert for ReloadableCert {
+    fn resolve(&self, _: &[&[u8]], _: &[SignatureScheme]) -> Option<Arc<CertifiedKey>> {
+        Some(self.current())
+    }
+
+    fn has_certs(&self) -> bool {
+        true
+    }
+}
+
+impl TlsDetails {
+    /// Reads the parameters of the handshake completed by `connection`.
+    pub(crate) fn new(connection: &CommonState) -> Self {
+        let version = connection
+            .protocol_version()
+            .map_or_else(String::new, |version| format!("{version:?}"));
+        let cipher_suite = connection
+            .negotiated_cipher_suite()
+            .map_or_else(String::new, |suite| format!("{:?}", suite.suite()));
+        let alpn = connection
+            .alpn_protocol()
+            .map(|alpn| String::from_utf8_lossy(alpn).into_owned());
+        let peer_cert_sha256 = connection
+            .peer_certificates()
+            .and_then(<[_]>::first)
+            .map(|cert| {
+                let hash = digest::digest(&digest::SHA256, cert);
+                hash.as_ref().iter().fold(String::new(), |mut hex, byte| {
+                    let _ = write!(hex, "{byte:02x}");
+                    hex
+                })
+            });
+        Self {
+            version,
+            cipher_suite,
+            alpn,
+            peer_cert_sha256,
+            resumed: connection.handshake_kind() == Some(HandshakeKind::Resumed),
+        }
+    }
+}
+
+#[cfg(feature = "cert-watch")]
+impl Drop for WatchHandle {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+/// Reads the certificate chain and its private key, checking the expiry of the
+/// chain with the `cert-expiry-check` feature.
+fn load(
+    cert_chain: &Path,
+    cert_priv_key: &Path,
+) -> Result<(Vec<CertificateDer<'static>>, PrivateKeyDer<'static>), Error> {
+    let (cert_chain, cert_priv_key) = read(cert_chain, cert_priv_key)?;
+    #[cfg(feature = "cert-expiry-check")]
+    check_expiry(&cert_chain)?;
+    Ok((cert_chain, cert_priv_key))
+}
+
+/// Reads the certificate chain and its private key.
+fn read(
+    cert_chain: &Path,
+    cert_priv_key: &Path,
+) -> Result<(Vec<CertificateDer<'static>>, PrivateKeyDer<'static>), Error> {
+    let cert_chain = read_certs(cert_chain)?;
+    let cert_priv_key = File::open(cert_priv_key).map_err(Error::CertPrivKeyIo)?;
+    let cert_priv_key = private_key(&mut BufReader::new(cert_priv_key))
+        .map_err(Error::CertPrivKeyIo)?
+        .ok_or(Error::CertPrivKeyMissing)?;
+    Ok((cert_chain, cert_priv_key))
+}
+
+/// Reads the certificate chain.
+fn read_certs(cert_chain: &Path) -> Result<Vec<CertificateDer<'static>>, Error> {
+    let cert_chain = File::open(cert_chain).map_err(Error::CertChainIo)?;
+    certs(&mut BufReader::new(cert_chain))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(Error::CertChainIo)
+}
+
+fn certified_key(cert_chain: &Path, cert_priv_key: &Path) -> Result<Arc<CertifiedKey>, Error> {
+    let (cert_chain, cert_priv_key) = load(cert_chain, cert_priv_key)?;
+    let key = any_supported_type(&cert_priv_key).map_err(Error::ServerConfig)?;
+    Ok(Arc::new(CertifiedKey::new(cert_chain, key)))
+}
+
+fn root_cert_store() -> RootCertStore {
+    let mut root_cert_store = RootCertStore::empty();
+    root_cert_store.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned());
+    root_cert_store
+}
+
+/// Returns the DNS names of the subject alternative names of `cert`, wildcard
+/// names excepted.
+#[cfg(feature = "multi-cert")]
+fn dns_names(cert: &CertificateDer<'_>) -> Result<Vec<String>, Error> {
+    use x509_parser::extensions::GeneralName;
+    use x509_parser::parse_x509_certificate;
+
+    let (_, cert) =
+        parse_x509_certificate(cert).map_err(|err| Error::CertParse(err.to_string()))?;
+    let names = cert
+        .subject_alternative_name()
+        .map_err(|err| Error::CertParse(err.to_string()))?;
+    let names = names
+        .iter()
+        .flat_map(|names| &names.value.general_names)
+        .filter_map(|name| match name {
+            GeneralName::DNSName(name) if !name.starts_with("*.") => Some(name.to_string()),
+            _ => None,
+        })
+        .collect();
+    Ok(names)
+}
+
+/// Logs a warning for every certificate in `cert_chain` expiring within
+/// [`EXPIRY_WARNING_PERIOD`] and fails if any of them has already expired.
+#[cfg(feature = "cert-expiry-check")]
+fn check_expiry(cert_chain: &[rustls::pki_types::CertificateDer<'_>]) -> Result<(), Error> {
+    use tracing::{error, warn};
+    use x509_parser::parse_x509_certificate;
+
+    let now = SystemTime::now();
+    for cert in cert_chain {
+        let (_, cert) =
+            parse_x509_certificate(cert).map_err(|err| Error::CertParse(err.to_string()))?;
+        let subject = cert.subject().to_string();
+        let not_after = cert.validity().not_after;
+        let expiry = system_time(not_after);
+        match expiry.duration_since(now) {
+            Err(_) => {
+                error!("Certificate `{subject}` expired at {not_after}");
+                return Err(Error::CertificateExpired { subject, expiry });
+            }
+            Ok(remaining) if remaining < EXPIRY_WARNING_PERIOD => {
+                let days = remaining.as_secs() / SECS_PER_DAY;
+                warn!("Certificate `{subject}` expires at {not_after} ({days} days remaining)");
+            }
+            Ok(_) => {}
+        }
+    }
+    Ok(())
+}
+
+/// Checks the certificate chain and private key files of a node before running
+/// a carrier with them, to fail on a misconfiguration at startup rather than
+/// on the first connection:
+///
+/// - the private key matches the public key of the certificate;
+/// - every certificate of the chain is within its validity period;
+/// - the certificate is valid for every name of `expected_sans`, DNS names or
+///   IP addresses.
+///
+/// The issuers of the chain are not checked, as the nodes may trust a private
+/// CA: see [`verify_chain`]. Returns the information of the certificate, the
+/// first of the chain.
+#[cfg(feature = "cert-expiry-check")]
+pub fn verify_cert(
+    cert_chain: &Path,
+    priv_key: &Path,
+    expected_sans: &[&str],
+) -> Result<CertInfo, Error> {
+    use rustls::client::verify_server_name;
+    use rustls::pki_types::ServerName;
+    use rustls::server::ParsedCertificate;
+
+    let (cert_chain, priv_key) = read(cert_chain, priv_key)?;
+    let Some(cert) = cert_chain.first() else {
+        return Err(Error::CertChainEmpty);
+    };
+    let key = any_supported_type(&priv_key).map_err(Error::ServerConfig)?;
+    CertifiedKey::new(cert_chain.clone(), key)
+        .keys_match()
+        .map_err(Error::CertKeyMismatch)?;
+    let now = SystemTime::now();
+    for cert in &cert_chain {
+        let CertInfo {
+            subject,
+            not_before,
+            not_after,
+            ..
+        } = cert_info(cert)?;
+        if now < not_before {
+            return Err(Error::CertificateNotYetValid {
+                subject,
+                not_before,
+            });
+        }
+        if now > not_after {
+            let expiry = not_after;
+            return Err(Error::CertificateExpired { subject, expiry });
+        }
+    }
+    let parsed = ParsedCertificate::try_from(cert).map_err(Error::CertUntrusted)?;
+    for &name in expected_sans {
+        let mismatch = |reason: String| Error::CertNameMismatch {
+            name: name.to_string(),
+            reason,
+        };
+        let server_name = ServerName::try_from(name).map_err(|err| mismatch(err.to_string()))?;
+        verify_server_name(&parsed, &server_name).map_err(|err| mismatch(err.to_string()))?;
+    }
+    cert_info(cert)
+}
+
+/// Checks that the certificate chain file of a node leads to one of `roots`,
+/// such as the CA of the nodes, as their peers check it.
+#[cfg(feature = "cert-expiry-check")]
+pub fn verify_chain(cert_chain: &Path, roots: &RootCertStore) -> Result<(), Error> {
+    use rustls::client::verify_server_cert_signed_by_trust_anchor;
+    use rustls::pki_types::UnixTime;
+    use rustls::server::ParsedCertificate;
+
+    let cert_chain = read_certs(cert_chain)?;
+    let Some((cert, intermediates)) = cert_chain.split_first() else {
+        return Err(Error::CertChainEmpty);
+    };
+    let parsed = ParsedCertificate::try_from(cert).map_err(Error::CertUntrusted)?;
+    let algorithms = rustls::crypto::ring::default_provider()
+        .signature_verification_algorithms
+        .all;
+    verify_server_cert_signed_by_trust_anchor(
+        &parsed,
+        roots,
+        intermediates,
+        UnixTime::now(),
+        algorithms,
+    )
+    .map_err(Error::CertUntrusted)
+}
+
+/// Returns the subject, subject alternative names, validity and key type of
+/// `cert`, such as presented by a node.
+#[cfg(feature = "cert-expiry-check")]
+pub fn cert_info(cert: &CertificateDer<'_>) -> Result<CertInfo, Error> {
+    use x509_parser::parse_x509_certificate;
+
+    let (_, cert) =
+        parse_x509_certificate(cert).map_err(|err| Error::CertParse(err.to_string()))?;
+    let sans = cert
+        .subject_alternative_name()
+        .map_err(|err| Error::CertParse(err.to_string()))?;
+    let sans = sans
+        .iter()
+        .flat_map(|sans| &sans.value.general_names)
+        .map(ToString::to_string)
+        .collect();
+    let validity = cert.validity();
+    Ok(CertInfo {
+        subject: cert.subject().to_string(),
+        sans,
+        not_before: system_time(validity.not_before),
+        not_after: system_time(validity.not_after),
+        key_type: key_type(cert.public_key()),
+    })
+}
+
+#[cfg(feature = "cert-expiry-check")]
+fn system_time(time: x509_parser::time::ASN1Time) -> SystemTime {
+    SystemTime::UNIX_EPOCH + Duration::from_secs(time.timestamp().try_into().unwrap_or(0))
+}
+
+/// Returns the type of `key`, with its curve or size, or the OID of its
+/// algorithm if unknown.
+#[cfg(feature = "cert-expiry-check")]
+fn key_type(key: &x509_parser::x509::SubjectPublicKeyInfo<'_>) -> String {
+    use x509_parser::oid_registry::{
+        OID_EC_P256, OID_KEY_TYPE_EC_PUBLIC_KEY, OID_NIST_EC_P384, OID_NIST_EC_P521,
+        OID_PKCS1_RSAENCRYPTION, OID_SIG_ED25519,
+    };
+    use x509_parser::public_key::PublicKey;
+
+    let algorithm = &key.algorithm.algorithm;
+    if *algorithm == OID_PKCS1_RSAENCRYPTION {
+        if let Ok(PublicKey::RSA(rsa)) = key.parsed() {
+            let modulus = rsa.modulus.iter().skip_while(|&&byte| byte == 0).count();
+            return format!("RSA {}", modulus * 8);
+        }
+    } else if *algorithm == OID_KEY_TYPE_EC_PUBLIC_KEY {
+        let curve = key
+            .algorithm
+            .parameters
+            .as_ref()
+            .and_then(|parameters| parameters.as_oid().ok());
+        let curve = match curve {
+            Some(curve) if curve == OID_EC_P256 => "P-256".to_string(),
+            Some(curve) if curve == OID_NIST_EC_P384 => "P-384".to_string(),
+            Some(curve) if curve == OID_NIST_EC_P521 => "P-521".to_string(),
+            Some(curve) => curve.to_id_string(),
+            None => "unknown curve".to_string(),
+        };
+        return format!("ECDSA {curve}");
+    } else if *algorithm == OID_SIG_ED25519 {
+        return "Ed25519".to_string();
+    }
+    algorithm.to_id_string()
+}
@@ -0,0 +1,84 @@
+//! W3C trace context propagation, with the `otel` feature.
+//!
+//! The requests sent from within a span carry its context in their
+//! `traceparent` and `tracestate` fields. The receiving carrier handles them in
+//! a `request` span with that remote parent, so a trace continues across the
+//! nodes. A request without a context, or with a malformed one, is handled in
+//! the root span of a new trace.
+
+use crate::messages::NodeRequest;
+use opentelemetry::trace::{SpanContext, SpanId, TraceContextExt, TraceFlags, TraceId, TraceState};
+use opentelemetry::Context;
+use std::str::FromStr;
+use tracing::Span;
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+/// Version of the `traceparent` format written.
+const VERSION: &str = "00";
+
+/// Sets the trace context of `request` to the one of the current span, unless
+/// already set.
+pub(crate) fn inject(request: &mut NodeRequest) {
+    if !request.traceparent.is_empty() {
+        return;
+    }
+    let context = Span::current().context();
+    let span = context.span();
+    let span_context = span.span_context();
+    if !span_context.is_valid() {
+        return;
+    }
+    let flags = span_context.trace_flags() & TraceFlags::SAMPLED;
+    request.traceparent = format!(
+        "{VERSION}-{}-{}-{:02x}",
+        span_context.trace_id(),
+        span_context.span_id(),
+        flags.to_u8()
+    );
+    request.tracestate = span_context.trace_state().header();
+}
+
+/// Returns the context of the remote parent of `request`, empty if absent or
+/// malformed.
+pub(crate) fn extract(request: &NodeRequest) -> Context {
+    match parse(&request.traceparent, &request.tracestate) {
+        Some(parent) => Context::new().with_remote_span_context(parent),
+        None => Context::new(),
+    }
+}
+
+/// Sets the parent of the `span` handling `request`.
+pub(crate) fn set_parent(span: &Span, request: &NodeRequest) {
+    let parent = extract(request);
+    if parent.has_active_span() {
+        // Fails only if the span was already entered.
+        let _ = span.set_parent(parent);
+    }
+}
+
+fn parse(traceparent: &str, tracestate: &str) -> Option<SpanContext> {
+    let mut fields = traceparent.split('-');
+    let version = fields.next().filter(|version| is_hex(version, 2))?;
+    let trace_id = fields.next().filter(|trace_id| is_hex(trace_id, 32))?;
+    let span_id = fields.next().filter(|span_id| is_hex(span_id, 16))?;
+    let flags = fields.next().filter(|flags| is_hex(flags, 2))?;
+    // Later versions may append fields, version 00 has none.
+    if version == "ff" || (version == VERSION && fields.next().is_some()) {
+        return None;
+    }
+    let trace_id = TraceId::from_hex(trace_id).ok()?;
+    let span_id = SpanId::from_hex(span_id).ok()?;
+    let flags = TraceFlags::new(u8::from_str_radix(flags, 16).ok()?) & TraceFlags::SAMPLED;
+    if trace_id == TraceId::INVALID || span_id == SpanId::INVALID {
+        return None;
+    }
+    let state = TraceState::from_str(tracestate).unwrap_or_default();
+    Some(SpanContext::new(trace_id, span_id, flags, true, state))
+}
+
+fn is_hex(field: &str, len: usize) -> bool {
+    field.len() == len
+        && field
+            .bytes()
+            .all(|byte| byte.is_ascii_digit() || (b'a'..=b'f').contains(&byte))
+}
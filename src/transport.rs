@@ -0,0 +1,383 @@
+//! Connection transports.
+//!
+//! A [`Transport`] provides connected and authenticated byte streams to the
+//! node-to-node communication layer, which is generic over it. Available
+//! backends:
+//!
+//! - [`TlsTcpTransport`]: TLS over TCP, the default.
+//! - [`shared::SharedTransport`]: TLS over TCP, with the listener shared by
+//!   several carriers.
+//! - `PlainTcpTransport`: TCP without TLS (feature `no-tls`).
+//! - `quic::QuicTransport`: QUIC (feature `quic`).
+//! - `websocket::WebSocketTransport`: WebSocket over TLS (feature
+//!   `websocket`).
+//! - `memory::MemoryTransport`: in-process streams for tests (feature
+//!   `test-util`).
+//!
+//! Custom backends are passed to
+//! [`Carrier::run_with_transport`](crate::Carrier::run_with_transport).
+
+#[cfg(feature = "test-util")]
+pub mod memory;
+#[cfg(feature = "quic")]
+pub mod quic;
+pub mod shared;
+#[cfg(feature = "websocket")]
+pub mod websocket;
+
+use crate::tls::{self, TlsDetails};
+use futures::prelude::*;
+use rustls::pki_types::ServerName;
+use rustls::{ClientConfig, ServerConfig};
+#[cfg(feature = "no-tls")]
+use std::collections::HashMap;
+use std::io;
+#[cfg(feature = "no-tls")]
+use std::net::IpAddr;
+use std::path::Path;
+#[cfg(feature = "multi-cert")]
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use thiserror::Error;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::{TcpListener, TcpStream};
+use tokio_rustls::{TlsAcceptor, TlsConnector, TlsStream};
+use tokio_stream::wrappers::TcpListenerStream;
+
+/// Transport error.
+#[allow(missing_docs)]
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("Connect: {0}")]
+    Connect(io::Error),
+    #[error("Handshake: {0}")]
+    Handshake(io::Error),
+}
+
+/// Network address of a node.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct NodeAddr {
+    /// Host name or IP address to connect to.
+    pub host: String,
+    /// Port to connect to.
+    pub port: u16,
+    /// Name to verify the node certificate against.
+    pub tls_name: ServerName<'static>,
+}
+
+/// Identity of the remote end of a connection, as established by the
+/// transport.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct PeerIdentity {
+    /// Name of the remote node, if the transport could establish it. For TLS
+    /// this is the SNI server name, for plain TCP the name mapped to the peer
+    /// IP address.
+    pub name: Option<String>,
+}
+
+/// Gate of the data written to an outgoing connection before its handshake
+/// completes, as early data which may be replayed by an attacker.
+///
+/// The carrier lets through the prefix of the stream made of the messages
+/// safe to replay, and the transport holds the rest until the handshake
+/// completes. See [`Transport::early_data`].
+#[derive(Clone, Debug, Default)]
+pub struct EarlyData(Arc<AtomicU64>);
+
+/// Provider of connected and authenticated byte streams.
+pub trait Transport: Send + Sync + 'static {
+    /// Established connection.
+    type Conn: AsyncRead + AsyncWrite + Unpin + Send + 'static;
+    /// Accepted connection before the handshake.
+    type Accepted: Send + 'static;
+    /// Stream of accepted connections.
+    type Listener: Stream<Item = io::Result<Self::Accepted>> + Send + Unpin + 'static;
+
+    /// Returns the listening address for diagnostics.
+    fn listen_addr(&self) -> String;
+
+    /// Returns the remote address of an accepted connection for diagnostics.
+    fn peer_addr(&self, accepted: &Self::Accepted) -> String;
+
+    /// Starts listening for incoming connections.
+    fn bind(&self) -> impl Future<Output = io::Result<Self::Listener>> + Send;
+
+    /// Completes the handshake of an accepted connection.
+    fn accept(
+        &self,
+        accepted: Self::Accepted,
+    ) -> impl Future<Output = Result<(Self::Conn, PeerIdentity), Error>> + Send;
+
+    /// Connects to a node.
+    fn connect<'a>(
+        &'a self,
+        node: &'a NodeAddr,
+    ) -> impl Future<Output = Result<(Self::Conn, PeerIdentity), Error>> + Send + 'a;
+
+    /// Returns the gate of the early data of a connection returned by
+    /// [`connect`](Self::connect), if it may carry data before its handshake
+    /// completes. None by default.
+    fn early_data(&self, _conn: &Self::Conn) -> Option<EarlyData> {
+        None
+    }
+
+    /// Returns the parameters of the TLS handshake of a connection returned by
+    /// [`accept`](Self::accept) or [`connect`](Self::connect), if it has one.
+    /// None by default.
+    fn tls_details(&self, _conn: &Self::Conn) -> Option<TlsDetails> {
+        None
+    }
+}
+
+impl EarlyData {
+    /// Returns the length of the prefix of the stream that may be written as
+    /// early data.
+    #[must_use]
+    pub fn allowed(&self) -> u64 {
+        self.0.load(Ordering::Acquire)
+    }
+
+    /// Lets the `len` bytes at `offset` of the stream through, if all the
+    /// bytes before are.
+    pub(crate) fn allow(&self, offset: u64, len: u64) {
+        let _ = self
+            .0
+            .compare_exchange(offset, offset + len, Ordering::AcqRel, Ordering::Acquire);
+    }
+}
+
+/// TLS over TCP transport.
+pub struct TlsTcpTransport {
+    bind: String,
+    port: u16,
+    acceptor: TlsAcceptor,
+    connector: TlsConnector,
+}
+
+/// Plain TCP transport without any encryption or authentication.
+///
+/// The identity of an incoming connection is looked up by its source IP
+/// address. See [`TlsMode::Disabled`](crate::tls::TlsMode::Disabled) for the
+/// security implications.
+#[cfg(feature = "no-tls")]
+pub struct PlainTcpTransport {
+    bind: String,
+    port: u16,
+    peers: HashMap<IpAddr, String>,
+}
+
+impl TlsTcpTransport {
+    /// Creates a new [`TlsTcpTransport`] listening on `bind:port`.
+    #[must_use]
+    pub fn new(
+        bind: impl Into<String>,
+        port: u16,
+        server_config: Arc<ServerConfig>,
+        client_config: Arc<ClientConfig>,
+    ) -> Self {
+        Self::from_parts(
+            bind,
+            port,
+            TlsAcceptor::from(server_config),
+            TlsConnector::from(client_config),
+        )
+    }
+
+    /// Creates a new [`TlsTcpTransport`] listening on `bind:port` from an
+    /// existing acceptor and connector pair.
+    #[must_use]
+    pub fn from_parts(
+        bind: impl Into<String>,
+        port: u16,
+        acceptor: TlsAcceptor,
+        connector: TlsConnector,
+    ) -> Self {
+        Self {
+            bind: bind.into(),
+            port,
+            acceptor,
+            connector,
+        }
+    }
+
+    /// Creates a new [`TlsTcpTransport`] listening on `bind:port` with the
+    /// certificate loaded from files.
+    pub fn from_files(
+        bind: impl Into<String>,
+        port: u16,
+        cert_chain: &Path,
+        cert_priv_key: &Path,
+    ) -> Result<Self, tls::Error> {
+        let (server_config, client_config) = tls::init(cert_chain, cert_priv_key)?;
+        Ok(Self::new(bind, port, server_config, client_config))
+    }
+
+    /// Creates a new [`TlsTcpTransport`] listening on `bind:port` with several
+    /// certificates loaded from files, selected by SNI. See
+    /// [`tls::init_multi_cert`].
+    #[cfg(feature = "multi-cert")]
+    pub fn from_multi_cert_files(
+        bind: impl Into<String>,
+        port: u16,
+        certs: &[(PathBuf, PathBuf)],
+    ) -> Result<Self, tls::Error> {
+        let (server_config, client_config) =
+            tls::init_multi_cert_files(certs, tls::TlsConfig::default())?;
+        Ok(Self::new(bind, port, server_config, client_config))
+    }
+}
+
+impl Transport for TlsTcpTransport {
+    type Conn = TlsStream<TcpStream>;
+    type Accepted = TcpStream;
+    type Listener = TcpListenerStream;
+
+    fn listen_addr(&self) -> String {
+        format!("{}:{}", self.bind, self.port)
+    }
+
+    fn peer_addr(&self, accepted: &Self::Accepted) -> String {
+        accepted
+            .peer_addr()
+            .map_or_else(|_| "unknown".to_string(), |addr| addr.to_string())
+    }
+
+    async fn bind(&self) -> io::Result<Self::Listener> {
+        let listener = TcpListener::bind((self.bind.as_str(), self.port)).await?;
+        Ok(TcpListenerStream::new(listener))
+    }
+
+    async fn accept(&self, accepted: Self::Accepted) -> Result<(Self::Conn, PeerIdentity), Error> {
+        let stream = self
+            .acceptor
+            .accept(accepted)
+            .await
+            .map_err(Error::Handshake)?;
+        let name = stream.get_ref().1.server_name().map(ToString::to_string);
+        Ok((stream.into(), PeerIdentity { name }))
+    }
+
+    async fn connect<'a>(
+        &'a self,
+        node: &'a NodeAddr,
+    ) -> Result<(Self::Conn, PeerIdentity), Error> {
+        let stream = tls_connect(&self.connector, node).await?;
+        let name = Some(node.host.clone());
+        Ok((stream, PeerIdentity { name }))
+    }
+
+    fn tls_details(&self, conn: &Self::Conn) -> Option<TlsDetails> {
+        Some(TlsDetails::new(conn.get_ref().1))
+    }
+}
+
+/// Connects to `node` with TLS over TCP.
+pub(crate) async fn tls_connect(
+    connector: &TlsConnector,
+    node: &NodeAddr,
+) -> Result<TlsStream<TcpStream>, Error> {
+    let stream = TcpStream::connect((node.host.as_str(), node.port))
+        .await
+        .map_err(Error::Connect)?;
+    let stream = connector
+        .connect(node.tls_name.clone(), stream)
+        .await
+        .map_err(Error::Handshake)?;
+    Ok(stream.into())
+}
+
+#[cfg(feature = "no-tls")]
+impl PlainTcpTransport {
+    /// Creates a new [`PlainTcpTransport`] listening on `bind:port`. `peers`
+    /// maps the IP addresses of the other nodes to their names.
+    #[must_use]
+    pub fn new(bind: impl Into<String>, port: u16, peers: HashMap<IpAddr, String>) -> Self {
+        Self {
+            bind: bind.into(),
+            port,
+            peers,
+        }
+    }
+}
+
+#[cfg(feature = "no-tls")]
+impl Transport for PlainTcpTransport {
+    type Conn = TcpStream;
+    type Accepted = TcpStream;
+    type Listener = TcpListenerStream;
+
+    fn listen_addr(&self) -> String {
+        format!("{}:{}", self.bind, self.port)
+    }
+
+    fn peer_addr(&self, accepted: &Self::Accepted) -> String {
+        accepted
+            .peer_addr()
+            .map_or_else(|_| "unknown".to_string(), |addr| addr.to_string())
+    }
+
+    async fn bind(&self) -> io::Result<Self::Listener> {
+        let listener = TcpListener::bind((self.bind.as_str(), self.port)).await?;
+        Ok(TcpListenerStream::new(listener))
+    }
+
+    async fn accept(&self, accepted: Self::Accepted) -> Result<(Self::Conn, PeerIdentity), Error> {
+        let addr = accepted.peer_addr().map_err(Error::Handshake)?;
+        let name = self.peers.get(&addr.ip().to_canonical()).cloned();
+        Ok((accepted, PeerIdentity { name }))
+    }
+
+    async fn connect<'a>(
+        &'a self,
+        node: &'a NodeAddr,
+    ) -> Result<(Self::Conn, PeerIdentity), Error> {
+        let stream = TcpStream::connect((node.host.as_str(), node.port))
+            .await
+            .map_err(Error::Connect)?;
+        let name = Some(node.host.clone());
+        Ok((stream, PeerIdentity { name }))
+    }
+}
+
+/// Transport kept by the caller while a carrier runs with it, such as a QUIC
+/// transport to rebind.
+impl<T: Transport> Transport for Arc<T> {
+    type Conn = T::Conn;
+    type Accepted = T::Accepted;
+    type Listener = T::Listener;
+
+    fn listen_addr(&self) -> String {
+        (**self).listen_addr()
+    }
+
+    fn peer_addr(&self, accepted: &Self::Accepted) -> String {
+        (**self).peer_addr(accepted)
+    }
+
+    fn bind(&self) -> impl Future<Output = io::Result<Self::Listener>> + Send {
+        (**self).bind()
+    }
+
+    fn accept(
+        &self,
+        accepted: Self::Accepted,
+    ) -> impl Future<Output = Result<(Self::Conn, PeerIdentity), Error>> + Send {
+        (**self).accept(accepted)
+    }
+
+    fn connect<'a>(
+        &'a self,
+        node: &'a NodeAddr,
+    ) -> impl Future<Output = Result<(Self::Conn, PeerIdentity), Error>> + Send + 'a {
+        (**self).connect(node)
+    }
+
+    fn early_data(&self, conn: &Self::Conn) -> Option<EarlyData> {
+        (**self).early_data(conn)
+    }
+
+    fn tls_details(&self, conn: &Self::Conn) -> Option<TlsDetails> {
+        (**self).tls_details(conn)
+    }
+}
@@ -0,0 +1,102 @@
+//! In-memory transport.
+//!
+//! Nodes attached to the same [`MemoryNetwork`] connect to each other through
+//! in-process duplex pipes. The accepting side learns the name of the
+//! connecting node, which makes the transport convenient for tests.
+
+use super::{Error, NodeAddr, PeerIdentity, Transport};
+use futures::channel::mpsc;
+use futures::prelude::*;
+use std::collections::HashMap;
+use std::io;
+use std::sync::{Arc, Mutex};
+use tokio::io::{duplex, DuplexStream};
+
+const BUFFER_SIZE: usize = 64 * 1024;
+
+type Accepted = (DuplexStream, String);
+
+/// Registry of in-memory listeners.
+#[derive(Clone, Default)]
+pub struct MemoryNetwork {
+    listeners: Arc<Mutex<HashMap<String, mpsc::UnboundedSender<Accepted>>>>,
+}
+
+/// Transport of a single node attached to a [`MemoryNetwork`].
+pub struct MemoryTransport {
+    network: MemoryNetwork,
+    name: String,
+}
+
+impl MemoryNetwork {
+    /// Creates a new empty network.
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates a transport for the node `name`.
+    #[must_use]
+    pub fn transport(&self, name: impl Into<String>) -> MemoryTransport {
+        MemoryTransport {
+            network: self.clone(),
+            name: name.into(),
+        }
+    }
+}
+
+impl Transport for MemoryTransport {
+    type Conn = DuplexStream;
+    type Accepted = Accepted;
+    type Listener =
+        stream::Map<mpsc::UnboundedReceiver<Accepted>, fn(Accepted) -> io::Result<Accepted>>;
+
+    fn listen_addr(&self) -> String {
+        format!("memory:{}", self.name)
+    }
+
+    fn peer_addr(&self, (_, name): &Self::Accepted) -> String {
+        format!("memory:{name}")
+    }
+
+    async fn bind(&self) -> io::Result<Self::Listener> {
+        let (tx, rx) = mpsc::unbounded();
+        self.network
+            .listeners
+            .lock()
+            .unwrap()
+            .insert(self.name.clone(), tx);
+        Ok(rx.map(Ok as fn(Accepted) -> io::Result<Accepted>))
+    }
+
+    async fn accept(
+        &self,
+        (stream, name): Self::Accepted,
+    ) -> Result<(Self::Conn, PeerIdentity), Error> {
+        Ok((stream, PeerIdentity { name: Some(name) }))
+    }
+
+    async fn connect<'a>(
+        &'a self,
+        node: &'a NodeAddr,
+    ) -> Result<(Self::Conn, PeerIdentity), Error> {
+        let (client, server) = duplex(BUFFER_SIZE);
+        let listener = self
+            .network
+            .listeners
+            .lock()
+            .unwrap()
+            .get(&node.host)
+            .cloned();
+        listener
+            .ok_or_else(|| io::ErrorKind::ConnectionRefused.into())
+            .and_then(|listener| {
+                listener
+                    .unbounded_send((server, self.name.clone()))
+                    .map_err(|_| io::ErrorKind::ConnectionRefused.into())
+            })
+            .map_err(Error::Connect)?;
+        let name = Some(node.host.clone());
+        Ok((client, PeerIdentity { name }))
+    }
+}
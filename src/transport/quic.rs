@@ -0,0 +1,368 @@
+//! QUIC transport.
+//!
+//! Every node-to-node connection is carried by a single bidirectional QUIC
+//! stream. The listening and the connecting sides share one UDP endpoint, and
+//! the peers authenticate with the same certificates as over TCP.
+//!
+//! With [`QuicConfig::zero_rtt`], a connection to a node resuming the TLS
+//! session of a previous one writes its first request as 0-RTT early data,
+//! answered one round trip after the connection attempt instead of two. Early
+//! data may be replayed by an attacker, so only a first request marked
+//! [`is_idempotent`](crate::messages::NodeRequest::is_idempotent) goes early,
+//! along with the [`Hello`](crate::hello::Hello) preceding it, and anything
+//! else waits for the handshake to complete. If the node rejects the early
+//! data, such as after a restart losing the session, it is written again
+//! once the handshake completes, on a new stream.
+
+use super::{EarlyData, Error, NodeAddr, PeerIdentity, Transport};
+use futures::future::BoxFuture;
+use futures::prelude::*;
+use futures::ready;
+use futures::stream::BoxStream;
+use quinn::crypto::rustls::{HandshakeData, QuicClientConfig, QuicServerConfig};
+use quinn::{
+    Connection, Endpoint, RecvStream, SendStream, TransportConfig, VarInt, ZeroRttAccepted,
+};
+use rustls::pki_types::ServerName;
+use std::any::Any;
+use std::net::{IpAddr, SocketAddr, UdpSocket};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::Duration;
+use std::{io, mem};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::net::lookup_host;
+
+const DEFAULT_MAX_CONCURRENT_STREAMS: u32 = 16;
+const DEFAULT_IDLE_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// QUIC connection settings.
+#[derive(Clone, Debug)]
+pub struct QuicConfig {
+    /// Maximum number of concurrent bidirectional streams a peer may open.
+    pub max_concurrent_streams: u32,
+    /// Time of inactivity after which a connection is closed. Keep-alive
+    /// packets are sent at a third of this interval.
+    pub idle_timeout: Duration,
+    /// Whether the connections resuming a TLS session send their first
+    /// request as 0-RTT early data, if idempotent, and accept the early data
+    /// of the nodes. Off by default.
+    pub zero_rtt: bool,
+}
+
+/// QUIC transport.
+pub struct QuicTransport {
+    bind: SocketAddr,
+    server_config: quinn::ServerConfig,
+    client_config: quinn::ClientConfig,
+    zero_rtt: bool,
+    endpoint: Mutex<Option<Endpoint>>,
+}
+
+/// A bidirectional QUIC stream.
+pub struct QuicStream {
+    send: SendStream,
+    recv: RecvStream,
+    connection: Connection,
+    /// Early data of an outgoing 0-RTT connection, until settled.
+    early: Option<Early>,
+}
+
+/// Early data of an outgoing 0-RTT connection.
+enum Early {
+    /// Handshake in progress.
+    Handshake {
+        accepted: ZeroRttAccepted,
+        gate: EarlyData,
+        /// Bytes written as early data, to write again if rejected.
+        written: Vec<u8>,
+    },
+    /// Early data rejected, being written again on a new stream.
+    Rejected(BoxFuture<'static, io::Result<(SendStream, RecvStream)>>),
+}
+
+impl Default for QuicConfig {
+    fn default() -> Self {
+        Self {
+            max_concurrent_streams: DEFAULT_MAX_CONCURRENT_STREAMS,
+            idle_timeout: DEFAULT_IDLE_TIMEOUT,
+            zero_rtt: false,
+        }
+    }
+}
+
+impl QuicTransport {
+    /// Creates a new [`QuicTransport`] listening on `bind`. The TLS
+    /// configurations are usually obtained from [`tls::init`](crate::tls::init).
+    pub fn new(
+        bind: SocketAddr,
+        server_config: Arc<rustls::ServerConfig>,
+        client_config: Arc<rustls::ClientConfig>,
+        config: &QuicConfig,
+    ) -> io::Result<Self> {
+        let mut transport_config = TransportConfig::default();
+        transport_config
+            .max_concurrent_bidi_streams(config.max_concurrent_streams.into())
+            .max_idle_timeout(Some(config.idle_timeout.try_into().map_err(invalid)?))
+            .keep_alive_interval(Some(config.idle_timeout / 3));
+        let transport_config = Arc::new(transport_config);
+        let (server_config, client_config) = if config.zero_rtt {
+            let mut server_config = (*server_config).clone();
+            server_config.max_early_data_size = u32::MAX;
+            let mut client_config = (*client_config).clone();
+            client_config.enable_early_data = true;
+            (Arc::new(server_config), Arc::new(client_config))
+        } else {
+            (server_config, client_config)
+        };
+
+        let server_crypto = QuicServerConfig::try_from(server_config).map_err(invalid)?;
+        let mut server_config = quinn::ServerConfig::with_crypto(Arc::new(server_crypto));
+        server_config.transport_config(Arc::clone(&transport_config));
+
+        let client_crypto = QuicClientConfig::try_from(client_config).map_err(invalid)?;
+        let mut client_config = quinn::ClientConfig::new(Arc::new(client_crypto));
+        client_config.transport_config(transport_config);
+
+        Ok(Self {
+            bind,
+            server_config,
+            client_config,
+            zero_rtt: config.zero_rtt,
+            endpoint: Mutex::new(None),
+        })
+    }
+
+    /// Moves the endpoint to a new UDP socket. Established connections migrate
+    /// to the new socket.
+    pub fn rebind(&self, socket: UdpSocket) -> io::Result<()> {
+        self.endpoint()?.rebind(socket)
+    }
+
+    fn endpoint(&self) -> io::Result<Endpoint> {
+        let mut endpoint = self.endpoint.lock().unwrap();
+        if let Some(endpoint) = &*endpoint {
+            return Ok(endpoint.clone());
+        }
+        let mut new_endpoint = Endpoint::server(self.server_config.clone(), self.bind)?;
+        new_endpoint.set_default_client_config(self.client_config.clone());
+        *endpoint = Some(new_endpoint.clone());
+        Ok(new_endpoint)
+    }
+}
+
+impl Drop for QuicTransport {
+    fn drop(&mut self) {
+        if let Some(endpoint) = &*self.endpoint.lock().unwrap() {
+            endpoint.close(VarInt::from_u32(0), b"shutdown");
+        }
+    }
+}
+
+fn invalid(err: impl std::error::Error + Send + Sync + 'static) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, err)
+}
+
+impl Transport for QuicTransport {
+    type Conn = QuicStream;
+    type Accepted = quinn::Incoming;
+    type Listener = BoxStream<'static, io::Result<quinn::Incoming>>;
+
+    fn listen_addr(&self) -> String {
+        format!("quic://{}", self.bind)
+    }
+
+    fn peer_addr(&self, accepted: &Self::Accepted) -> String {
+        accepted.remote_address().to_string()
+    }
+
+    async fn bind(&self) -> io::Result<Self::Listener> {
+        let endpoint = self.endpoint()?;
+        Ok(stream::unfold(endpoint, |endpoint| async move {
+            let incoming = endpoint.accept().await?;
+            Some((Ok(incoming), endpoint))
+        })
+        .boxed())
+    }
+
+    async fn accept(&self, accepted: Self::Accepted) -> Result<(Self::Conn, PeerIdentity), Error> {
+        let mut connecting = accepted
+            .accept()
+            .map_err(|err| Error::Handshake(err.into()))?;
+        let name = connecting.handshake_data().await.ok().and_then(server_name);
+        // With 0-RTT, the early data is read and answered right away rather
+        // than once the handshake completes. The peers are identified by the
+        // server name alone, known already.
+        let connecting = if self.zero_rtt {
+            connecting.into_0rtt().map(|(connection, _)| connection)
+        } else {
+            Err(connecting)
+        };
+        let connection = match connecting {
+            Ok(connection) => connection,
+            Err(connecting) => connecting
+                .await
+                .map_err(|err| Error::Handshake(err.into()))?,
+        };
+        let (send, recv) = connection
+            .accept_bi()
+            .await
+            .map_err(|err| Error::Handshake(err.into()))?;
+        let stream = QuicStream {
+            send,
+            recv,
+            connection,
+            early: None,
+        };
+        Ok((stream, PeerIdentity { name }))
+    }
+
+    async fn connect<'a>(
+        &'a self,
+        node: &'a NodeAddr,
+    ) -> Result<(Self::Conn, PeerIdentity), Error> {
+        let endpoint = self.endpoint().map_err(Error::Connect)?;
+        let addr = lookup_host((node.host.as_str(), node.port))
+            .await
+            .map_err(Error::Connect)?
+            .find(|addr| addr.is_ipv4() == self.bind.is_ipv4())
+            .ok_or_else(|| Error::Connect(io::ErrorKind::AddrNotAvailable.into()))?;
+        let server_name = match &node.tls_name {
+            ServerName::DnsName(name) => name.as_ref().to_string(),
+            ServerName::IpAddress(ip) => IpAddr::from(*ip).to_string(),
+            _ => node.host.clone(),
+        };
+        let connecting = endpoint
+            .connect(addr, &server_name)
+            .map_err(|err| Error::Connect(invalid(err)))?;
+        let connecting = if self.zero_rtt {
+            connecting.into_0rtt()
+        } else {
+            Err(connecting)
+        };
+        let (connection, accepted) = match connecting {
+            Ok((connection, accepted)) => (connection, Some(accepted)),
+            Err(connecting) => {
+                let connection = connecting
+                    .await
+                    .map_err(|err| Error::Handshake(err.into()))?;
+                (connection, None)
+            }
+        };
+        let (send, recv) = connection
+            .open_bi()
+            .await
+            .map_err(|err| Error::Handshake(err.into()))?;
+        let stream = QuicStream {
+            send,
+            recv,
+            connection,
+            early: accepted.map(|accepted| Early::Handshake {
+                accepted,
+                gate: EarlyData::default(),
+                written: Vec::new(),
+            }),
+        };
+        let name = Some(node.host.clone());
+        Ok((stream, PeerIdentity { name }))
+    }
+
+    fn early_data(&self, conn: &Self::Conn) -> Option<EarlyData> {
+        match &conn.early {
+            Some(Early::Handshake { gate, .. }) => Some(gate.clone()),
+            _ => None,
+        }
+    }
+}
+
+fn server_name(data: Box<dyn Any>) -> Option<String> {
+    data.downcast::<HandshakeData>().ok()?.server_name
+}
+
+impl QuicStream {
+    /// Waits for the handshake of a 0-RTT connection to complete, and writes
+    /// the early data again on a new stream if rejected.
+    fn poll_settle(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        loop {
+            match &mut self.early {
+                None => return Poll::Ready(Ok(())),
+                Some(Early::Handshake {
+                    accepted, written, ..
+                }) => {
+                    if ready!(Pin::new(accepted).poll(cx)) {
+                        self.early = None;
+                    } else {
+                        let connection = self.connection.clone();
+                        let written = mem::take(written);
+                        self.early = Some(Early::Rejected(Box::pin(async move {
+                            let (mut send, recv) = connection.open_bi().await?;
+                            send.write_all(&written).await?;
+                            Ok((send, recv))
+                        })));
+                    }
+                }
+                Some(Early::Rejected(reopen)) => {
+                    let result = ready!(reopen.as_mut().poll(cx));
+                    self.early = None;
+                    (self.send, self.recv) = result?;
+                }
+            }
+        }
+    }
+}
+
+impl AsyncRead for QuicStream {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        // The responses to the early data arrive after the handshake anyway.
+        ready!(self.poll_settle(cx))?;
+        Pin::new(&mut self.recv).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for QuicStream {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = &mut *self;
+        if this.poll_settle(cx)?.is_pending() {
+            // Only the bytes let through by the gate go before the handshake
+            // completes.
+            let Some(Early::Handshake { gate, written, .. }) = &mut this.early else {
+                return Poll::Pending;
+            };
+            let allowed = gate.allowed().saturating_sub(written.len() as u64);
+            if allowed == 0 {
+                return Poll::Pending;
+            }
+            let len = buf.len().min(allowed.try_into().unwrap_or(usize::MAX));
+            let n = ready!(AsyncWrite::poll_write(
+                Pin::new(&mut this.send),
+                cx,
+                &buf[..len]
+            ))?;
+            written.extend_from_slice(&buf[..n]);
+            return Poll::Ready(Ok(n));
+        }
+        AsyncWrite::poll_write(Pin::new(&mut this.send), cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        // Flushing the early data does not wait for the handshake.
+        if !matches!(self.early, Some(Early::Handshake { .. })) {
+            ready!(self.poll_settle(cx))?;
+        }
+        AsyncWrite::poll_flush(Pin::new(&mut self.send), cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        ready!(self.poll_settle(cx))?;
+        AsyncWrite::poll_shutdown(Pin::new(&mut self.send), cx)
+    }
+}
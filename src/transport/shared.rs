@@ -0,0 +1,222 @@
+//! A single TLS listener shared by several carriers.
+//!
+//! The [`SharedListener`] owns the listening socket and completes the TLS
+//! handshakes. Each connection is then routed by its SNI server name to the
+//! carrier which claimed the name, through that carrier's [`SharedTransport`].
+//! Connections with an unclaimed name are rejected.
+
+use super::{tls_connect, Error, NodeAddr, PeerIdentity, Transport};
+use crate::tls::TlsDetails;
+use crate::{is_transient_accept_error, spawn_named, ACCEPT_RETRY_INTERVAL};
+use futures::channel::mpsc;
+use futures::prelude::*;
+use rustls::{ClientConfig, ServerConfig};
+use std::collections::HashMap;
+use std::io;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::time::sleep;
+use tokio_rustls::server::TlsStream as ServerTlsStream;
+use tokio_rustls::{TlsAcceptor, TlsConnector, TlsStream};
+use tracing::{debug, info, warn};
+
+/// TLS listener shared by several carriers.
+#[derive(Clone)]
+pub struct SharedListener {
+    inner: Arc<Inner>,
+}
+
+/// Transport of a carrier behind a [`SharedListener`].
+pub struct SharedTransport {
+    inner: Arc<Inner>,
+    names: Vec<String>,
+    connector: TlsConnector,
+}
+
+/// Connection routed to a [`SharedTransport`], with the handshake completed.
+pub struct Routed {
+    stream: ServerTlsStream<TcpStream>,
+    name: String,
+    peer: SocketAddr,
+}
+
+/// Stream of the connections routed to a [`SharedTransport`]. Releases the
+/// claimed names when dropped.
+pub struct RoutedStream {
+    inner: Arc<Inner>,
+    id: u64,
+    names: Vec<String>,
+    rx: mpsc::UnboundedReceiver<Routed>,
+}
+
+struct Inner {
+    bind: String,
+    port: u16,
+    acceptor: TlsAcceptor,
+    routes: Mutex<HashMap<String, Route>>,
+    next_id: AtomicU64,
+}
+
+struct Route {
+    id: u64,
+    tx: mpsc::UnboundedSender<Routed>,
+}
+
+impl SharedListener {
+    /// Creates a new [`SharedListener`] on `bind:port`.
+    #[must_use]
+    pub fn new(bind: impl Into<String>, port: u16, server_config: Arc<ServerConfig>) -> Self {
+        let inner = Inner {
+            bind: bind.into(),
+            port,
+            acceptor: TlsAcceptor::from(server_config),
+            routes: Mutex::new(HashMap::new()),
+            next_id: AtomicU64::new(0),
+        };
+        Self {
+            inner: Arc::new(inner),
+        }
+    }
+
+    /// Creates a [`SharedTransport`] receiving the connections with an SNI
+    /// server name from `names`. The names are claimed when the carrier starts
+    /// listening, and released when it stops.
+    pub fn transport(
+        &self,
+        names: impl IntoIterator<Item = impl Into<String>>,
+        client_config: Arc<ClientConfig>,
+    ) -> SharedTransport {
+        SharedTransport {
+            inner: Arc::clone(&self.inner),
+            names: names.into_iter().map(Into::into).collect(),
+            connector: TlsConnector::from(client_config),
+        }
+    }
+
+    /// Accepts the connections and routes them to the carriers. Runs until
+    /// a non-transient accept failure.
+    pub async fn run(&self) -> io::Result<()> {
+        let listener = TcpListener::bind((self.inner.bind.as_str(), self.inner.port)).await?;
+        info!(
+            "Listening for shared incoming connections to {}:{}",
+            self.inner.bind, self.inner.port
+        );
+        loop {
+            let (stream, peer) = match listener.accept().await {
+                Ok(accepted) => accepted,
+                Err(err) if is_transient_accept_error(&err) => {
+                    warn!("Transient accept failure: {err}");
+                    sleep(ACCEPT_RETRY_INTERVAL).await;
+                    continue;
+                }
+                Err(err) => return Err(err),
+            };
+            let inner = Arc::clone(&self.inner);
+            spawn_named(&format!("carrier-shared-in:{peer}"), async move {
+                if let Err(err) = inner.route(stream, peer).await {
+                    debug!("Shared connection from {peer} rejected: {err}");
+                }
+            });
+        }
+    }
+}
+
+impl Inner {
+    async fn route(&self, stream: TcpStream, peer: SocketAddr) -> io::Result<()> {
+        let stream = self.acceptor.accept(stream).await?;
+        let name = stream
+            .get_ref()
+            .1
+            .server_name()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "missing SNI"))?
+            .to_string();
+        let routes = self.routes.lock().unwrap();
+        let route = routes.get(&name).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("unclaimed server name `{name}`"),
+            )
+        })?;
+        route
+            .tx
+            .unbounded_send(Routed { stream, name, peer })
+            .map_err(|_| io::Error::from(io::ErrorKind::ConnectionRefused))
+    }
+}
+
+impl Transport for SharedTransport {
+    type Conn = TlsStream<TcpStream>;
+    type Accepted = Routed;
+    type Listener = RoutedStream;
+
+    fn listen_addr(&self) -> String {
+        format!("{}:{} (shared)", self.inner.bind, self.inner.port)
+    }
+
+    fn peer_addr(&self, accepted: &Self::Accepted) -> String {
+        accepted.peer.to_string()
+    }
+
+    async fn bind(&self) -> io::Result<Self::Listener> {
+        let mut routes = self.inner.routes.lock().unwrap();
+        if let Some(name) = self.names.iter().find(|name| routes.contains_key(*name)) {
+            return Err(io::Error::new(
+                io::ErrorKind::AddrInUse,
+                format!("server name `{name}` is already claimed"),
+            ));
+        }
+        let id = self.inner.next_id.fetch_add(1, Ordering::Relaxed);
+        let (tx, rx) = mpsc::unbounded();
+        for name in &self.names {
+            let tx = tx.clone();
+            routes.insert(name.clone(), Route { id, tx });
+        }
+        Ok(RoutedStream {
+            inner: Arc::clone(&self.inner),
+            id,
+            names: self.names.clone(),
+            rx,
+        })
+    }
+
+    async fn accept(&self, accepted: Self::Accepted) -> Result<(Self::Conn, PeerIdentity), Error> {
+        let Routed { stream, name, .. } = accepted;
+        Ok((stream.into(), PeerIdentity { name: Some(name) }))
+    }
+
+    async fn connect<'a>(
+        &'a self,
+        node: &'a NodeAddr,
+    ) -> Result<(Self::Conn, PeerIdentity), Error> {
+        let stream = tls_connect(&self.connector, node).await?;
+        let name = Some(node.host.clone());
+        Ok((stream, PeerIdentity { name }))
+    }
+
+    fn tls_details(&self, conn: &Self::Conn) -> Option<TlsDetails> {
+        Some(TlsDetails::new(conn.get_ref().1))
+    }
+}
+
+impl Stream for RoutedStream {
+    type Item = io::Result<Routed>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.rx.poll_next_unpin(cx).map(|routed| routed.map(Ok))
+    }
+}
+
+impl Drop for RoutedStream {
+    fn drop(&mut self) {
+        let mut routes = self.inner.routes.lock().unwrap();
+        for name in &self.names {
+            if routes.get(name).is_some_and(|route| route.id == self.id) {
+                routes.remove(name);
+            }
+        }
+    }
+}
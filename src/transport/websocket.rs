@@ -0,0 +1,271 @@
+//! WebSocket over TLS transport.
+//!
+//! For environments where only HTTPS-shaped traffic can leave the network. The
+//! connecting side establishes a `wss://` connection to [`WS_PATH`], optionally
+//! tunneled through an HTTP proxy with `CONNECT`. The listening side performs
+//! the HTTP/1.1 upgrade before handing the connection to the normal serve
+//! logic. The node identity is still established by SNI.
+//!
+//! The byte stream, including the length prefixes of the protobuf frames, is
+//! carried as a sequence of binary WebSocket messages. Message boundaries carry
+//! no meaning: a protobuf frame may span several messages, and a message may
+//! contain several frames.
+
+use super::{Error, NodeAddr, PeerIdentity, Transport};
+use crate::tls::TlsDetails;
+use futures::prelude::*;
+use rustls::{ClientConfig, ServerConfig};
+use std::io;
+use std::mem;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{ready, Context, Poll};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, ReadBuf};
+use tokio::net::{TcpListener, TcpStream};
+use tokio_rustls::{TlsAcceptor, TlsConnector, TlsStream};
+use tokio_stream::wrappers::TcpListenerStream;
+use tokio_tungstenite::tungstenite::handshake::server::{ErrorResponse, Request, Response};
+use tokio_tungstenite::tungstenite::http::StatusCode;
+use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::{accept_hdr_async, client_async, WebSocketStream};
+
+/// HTTP path of the WebSocket endpoint.
+pub const WS_PATH: &str = "/mpc-carrier";
+
+const MAX_MESSAGE_LEN: usize = 64 * 1024;
+const MAX_PROXY_RESPONSE_LEN: usize = 8 * 1024;
+
+/// WebSocket over TLS over TCP transport.
+pub struct WebSocketTransport {
+    bind: String,
+    port: u16,
+    acceptor: TlsAcceptor,
+    connector: TlsConnector,
+    proxy: Option<(String, u16)>,
+}
+
+/// Byte stream carried over a WebSocket connection.
+pub struct WebSocketConn<S> {
+    ws: WebSocketStream<S>,
+    read_buf: Vec<u8>,
+    read_pos: usize,
+    write_buf: Vec<u8>,
+}
+
+impl WebSocketTransport {
+    /// Creates a new [`WebSocketTransport`] listening on `bind:port`.
+    #[must_use]
+    pub fn new(
+        bind: impl Into<String>,
+        port: u16,
+        server_config: Arc<ServerConfig>,
+        client_config: Arc<ClientConfig>,
+    ) -> Self {
+        Self {
+            bind: bind.into(),
+            port,
+            acceptor: TlsAcceptor::from(server_config),
+            connector: TlsConnector::from(client_config),
+            proxy: None,
+        }
+    }
+
+    /// Tunnels the outgoing connections through an HTTP proxy at `host:port`
+    /// using the `CONNECT` method.
+    #[must_use]
+    pub fn proxy(mut self, host: impl Into<String>, port: u16) -> Self {
+        self.proxy = Some((host.into(), port));
+        self
+    }
+}
+
+impl Transport for WebSocketTransport {
+    type Conn = WebSocketConn<TlsStream<TcpStream>>;
+    type Accepted = TcpStream;
+    type Listener = TcpListenerStream;
+
+    fn listen_addr(&self) -> String {
+        format!("{}:{}{WS_PATH}", self.bind, self.port)
+    }
+
+    fn peer_addr(&self, accepted: &Self::Accepted) -> String {
+        accepted
+            .peer_addr()
+            .map_or_else(|_| "unknown".to_string(), |addr| addr.to_string())
+    }
+
+    async fn bind(&self) -> io::Result<Self::Listener> {
+        let listener = TcpListener::bind((self.bind.as_str(), self.port)).await?;
+        Ok(TcpListenerStream::new(listener))
+    }
+
+    async fn accept(&self, accepted: Self::Accepted) -> Result<(Self::Conn, PeerIdentity), Error> {
+        let stream = self
+            .acceptor
+            .accept(accepted)
+            .await
+            .map_err(Error::Handshake)?;
+        let name = stream.get_ref().1.server_name().map(ToString::to_string);
+        let ws = accept_hdr_async(TlsStream::from(stream), check_path)
+            .await
+            .map_err(|err| Error::Handshake(io::Error::other(err)))?;
+        Ok((WebSocketConn::new(ws), PeerIdentity { name }))
+    }
+
+    async fn connect<'a>(
+        &'a self,
+        node: &'a NodeAddr,
+    ) -> Result<(Self::Conn, PeerIdentity), Error> {
+        let stream = match &self.proxy {
+            Some((host, port)) => {
+                let mut stream = TcpStream::connect((host.as_str(), *port))
+                    .await
+                    .map_err(Error::Connect)?;
+                http_connect(&mut stream, &node.host, node.port)
+                    .await
+                    .map_err(Error::Connect)?;
+                stream
+            }
+            None => TcpStream::connect((node.host.as_str(), node.port))
+                .await
+                .map_err(Error::Connect)?,
+        };
+        let stream = self
+            .connector
+            .connect(node.tls_name.clone(), stream)
+            .await
+            .map_err(Error::Handshake)?;
+        let url = format!("wss://{}{WS_PATH}", authority(&node.host, node.port));
+        let (ws, _) = client_async(url, TlsStream::from(stream))
+            .await
+            .map_err(|err| Error::Handshake(io::Error::other(err)))?;
+        let name = Some(node.host.clone());
+        Ok((WebSocketConn::new(ws), PeerIdentity { name }))
+    }
+
+    fn tls_details(&self, conn: &Self::Conn) -> Option<TlsDetails> {
+        Some(TlsDetails::new(conn.ws.get_ref().get_ref().1))
+    }
+}
+
+impl<S> WebSocketConn<S> {
+    fn new(ws: WebSocketStream<S>) -> Self {
+        Self {
+            ws,
+            read_buf: Vec::new(),
+            read_pos: 0,
+            write_buf: Vec::new(),
+        }
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> WebSocketConn<S> {
+    fn poll_send_buffered(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        if self.write_buf.is_empty() {
+            return Poll::Ready(Ok(()));
+        }
+        ready!(self.ws.poll_ready_unpin(cx)).map_err(io::Error::other)?;
+        let message = Message::Binary(mem::take(&mut self.write_buf));
+        self.ws
+            .start_send_unpin(message)
+            .map_err(io::Error::other)?;
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> AsyncRead for WebSocketConn<S> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = &mut *self;
+        while this.read_pos == this.read_buf.len() {
+            match ready!(this.ws.poll_next_unpin(cx)) {
+                Some(Ok(Message::Binary(data))) => {
+                    this.read_buf = data;
+                    this.read_pos = 0;
+                }
+                Some(Ok(Message::Close(_))) | None => return Poll::Ready(Ok(())),
+                Some(Ok(_)) => {}
+                Some(Err(err)) => return Poll::Ready(Err(io::Error::other(err))),
+            }
+        }
+        let len = buf.remaining().min(this.read_buf.len() - this.read_pos);
+        buf.put_slice(&this.read_buf[this.read_pos..this.read_pos + len]);
+        this.read_pos += len;
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> AsyncWrite for WebSocketConn<S> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        if self.write_buf.len() >= MAX_MESSAGE_LEN {
+            ready!(self.poll_send_buffered(cx))?;
+        }
+        let len = buf.len().min(MAX_MESSAGE_LEN - self.write_buf.len());
+        self.write_buf.extend_from_slice(&buf[..len]);
+        Poll::Ready(Ok(len))
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        ready!(self.poll_send_buffered(cx))?;
+        self.ws.poll_flush_unpin(cx).map_err(io::Error::other)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        ready!(self.poll_send_buffered(cx))?;
+        self.ws.poll_close_unpin(cx).map_err(io::Error::other)
+    }
+}
+
+#[allow(clippy::result_large_err)] // signature required by tungstenite
+fn check_path(request: &Request, response: Response) -> Result<Response, ErrorResponse> {
+    if request.uri().path() == WS_PATH {
+        Ok(response)
+    } else {
+        let mut response = ErrorResponse::new(None);
+        *response.status_mut() = StatusCode::NOT_FOUND;
+        Err(response)
+    }
+}
+
+/// Opens a tunnel to `host:port` through an HTTP proxy.
+async fn http_connect(stream: &mut TcpStream, host: &str, port: u16) -> io::Result<()> {
+    let authority = authority(host, port);
+    let request = format!("CONNECT {authority} HTTP/1.1\r\nHost: {authority}\r\n\r\n");
+    stream.write_all(request.as_bytes()).await?;
+    // Read byte by byte to not consume anything past the response head.
+    let mut response = Vec::new();
+    while !response.ends_with(b"\r\n\r\n") {
+        if response.len() >= MAX_PROXY_RESPONSE_LEN {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "proxy response too large",
+            ));
+        }
+        response.push(stream.read_u8().await?);
+    }
+    let status = response.split(|&b| b == b' ').nth(1).unwrap_or_default();
+    if status == b"200" {
+        Ok(())
+    } else {
+        let line = response.split(|&b| b == b'\r').next().unwrap_or_default();
+        Err(io::Error::new(
+            io::ErrorKind::ConnectionRefused,
+            format!("proxy CONNECT: {}", String::from_utf8_lossy(line)),
+        ))
+    }
+}
+
+fn authority(host: &str, port: u16) -> String {
+    if host.contains(':') {
+        format!("[{host}]:{port}")
+    } else {
+        format!("{host}:{port}")
+    }
+}
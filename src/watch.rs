@@ -0,0 +1,175 @@
+//! Hot reload of the node set from a peers file.
+//!
+//! The file lists one node per line in the form of `host:port` or
+//! `[ipv6]:port`. Empty lines and lines starting with `#` are ignored. The file
+//! is polled for modifications, and every change is applied to the running
+//! [`Carrier`](crate::Carrier) through a [`CarrierHandle`].
+
+use crate::channels::Outgoing;
+use crate::config::{self, ConfigError};
+use crate::control::{AddError, CarrierHandle, RemoveError};
+use std::collections::HashMap;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+use thiserror::Error;
+use tokio::fs;
+use tokio::time::sleep;
+use tracing::{info, warn};
+
+const DEFAULT_POLL_INTERVAL: Duration = Duration::from_secs(1);
+const DEFAULT_DEBOUNCE: Duration = Duration::from_millis(500);
+
+/// Watcher applying the changes of a peers file to a running carrier.
+pub struct ConfigWatcher {
+    path: PathBuf,
+    handle: CarrierHandle,
+    nodes: HashMap<String, u16>,
+    self_name: Option<String>,
+    poll_interval: Duration,
+    debounce: Duration,
+}
+
+/// Modification stamp of the watched file.
+type Stamp = Option<(SystemTime, u64)>;
+
+impl ConfigWatcher {
+    /// Creates a new [`ConfigWatcher`] of the file at `path`. The `nodes` are
+    /// the ones the carrier was created with.
+    #[must_use]
+    pub fn new(
+        path: impl Into<PathBuf>,
+        handle: CarrierHandle,
+        nodes: HashMap<String, u16>,
+    ) -> Self {
+        Self {
+            path: path.into(),
+            handle,
+            nodes,
+            self_name: None,
+            poll_interval: DEFAULT_POLL_INTERVAL,
+            debounce: DEFAULT_DEBOUNCE,
+        }
+    }
+
+    /// Skips the entries with the host equal to `name`, so the same file can
+    /// be shared by all nodes.
+    #[must_use]
+    pub fn self_name(mut self, name: impl Into<String>) -> Self {
+        self.self_name = Some(name.into());
+        self
+    }
+
+    /// Sets the interval between the checks of the file. Defaults to 1 second.
+    #[must_use]
+    pub fn poll_interval(mut self, interval: Duration) -> Self {
+        self.poll_interval = interval;
+        self
+    }
+
+    /// Sets how long the file must stay unmodified before it is applied, so a
+    /// burst of writes is applied once. Defaults to 500 milliseconds.
+    #[must_use]
+    pub fn debounce(mut self, debounce: Duration) -> Self {
+        self.debounce = debounce;
+        self
+    }
+
+    /// Watches the file until the carrier stops. The [`Outgoing`] handle of
+    /// every added node is passed to `on_added`.
+    ///
+    /// A file which fails to parse is logged and skipped, keeping the current
+    /// node set.
+    pub async fn run(mut self, mut on_added: impl FnMut(&str, Outgoing) + Send) {
+        let mut applied = stamp(&self.path).await;
+        loop {
+            sleep(self.poll_interval).await;
+            let mut current = stamp(&self.path).await;
+            if current == applied {
+                continue;
+            }
+            loop {
+                sleep(self.debounce).await;
+                let next = stamp(&self.path).await;
+                if next == current {
+                    break;
+                }
+                current = next;
+            }
+            applied = current;
+            match self.read().await {
+                Ok(nodes) => {
+                    if self.apply(nodes, &mut on_added).await.is_err() {
+                        return;
+                    }
+                }
+                Err(err) => warn!("Peers file {} rejected: {err}", self.path.display()),
+            }
+        }
+    }
+
+    async fn read(&self) -> Result<HashMap<String, u16>, ReadError> {
+        let contents = fs::read_to_string(&self.path).await?;
+        let lines = contents
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'));
+        let nodes = config::parse_nodes(lines, self.self_name.as_deref())?;
+        for node in nodes.keys() {
+            config::server_name(node)?;
+        }
+        Ok(nodes)
+    }
+
+    /// Applies the difference between the current node set and `nodes`.
+    /// Returns an error if the carrier is stopped.
+    async fn apply(
+        &mut self,
+        nodes: HashMap<String, u16>,
+        on_added: &mut (impl FnMut(&str, Outgoing) + Send),
+    ) -> Result<(), ()> {
+        let removed = self
+            .nodes
+            .iter()
+            .filter(|(node, port)| nodes.get(*node) != Some(port))
+            .map(|(node, _)| node.clone())
+            .collect::<Vec<_>>();
+        for node in removed {
+            match self.handle.remove_node(&node).await {
+                Ok(()) | Err(RemoveError::NotFound(_)) => {
+                    info!("Peers file: removed node {node}");
+                    self.nodes.remove(&node);
+                }
+                Err(RemoveError::Stopped) => return Err(()),
+            }
+        }
+        for (node, port) in nodes {
+            if self.nodes.contains_key(&node) {
+                continue;
+            }
+            match self.handle.add_node(node.clone(), port).await {
+                Ok(outgoing) => {
+                    info!("Peers file: added node {node} on port {port}");
+                    on_added(&node, outgoing);
+                    self.nodes.insert(node, port);
+                }
+                Err(AddError::Stopped) => return Err(()),
+                Err(err) => warn!("Peers file: node {node} not added: {err}"),
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Error, Debug)]
+enum ReadError {
+    #[error("{0}")]
+    Io(#[from] io::Error),
+    #[error("{0}")]
+    Config(#[from] ConfigError),
+}
+
+async fn stamp(path: &Path) -> Stamp {
+    let metadata = fs::metadata(path).await.ok()?;
+    Some((metadata.modified().ok()?, metadata.len()))
+}
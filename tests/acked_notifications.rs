@@ -0,0 +1,71 @@
+//! Notifications acknowledged by the receiving carrier, written again on a
+//! new connection until acknowledged.
+
+mod common;
+
+use common::{carrier, spawn, timeout};
+use futures::StreamExt;
+use mpc_carrier::channels::{NotificationReceiver, Outgoing};
+use mpc_carrier::dedup::DeduplicationConfig;
+use mpc_carrier::hello::{Feature, HelloConfig, HelloMode};
+use mpc_carrier::testing::faults::{FaultPlan, FaultyTransport};
+use mpc_carrier::transport::memory::MemoryNetwork;
+use mpc_carrier::Carrier;
+use std::time::Duration;
+use tokio::time::sleep;
+
+fn hello(carrier: Carrier, node_name: &str) -> Carrier {
+    carrier.hello(HelloConfig {
+        mode: HelloMode::Required,
+        node_name: node_name.to_string(),
+        features: vec![
+            Feature::Envelope,
+            Feature::Notifications,
+            Feature::AckedNotifications,
+        ],
+    })
+}
+
+/// Starts `a` notifying `b`, the first connection of `a` killed once it wrote
+/// its hello and a notification, so before reading the acknowledgement.
+fn start(deduplication: bool) -> (Outgoing, NotificationReceiver) {
+    let network = MemoryNetwork::new();
+    let (carrier_a, _incoming, outgoing) = carrier(&["b"]);
+    let transport =
+        FaultyTransport::new(network.transport("a")).dialed(FaultPlan::kill_after_frames(2));
+    spawn(hello(carrier_a, "a"), transport);
+    let (mut carrier_b, mut incoming, _outgoing) = carrier(&["a"]);
+    if deduplication {
+        carrier_b = carrier_b.deduplication(DeduplicationConfig {
+            max_entries: 100,
+            ttl: Duration::from_secs(10),
+        });
+    }
+    spawn(hello(carrier_b, "b"), network.transport("b"));
+    (outgoing, incoming.take_notifications().unwrap())
+}
+
+#[tokio::test]
+async fn lost_ack_resent_once_with_deduplication() {
+    let (mut outgoing, mut notifications) = start(true);
+    timeout(outgoing.notify_acked("b", vec![1])).await.unwrap();
+    let (node, notification) = timeout(notifications.next()).await.unwrap();
+    assert_eq!(node, "a");
+    assert_eq!(notification.payload, [1]);
+    // The notification written again is acknowledged, not delivered.
+    timeout(outgoing.notify_acked("b", vec![2])).await.unwrap();
+    let (_, notification) = timeout(notifications.next()).await.unwrap();
+    assert_eq!(notification.payload, [2]);
+    sleep(Duration::from_millis(100)).await;
+    assert!(notifications.try_recv().is_err());
+}
+
+#[tokio::test]
+async fn lost_ack_resent_twice_without_deduplication() {
+    let (mut outgoing, mut notifications) = start(false);
+    timeout(outgoing.notify_acked("b", vec![1])).await.unwrap();
+    for _ in 0..2 {
+        let (_, notification) = timeout(notifications.next()).await.unwrap();
+        assert_eq!(notification.payload, [1]);
+    }
+}
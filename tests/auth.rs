@@ -0,0 +1,42 @@
+//! Message authentication with pre-shared keys.
+
+mod common;
+
+use common::{carrier, respond, spawn, timeout};
+use mpc_carrier::auth::AuthKey;
+use mpc_carrier::channels::SendError;
+use mpc_carrier::messages::{fixtures, NodeRequest};
+use mpc_carrier::status;
+use mpc_carrier::testing::faults::{FaultPlan, FaultyTransport};
+use mpc_carrier::transport::memory::MemoryNetwork;
+use mpc_carrier::Carrier;
+
+fn authenticated(carrier: Carrier) -> Carrier {
+    carrier.message_auth(|_| Some(AuthKey::new(b"secret")))
+}
+
+#[tokio::test]
+async fn tampered_request_rejected() {
+    let network = MemoryNetwork::new();
+    let (carrier_a, _incoming, mut outgoing) = carrier(&["b"]);
+    // Flips a byte within the payload of the first request.
+    let plan = FaultPlan::new().flip_byte(500);
+    let transport = FaultyTransport::new(network.transport("a")).dialed(plan);
+    spawn(authenticated(carrier_a), transport);
+    let (carrier_b, incoming, _outgoing) = carrier(&["a"]);
+    let handle = carrier_b.handle();
+    spawn(authenticated(carrier_b), network.transport("b"));
+    respond(incoming);
+
+    let request = |seed| NodeRequest {
+        payload: vec![0x55; 1000],
+        ..fixtures::node_request(seed)
+    };
+    let result = timeout(outgoing.send("b", request(1))).await;
+    let failed = status::AUTH_FAILED;
+    assert!(matches!(result, Err(SendError::Remote { status, .. }) if status == failed));
+    assert_eq!(handle.debug_state().nodes["a"].auth_failures, 1);
+    // The next requests are intact.
+    let response = timeout(outgoing.send("b", request(2))).await;
+    assert_eq!(response.unwrap().request_id, request(2).request_id);
+}
@@ -0,0 +1,39 @@
+//! Gateway relaying requests between two networks.
+
+mod common;
+
+use common::{carrier, spawn, timeout};
+use futures::future;
+use mpc_carrier::messages::{fixtures, NodeRequest};
+use mpc_carrier::transport::memory::MemoryNetwork;
+
+#[tokio::test]
+async fn request_crosses_gateway_and_back() {
+    let (upstream, downstream) = (MemoryNetwork::new(), MemoryNetwork::new());
+    let (carrier_a, _incoming, mut outgoing) = carrier(&["b"]);
+    spawn(carrier_a, upstream.transport("a"));
+    let (carrier_c, mut incoming, _outgoing) = carrier(&["b"]);
+    spawn(carrier_c, downstream.transport("c"));
+    let (gateway_up, _incoming, _outgoing) = carrier(&["a"]);
+    let (gateway_down, _incoming, _outgoing) = carrier(&["c"]);
+    let gateway = gateway_up.chain(gateway_down).add_routing_rule("a", "c");
+    let (up, down) = (upstream.transport("b"), downstream.transport("b"));
+    tokio::spawn(gateway.run(up, down));
+
+    let request = NodeRequest {
+        session_id: Some(9),
+        ..fixtures::node_request(1)
+    };
+    let send = outgoing.send("b", request.clone());
+    let answer = async {
+        let (node, callback) = incoming.recv().await.unwrap();
+        // Relayed as is by the gateway.
+        assert_eq!(node, "b");
+        assert_eq!(callback.message.request_id, request.request_id);
+        assert_eq!(callback.message.session_id, Some(9));
+        assert_eq!(callback.message.payload, request.payload);
+        callback.respond(fixtures::node_response(&request)).unwrap();
+    };
+    let (response, ()) = timeout(future::join(send, answer)).await;
+    assert_eq!(response.unwrap().request_id, request.request_id);
+}
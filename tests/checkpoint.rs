@@ -0,0 +1,99 @@
+//! Requests checkpointed and restored across a restart.
+
+mod common;
+
+use common::{carrier, spawn, timeout};
+use mpc_carrier::channels::SerializableNodeRequest;
+use mpc_carrier::messages::{fixtures, NodeRequest};
+use mpc_carrier::transport::memory::MemoryNetwork;
+use mpc_carrier::Error;
+
+fn request() -> NodeRequest {
+    NodeRequest {
+        session_id: Some(7),
+        epoch: Some(3),
+        priority: 2,
+        auth_tag: vec![9; 32],
+        request_seq: 5,
+        traceparent: "00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01".to_string(),
+        tracestate: "vendor=value".to_string(),
+        service: "sign".to_string(),
+        is_idempotent: true,
+        ..fixtures::node_request(1)
+    }
+}
+
+fn checkpoint() -> SerializableNodeRequest {
+    SerializableNodeRequest::new("b", request())
+}
+
+#[test]
+fn checkpoint_round_trips() {
+    let request = checkpoint();
+    let json = serde_json::to_string(&request).unwrap();
+    let restored = serde_json::from_str::<SerializableNodeRequest>(&json).unwrap();
+    assert_eq!(restored, request);
+    // Every field survives.
+    assert_eq!(NodeRequest::from(restored), self::request());
+}
+
+#[test]
+fn checkpoint_predating_fields_restores() {
+    let json = r#"{"request_id":[1],"payload":[2],"session_id":null,"epoch":null,"priority":0}"#;
+    let restored = serde_json::from_str::<SerializableNodeRequest>(json).unwrap();
+    assert_eq!(restored.service, "");
+    assert!(!restored.is_idempotent);
+    assert_eq!(restored.node, "");
+    assert_eq!(restored.request_seq, 0);
+}
+
+#[tokio::test]
+async fn restored_request_answered_after_restart() {
+    let network = MemoryNetwork::new();
+    let (carrier_b, incoming, _outgoing) = carrier(&["a"]);
+    let mut sign = incoming.route("sign");
+    spawn(carrier_b, network.transport("b"));
+    let (carrier_a, _incoming, mut outgoing) = carrier(&["b"]);
+    let run = spawn(carrier_a, network.transport("a"));
+    let send = tokio::spawn(async move { outgoing.send("b", request()).await });
+
+    // Handled by `b` as `a` crashes, checkpointed before.
+    let (_, unanswered) = timeout(sign.recv()).await.unwrap();
+    let json = serde_json::to_string(&checkpoint()).unwrap();
+    run.abort();
+    let _ = run.await;
+    // The request fails with its carrier.
+    assert!(timeout(send).await.unwrap().is_err());
+    drop(unanswered);
+
+    let (carrier_a, _incoming, _outgoing) = carrier(&["b"]);
+    let checkpoint = serde_json::from_str(&json).unwrap();
+    let response = carrier_a.restore_checkpoint("b", checkpoint).unwrap();
+    spawn(carrier_a, network.transport("a"));
+    let (node, callback) = timeout(sign.recv()).await.unwrap();
+    assert_eq!(node, "a");
+    let replayed = &callback.message;
+    assert_eq!(replayed.request_id, request().request_id);
+    assert_eq!(replayed.traceparent, request().traceparent);
+    assert_eq!(replayed.tracestate, request().tracestate);
+    let expected = fixtures::node_response(replayed);
+    callback.respond(expected.clone()).unwrap();
+    let response = timeout(response).await.unwrap();
+    assert_eq!(response.request_id, expected.request_id);
+    assert_eq!(response.status, expected.status);
+}
+
+#[test]
+fn checkpoint_restored_for_its_node_only() {
+    let (carrier, _incoming, _outgoing) = carrier(&["b", "c"]);
+    let result = carrier.restore_checkpoint("c", checkpoint());
+    assert!(matches!(
+        result,
+        Err(Error::CheckpointNode { node, checkpointed }) if node == "c" && checkpointed == "b"
+    ));
+    let result = carrier.restore_checkpoint("d", checkpoint());
+    assert!(matches!(result, Err(Error::UnknownNode(node)) if node == "d"));
+    // A checkpoint without its node restores for any.
+    let request = SerializableNodeRequest::from(request());
+    assert!(carrier.restore_checkpoint("c", request).is_ok());
+}
@@ -0,0 +1,72 @@
+//! Churn of a node forced to reconnect rapidly, flapping then settling.
+
+mod common;
+
+use common::{client, respond, server, spawn, timeout};
+use mpc_carrier::lifecycle::CarrierEvent;
+use mpc_carrier::messages::fixtures;
+use mpc_carrier::testing::faults::{FaultPlan, FaultyTransport};
+use mpc_carrier::transport::memory::MemoryNetwork;
+use std::time::Duration;
+
+const BOUNCES: u64 = 4;
+const TRANSITIONS: usize = 4;
+const WINDOW: Duration = Duration::from_secs(2);
+
+#[tokio::test]
+async fn rapid_reconnects_trip_and_clear_flapping() {
+    let network = MemoryNetwork::new();
+    let (carrier, _incoming, mut outgoing) = client(&["b"]);
+    let mut carrier = carrier.flap_threshold(TRANSITIONS, WINDOW);
+    let mut events = carrier.events();
+    let handle = carrier.handle();
+    // The first connections break on their first request, the next one lasts.
+    let mut transport = FaultyTransport::new(network.transport("a"));
+    for _ in 0..BOUNCES {
+        transport = transport.dialed(FaultPlan::kill_after_frames(1));
+    }
+    spawn(carrier, transport);
+    let (carrier_b, incoming, _outgoing) = server(&["a"]);
+    spawn(carrier_b, network.transport("b"));
+    respond(incoming);
+
+    let mut seed = 0;
+    let transitions = timeout(async {
+        loop {
+            seed += 1;
+            let _ = outgoing.send("b", fixtures::node_request(seed)).await;
+            if let Ok(CarrierEvent::FlappingStarted {
+                node, transitions, ..
+            }) = events.try_recv()
+            {
+                assert_eq!(node, "b");
+                return transitions;
+            }
+        }
+    })
+    .await;
+    assert!(transitions > TRANSITIONS, "{transitions}");
+    // The sweep may find the node flapping before its last bounce.
+    timeout(outgoing.send("b", fixtures::node_request(0)))
+        .await
+        .unwrap();
+    let state = &handle.debug_state().nodes["b"];
+    assert!(state.flapping);
+    assert_eq!(state.reconnects, BOUNCES);
+    assert!(state.downtime_last_hour > Duration::ZERO);
+    assert_eq!(handle.connection_lifetimes("b").unwrap().count(), BOUNCES);
+
+    // Settled once the transitions leave the window.
+    let duration = timeout(async {
+        loop {
+            if let Some(CarrierEvent::FlappingStopped { node, duration, .. }) = events.recv().await
+            {
+                assert_eq!(node, "b");
+                return duration;
+            }
+        }
+    })
+    .await;
+    assert!(duration >= WINDOW / 2, "{duration:?}");
+    assert!(!handle.debug_state().nodes["b"].flapping);
+}
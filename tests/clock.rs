@@ -0,0 +1,93 @@
+//! Estimation of the clock offset of the nodes from the timestamps of the
+//! envelopes.
+
+mod common;
+
+use common::{client, spawn, timeout};
+use futures::StreamExt;
+use mpc_carrier::control::CarrierHandle;
+use mpc_carrier::hello::{Feature, Hello, HelloConfig, HelloMode, HELLO_MAGIC};
+use mpc_carrier::messages::envelope::Kind;
+use mpc_carrier::messages::{fixtures, Envelope, NodeResponse};
+use mpc_carrier::protobuf_tcp;
+use mpc_carrier::transport::memory::MemoryNetwork;
+use mpc_carrier::transport::Transport;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const SKEW: Duration = Duration::from_millis(300);
+
+/// Starts a carrier `a` dialing a simulated node `b`, answering the requests
+/// with the time of its clock ahead by `skew`, or without the timestamps.
+async fn start(skew: Option<Duration>) -> CarrierHandle {
+    let network = MemoryNetwork::new();
+    let peer = network.transport("b");
+    let mut listener = peer.bind().await.unwrap();
+    let (carrier, _incoming, mut outgoing) = client(&["b"]);
+    let carrier = carrier.hello(HelloConfig {
+        mode: HelloMode::Required,
+        node_name: "a".to_string(),
+        features: vec![Feature::Envelope],
+    });
+    let handle = carrier.handle();
+    spawn(carrier, network.transport("a"));
+
+    tokio::spawn(async move {
+        let accepted = listener.next().await.unwrap().unwrap();
+        let (conn, _) = peer.accept(accepted).await.unwrap();
+        let (mut reader, mut writer) = protobuf_tcp::new(conn, 1 << 20);
+        reader.read::<Hello>().await.unwrap();
+        let mut hello = Hello {
+            node_name: "b".to_string(),
+            magic: HELLO_MAGIC,
+            ..Hello::default()
+        };
+        hello.push_features(Feature::Envelope);
+        writer.write(hello).await.unwrap();
+        writer.flush().await.unwrap();
+        while let Ok(envelope) = reader.read::<Envelope>().await {
+            let Some(Kind::Request(request)) = envelope.kind else {
+                continue;
+            };
+            let (request_sent_at_us, sent_at_us) = match skew {
+                Some(skew) => {
+                    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap() + skew;
+                    (envelope.sent_at_us, now.as_micros().try_into().unwrap())
+                }
+                None => (0, 0),
+            };
+            let response = NodeResponse {
+                request_sent_at_us,
+                ..fixtures::node_response(&request)
+            };
+            let envelope = Envelope {
+                kind: Some(Kind::Response(response)),
+                sent_at_us,
+                ..Envelope::default()
+            };
+            writer.write(envelope).await.unwrap();
+            writer.flush().await.unwrap();
+        }
+    });
+
+    for seed in 0..20 {
+        let request = fixtures::node_request(seed);
+        let response = timeout(outgoing.send("b", request.clone())).await;
+        assert_eq!(response.unwrap().request_id, request.request_id);
+    }
+    handle
+}
+
+#[tokio::test]
+async fn offset_of_skewed_node_recovered() {
+    let handle = start(Some(SKEW)).await;
+    let offset_us = handle.debug_state().nodes["b"].clock_offset_us.unwrap();
+    let skew_us = i64::try_from(SKEW.as_micros()).unwrap();
+    // Within the round trips over memory.
+    assert!((offset_us - skew_us).abs() < 20_000, "{offset_us}");
+}
+
+#[tokio::test]
+async fn no_estimate_without_timestamps() {
+    let handle = start(None).await;
+    assert_eq!(handle.debug_state().nodes["b"].clock_offset_us, None);
+}
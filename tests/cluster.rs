@@ -0,0 +1,117 @@
+//! Cluster of carriers over TLS, with nodes killed, restarted and shut down.
+
+mod common;
+
+use common::timeout;
+use mpc_carrier::channels::SendError;
+use mpc_carrier::messages::fixtures;
+use mpc_carrier::status;
+use mpc_carrier::testing::{Cluster, ClusterNode};
+use mpc_carrier::Carrier;
+use std::collections::HashMap;
+use std::mem;
+use std::time::Duration;
+use tokio::time::sleep;
+
+/// Sends a request from `from` to `to`, answered by `to`.
+async fn exchange(from: &mut ClusterNode, to: &mut ClusterNode, seed: u64) {
+    let request = fixtures::node_request(seed);
+    let answer = async {
+        let (node, callback) = to.incoming.recv().await.unwrap();
+        assert_eq!(node, from.name);
+        let response = fixtures::node_response(&callback.message);
+        callback.respond(response).unwrap();
+    };
+    let (response, ()) = timeout(async {
+        tokio::join!(from.outgoing.send(to.name.clone(), request.clone()), answer)
+    })
+    .await;
+    assert_eq!(response.unwrap().request_id, request.request_id);
+}
+
+/// Exchanges a request between every pair of nodes, both ways.
+async fn exchange_all(cluster: &mut Cluster, seed: u64) {
+    let nodes = cluster.nodes();
+    for i in 0..nodes.len() {
+        for j in 0..nodes.len() {
+            if i != j {
+                let (from, to) = pair(nodes, i, j);
+                exchange(from, to, seed).await;
+            }
+        }
+    }
+}
+
+/// Returns the nodes `i` and `j` of `nodes`, distinct.
+fn pair(nodes: &mut [ClusterNode], i: usize, j: usize) -> (&mut ClusterNode, &mut ClusterNode) {
+    assert_ne!(i, j);
+    if i < j {
+        let (left, right) = nodes.split_at_mut(j);
+        (&mut left[i], &mut right[0])
+    } else {
+        let (left, right) = nodes.split_at_mut(i);
+        (&mut right[0], &mut left[j])
+    }
+}
+
+#[tokio::test]
+async fn requests_exchanged_both_ways() {
+    let mut cluster = Cluster::start(3);
+    exchange_all(&mut cluster, 1).await;
+    for node in cluster.nodes() {
+        assert_eq!(node.handle.debug_state().nodes.len(), 2);
+    }
+    timeout(cluster.shutdown()).await.unwrap();
+}
+
+#[tokio::test]
+async fn killed_node_recovers_on_restart() {
+    let mut cluster = Cluster::start(3);
+    exchange_all(&mut cluster, 1).await;
+    let port = cluster.node(1).port();
+
+    cluster.kill(1).await;
+    assert!(!cluster.node(1).handle.is_running());
+    let name = cluster.node(1).name.to_string();
+    timeout(async {
+        while cluster.node(0).handle.debug_state().nodes[&name].connections > 0 {
+            sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await;
+    let (from, to) = pair(cluster.nodes(), 0, 2);
+    exchange(from, to, 2).await;
+
+    cluster.restart(1).await;
+    assert_eq!(cluster.node(1).port(), port);
+    exchange_all(&mut cluster, 3).await;
+    timeout(cluster.shutdown()).await.unwrap();
+}
+
+#[tokio::test]
+async fn shutdown_answers_request_in_flight() {
+    let mut cluster = Cluster::start(2);
+    exchange_all(&mut cluster, 1).await;
+    // The shutdown drops the channels of the nodes, so the request is sent
+    // through the outgoing ones taken from node 0, a spare left in place.
+    let (_carrier, _incoming, spare) = Carrier::new(HashMap::new());
+    let mut outgoing = mem::replace(&mut cluster.node(0).outgoing, spare);
+    let to = cluster.node(1).name.clone();
+    let request = fixtures::node_request(2);
+    let send = tokio::spawn(async move { outgoing.send(to, request).await });
+    timeout(async {
+        while cluster.node(1).handle.debug_state().nodes["node0.test"].incoming_queue == 0 {
+            sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await;
+
+    // Answered by node 1 as its channels are dropped, before node 0 returns.
+    timeout(cluster.shutdown()).await.unwrap();
+    match timeout(send).await.unwrap() {
+        Err(SendError::Remote { status: code, .. }) => {
+            assert_eq!(code, status::HANDLER_DROPPED);
+        }
+        sent => panic!("not dropped by the handler: {sent:?}"),
+    }
+}
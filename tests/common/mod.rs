@@ -0,0 +1,168 @@
+//! Helpers of the integration tests, running carriers over the in-memory
+//! transport, or over TLS with the certificates of [`pki`].
+
+#![allow(dead_code)]
+
+pub mod pki;
+
+use futures::future;
+use mpc_carrier::channels::{Incoming, Outgoing};
+use mpc_carrier::config::{Direction, NodeId};
+use mpc_carrier::messages::fixtures;
+use mpc_carrier::transport::Transport;
+use mpc_carrier::{Carrier, Error};
+use std::collections::HashMap;
+use std::future::Future;
+use std::io;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::task::JoinHandle;
+use tracing::subscriber::DefaultGuard;
+use tracing::Level;
+use tracing_subscriber::fmt::MakeWriter;
+
+/// Bound of the waits of the tests, well above the reconnection delays.
+pub const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Log lines written by the subscriber of a test.
+#[derive(Clone, Default)]
+pub struct Logs(Arc<Mutex<Vec<u8>>>);
+
+/// Returns a carrier knowing `peers`, all on the same placeholder port, the
+/// in-memory transport addressing the nodes by name.
+pub fn carrier(peers: &[&str]) -> (Carrier, Incoming, Outgoing) {
+    let nodes = peers
+        .iter()
+        .map(|peer| ((*peer).to_string(), 1))
+        .collect::<HashMap<_, _>>();
+    Carrier::new(nodes)
+}
+
+/// Returns a carrier accepting the connections of `peers`.
+pub fn server(peers: &[&str]) -> (Carrier, Incoming, Outgoing) {
+    let (mut carrier, incoming, outgoing) = carrier(peers);
+    for peer in peers {
+        carrier = carrier.direction(*peer, Direction::Accept);
+    }
+    (carrier, incoming, outgoing)
+}
+
+/// Returns a carrier dialing `peers`, without a listener.
+pub fn client(peers: &[&str]) -> (Carrier, Incoming, Outgoing) {
+    let (mut carrier, incoming, outgoing) = carrier(peers);
+    for peer in peers {
+        carrier = carrier.direction(*peer, Direction::Dial);
+    }
+    (carrier.skip_unused_listener(true), incoming, outgoing)
+}
+
+/// Runs `carrier` over `transport` in a task of its own.
+pub fn spawn<T: Transport>(mut carrier: Carrier, transport: T) -> JoinHandle<Result<(), Error>> {
+    tokio::spawn(async move { carrier.run_with_transport(transport).await })
+}
+
+/// Answers the requests of `incoming` with [`fixtures::node_response`].
+pub fn respond(mut incoming: Incoming) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        while let Some((_, callback)) = incoming.recv().await {
+            let response = fixtures::node_response(&callback.message);
+            let _ = callback.respond(response);
+        }
+    })
+}
+
+/// Awaits `future`, failing the test after [`TIMEOUT`].
+pub async fn timeout<F: Future>(future: F) -> F::Output {
+    tokio::time::timeout(TIMEOUT, future)
+        .await
+        .expect("timed out")
+}
+
+/// Sends a request from every node to every other one, each answered by the
+/// node receiving it, the nodes being given by name with their channels.
+pub async fn exchange_all(nodes: &mut [(NodeId, &mut Incoming, &mut Outgoing)]) {
+    let mut seed = 0;
+    for from in 0..nodes.len() {
+        for to in 0..nodes.len() {
+            if from == to {
+                continue;
+            }
+            seed += 1;
+            let request = fixtures::node_request(seed);
+            let (sender, receiver) = if from < to {
+                let (left, right) = nodes.split_at_mut(to);
+                (&mut left[from], &mut right[0])
+            } else {
+                let (left, right) = nodes.split_at_mut(from);
+                (&mut right[0], &mut left[to])
+            };
+            let send = sender.2.send(receiver.0.clone(), request.clone());
+            let answer = async {
+                let (node, callback) = receiver.1.recv().await.unwrap();
+                assert_eq!(node, sender.0);
+                assert_eq!(callback.message, request);
+                let response = fixtures::node_response(&callback.message);
+                callback.respond(response).unwrap();
+            };
+            let (response, ()) = timeout(future::join(send, answer)).await;
+            assert_eq!(response.unwrap().request_id, request.request_id);
+        }
+    }
+}
+
+impl Logs {
+    /// Captures the logs of the current thread, down to the debug level, until
+    /// the guard is dropped.
+    pub fn capture() -> (Self, DefaultGuard) {
+        let logs = Self::default();
+        let subscriber = tracing_subscriber::fmt()
+            .with_max_level(Level::DEBUG)
+            .with_ansi(false)
+            .with_writer(logs.clone())
+            .finish();
+        (logs, tracing::subscriber::set_default(subscriber))
+    }
+
+    /// Returns the first line containing `pattern`, if any.
+    pub fn find(&self, pattern: &str) -> Option<String> {
+        let logs = self.0.lock().unwrap();
+        String::from_utf8_lossy(&logs)
+            .lines()
+            .find(|line| line.contains(pattern))
+            .map(ToString::to_string)
+    }
+
+    /// Returns the number of the lines containing `pattern`.
+    pub fn count(&self, pattern: &str) -> usize {
+        self.lines(pattern).len()
+    }
+
+    /// Returns the lines containing `pattern`, in order.
+    pub fn lines(&self, pattern: &str) -> Vec<String> {
+        let logs = self.0.lock().unwrap();
+        String::from_utf8_lossy(&logs)
+            .lines()
+            .filter(|line| line.contains(pattern))
+            .map(ToString::to_string)
+            .collect()
+    }
+}
+
+impl io::Write for Logs {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl MakeWriter<'_> for Logs {
+    type Writer = Self;
+
+    fn make_writer(&self) -> Self {
+        self.clone()
+    }
+}
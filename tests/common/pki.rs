@@ -0,0 +1,104 @@
+//! Certificates issued by a CA generated on the fly.
+
+use mpc_carrier::transport::NodeAddr;
+use rcgen::{BasicConstraints, Certificate, CertificateParams, IsCa, KeyPair};
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, PrivatePkcs8KeyDer, ServerName};
+use rustls::{ClientConfig, RootCertStore, ServerConfig};
+use std::net::{TcpListener, UdpSocket};
+use std::sync::Arc;
+
+/// Address of the nodes.
+pub const HOST: &str = "127.0.0.1";
+
+/// Certificate authority of a test.
+pub struct Pki {
+    ca: Certificate,
+    ca_key: KeyPair,
+}
+
+/// Certificate chain and private key of a node.
+pub type Identity = (Vec<CertificateDer<'static>>, PrivateKeyDer<'static>);
+
+impl Pki {
+    /// Generates a CA.
+    pub fn new() -> Self {
+        let ca_key = KeyPair::generate().unwrap();
+        let mut ca = CertificateParams::default();
+        ca.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        let ca = ca.self_signed(&ca_key).unwrap();
+        Self { ca, ca_key }
+    }
+
+    /// Issues a certificate for the DNS `names`, chained to the CA.
+    pub fn issue(&self, names: &[&str]) -> Identity {
+        let key = KeyPair::generate().unwrap();
+        let names = names.iter().map(ToString::to_string).collect::<Vec<_>>();
+        let cert = CertificateParams::new(names)
+            .unwrap()
+            .signed_by(&key, &self.ca, &self.ca_key)
+            .unwrap();
+        let chain = vec![cert.der().clone(), self.ca.der().clone()];
+        let key = PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(key.serialize_der()));
+        (chain, key)
+    }
+
+    /// Returns the PEM encoding of a certificate issued for `names`, with its
+    /// key, for the file-based initializations.
+    pub fn issue_pem(&self, names: &[&str]) -> (String, String) {
+        let key = KeyPair::generate().unwrap();
+        let names = names.iter().map(ToString::to_string).collect::<Vec<_>>();
+        let cert = CertificateParams::new(names)
+            .unwrap()
+            .signed_by(&key, &self.ca, &self.ca_key)
+            .unwrap();
+        (cert.pem() + &self.ca.pem(), key.serialize_pem())
+    }
+
+    /// Returns a server configuration presenting a certificate for `names`.
+    pub fn server_config(&self, names: &[&str]) -> Arc<ServerConfig> {
+        let (chain, key) = self.issue(names);
+        let config = ServerConfig::builder()
+            .with_no_client_auth()
+            .with_single_cert(chain, key)
+            .unwrap();
+        Arc::new(config)
+    }
+
+    /// Returns a client configuration trusting the CA.
+    pub fn client_config(&self) -> Arc<ClientConfig> {
+        let config = ClientConfig::builder()
+            .with_root_certificates(self.roots())
+            .with_no_client_auth();
+        Arc::new(config)
+    }
+
+    /// Returns the root store of the CA.
+    pub fn roots(&self) -> RootCertStore {
+        let mut roots = RootCertStore::empty();
+        roots.add(self.ca.der().clone()).unwrap();
+        roots
+    }
+}
+
+/// Returns the address of a node listening on `port` of [`HOST`], with its
+/// certificate checked against `tls_name`, also the name identifying the
+/// connecting node to the listening one.
+pub fn addr(port: u16, tls_name: &str) -> NodeAddr {
+    NodeAddr {
+        host: HOST.to_string(),
+        port,
+        tls_name: ServerName::try_from(tls_name.to_string()).unwrap(),
+    }
+}
+
+/// Returns a TCP port free on [`HOST`] when called.
+pub fn tcp_port() -> u16 {
+    let listener = TcpListener::bind((HOST, 0)).unwrap();
+    listener.local_addr().unwrap().port()
+}
+
+/// Returns a UDP port free on [`HOST`] when called.
+pub fn udp_port() -> u16 {
+    let socket = UdpSocket::bind((HOST, 0)).unwrap();
+    socket.local_addr().unwrap().port()
+}
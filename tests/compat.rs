@@ -0,0 +1,14 @@
+//! Wire compatibility with the golden encodings and with the peers of the
+//! first release.
+
+use mpc_carrier::messages::compat::{assert_compatible, assert_old_peer_interop};
+
+#[test]
+fn compat() {
+    assert_compatible();
+}
+
+#[tokio::test]
+async fn old_peer_interop() {
+    assert_old_peer_interop().await;
+}
@@ -0,0 +1,110 @@
+//! Per-message payload compression, and its interplay with the whole
+//! connections compressed by the negotiating hook.
+
+mod common;
+
+use common::{carrier, spawn, timeout};
+use futures::future;
+use mpc_carrier::compression::{CompressionNegotiator, PayloadCompression};
+use mpc_carrier::hello::{Feature, HelloConfig, HelloMode};
+use mpc_carrier::messages::{fixtures, CompressionAlgorithm, NodeRequest};
+use mpc_carrier::stats::NodeState;
+use mpc_carrier::transport::memory::MemoryNetwork;
+use mpc_carrier::Carrier;
+
+fn compressing(carrier: Carrier, node_name: &str) -> Carrier {
+    carrier
+        .hello(HelloConfig {
+            mode: HelloMode::Required,
+            node_name: node_name.to_string(),
+            features: vec![Feature::Compression],
+        })
+        .payload_compression(PayloadCompression::default())
+}
+
+#[tokio::test]
+async fn large_payload_compressed_small_one_not() {
+    let network = MemoryNetwork::new();
+    let (carrier_a, _incoming, mut outgoing) = carrier(&["b"]);
+    let carrier_a = compressing(carrier_a, "a");
+    let handle = carrier_a.handle();
+    spawn(carrier_a, network.transport("a"));
+    let (carrier_b, mut incoming, _outgoing) = carrier(&["a"]);
+    spawn(compressing(carrier_b, "b"), network.transport("b"));
+
+    for len in [4 << 20, 100] {
+        let request = NodeRequest {
+            payload: vec![0; len],
+            ..fixtures::node_request(len as u64)
+        };
+        let send = outgoing.send("b", request.clone());
+        let answer = async {
+            let (_, callback) = incoming.recv().await.unwrap();
+            // The application sees the payload as sent.
+            assert_eq!(callback.message.payload, request.payload);
+            callback.respond(fixtures::node_response(&request)).unwrap();
+        };
+        let (response, ()) = timeout(future::join(send, answer)).await;
+        response.unwrap();
+    }
+    let state = &handle.debug_state().nodes["b"];
+    assert_eq!(state.compressed_frames, 1);
+    assert_eq!(state.uncompressed_frames, 1);
+    assert_eq!(state.bytes_before_compression, 4 << 20);
+    assert!(state.bytes_after_compression < 1 << 10);
+    assert!(state.bytes_sent < 1 << 20, "{}", state.bytes_sent);
+}
+
+/// Sends a 4 MiB payload from `a` to `b`, both compressing their payloads and
+/// negotiating the compression of the connection with their `negotiators`,
+/// and returns the state of `b` seen by `a`.
+async fn send_negotiated(a: CompressionNegotiator, b: CompressionNegotiator) -> NodeState {
+    let network = MemoryNetwork::new();
+    let (carrier_a, _incoming, mut outgoing) = carrier(&["b"]);
+    let carrier_a = compressing(carrier_a, "a").pre_connect_hook(a);
+    let handle = carrier_a.handle();
+    spawn(carrier_a, network.transport("a"));
+    let (carrier_b, mut incoming, _outgoing) = carrier(&["a"]);
+    let carrier_b = compressing(carrier_b, "b").pre_connect_hook(b);
+    spawn(carrier_b, network.transport("b"));
+
+    let request = NodeRequest {
+        payload: vec![0; 4 << 20],
+        ..fixtures::node_request(1)
+    };
+    let send = outgoing.send("b", request.clone());
+    let answer = async {
+        let (_, callback) = incoming.recv().await.unwrap();
+        assert_eq!(callback.message.payload, request.payload);
+        callback.respond(fixtures::node_response(&request)).unwrap();
+    };
+    let (response, ()) = timeout(future::join(send, answer)).await;
+    response.unwrap();
+    handle.debug_state().nodes["b"].clone()
+}
+
+#[tokio::test]
+async fn negotiated_connection_compression_replaces_payload_compression() {
+    let state = send_negotiated(
+        CompressionNegotiator::default(),
+        CompressionNegotiator::default(),
+    )
+    .await;
+    // Compressed once, with the connection.
+    assert_eq!(state.compressed_frames, 0);
+    assert_eq!(state.uncompressed_frames, 1);
+    assert!(state.bytes_sent < 1 << 20, "{}", state.bytes_sent);
+}
+
+#[tokio::test]
+async fn declined_connection_compression_falls_back_to_payloads() {
+    let state = send_negotiated(
+        CompressionNegotiator::default(),
+        CompressionNegotiator::new([CompressionAlgorithm::None]),
+    )
+    .await;
+    // Compressed once, with the payload.
+    assert_eq!(state.compressed_frames, 1);
+    assert_eq!(state.bytes_before_compression, 4 << 20);
+    assert!(state.bytes_sent < 1 << 20, "{}", state.bytes_sent);
+}
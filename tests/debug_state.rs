@@ -0,0 +1,59 @@
+//! Snapshot of the state of the nodes, for troubleshooting a wedged request.
+
+mod common;
+
+use common::{client, server, spawn, timeout};
+use mpc_carrier::messages::fixtures;
+use mpc_carrier::stats::ConnectionState;
+use mpc_carrier::transport::memory::MemoryNetwork;
+use std::time::Duration;
+use tokio::time::sleep;
+
+#[tokio::test]
+async fn wedged_request_ages() {
+    let network = MemoryNetwork::new();
+    // `c` never comes up.
+    let (carrier, _incoming, mut outgoing) = client(&["b", "c"]);
+    let handle = carrier.handle();
+    spawn(carrier, network.transport("a"));
+    // `b` takes the request and never answers it.
+    let (carrier, mut incoming, _outgoing) = server(&["a"]);
+    let handle_b = carrier.handle();
+    spawn(carrier, network.transport("b"));
+    let request = fixtures::node_request(1);
+    let wedged = tokio::spawn(async move { outgoing.send("b", request).await });
+    let (_, _callback) = timeout(incoming.recv()).await.unwrap();
+    timeout(async {
+        while handle.debug_state().nodes["c"].last_error.is_none() {
+            sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await;
+
+    let state = handle.debug_state();
+    let b = &state.nodes["b"];
+    assert_eq!(b.connection, ConnectionState::Connected);
+    assert!(b.connection_age.is_some());
+    assert_eq!((b.outgoing_queue, b.inflight), (0, 1));
+    assert!(b.oldest_inflight_id_hash.is_some());
+    let age = b.oldest_inflight.unwrap();
+    sleep(Duration::from_millis(50)).await;
+    let later = handle.debug_state().nodes["b"].oldest_inflight.unwrap();
+    assert!(
+        later >= age + Duration::from_millis(50),
+        "{age:?} {later:?}"
+    );
+    assert_eq!(handle_b.debug_state().nodes["a"].awaiting_response, 1);
+
+    let c = &state.nodes["c"];
+    assert!(matches!(
+        c.connection,
+        ConnectionState::Connecting | ConnectionState::Backoff
+    ));
+    // Serialized for an admin endpoint, the durations in seconds.
+    let json = serde_json::to_value(&state).unwrap();
+    assert_eq!(json["nodes"]["b"]["inflight"], 1);
+    assert_eq!(json["nodes"]["b"]["connection"], "connected");
+    assert!(json["nodes"]["b"]["oldest_inflight"].as_f64().unwrap() > 0.0);
+    wedged.abort();
+}
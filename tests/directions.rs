@@ -0,0 +1,39 @@
+//! Dial-only and accept-only nodes.
+
+mod common;
+
+use common::{client, respond, server, spawn, timeout};
+use mpc_carrier::messages::fixtures;
+use mpc_carrier::transport::memory::MemoryNetwork;
+use mpc_carrier::transport::{NodeAddr, Transport};
+use rustls::pki_types::ServerName;
+
+#[tokio::test]
+async fn hub_and_spoke_exchange_both_ways() {
+    let network = MemoryNetwork::new();
+    let (carrier, hub_incoming, mut hub) = server(&["spoke"]);
+    let hub_handle = carrier.handle();
+    spawn(carrier, network.transport("hub"));
+    respond(hub_incoming);
+    let (carrier, spoke_incoming, mut spoke) = client(&["hub"]);
+    spawn(carrier, network.transport("spoke"));
+    respond(spoke_incoming);
+
+    let request = fixtures::node_request(1);
+    let response = timeout(spoke.send("hub", request.clone())).await.unwrap();
+    assert_eq!(response.request_id, request.request_id);
+    // The hub never dials, so its request rides the connection of the spoke.
+    let request = fixtures::node_request(2);
+    let response = timeout(hub.send("spoke", request.clone())).await.unwrap();
+    assert_eq!(response.request_id, request.request_id);
+    let state = hub_handle.debug_state();
+    assert_eq!(state.nodes["spoke"].connections, 1);
+    assert_eq!(state.nodes["spoke"].retry_in, None);
+    // Nor does the spoke listen.
+    let spoke = NodeAddr {
+        host: "spoke".to_string(),
+        port: 1,
+        tls_name: ServerName::try_from("spoke").unwrap(),
+    };
+    assert!(network.transport("probe").connect(&spoke).await.is_err());
+}
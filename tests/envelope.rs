@@ -0,0 +1,122 @@
+//! Envelopes wrapping every message kind.
+
+mod common;
+
+use common::{carrier, respond, spawn, timeout};
+use mpc_carrier::hello::{Feature, Hello, HelloConfig, HelloMode, HELLO_MAGIC};
+use mpc_carrier::messages::envelope::Kind;
+use mpc_carrier::messages::{
+    fixtures, Ack, Backpressure, Cancel, CompressionAlgorithm, Envelope, Heartbeat,
+    NodeNotification,
+};
+use mpc_carrier::protobuf_tcp;
+use mpc_carrier::transport::memory::MemoryNetwork;
+use mpc_carrier::transport::{NodeAddr, Transport};
+use prost::Message;
+use rustls::pki_types::ServerName;
+use std::time::Duration;
+use tokio::time::sleep;
+
+/// Envelope of a kind added by a newer peer.
+#[derive(Clone, PartialEq, prost::Message)]
+struct FutureEnvelope {
+    #[prost(bytes = "vec", tag = "11")]
+    kind: Vec<u8>,
+}
+
+#[test]
+fn every_kind_round_trips() {
+    let request = fixtures::node_request(1);
+    let kinds = [
+        Kind::Request(request.clone()),
+        Kind::Response(fixtures::node_response(&request)),
+        Kind::Notification(NodeNotification {
+            payload: vec![1, 2],
+            ack_id: 3,
+        }),
+        Kind::Heartbeat(Heartbeat {
+            probe_sent_at_us: 4,
+        }),
+        Kind::Cancel(Cancel {
+            request_id: vec![5],
+            request_seq: 6,
+        }),
+        Kind::Ack(Ack { ack_id: 7 }),
+        Kind::Backpressure(Backpressure {
+            depth: 8,
+            capacity: 9,
+        }),
+    ];
+    for kind in kinds {
+        let envelope = Envelope {
+            kind: Some(kind),
+            compression: CompressionAlgorithm::Zstd.into(),
+            seq: 10,
+            sent_at_us: 11,
+        };
+        let decoded = Envelope::decode(envelope.encode_to_vec().as_slice()).unwrap();
+        assert_eq!(decoded, envelope);
+    }
+    let future = FutureEnvelope { kind: vec![1] };
+    let decoded = Envelope::decode(future.encode_to_vec().as_slice()).unwrap();
+    assert_eq!(decoded.kind, None);
+}
+
+#[tokio::test]
+async fn unknown_kind_skipped() {
+    let network = MemoryNetwork::new();
+    let (carrier_b, incoming, _outgoing) = carrier(&["a"]);
+    let carrier_b = carrier_b.hello(HelloConfig {
+        mode: HelloMode::Required,
+        node_name: "b".to_string(),
+        features: vec![Feature::Envelope],
+    });
+    let handle = carrier_b.handle();
+    spawn(carrier_b, network.transport("b"));
+    respond(incoming);
+
+    // A newer `a`, sending an envelope of a kind unknown to `b`.
+    let a = network.transport("a");
+    let addr = NodeAddr {
+        host: "b".to_string(),
+        port: 1,
+        tls_name: ServerName::try_from("b").unwrap(),
+    };
+    let conn = timeout(async {
+        loop {
+            match a.connect(&addr).await {
+                Ok((conn, _)) => break conn,
+                Err(_) => sleep(Duration::from_millis(10)).await,
+            }
+        }
+    })
+    .await;
+    let (mut reader, mut writer) = protobuf_tcp::new(conn, 1 << 20);
+    let mut hello = Hello {
+        node_name: "a".to_string(),
+        magic: HELLO_MAGIC,
+        ..Hello::default()
+    };
+    hello.push_features(Feature::Envelope);
+    writer.write(hello).await.unwrap();
+    writer.flush().await.unwrap();
+    timeout(reader.read::<Hello>()).await.unwrap();
+    writer
+        .write(FutureEnvelope { kind: vec![1] })
+        .await
+        .unwrap();
+    let request = fixtures::node_request(1);
+    let envelope = Envelope {
+        kind: Some(Kind::Request(request.clone())),
+        ..Envelope::default()
+    };
+    writer.write(envelope).await.unwrap();
+    writer.flush().await.unwrap();
+
+    let envelope = timeout(reader.read::<Envelope>()).await.unwrap();
+    let Some(Kind::Response(response)) = envelope.kind else {
+        panic!("{envelope:?}");
+    };
+    assert_eq!(response.request_id, request.request_id);
+    assert_eq!(handle.debug_state().nodes["a"].unknown_envelopes, 1);
+}
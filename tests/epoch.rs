@@ -0,0 +1,48 @@
+//! Requests stamped with an epoch, filtered by the receiver.
+
+mod common;
+
+use common::{carrier, respond, spawn, timeout};
+use mpc_carrier::channels::SendError;
+use mpc_carrier::messages::fixtures;
+use mpc_carrier::middleware::{Epoch, EpochFilter};
+use mpc_carrier::status;
+use mpc_carrier::transport::memory::MemoryNetwork;
+
+#[tokio::test]
+async fn bump_rejects_requests_of_old_epoch() {
+    let network = MemoryNetwork::new();
+    let (sender, receiver) = (Epoch::new(1), Epoch::new(1));
+    let (carrier_a, _incoming, mut outgoing) = carrier(&["b"]);
+    outgoing.set_epoch(Some(sender.clone()));
+    spawn(carrier_a, network.transport("a"));
+    let (carrier_b, incoming, _outgoing) = carrier(&["a"]);
+    let filter = receiver.clone();
+    let carrier_b = carrier_b.wrap_bus(move |bus| EpochFilter::new(bus, filter));
+    spawn(carrier_b, network.transport("b"));
+    respond(incoming);
+
+    let mut rejected = 0;
+    for seed in 0..30 {
+        // The receiver moves on first, then the sender.
+        match seed {
+            10 => assert_eq!(receiver.bump(), 2),
+            15 => assert_eq!(sender.bump(), 2),
+            _ => {}
+        }
+        let request = fixtures::node_request(seed);
+        match timeout(outgoing.send("b", request)).await {
+            Ok(_) => {}
+            Err(SendError::Remote { status, .. }) if status == status::EPOCH_MISMATCH => {
+                rejected += 1;
+            }
+            Err(err) => panic!("{err}"),
+        }
+    }
+    assert_eq!(rejected, 5);
+
+    // A request without an epoch is rejected too.
+    outgoing.set_epoch(None);
+    let result = timeout(outgoing.send("b", fixtures::node_request(30))).await;
+    assert!(matches!(result, Err(SendError::Remote { .. })));
+}
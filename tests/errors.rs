@@ -0,0 +1,110 @@
+//! Context of the errors, as rendered in the logs, and their counts by kind.
+
+mod common;
+
+use common::pki::{addr, tcp_port, Pki, HOST};
+use common::{client, spawn, timeout, Logs};
+use futures::StreamExt;
+use mpc_carrier::config::Direction;
+use mpc_carrier::errors::{ErrorAccounting, ErrorCount, ErrorKind};
+use mpc_carrier::transport::memory::MemoryNetwork;
+use mpc_carrier::transport::{TlsTcpTransport, Transport};
+use mpc_carrier::Carrier;
+use std::time::Duration;
+use tokio::io::AsyncWriteExt;
+use tokio::time::sleep;
+
+/// Waits for an error of `kind` with `node` counted, and returns its count.
+async fn counted(errors: &ErrorAccounting, node: Option<&str>, kind: ErrorKind) -> ErrorCount {
+    timeout(async {
+        loop {
+            let found = errors
+                .snapshot()
+                .into_iter()
+                .find(|count| count.node.as_deref() == node && count.kind == kind);
+            if let Some(count) = found {
+                return count;
+            }
+            sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await
+}
+
+#[tokio::test]
+async fn outgoing_failure_names_node_and_addr() {
+    // The test runtime runs the carrier on this thread.
+    let (logs, _subscriber) = Logs::capture();
+    let network = MemoryNetwork::new();
+    let (carrier, _incoming, _outgoing) = client(&["b"]);
+    spawn(carrier, network.transport("a"));
+
+    let line = timeout(async {
+        loop {
+            if let Some(line) = logs.find("Connection failure") {
+                return line;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await;
+    assert!(line.contains("node b (b:1): "), "{line}");
+}
+
+#[tokio::test]
+async fn refused_connection_counted_as_socket() {
+    let network = MemoryNetwork::new();
+    let (carrier, _incoming, _outgoing) = client(&["b"]);
+    let errors = carrier.errors();
+    spawn(carrier, network.transport("a"));
+
+    let count = counted(&errors, Some("b"), ErrorKind::Socket).await;
+    assert_eq!(count.to_string(), format!("{} socket with b", count.count));
+    assert_eq!(errors.count(ErrorKind::Tls), 0);
+}
+
+#[tokio::test]
+async fn untrusted_certificate_counted_as_tls() {
+    let (pki, other_pki) = (Pki::new(), Pki::new());
+    let port = tcp_port();
+    let (carrier_b, _incoming, _outgoing) = Carrier::with_addrs([("a", addr(1, "a.test"))]);
+    let carrier_b = carrier_b.direction("a", Direction::Accept);
+    let errors_b = carrier_b.errors();
+    let server_config = other_pki.server_config(&["b.test"]);
+    spawn(
+        carrier_b,
+        TlsTcpTransport::new(HOST, port, server_config, other_pki.client_config()),
+    );
+    let (carrier_a, _incoming, _outgoing) = Carrier::with_addrs([("b", addr(port, "b.test"))]);
+    let carrier_a = carrier_a
+        .direction("b", Direction::Dial)
+        .skip_unused_listener(true);
+    let errors_a = carrier_a.errors();
+    spawn(
+        carrier_a,
+        TlsTcpTransport::new(HOST, 0, pki.server_config(&[]), pki.client_config()),
+    );
+
+    counted(&errors_a, Some("b"), ErrorKind::Tls).await;
+    // Before the name of the dialing node is known.
+    counted(&errors_b, None, ErrorKind::Tls).await;
+}
+
+#[tokio::test]
+async fn malformed_frame_counted_as_decode() {
+    let network = MemoryNetwork::new();
+    let peer = network.transport("b");
+    let mut listener = peer.bind().await.unwrap();
+    let (carrier, _incoming, _outgoing) = client(&["b"]);
+    let errors = carrier.errors();
+    spawn(carrier, network.transport("a"));
+
+    let accepted = listener.next().await.unwrap().unwrap();
+    let (mut conn, _) = peer.accept(accepted).await.unwrap();
+    // A frame of 2 bytes, a field of the invalid wire type 7.
+    conn.write_all(&[0, 0, 0, 2, 0xff, 0xff]).await.unwrap();
+    conn.flush().await.unwrap();
+
+    let count = counted(&errors, Some("b"), ErrorKind::Decode).await;
+    assert_eq!(count.count, 1);
+}
@@ -0,0 +1,90 @@
+//! Lifecycle events of the connections with a node bouncing.
+
+mod common;
+
+use common::{client, respond, server, spawn, timeout};
+use mpc_carrier::lifecycle::{CarrierEvent, Side};
+use mpc_carrier::messages::fixtures;
+use mpc_carrier::transport::memory::MemoryNetwork;
+use std::time::Duration;
+use tokio::sync::mpsc::Receiver;
+use tokio::time::sleep;
+
+/// Events of a connection, as matched in order.
+#[derive(Debug, PartialEq)]
+enum Step {
+    Attempt,
+    Established,
+    Closed,
+}
+
+/// Receives the events until `closed` connections closed, and returns their
+/// steps, the attempts repeated while the node is down counted once.
+async fn steps(events: &mut Receiver<CarrierEvent>, closed: usize) -> Vec<Step> {
+    let mut steps = Vec::new();
+    while steps.iter().filter(|step| **step == Step::Closed).count() < closed {
+        let step = match timeout(events.recv()).await.unwrap() {
+            CarrierEvent::OutgoingAttempt { node, addr, .. } => {
+                assert_eq!((node.as_str(), addr.as_str()), ("b", "b:1"));
+                Step::Attempt
+            }
+            CarrierEvent::Established { node, side, .. } => {
+                assert_eq!((node.as_str(), side), ("b", Side::Outgoing));
+                Step::Established
+            }
+            CarrierEvent::ConnectionClosed {
+                node, bytes_sent, ..
+            } => {
+                assert_eq!(node, "b");
+                assert!(bytes_sent > 0);
+                Step::Closed
+            }
+            _ => continue,
+        };
+        if !(step == Step::Attempt && steps.last() == Some(&Step::Attempt)) {
+            steps.push(step);
+        }
+    }
+    steps
+}
+
+#[tokio::test]
+async fn bounced_node_events_in_order() {
+    let network = MemoryNetwork::new();
+    let (mut carrier, _incoming, mut outgoing) = client(&["b"]);
+    let mut events = carrier.events();
+    let handle = carrier.handle();
+    spawn(carrier, network.transport("a"));
+
+    for bounce in 0..3 {
+        let (carrier, incoming, _outgoing) = server(&["a"]);
+        let node = spawn(carrier, network.transport("b"));
+        respond(incoming);
+        let request = fixtures::node_request(bounce);
+        let response = timeout(outgoing.send("b", request.clone())).await;
+        assert_eq!(response.unwrap().request_id, request.request_id);
+        node.abort();
+        // Closed before the next one.
+        timeout(async {
+            while handle.debug_state().nodes["b"].connections > 0 {
+                sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await;
+    }
+    assert_eq!(
+        steps(&mut events, 3).await,
+        [
+            Step::Attempt,
+            Step::Established,
+            Step::Closed,
+            Step::Attempt,
+            Step::Established,
+            Step::Closed,
+            Step::Attempt,
+            Step::Established,
+            Step::Closed,
+        ]
+    );
+    assert_eq!(handle.dropped_events(), 0);
+}
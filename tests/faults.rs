@@ -0,0 +1,181 @@
+//! Recovery of the carriers from the faults injected into their connections.
+
+mod common;
+
+use common::{client, server, spawn, timeout};
+use mpc_carrier::channels::{Incoming, Outgoing};
+use mpc_carrier::control::CarrierHandle;
+use mpc_carrier::dedup::DeduplicationConfig;
+use mpc_carrier::errors::ErrorKind;
+use mpc_carrier::hello::{Feature, HelloConfig, HelloMode};
+use mpc_carrier::messages::fixtures;
+use mpc_carrier::node::HEARTBEAT_INTERVAL;
+use mpc_carrier::stats::RTT_STALE_AFTER;
+use mpc_carrier::testing::faults::{FaultPlan, FaultyTransport, ReadStall};
+use mpc_carrier::transport::memory::MemoryNetwork;
+use mpc_carrier::Carrier;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::time::sleep;
+
+/// Answers the requests of `incoming`, counting the calls of the handler.
+fn count_calls(mut incoming: Incoming) -> Arc<AtomicUsize> {
+    let calls = Arc::new(AtomicUsize::new(0));
+    let counted = Arc::clone(&calls);
+    tokio::spawn(async move {
+        while let Some((_, callback)) = incoming.recv().await {
+            counted.fetch_add(1, Ordering::Relaxed);
+            let response = fixtures::node_response(&callback.message);
+            let _ = callback.respond(response);
+        }
+    });
+    calls
+}
+
+/// Starts `a` dialing `b` over a connection playing `plan` first, and returns
+/// the handle of `a`, its channels to `b`, and the calls of the handler of `b`
+/// once the connection wrote its first heartbeat probe and the answer to the
+/// probe of `b`, the 2 frames before the requests counted by `plan`.
+async fn start(
+    plan: FaultPlan,
+    configure_b: impl FnOnce(Carrier) -> Carrier,
+) -> (CarrierHandle, Outgoing, Arc<AtomicUsize>) {
+    let network = MemoryNetwork::new();
+    let (carrier_b, incoming, _outgoing) = server(&["a"]);
+    spawn(configure_b(carrier_b), network.transport("b"));
+    let calls = count_calls(incoming);
+    let (carrier_a, _incoming, outgoing) = client(&["b"]);
+    let handle = carrier_a.handle();
+    let transport = FaultyTransport::new(network.transport("a")).dialed(plan);
+    spawn(carrier_a, transport);
+    timeout(async {
+        while handle.debug_state().nodes["b"].frames_sent < 2 {
+            sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await;
+    (handle, outgoing, calls)
+}
+
+async fn wait_for_reconnects(handle: &CarrierHandle, reconnects: u64) {
+    timeout(async {
+        while handle.debug_state().nodes["b"].reconnects < reconnects {
+            sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await;
+}
+
+#[tokio::test]
+async fn killed_connection_reconnects() {
+    let (handle, mut outgoing, _calls) = start(FaultPlan::kill_after_frames(4), |b| b).await;
+    timeout(outgoing.send("b", fixtures::node_request(1)))
+        .await
+        .unwrap();
+    // Written as the last frame, its response lost with the connection.
+    assert!(timeout(outgoing.send("b", fixtures::node_request(2)))
+        .await
+        .is_err());
+    let request = fixtures::node_request(3);
+    let response = timeout(outgoing.send("b", request.clone())).await;
+    assert_eq!(response.unwrap().request_id, request.request_id);
+    assert_eq!(handle.debug_state().nodes["b"].reconnects, 1);
+}
+
+#[tokio::test]
+async fn duplicated_request_handled_once_with_deduplication() {
+    let dedup = |b: Carrier| {
+        b.deduplication(DeduplicationConfig {
+            max_entries: 100,
+            ttl: Duration::from_secs(10),
+        })
+    };
+    let (handle, mut outgoing, calls) = start(FaultPlan::duplicate_after_frames(2), dedup).await;
+    for seed in 1..=3 {
+        let request = fixtures::node_request(seed);
+        let response = timeout(outgoing.send("b", request.clone())).await;
+        assert_eq!(response.unwrap().request_id, request.request_id);
+    }
+    assert_eq!(calls.load(Ordering::Relaxed), 3);
+    let state = &handle.debug_state().nodes["b"];
+    assert_eq!((state.connections, state.reconnects), (1, 0));
+    assert_eq!(handle.errors().count(ErrorKind::UnexpectedResponse), 0);
+}
+
+#[tokio::test]
+async fn duplicated_request_handled_twice_without_deduplication() {
+    let (handle, mut outgoing, calls) = start(FaultPlan::duplicate_after_frames(2), |b| b).await;
+    for seed in 1..=2 {
+        let request = fixtures::node_request(seed);
+        let response = timeout(outgoing.send("b", request.clone())).await;
+        assert_eq!(response.unwrap().request_id, request.request_id);
+    }
+    // The response to the copy matches no request, breaking the connection.
+    wait_for_reconnects(&handle, 1).await;
+    assert_eq!(calls.load(Ordering::Relaxed), 3);
+    assert_eq!(handle.errors().count(ErrorKind::UnexpectedResponse), 1);
+    timeout(outgoing.send("b", fixtures::node_request(3)))
+        .await
+        .unwrap();
+}
+
+#[tokio::test]
+async fn corrupted_request_closes_connection() {
+    let (handle, mut outgoing, calls) = start(FaultPlan::corrupt_after_frames(3), |b| b).await;
+    timeout(outgoing.send("b", fixtures::node_request(1)))
+        .await
+        .unwrap();
+    assert!(timeout(outgoing.send("b", fixtures::node_request(2)))
+        .await
+        .is_err());
+    wait_for_reconnects(&handle, 1).await;
+    timeout(outgoing.send("b", fixtures::node_request(3)))
+        .await
+        .unwrap();
+    assert_eq!(calls.load(Ordering::Relaxed), 2);
+}
+
+#[tokio::test(start_paused = true)]
+async fn stalled_reads_leave_estimate_stale() {
+    let hello = |carrier: Carrier, node_name: &str| {
+        carrier.hello(HelloConfig {
+            mode: HelloMode::Required,
+            node_name: node_name.to_string(),
+            features: vec![Feature::Envelope],
+        })
+    };
+    let network = MemoryNetwork::new();
+    let (carrier_b, incoming, _outgoing) = server(&["a"]);
+    spawn(hello(carrier_b, "b"), network.transport("b"));
+    count_calls(incoming);
+    let (carrier_a, _incoming, mut outgoing) = client(&["b"]);
+    let handle = carrier_a.handle();
+    // After the hello, the first probe and the answer to the probe of `b`,
+    // then the first request.
+    let stall = ReadStall::new();
+    let plan = FaultPlan::new().after_frames(4).stall_reads(&stall);
+    let transport = FaultyTransport::new(network.transport("a")).dialed(plan);
+    spawn(hello(carrier_a, "a"), transport);
+    while handle.rtt("b").is_none() {
+        sleep(Duration::from_millis(10)).await;
+    }
+    assert_eq!(handle.debug_state().nodes["b"].frames_sent, 3);
+
+    let request = fixtures::node_request(1);
+    let send = tokio::spawn(async move { outgoing.send("b", request).await });
+    sleep(RTT_STALE_AFTER + HEARTBEAT_INTERVAL).await;
+    let estimate = handle.rtt("b").unwrap();
+    assert!(estimate.stale, "{estimate:?}");
+    assert_eq!(estimate.samples, 1);
+    assert!(!send.is_finished());
+    assert_eq!(handle.debug_state().nodes["b"].connections, 1);
+
+    // The probes answered meanwhile are read late, refreshing the estimate.
+    stall.release();
+    send.await.unwrap().unwrap();
+    sleep(HEARTBEAT_INTERVAL).await;
+    let estimate = handle.rtt("b").unwrap();
+    assert!(!estimate.stale, "{estimate:?}");
+    assert_eq!(handle.debug_state().nodes["b"].reconnects, 0);
+}
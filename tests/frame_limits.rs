@@ -0,0 +1,82 @@
+//! Frame limits announced in the handshake and enforced by the sender.
+
+mod common;
+
+use common::{carrier, client, respond, server, spawn, timeout};
+use mpc_carrier::channels::{Outgoing, SendError};
+use mpc_carrier::hello::{HelloConfig, HelloMode};
+use mpc_carrier::messages::{fixtures, NodeRequest};
+use mpc_carrier::transport::memory::MemoryNetwork;
+use mpc_carrier::Carrier;
+
+const MIB: usize = 1 << 20;
+
+fn hello(carrier: Carrier, node_name: &str) -> Carrier {
+    carrier.hello(HelloConfig {
+        mode: HelloMode::Required,
+        node_name: node_name.to_string(),
+        features: Vec::new(),
+    })
+}
+
+fn request(seed: u64, len: usize) -> NodeRequest {
+    NodeRequest {
+        payload: vec![0x55; len],
+        ..fixtures::node_request(seed)
+    }
+}
+
+async fn exchange(outgoing: &mut Outgoing, node: &str, request: NodeRequest) {
+    let response = timeout(outgoing.send(node, request.clone())).await;
+    assert_eq!(response.unwrap().request_id, request.request_id);
+}
+
+#[tokio::test]
+async fn big_side_fails_at_send() {
+    let network = MemoryNetwork::new();
+    let (carrier_a, incoming, mut outgoing_a) = client(&["b"]);
+    spawn(
+        hello(carrier_a.max_frame_len(32 * MIB), "a"),
+        network.transport("a"),
+    );
+    respond(incoming);
+    let (carrier_b, incoming, mut outgoing_b) = server(&["a"]);
+    let handle_b = carrier_b.handle();
+    spawn(
+        hello(carrier_b.max_frame_len(MIB), "b"),
+        network.transport("b"),
+    );
+    respond(incoming);
+
+    // The limits are known once connected.
+    exchange(&mut outgoing_a, "b", request(1, 100)).await;
+    let result = outgoing_a.send("b", request(2, 2 * MIB)).await;
+    assert!(matches!(
+        result,
+        Err(SendError::TooLarge { len, peer_max_frame_len, max_frame_len })
+            if len > 2 * MIB && peer_max_frame_len == MIB && max_frame_len == 32 * MIB
+    ));
+    assert!(handle_b.debug_state().nodes["a"].bytes_received < MIB as u64);
+    // The connection is kept.
+    exchange(&mut outgoing_a, "b", request(3, 100)).await;
+    assert_eq!(handle_b.debug_state().nodes["a"].connections, 1);
+    // The other way, within the limit of the big side.
+    exchange(&mut outgoing_b, "a", request(4, 2 * MIB)).await;
+}
+
+#[tokio::test]
+async fn peer_without_hello_assumed_at_8_mib() {
+    let network = MemoryNetwork::new();
+    let (carrier_a, _incoming, mut outgoing) = carrier(&["b"]);
+    spawn(carrier_a.max_frame_len(32 * MIB), network.transport("a"));
+    let (carrier_b, incoming, _outgoing) = carrier(&["a"]);
+    spawn(carrier_b, network.transport("b"));
+    respond(incoming);
+
+    exchange(&mut outgoing, "b", request(1, 100)).await;
+    let result = outgoing.send("b", request(2, 9 * MIB)).await;
+    assert!(matches!(
+        result,
+        Err(SendError::TooLarge { peer_max_frame_len, .. }) if peer_max_frame_len == 8 * MIB
+    ));
+}
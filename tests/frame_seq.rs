@@ -0,0 +1,73 @@
+//! Numbering of the envelopes of a connection, and the detection of its gaps.
+
+mod common;
+
+use common::{client, respond, server, spawn, timeout, Logs};
+use mpc_carrier::control::CarrierHandle;
+use mpc_carrier::dedup::DeduplicationConfig;
+use mpc_carrier::hello::{Feature, HelloConfig, HelloMode};
+use mpc_carrier::messages::fixtures;
+use mpc_carrier::testing::faults::{FaultPlan, FaultyTransport};
+use mpc_carrier::transport::memory::MemoryNetwork;
+use mpc_carrier::Carrier;
+use std::time::Duration;
+
+fn hello(carrier: Carrier, node_name: &str, features: &[Feature]) -> Carrier {
+    carrier.hello(HelloConfig {
+        mode: HelloMode::Required,
+        node_name: node_name.to_string(),
+        features: features.to_vec(),
+    })
+}
+
+/// Sends 3 requests from `a` to `b`, an envelope written after the first one
+/// written twice, and returns the handles of `a` and `b`. The copy is dropped by the
+/// deduplication of `b`, as its response would break the connection.
+async fn duplicate_second(features: &[Feature]) -> (CarrierHandle, CarrierHandle) {
+    let network = MemoryNetwork::new();
+    let (carrier_a, _incoming, mut outgoing) = client(&["b"]);
+    let handle_a = carrier_a.handle();
+    // After the hello and the first request.
+    let plan = FaultPlan::duplicate_after_frames(2);
+    let transport = FaultyTransport::new(network.transport("a")).dialed(plan);
+    spawn(hello(carrier_a, "a", features), transport);
+    let (carrier_b, incoming, _outgoing) = server(&["a"]);
+    let carrier_b = carrier_b.deduplication(DeduplicationConfig {
+        max_entries: 100,
+        ttl: Duration::from_secs(10),
+    });
+    let handle_b = carrier_b.handle();
+    spawn(hello(carrier_b, "b", features), network.transport("b"));
+    respond(incoming);
+
+    for seed in 1..=3 {
+        let request = fixtures::node_request(seed);
+        let response = timeout(outgoing.send("b", request.clone())).await;
+        assert_eq!(response.unwrap().request_id, request.request_id);
+    }
+    (handle_a, handle_b)
+}
+
+#[tokio::test]
+async fn repeated_frame_reported_as_gap() {
+    let (logs, _subscriber) = Logs::capture();
+    let (handle_a, handle_b) = duplicate_second(&[Feature::Envelope, Feature::FrameSeq]).await;
+    // Probes of the round-trip time may follow the requests.
+    assert!(handle_a.debug_state().nodes["b"].last_sent_seq >= 3);
+    let state = &handle_b.debug_state().nodes["a"];
+    assert!(state.last_received_seq >= 3);
+    assert_eq!(state.sequence_gaps, 1);
+    let line = logs.find("SequenceGap").expect("no gap event");
+    assert!(line.contains("node=a"), "{line}");
+}
+
+#[tokio::test]
+async fn unnumbered_without_negotiation() {
+    let (logs, _subscriber) = Logs::capture();
+    let (handle_a, handle_b) = duplicate_second(&[Feature::Envelope]).await;
+    assert_eq!(handle_a.debug_state().nodes["b"].last_sent_seq, 0);
+    let state = &handle_b.debug_state().nodes["a"];
+    assert_eq!(state.last_received_seq, 0);
+    assert_eq!(state.sequence_gaps, 0);
+    assert_eq!(logs.find("SequenceGap"), None);
+}
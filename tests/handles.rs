@@ -0,0 +1,39 @@
+//! Carrier lifetime tied to its `Incoming` and `Outgoing` handles.
+
+mod common;
+
+use common::{server, spawn};
+use mpc_carrier::transport::memory::MemoryNetwork;
+use mpc_carrier::Error;
+use std::time::Duration;
+
+#[tokio::test]
+async fn dropped_handles_stop_run() {
+    let network = MemoryNetwork::new();
+    let (carrier, incoming, outgoing) = server(&["a"]);
+    let run = spawn(
+        carrier.shutdown_on_handles_dropped(true),
+        network.transport("b"),
+    );
+    drop(incoming);
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    assert!(!run.is_finished(), "stopped with Outgoing alive");
+    drop(outgoing);
+    let result = tokio::time::timeout(Duration::from_secs(1), run)
+        .await
+        .expect("run not stopped")
+        .unwrap();
+    assert!(matches!(result, Err(Error::HandlesDropped)), "{result:?}");
+}
+
+#[tokio::test]
+async fn dropped_handles_ignored_by_default() {
+    let network = MemoryNetwork::new();
+    let (carrier, incoming, outgoing) = server(&["a"]);
+    let handle = carrier.handle();
+    let run = spawn(carrier, network.transport("b"));
+    drop((incoming, outgoing));
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    assert!(!run.is_finished());
+    assert!(handle.is_running());
+}
@@ -0,0 +1,70 @@
+//! Bound of the incoming TLS handshakes in progress.
+
+mod common;
+
+use common::pki::{addr, tcp_port, Pki, HOST};
+use common::{respond, spawn, timeout};
+use mpc_carrier::config::Direction;
+use mpc_carrier::messages::fixtures;
+use mpc_carrier::transport::TlsTcpTransport;
+use mpc_carrier::Carrier;
+use std::time::Duration;
+use tokio::net::TcpStream;
+use tokio::time::sleep;
+
+const LIMIT: usize = 4;
+
+#[tokio::test]
+async fn handshakes_stay_within_limit() {
+    let pki = Pki::new();
+    let port = tcp_port();
+    let (carrier, incoming, _outgoing) = Carrier::with_addrs([("a.test", addr(1, "b.test"))]);
+    let carrier = carrier
+        .direction("a.test", Direction::Accept)
+        .max_concurrent_handshakes(LIMIT);
+    let handle = carrier.handle();
+    let server_config = pki.server_config(&["a.test"]);
+    spawn(
+        carrier,
+        TlsTcpTransport::new(HOST, port, server_config, pki.client_config()),
+    );
+    respond(incoming);
+
+    // Clients never sending their hello keep their handshakes in progress.
+    let mut stalled = Vec::new();
+    for _ in 0..5 * LIMIT {
+        let stream = timeout(async {
+            loop {
+                match TcpStream::connect((HOST, port)).await {
+                    Ok(stream) => break stream,
+                    Err(_) => sleep(Duration::from_millis(10)).await,
+                }
+            }
+        })
+        .await;
+        stalled.push(stream);
+    }
+    timeout(async {
+        while handle.handshakes_in_progress() < LIMIT {
+            sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await;
+    for _ in 0..20 {
+        assert_eq!(handle.handshakes_in_progress(), LIMIT);
+        sleep(Duration::from_millis(10)).await;
+    }
+
+    // The queued sockets get their turn once the stalled ones go away.
+    drop(stalled);
+    let (carrier, _incoming, mut outgoing) = Carrier::with_addrs([("b", addr(port, "a.test"))]);
+    let carrier = carrier
+        .direction("b", Direction::Dial)
+        .skip_unused_listener(true);
+    let transport = TlsTcpTransport::new(HOST, 0, pki.server_config(&[]), pki.client_config());
+    spawn(carrier, transport);
+    let request = fixtures::node_request(1);
+    let response = timeout(outgoing.send("b", request.clone())).await;
+    assert_eq!(response.unwrap().request_id, request.request_id);
+    assert!(handle.handshakes_in_progress() <= LIMIT);
+}
@@ -0,0 +1,80 @@
+//! Health probes of a carrier, following the connectivity with its nodes.
+
+mod common;
+
+use common::{client, respond, server, spawn, timeout};
+use mpc_carrier::control::CarrierHandle;
+use mpc_carrier::health;
+use mpc_carrier::transport::memory::MemoryNetwork;
+use std::net::SocketAddr;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::time::sleep;
+
+/// Returns the status code and the body of a `GET` of `path`.
+async fn get(addr: SocketAddr, path: &str) -> (u16, String) {
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    let request = format!("GET {path} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n");
+    stream.write_all(request.as_bytes()).await.unwrap();
+    let mut response = String::new();
+    timeout(stream.read_to_string(&mut response)).await.unwrap();
+    let status = response[9..12].parse().unwrap();
+    let body = response.split("\r\n\r\n").nth(1).unwrap().to_string();
+    (status, body)
+}
+
+async fn wait_for_connections(handle: &CarrierHandle, node: &str, connections: usize) {
+    timeout(async {
+        while handle.debug_state().nodes[node].connections != connections {
+            sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await;
+}
+
+#[tokio::test]
+async fn probes_follow_connectivity() {
+    let network = MemoryNetwork::new();
+    let mut peers = Vec::new();
+    for name in ["b", "c"] {
+        let (carrier, incoming, _outgoing) = server(&["a"]);
+        peers.push(spawn(carrier, network.transport(name)));
+        respond(incoming);
+    }
+    let (carrier, _incoming, _outgoing) = client(&["b", "c"]);
+    let handle = carrier.handle();
+    let task = spawn(carrier, network.transport("a"));
+    let server = health::serve(&handle, ([127, 0, 0, 1], 0).into())
+        .await
+        .unwrap();
+    let addr = server.local_addr();
+
+    wait_for_connections(&handle, "b", 1).await;
+    wait_for_connections(&handle, "c", 1).await;
+    assert_eq!(get(addr, "/healthz").await, (200, "ok".to_string()));
+    let (status, body) = get(addr, "/readyz").await;
+    assert_eq!(status, 200);
+    assert!(body.starts_with(r#"{"status":"ok","#), "{body}");
+
+    peers.pop().unwrap().abort();
+    wait_for_connections(&handle, "c", 0).await;
+    let (status, body) = get(addr, "/readyz").await;
+    assert_eq!(status, 200);
+    assert!(body.contains(r#""status":"degraded""#), "{body}");
+    assert!(body.contains(r#""disconnected":["c"]"#), "{body}");
+    // The liveness probe ignores the peers.
+    assert_eq!(get(addr, "/healthz").await.0, 200);
+
+    task.abort();
+    timeout(async {
+        while handle.is_running() {
+            sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await;
+    assert_eq!(get(addr, "/healthz").await.0, 503);
+    assert_eq!(get(addr, "/readyz").await.0, 503);
+    assert_eq!(get(addr, "/other").await.0, 404);
+    server.stop();
+}
@@ -0,0 +1,71 @@
+//! Receiving the requests of the other nodes.
+
+mod common;
+
+use common::{client, server, spawn, timeout};
+use mpc_carrier::messages::fixtures;
+use mpc_carrier::transport::memory::MemoryNetwork;
+
+#[tokio::test]
+async fn peek_then_recv_same_request() {
+    let network = MemoryNetwork::new();
+    let (carrier, mut incoming, _outgoing) = server(&["a"]);
+    spawn(carrier, network.transport("b"));
+    let (carrier, _incoming, mut outgoing) = client(&["b"]);
+    spawn(carrier, network.transport("a"));
+    let requests = fixtures::random_requests(2, 0);
+    let sent = requests.clone();
+    let sender = tokio::spawn(async move {
+        for request in sent {
+            outgoing.send("b", request).await.unwrap();
+        }
+    });
+
+    let (node, request) = timeout(incoming.peek()).await.unwrap();
+    assert_eq!((node.as_str(), request), ("a", &requests[0]));
+    // Peeking again leaves the request in place.
+    let (_, request) = incoming.peek().await.unwrap();
+    assert_eq!(request, &requests[0]);
+    let (node, callback) = incoming.recv().await.unwrap();
+    assert_eq!((node.as_str(), &callback.message), ("a", &requests[0]));
+    let response = fixtures::node_response(&requests[0]);
+    callback.respond(response).unwrap();
+
+    let (_, callback) = timeout(incoming.recv()).await.unwrap();
+    assert_eq!(callback.message, requests[1]);
+    let response = fixtures::node_response(&requests[1]);
+    callback.respond(response).unwrap();
+    timeout(sender).await.unwrap();
+}
+
+#[tokio::test]
+async fn recv_session_leaves_other_sessions_buffered() {
+    let network = MemoryNetwork::new();
+    let (carrier, mut incoming, _outgoing) = server(&["a", "c"]);
+    spawn(carrier, network.transport("b"));
+    let mut senders = Vec::new();
+    for (node, session_id) in [("a", 1), ("c", 2)] {
+        let (carrier, _incoming, mut outgoing) = client(&["b"]);
+        spawn(carrier, network.transport(node));
+        outgoing.set_session_id(Some(session_id));
+        senders.push(tokio::spawn(async move {
+            outgoing.send("b", fixtures::node_request(session_id)).await
+        }));
+    }
+
+    // The request received first is left buffered for the next receive.
+    let (_, request) = timeout(incoming.peek()).await.unwrap();
+    let first = request.session_id.unwrap();
+    let second = 3 - first;
+    let (_, callback) = timeout(incoming.recv_session(second)).await.unwrap();
+    assert_eq!(callback.message.session_id, Some(second));
+    let response = fixtures::node_response(&callback.message);
+    callback.respond(response).unwrap();
+    let (_, callback) = timeout(incoming.recv()).await.unwrap();
+    assert_eq!(callback.message.session_id, Some(first));
+    let response = fixtures::node_response(&callback.message);
+    callback.respond(response).unwrap();
+    for sender in senders {
+        timeout(sender).await.unwrap().unwrap();
+    }
+}
@@ -0,0 +1,39 @@
+//! Histogram of the response latencies.
+
+mod common;
+
+use common::{carrier, spawn, timeout};
+use mpc_carrier::messages::fixtures;
+use mpc_carrier::transport::memory::MemoryNetwork;
+use std::time::Duration;
+use tokio::time::sleep;
+
+const REQUESTS: u64 = 100;
+const DELAY: Duration = Duration::from_millis(20);
+
+#[tokio::test]
+async fn median_falls_in_bucket_of_delay() {
+    let network = MemoryNetwork::new();
+    let (carrier_a, _incoming, mut outgoing) = carrier(&["b"]);
+    let histogram = carrier_a.latency_histogram("b").unwrap();
+    spawn(carrier_a, network.transport("a"));
+    let (carrier_b, mut incoming, _outgoing) = carrier(&["a"]);
+    spawn(carrier_b, network.transport("b"));
+    // Answers every request after `DELAY`.
+    tokio::spawn(async move {
+        while let Some((_, callback)) = incoming.recv().await {
+            sleep(DELAY).await;
+            let response = fixtures::node_response(&callback.message);
+            let _ = callback.respond(response);
+        }
+    });
+
+    for seed in 0..REQUESTS {
+        let request = fixtures::node_request(seed);
+        timeout(outgoing.send("b", request)).await.unwrap();
+    }
+    assert_eq!(histogram.count(), REQUESTS);
+    assert!(histogram.sum() >= DELAY * 100);
+    // 20 ms falls in the bucket from 10 to 50 ms.
+    assert_eq!(histogram.percentile(50.0), Some(0.05));
+}
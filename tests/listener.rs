@@ -0,0 +1,146 @@
+//! Failures of the listener, injected with the fault-injection transport.
+
+mod common;
+
+use common::{client, respond, server, spawn, timeout};
+use futures::future::{self, BoxFuture};
+use futures::FutureExt;
+use mpc_carrier::errors::ErrorKind;
+use mpc_carrier::hook::{self, PreConnectHook};
+use mpc_carrier::messages::fixtures;
+use mpc_carrier::protobuf_tcp::{Reader, Writer};
+use mpc_carrier::supervisor::Policy;
+use mpc_carrier::testing::faults::FaultyTransport;
+use mpc_carrier::transport::memory::MemoryNetwork;
+use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Hook panicking on the next connection accepted once armed.
+#[derive(Clone, Default)]
+struct PanicOnAccept(Arc<AtomicBool>);
+
+impl PreConnectHook for PanicOnAccept {
+    fn on_connect<'a>(
+        &'a self,
+        _reader: &'a mut Reader,
+        _writer: &'a mut Writer,
+    ) -> BoxFuture<'a, Result<(), hook::Error>> {
+        future::ok(()).boxed()
+    }
+
+    fn on_accept<'a>(
+        &'a self,
+        _reader: &'a mut Reader,
+        _writer: &'a mut Writer,
+    ) -> BoxFuture<'a, Result<(), hook::Error>> {
+        assert!(!self.0.swap(false, Ordering::Relaxed), "armed hook");
+        future::ok(()).boxed()
+    }
+}
+
+#[tokio::test]
+async fn transient_accept_failure_keeps_listening() {
+    let network = MemoryNetwork::new();
+    let (carrier, incoming, _outgoing) = server(&["a", "c"]);
+    let transport = FaultyTransport::new(network.transport("b"));
+    let faults = transport.listener_faults();
+    let handle = carrier.handle();
+    let run = spawn(carrier, transport);
+    respond(incoming);
+
+    let (carrier, _incoming, mut a) = client(&["b"]);
+    spawn(carrier, network.transport("a"));
+    let request = fixtures::node_request(1);
+    let response = timeout(a.send("b", request.clone())).await.unwrap();
+    assert_eq!(response.request_id, request.request_id);
+
+    faults.fail_accept(io::Error::from_raw_os_error(libc::EMFILE));
+    let (carrier, _incoming, mut c) = client(&["b"]);
+    spawn(carrier, network.transport("c"));
+    let request = fixtures::node_request(2);
+    let response = timeout(c.send("b", request.clone())).await.unwrap();
+    assert_eq!(response.request_id, request.request_id);
+
+    let request = fixtures::node_request(3);
+    let response = timeout(a.send("b", request.clone())).await.unwrap();
+    assert_eq!(response.request_id, request.request_id);
+    tokio::time::sleep(Duration::from_millis(10)).await;
+    assert!(!run.is_finished());
+    assert!(handle.is_running());
+}
+
+#[tokio::test]
+async fn failed_listener_restarts_while_traffic_continues() {
+    let network = MemoryNetwork::new();
+    let (carrier, incoming, _outgoing) = server(&["a", "c"]);
+    let carrier = carrier.listener_policy(Policy::Restart {
+        backoff: Duration::from_millis(50),
+    });
+    let transport = FaultyTransport::new(network.transport("b"));
+    let faults = transport.listener_faults();
+    let handle = carrier.handle();
+    let run = spawn(carrier, transport);
+    respond(incoming);
+
+    let (carrier, _incoming, mut a) = client(&["b"]);
+    spawn(carrier, network.transport("a"));
+    let request = fixtures::node_request(1);
+    timeout(a.send("b", request)).await.unwrap();
+
+    faults.fail_accept(io::Error::other("listener gone"));
+    // The connection of `a` outlives the listener, down for its backoff.
+    for seed in 2..10 {
+        let request = fixtures::node_request(seed);
+        let response = timeout(a.send("b", request.clone())).await.unwrap();
+        assert_eq!(response.request_id, request.request_id);
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    let (carrier, _incoming, mut c) = client(&["b"]);
+    spawn(carrier, network.transport("c"));
+    let request = fixtures::node_request(10);
+    let response = timeout(c.send("b", request.clone())).await.unwrap();
+    assert_eq!(response.request_id, request.request_id);
+
+    assert!(!run.is_finished());
+    assert_eq!(handle.errors().count(ErrorKind::Listener), 1);
+}
+
+#[tokio::test]
+async fn panicking_connection_closed_alone() {
+    let network = MemoryNetwork::new();
+    let (carrier, incoming, _outgoing) = server(&["a", "c"]);
+    let armed = PanicOnAccept::default();
+    let carrier = carrier.pre_connect_hook(armed.clone());
+    let handle = carrier.handle();
+    let run = spawn(carrier, network.transport("b"));
+    respond(incoming);
+
+    let (carrier, _incoming, mut c) = client(&["b"]);
+    spawn(carrier, network.transport("c"));
+    let request = fixtures::node_request(1);
+    timeout(c.send("b", request)).await.unwrap();
+
+    armed.0.store(true, Ordering::Relaxed);
+    let (carrier, _incoming, mut a) = client(&["b"]);
+    let a_handle = carrier.handle();
+    spawn(carrier, network.transport("a"));
+    timeout(async {
+        while handle.errors().count(ErrorKind::Panicked) == 0 {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await;
+    // The connection of `c` and the listener serve on.
+    let request = fixtures::node_request(2);
+    timeout(c.send("b", request)).await.unwrap();
+    assert_eq!(handle.debug_state().nodes["c"].connections, 1);
+    assert!(!run.is_finished());
+
+    // Reconnecting, `a` gets through.
+    let request = fixtures::node_request(3);
+    timeout(a.send("b", request)).await.unwrap();
+    assert_eq!(a_handle.debug_state().nodes["b"].reconnects, 1);
+    assert_eq!(handle.errors().count(ErrorKind::Listener), 0);
+}
@@ -0,0 +1,30 @@
+//! Rate limiting of the logs of repeated connection failures.
+
+mod common;
+
+use common::{client, spawn, timeout, Logs};
+use mpc_carrier::lifecycle::CarrierEvent;
+use mpc_carrier::transport::memory::MemoryNetwork;
+
+#[tokio::test]
+async fn failure_storm_logged_once_per_node() {
+    let (logs, _subscriber) = Logs::capture();
+    let network = MemoryNetwork::new();
+    let (mut carrier, _incoming, _outgoing) = client(&["b", "c"]);
+    let mut events = carrier.events();
+    spawn(carrier, network.transport("a"));
+
+    // Neither b nor c ever comes up, so that every dial fails.
+    let mut attempts = 0;
+    while attempts < 10 {
+        if let CarrierEvent::OutgoingAttempt { .. } = timeout(events.recv()).await.unwrap() {
+            attempts += 1;
+        }
+    }
+    let failures = logs.count("Connection failure");
+    assert_eq!(
+        failures, 2,
+        "{failures} failures logged after {attempts} attempts"
+    );
+    assert_eq!(logs.count("Suppressed"), 0);
+}
@@ -0,0 +1,112 @@
+//! Accounting of the messages discarded by the carrier.
+
+mod common;
+
+use common::{carrier, respond, spawn, timeout};
+use mpc_carrier::channels::Outgoing;
+use mpc_carrier::loss::{Loss, LossAccounting, LossReason};
+use mpc_carrier::messages::{fixtures, NodeRequest};
+use mpc_carrier::middleware::Direction;
+use mpc_carrier::transport::memory::MemoryNetwork;
+use std::panic;
+use std::time::Duration;
+use tokio::time::sleep;
+
+/// Starts `a` sending to `b`, and returns the outgoing channels of `a` with
+/// the losses of `a` and `b`. A request to `b` is answered, dropped or
+/// answered after 200 ms for a payload of 0, 1 or 2.
+fn start() -> (Outgoing, LossAccounting, LossAccounting) {
+    let network = MemoryNetwork::new();
+    let (carrier_a, _incoming, outgoing) = carrier(&["b"]);
+    let losses_a = carrier_a.losses();
+    spawn(carrier_a, network.transport("a"));
+    let (carrier_b, mut incoming, _outgoing) = carrier(&["a"]);
+    let losses_b = carrier_b.losses();
+    spawn(carrier_b, network.transport("b"));
+    tokio::spawn(async move {
+        while let Some((_, callback)) = incoming.recv().await {
+            let response = fixtures::node_response(&callback.message);
+            match callback.message.payload[..] {
+                [1] => drop(callback),
+                [2] => {
+                    tokio::spawn(async move {
+                        sleep(Duration::from_millis(200)).await;
+                        let _ = callback.respond(response);
+                    });
+                }
+                _ => {
+                    let _ = callback.respond(response);
+                }
+            }
+        }
+    });
+    (outgoing, losses_a, losses_b)
+}
+
+fn request(seed: u64, kind: u8) -> NodeRequest {
+    NodeRequest {
+        payload: vec![kind],
+        ..fixtures::node_request(seed)
+    }
+}
+
+async fn wait_for_total(losses: &LossAccounting, total: u64) {
+    timeout(async {
+        while losses.total() < total {
+            sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await;
+}
+
+#[tokio::test]
+async fn clean_exchange_loses_nothing() {
+    let network = MemoryNetwork::new();
+    let (carrier_a, _incoming, mut outgoing) = carrier(&["b"]);
+    let losses = carrier_a.losses();
+    spawn(carrier_a, network.transport("a"));
+    let (carrier_b, incoming, _outgoing) = carrier(&["a"]);
+    spawn(carrier_b, network.transport("b"));
+    respond(incoming);
+    for seed in 0..10 {
+        timeout(outgoing.send("b", fixtures::node_request(seed)))
+            .await
+            .unwrap();
+    }
+    losses.assert_no_losses();
+}
+
+#[tokio::test]
+async fn losses_counted_by_reason() {
+    let (mut outgoing, losses_a, losses_b) = start();
+    timeout(outgoing.send("b", request(1, 0))).await.unwrap();
+
+    // Dropped by the application of `b`.
+    assert!(timeout(outgoing.send("b", request(2, 1))).await.is_err());
+    // Given up by the caller before its response.
+    let given_up =
+        tokio::time::timeout(Duration::from_millis(50), outgoing.send("b", request(3, 2)));
+    assert!(given_up.await.is_err());
+    // Without the notifications negotiated by a hello.
+    timeout(outgoing.notify("b", vec![4])).await.unwrap();
+    wait_for_total(&losses_a, 2).await;
+
+    let loss = |direction, reason| Loss {
+        node: "b".into(),
+        direction,
+        reason,
+        count: 1,
+    };
+    assert_eq!(
+        losses_a.snapshot(),
+        [
+            loss(Direction::Incoming, LossReason::Abandoned),
+            loss(Direction::Outgoing, LossReason::NotNegotiated),
+        ]
+    );
+    let Loss { node, reason, .. } = &losses_b.snapshot()[0];
+    assert_eq!((node.as_str(), *reason), ("a", LossReason::HandlerDropped));
+    let panicked = panic::catch_unwind(|| losses_b.assert_no_losses()).unwrap_err();
+    let message = panicked.downcast_ref::<String>().unwrap();
+    assert_eq!(message, "Messages lost: 1 handler_dropped from a");
+}
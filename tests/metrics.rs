@@ -0,0 +1,78 @@
+//! Statistics exported through the `metrics` facade.
+
+mod common;
+
+use common::{client, respond, server, spawn, timeout};
+use metrics_util::debugging::{DebugValue, DebuggingRecorder};
+use metrics_util::CompositeKey;
+use mpc_carrier::messages::fixtures;
+use mpc_carrier::metrics::PUBLISH_INTERVAL;
+use mpc_carrier::transport::memory::MemoryNetwork;
+use std::time::Duration;
+use tokio::time::sleep;
+
+type Series = Vec<(CompositeKey, DebugValue)>;
+
+/// Returns the value of the series `name` with `labels`.
+fn value<'a>(series: &'a Series, name: &str, labels: &[(&str, &str)]) -> &'a DebugValue {
+    series
+        .iter()
+        .find(|(key, _)| {
+            let key = key.key();
+            key.name() == name
+                && key.labels().count() == labels.len()
+                && labels.iter().all(|(label, value)| {
+                    key.labels()
+                        .any(|l| l.key() == *label && l.value() == *value)
+                })
+        })
+        .map(|(_, value)| value)
+        .unwrap_or_else(|| panic!("no series {name} {labels:?}"))
+}
+
+#[tokio::test]
+async fn exchange_published() {
+    let recorder = DebuggingRecorder::new();
+    let snapshotter = recorder.snapshotter();
+    recorder.install().unwrap();
+    let network = MemoryNetwork::new();
+    let (carrier, _incoming, mut outgoing) = client(&["b"]);
+    spawn(carrier, network.transport("a"));
+    let (carrier, incoming, _outgoing) = server(&["a"]);
+    spawn(carrier, network.transport("b"));
+    respond(incoming);
+
+    for seed in 0..5 {
+        timeout(outgoing.send("b", fixtures::node_request(seed)))
+            .await
+            .unwrap();
+    }
+    // Until published once more.
+    sleep(PUBLISH_INTERVAL + Duration::from_millis(200)).await;
+    let series = snapshotter
+        .snapshot()
+        .into_vec()
+        .into_iter()
+        .map(|(key, _, _, value)| (key, value))
+        .collect::<Series>();
+
+    let connections = value(&series, "mpc_carrier_connections", &[("node", "b")]);
+    assert_eq!(*connections, DebugValue::Gauge(1.0.into()));
+    let labels = [("node", "b"), ("direction", "outgoing")];
+    let DebugValue::Counter(sent) = value(&series, "mpc_carrier_bytes_total", &labels) else {
+        panic!("bytes_total not a counter");
+    };
+    assert!(*sent > 0);
+    let labels = [("node", "a"), ("direction", "incoming")];
+    assert_eq!(
+        value(&series, "mpc_carrier_bytes_total", &labels),
+        &DebugValue::Counter(*sent)
+    );
+    let name = "mpc_carrier_request_duration_seconds";
+    let DebugValue::Histogram(latencies) = value(&series, name, &[("node", "b")]) else {
+        panic!("{name} not a histogram");
+    };
+    assert_eq!(latencies.len(), 5);
+    let inflight = value(&series, "mpc_carrier_inflight_requests", &[("node", "b")]);
+    assert_eq!(*inflight, DebugValue::Gauge(0.0.into()));
+}
@@ -0,0 +1,67 @@
+//! One-way notifications.
+
+mod common;
+
+use common::{carrier, respond, spawn, timeout};
+use futures::StreamExt;
+use mpc_carrier::channels::{NotificationReceiver, Outgoing};
+use mpc_carrier::hello::{Feature, HelloConfig, HelloMode};
+use mpc_carrier::messages::fixtures;
+use mpc_carrier::transport::memory::MemoryNetwork;
+use mpc_carrier::Carrier;
+use std::time::Duration;
+use tokio::time::sleep;
+
+const MESSAGES: u8 = 50;
+
+fn hello(carrier: Carrier, node_name: &str) -> Carrier {
+    carrier.hello(HelloConfig {
+        mode: HelloMode::Required,
+        node_name: node_name.to_string(),
+        features: vec![Feature::Notifications],
+    })
+}
+
+/// Sends `MESSAGES` notifications from `a` to `b`, each followed by a request.
+async fn start(with_hello: bool) -> (Outgoing, NotificationReceiver) {
+    let network = MemoryNetwork::new();
+    let (mut carrier_a, _incoming, mut outgoing) = carrier(&["b"]);
+    let (mut carrier_b, mut incoming, _outgoing) = carrier(&["a"]);
+    if with_hello {
+        carrier_a = hello(carrier_a, "a");
+        carrier_b = hello(carrier_b, "b");
+    }
+    spawn(carrier_a, network.transport("a"));
+    spawn(carrier_b, network.transport("b"));
+    let notifications = incoming.take_notifications().unwrap();
+    respond(incoming);
+
+    for seed in 0..MESSAGES {
+        timeout(outgoing.notify("b", vec![seed])).await.unwrap();
+        let request = fixtures::node_request(seed.into());
+        let response = timeout(outgoing.send("b", request.clone())).await;
+        assert_eq!(response.unwrap().request_id, request.request_id);
+    }
+    (outgoing, notifications)
+}
+
+#[tokio::test]
+async fn interleaved_notifications_arrive_once() {
+    let (_outgoing, mut notifications) = start(true).await;
+    // In order, as written on the same connection as the requests.
+    for seed in 0..MESSAGES {
+        let (node, notification) = timeout(notifications.next()).await.unwrap();
+        assert_eq!(node, "a");
+        assert_eq!(notification.payload, [seed]);
+    }
+    sleep(Duration::from_millis(100)).await;
+    assert!(notifications.try_recv().is_err());
+}
+
+#[tokio::test]
+async fn notifications_dropped_without_feature() {
+    // The requests keep flowing while the notifications are dropped.
+    let (_outgoing, mut notifications) = start(false).await;
+    sleep(Duration::from_millis(100)).await;
+    assert!(notifications.try_recv().is_err());
+}
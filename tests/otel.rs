@@ -0,0 +1,80 @@
+//! Spans and instruments of a loopback exchange, exported in memory.
+
+mod common;
+
+use common::{carrier, respond, spawn, timeout};
+use mpc_carrier::messages::fixtures;
+use mpc_carrier::transport::memory::MemoryNetwork;
+use opentelemetry::metrics::MeterProvider;
+use opentelemetry::trace::{SpanKind, TracerProvider};
+use opentelemetry::{KeyValue, Value};
+use opentelemetry_sdk::metrics::data::{AggregatedMetrics, MetricData};
+use opentelemetry_sdk::metrics::{InMemoryMetricExporter, PeriodicReader, SdkMeterProvider};
+use opentelemetry_sdk::trace::{InMemorySpanExporter, SdkTracerProvider};
+use tracing_subscriber::layer::SubscriberExt;
+
+#[tokio::test]
+async fn exchange_exported() {
+    let spans = InMemorySpanExporter::default();
+    let tracer_provider = SdkTracerProvider::builder()
+        .with_simple_exporter(spans.clone())
+        .build();
+    let layer = tracing_opentelemetry::layer().with_tracer(tracer_provider.tracer("test"));
+    let _subscriber = tracing::subscriber::set_default(tracing_subscriber::registry().with(layer));
+    let metrics = InMemoryMetricExporter::default();
+    let meter_provider = SdkMeterProvider::builder()
+        .with_reader(PeriodicReader::builder(metrics.clone()).build())
+        .build();
+
+    let network = MemoryNetwork::new();
+    let (carrier_a, _incoming, mut outgoing) = carrier(&["b"]);
+    let carrier_a = carrier_a.otel_meter(&meter_provider.meter("test"));
+    spawn(carrier_a, network.transport("a"));
+    let (carrier_b, incoming, _outgoing) = carrier(&["a"]);
+    spawn(carrier_b, network.transport("b"));
+    respond(incoming);
+    let request = fixtures::node_request(1);
+    let response = timeout(outgoing.send("b", request.clone())).await;
+    assert_eq!(response.unwrap().request_id, request.request_id);
+
+    // The server span of `b` is parented to the client span of `a`.
+    tracer_provider.force_flush().unwrap();
+    let spans = spans.get_finished_spans().unwrap();
+    let span = |kind| {
+        spans
+            .iter()
+            .find(|span| span.name == "mpc.rpc" && span.span_kind == kind)
+            .unwrap_or_else(|| panic!("no {kind:?} span in {spans:?}"))
+    };
+    let (client, server) = (span(SpanKind::Client), span(SpanKind::Server));
+    assert_eq!(
+        server.span_context.trace_id(),
+        client.span_context.trace_id()
+    );
+    assert_eq!(server.parent_span_id, client.span_context.span_id());
+    assert!(client.attributes.contains(&KeyValue::new("node", "b")));
+
+    meter_provider.force_flush().unwrap();
+    let metrics = metrics.get_finished_metrics().unwrap();
+    let bytes = metrics
+        .iter()
+        .flat_map(|metrics| metrics.scope_metrics())
+        .flat_map(|scope| scope.metrics())
+        .find(|metric| metric.name() == "mpc_carrier.bytes")
+        .expect("no bytes counter");
+    let AggregatedMetrics::U64(MetricData::Sum(sum)) = bytes.data() else {
+        panic!("not a counter: {bytes:?}");
+    };
+    let sent = sum
+        .data_points()
+        .find(|point| {
+            point.attributes().any(|attribute| {
+                attribute.key.as_str() == "direction" && attribute.value == Value::from("outgoing")
+            })
+        })
+        .expect("no outgoing bytes");
+    assert!(sent.value() > 0);
+    assert!(sent
+        .attributes()
+        .any(|attribute| *attribute == KeyValue::new("node", "b")));
+}
@@ -0,0 +1,90 @@
+//! Export of the spans to an OTLP endpoint, a mock collector over gRPC.
+
+mod common;
+
+use bytes::Bytes;
+use common::pki::HOST;
+use common::{carrier, respond, spawn, timeout};
+use h2::server::SendResponse;
+use h2::RecvStream;
+use http::{HeaderMap, Request, Response};
+use mpc_carrier::messages::fixtures;
+use mpc_carrier::transport::memory::MemoryNetwork;
+use tokio::net::TcpListener;
+use tokio::sync::mpsc;
+
+/// Accepts the gRPC calls of the exporters, sending the bodies of the calls
+/// to `exports` and answering them with an empty message.
+async fn collector(listener: TcpListener, exports: mpsc::UnboundedSender<Vec<u8>>) {
+    loop {
+        let (socket, _) = listener.accept().await.unwrap();
+        let exports = exports.clone();
+        tokio::spawn(async move {
+            let mut conn = h2::server::handshake(socket).await.unwrap();
+            while let Some(Ok((request, respond))) = conn.accept().await {
+                tokio::spawn(export(request, respond, exports.clone()));
+            }
+        });
+    }
+}
+
+async fn export(
+    request: Request<RecvStream>,
+    mut respond: SendResponse<Bytes>,
+    exports: mpsc::UnboundedSender<Vec<u8>>,
+) {
+    assert_eq!(
+        request.uri().path(),
+        "/opentelemetry.proto.collector.trace.v1.TraceService/Export"
+    );
+    let mut body = request.into_body();
+    let mut call = Vec::new();
+    while let Some(chunk) = body.data().await {
+        let chunk = chunk.unwrap();
+        let _ = body.flow_control().release_capacity(chunk.len());
+        call.extend_from_slice(&chunk);
+    }
+    let _ = exports.send(call);
+    let response = Response::builder()
+        .header("content-type", "application/grpc")
+        .body(())
+        .unwrap();
+    let mut send = respond.send_response(response, false).unwrap();
+    // A message of 0 bytes, uncompressed.
+    send.send_data(Bytes::from_static(&[0; 5]), false).unwrap();
+    let mut trailers = HeaderMap::new();
+    trailers.insert("grpc-status", "0".parse().unwrap());
+    send.send_trailers(trailers).unwrap();
+}
+
+fn contains(haystack: &[u8], needle: &[u8]) -> bool {
+    haystack
+        .windows(needle.len())
+        .any(|window| window == needle)
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn request_span_exported() {
+    let listener = TcpListener::bind((HOST, 0)).await.unwrap();
+    let endpoint = format!("http://{}", listener.local_addr().unwrap());
+    let (exports, mut exported) = mpsc::unbounded_channel();
+    tokio::spawn(collector(listener, exports));
+
+    let network = MemoryNetwork::new();
+    let (carrier_a, _incoming, mut outgoing) = carrier(&["b"]);
+    let run_a = spawn(carrier_a.otlp_endpoint(endpoint), network.transport("a"));
+    let (carrier_b, incoming, _outgoing) = carrier(&["a"]);
+    spawn(carrier_b, network.transport("b"));
+    respond(incoming);
+    let request = fixtures::node_request(1);
+    let response = timeout(outgoing.send("b", request.clone())).await;
+    assert_eq!(response.unwrap().request_id, request.request_id);
+
+    // The spans left are exported as the carrier is dropped.
+    run_a.abort();
+    assert!(timeout(run_a).await.unwrap_err().is_cancelled());
+    let call = timeout(exported.recv()).await.unwrap();
+    assert!(contains(&call, b"mpc.rpc"));
+    assert!(contains(&call, b"mpc-carrier"));
+    assert!(contains(&call, b"mpc.node_count"));
+}
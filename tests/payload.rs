@@ -0,0 +1,81 @@
+//! Round trips of the request payload between the current layout and the
+//! `distance_list` of the first release, deprecated in the current one.
+
+#![allow(deprecated)]
+
+mod common;
+
+use common::{carrier, spawn, timeout};
+use futures::future;
+use mpc_carrier::messages::compat::v0;
+use mpc_carrier::messages::{fixtures, NodeRequest};
+use mpc_carrier::transport::memory::MemoryNetwork;
+use prost::Message;
+
+#[test]
+fn old_request_decodes_as_payload() {
+    let old = v0::NodeRequest {
+        request_id: b"id".to_vec(),
+        distance_list: vec![1, 2, 3],
+    };
+    let new = NodeRequest::decode(old.encode_to_vec().as_slice()).unwrap();
+    assert_eq!(new.request_id, old.request_id);
+    assert_eq!(new.payload, old.distance_list);
+    assert_eq!(new.encode_to_vec(), old.encode_to_vec());
+}
+
+#[test]
+fn new_request_decodes_as_distance_list() {
+    let new = NodeRequest {
+        request_id: b"id".to_vec(),
+        payload: vec![1, 2, 3],
+        ..NodeRequest::default()
+    };
+    let old = v0::NodeRequest::decode(new.encode_to_vec().as_slice()).unwrap();
+    assert_eq!(old.request_id, new.request_id);
+    assert_eq!(old.distance_list, new.payload);
+}
+
+#[test]
+fn deprecated_distance_list_round_trips() {
+    let new = NodeRequest {
+        request_id: b"id".to_vec(),
+        distance_list: vec![1, 2, 3],
+        ..NodeRequest::default()
+    };
+    let decoded = NodeRequest::decode(new.encode_to_vec().as_slice()).unwrap();
+    assert_eq!(decoded, new);
+    assert!(decoded.payload.is_empty());
+}
+
+#[tokio::test]
+async fn deprecated_distance_list_delivered_as_payload() {
+    let network = MemoryNetwork::new();
+    let (carrier_a, _incoming, mut outgoing) = carrier(&["b"]);
+    spawn(carrier_a, network.transport("a"));
+    let (carrier_b, mut incoming, _outgoing) = carrier(&["a"]);
+    spawn(carrier_b, network.transport("b"));
+
+    for (payload, distance_list) in [(vec![], vec![1, 2, 3]), (vec![4, 5], vec![6])] {
+        let request = NodeRequest {
+            payload: payload.clone(),
+            distance_list: distance_list.clone(),
+            ..fixtures::node_request(1)
+        };
+        let send = outgoing.send("b", request.clone());
+        let answer = async {
+            let (_, callback) = incoming.recv().await.unwrap();
+            // The payload wins over the deprecated field.
+            let expected = if payload.is_empty() {
+                &distance_list
+            } else {
+                &payload
+            };
+            assert_eq!(&callback.message.payload, expected);
+            assert!(callback.message.distance_list.is_empty());
+            callback.respond(fixtures::node_response(&request)).unwrap();
+        };
+        let (response, ()) = timeout(future::join(send, answer)).await;
+        response.unwrap();
+    }
+}
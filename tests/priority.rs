@@ -0,0 +1,107 @@
+//! Requests scheduled by priority on both ends of a connection.
+
+mod common;
+
+use common::{carrier, client, server, spawn, timeout};
+use futures::{future, StreamExt};
+use mpc_carrier::hello::{Feature, HelloConfig, HelloMode};
+use mpc_carrier::messages::{fixtures, NodeRequest};
+use mpc_carrier::transport::memory::MemoryNetwork;
+use mpc_carrier::Carrier;
+use std::time::Duration;
+use tokio::time::sleep;
+
+/// Notifications queued ahead of the urgent request.
+const BULK: usize = 160;
+
+fn hello(carrier: Carrier, node_name: &str) -> Carrier {
+    carrier.hello(HelloConfig {
+        mode: HelloMode::Required,
+        node_name: node_name.to_string(),
+        features: vec![Feature::Notifications],
+    })
+}
+
+#[tokio::test]
+async fn urgent_request_overtakes_saturated_link() {
+    let network = MemoryNetwork::new();
+    let (carrier_a, _incoming, mut outgoing) = carrier(&["b"]);
+    spawn(hello(carrier_a, "a"), network.transport("a"));
+    let (carrier_b, mut incoming, _outgoing) = carrier(&["a"]);
+    spawn(hello(carrier_b, "b"), network.transport("b"));
+    let mut notifications = incoming.take_notifications().unwrap();
+
+    // Left unread, the notifications stall the link and queue up behind it.
+    for _ in 0..BULK {
+        timeout(outgoing.notify("b", vec![0; 32 << 10]))
+            .await
+            .unwrap();
+    }
+    let urgent = NodeRequest {
+        priority: 1,
+        ..fixtures::node_request(1)
+    };
+    let send = outgoing.send("b", urgent.clone());
+    let receive = async {
+        sleep(Duration::from_millis(100)).await;
+        let mut bulk_before = 0;
+        loop {
+            tokio::select! {
+                biased;
+                received = incoming.recv() => {
+                    let (_, callback) = received.unwrap();
+                    callback.respond(fixtures::node_response(&urgent)).unwrap();
+                    return bulk_before;
+                }
+                Some(_) = notifications.next() => bulk_before += 1,
+            }
+        }
+    };
+    let (response, bulk_before) = timeout(future::join(send, receive)).await;
+    response.unwrap();
+    // Only the notifications already written went first.
+    assert!(bulk_before < BULK / 2, "{bulk_before} notifications first");
+}
+
+#[tokio::test]
+async fn received_requests_handled_by_priority() {
+    let network = MemoryNetwork::new();
+    let (carrier, mut incoming, _outgoing) = server(&["a", "c", "d"]);
+    let handle = carrier.handle();
+    spawn(carrier, network.transport("b"));
+    let mut sends = Vec::new();
+    for (seed, (node, priority)) in [("a", 0), ("c", 2), ("d", 1)].into_iter().enumerate() {
+        let (carrier, _incoming, mut outgoing) = client(&["b"]);
+        spawn(carrier, network.transport(node));
+        let request = NodeRequest {
+            priority,
+            ..fixtures::node_request(seed as u64)
+        };
+        sends.push(async move { outgoing.send("b", request).await });
+    }
+    let sends = tokio::spawn(future::join_all(sends));
+
+    timeout(async {
+        let queued = || {
+            let state = handle.debug_state();
+            state
+                .nodes
+                .values()
+                .map(|node| node.incoming_queue)
+                .sum::<usize>()
+        };
+        while queued() < 3 {
+            sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await;
+    for (node, priority) in [("c", 2), ("d", 1), ("a", 0)] {
+        let (from, callback) = timeout(incoming.recv()).await.unwrap();
+        assert_eq!((from.as_str(), callback.message.priority), (node, priority));
+        let response = fixtures::node_response(&callback.message);
+        callback.respond(response).unwrap();
+    }
+    for response in timeout(sends).await.unwrap() {
+        response.unwrap();
+    }
+}
@@ -0,0 +1,108 @@
+//! Commit-reveal exchanges between the nodes of a cluster.
+
+mod common;
+
+use common::timeout;
+use mpc_carrier::channels::Incoming;
+use mpc_carrier::config::NodeId;
+use mpc_carrier::messages::{fixtures, NodeRequest, NodeResponse};
+use mpc_carrier::protocol::{CommitReveal, Error, Phase};
+use mpc_carrier::testing::Cluster;
+use mpc_carrier::Carrier;
+use std::collections::HashMap;
+use std::mem;
+use std::time::Duration;
+
+const SESSION: u64 = 7;
+
+/// Exchange of `outgoing`, as the results of its commit then its reveal.
+type Exchanged = (
+    Result<HashMap<NodeId, NodeResponse>, Error>,
+    Result<HashMap<NodeId, NodeResponse>, Error>,
+);
+
+/// Runs the exchange of node 0 of `cluster` in a task of its own, with the
+/// requests of `fixtures::node_request` 1 and 2.
+fn exchange(cluster: &mut Cluster) -> tokio::task::JoinHandle<Exchanged> {
+    let (_carrier, _incoming, spare) = Carrier::new(HashMap::new());
+    let mut outgoing = mem::replace(&mut cluster.node(0).outgoing, spare);
+    tokio::spawn(async move {
+        let mut exchange = CommitReveal::new(&mut outgoing, SESSION);
+        let commit = exchange.broadcast_commit(fixtures::node_request(1)).await;
+        let reveal = exchange.broadcast_reveal(fixtures::node_request(2)).await;
+        (commit, reveal)
+    })
+}
+
+/// Receives the next request of the session, and acknowledges it.
+async fn acknowledge(incoming: &mut Incoming) -> NodeRequest {
+    let (_, callback) = timeout(incoming.recv_session(SESSION)).await.unwrap();
+    let request = callback.message.clone();
+    callback.respond(fixtures::node_response(&request)).unwrap();
+    request
+}
+
+#[tokio::test]
+async fn phases_run_once_in_order() {
+    let (_carrier, _incoming, mut outgoing) = Carrier::new(HashMap::new());
+    let mut exchange = CommitReveal::new(&mut outgoing, SESSION);
+    let reveal = exchange.broadcast_reveal(fixtures::node_request(2)).await;
+    assert!(matches!(reveal, Err(Error::OutOfOrder(Phase::Reveal))));
+    // Without any node, the broadcasts complete at once.
+    assert!(exchange
+        .broadcast_commit(fixtures::node_request(1))
+        .await
+        .unwrap()
+        .is_empty());
+    let commit = exchange.broadcast_commit(fixtures::node_request(1)).await;
+    assert!(matches!(commit, Err(Error::OutOfOrder(Phase::Commit))));
+    exchange
+        .broadcast_reveal(fixtures::node_request(2))
+        .await
+        .unwrap();
+    let reveal = exchange.broadcast_reveal(fixtures::node_request(2)).await;
+    assert!(matches!(reveal, Err(Error::OutOfOrder(Phase::Reveal))));
+}
+
+#[tokio::test]
+async fn requests_stamped_with_session() {
+    let mut cluster = Cluster::start(3);
+    let exchanged = exchange(&mut cluster);
+    let names = [cluster.node(1).name.clone(), cluster.node(2).name.clone()];
+    for phase in 1..=2 {
+        for index in 1..=2 {
+            let request = acknowledge(&mut cluster.node(index).incoming).await;
+            assert_eq!(request.session_id, Some(SESSION));
+            assert_eq!(request.payload, fixtures::node_request(phase).payload);
+        }
+    }
+    let (commit, reveal) = timeout(exchanged).await.unwrap();
+    for responses in [commit.unwrap(), reveal.unwrap()] {
+        let mut nodes = responses.into_keys().collect::<Vec<_>>();
+        nodes.sort();
+        assert_eq!(nodes, names);
+    }
+}
+
+#[tokio::test]
+async fn reveal_refused_after_node_killed() {
+    let mut cluster = Cluster::start(3);
+    let exchanged = exchange(&mut cluster);
+    acknowledge(&mut cluster.node(1).incoming).await;
+    // Killed holding the commitment, unanswered.
+    let (_, callback) = timeout(cluster.node(2).incoming.recv_session(SESSION))
+        .await
+        .unwrap();
+    cluster.kill(2).await;
+    drop(callback);
+
+    let (commit, reveal) = timeout(exchanged).await.unwrap();
+    let killed = cluster.node(2).name.clone();
+    assert!(
+        matches!(&commit, Err(Error::Send { phase: Phase::Commit, node, .. }) if *node == killed),
+        "{commit:?}"
+    );
+    assert!(matches!(reveal, Err(Error::OutOfOrder(Phase::Reveal))));
+    let next = tokio::time::timeout(Duration::from_millis(100), cluster.node(1).incoming.recv());
+    assert!(next.await.is_err(), "secret revealed");
+}
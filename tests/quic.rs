@@ -0,0 +1,58 @@
+//! Exchanges over loopback QUIC.
+
+mod common;
+
+use common::pki::{addr, udp_port, Pki, HOST};
+use common::{respond, spawn, timeout};
+use mpc_carrier::config::Direction;
+use mpc_carrier::messages::fixtures;
+use mpc_carrier::transport::quic::{QuicConfig, QuicTransport};
+use mpc_carrier::Carrier;
+use std::net::{SocketAddr, UdpSocket};
+use std::sync::Arc;
+use std::time::Duration;
+
+const MESSAGES: u64 = 2000;
+
+#[tokio::test]
+async fn exchange_survives_migration() {
+    let pki = Pki::new();
+    let config = QuicConfig::default();
+    let port = udp_port();
+    // `a` dials `b` under the name `a.test`, identifying it to `b`.
+    let (carrier, incoming, _outgoing) = Carrier::with_addrs([("a.test", addr(1, "b.test"))]);
+    let carrier = carrier.direction("a.test", Direction::Accept);
+    let bind = SocketAddr::from(([127, 0, 0, 1], port));
+    let server_config = pki.server_config(&["a.test"]);
+    let transport = QuicTransport::new(bind, server_config, pki.client_config(), &config);
+    spawn(carrier, transport.unwrap());
+    respond(incoming);
+
+    let (carrier, _incoming, mut outgoing) = Carrier::with_addrs([("b", addr(port, "a.test"))]);
+    let carrier = carrier
+        .direction("b", Direction::Dial)
+        .skip_unused_listener(true);
+    let handle = carrier.handle();
+    let bind = SocketAddr::from(([127, 0, 0, 1], 0));
+    let server_config = pki.server_config(&["b.test"]);
+    let transport = QuicTransport::new(bind, server_config, pki.client_config(), &config);
+    let transport = Arc::new(transport.unwrap());
+    spawn(carrier, Arc::clone(&transport));
+
+    let mut age = Duration::ZERO;
+    for seed in 0..MESSAGES {
+        if seed == MESSAGES / 2 {
+            age = handle.debug_state().nodes["b"].connection_age.unwrap();
+            transport
+                .rebind(UdpSocket::bind((HOST, 0)).unwrap())
+                .unwrap();
+        }
+        let request = fixtures::node_request(seed);
+        let response = timeout(outgoing.send("b", request.clone())).await;
+        assert_eq!(response.unwrap().request_id, request.request_id);
+    }
+    // The connection migrated to the new socket instead of being replaced.
+    let state = handle.debug_state();
+    assert_eq!(state.nodes["b"].connections, 1);
+    assert!(state.nodes["b"].connection_age.unwrap() > age);
+}
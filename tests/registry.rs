@@ -0,0 +1,65 @@
+//! Re-pointing a node to a new address at runtime.
+
+mod common;
+
+use common::{respond, server, spawn, timeout};
+use mpc_carrier::channels::Outgoing;
+use mpc_carrier::config::Direction;
+use mpc_carrier::messages::fixtures;
+use mpc_carrier::transport::memory::MemoryNetwork;
+use mpc_carrier::transport::NodeAddr;
+use mpc_carrier::Carrier;
+use rustls::pki_types::ServerName;
+use std::time::Duration;
+use tokio::time::sleep;
+
+fn addr(host: &str) -> NodeAddr {
+    NodeAddr {
+        host: host.to_string(),
+        port: 1,
+        tls_name: ServerName::try_from("b").unwrap(),
+    }
+}
+
+async fn exchange(outgoing: &mut Outgoing, seed: u64) {
+    let request = fixtures::node_request(seed);
+    let response = timeout(outgoing.send("b", request.clone())).await;
+    assert_eq!(response.unwrap().request_id, request.request_id);
+}
+
+#[tokio::test]
+async fn traffic_follows_new_addr_after_reconnect() {
+    let network = MemoryNetwork::new();
+    let (carrier, incoming, _outgoing) = server(&["a"]);
+    let old = spawn(carrier, network.transport("b-old"));
+    respond(incoming);
+    let (carrier, incoming, _outgoing) = server(&["a"]);
+    let new = carrier.handle();
+    spawn(carrier, network.transport("b-new"));
+    respond(incoming);
+
+    let (carrier, _incoming, mut outgoing) = Carrier::with_addrs([("b", addr("b-old"))]);
+    let carrier = carrier
+        .direction("b", Direction::Dial)
+        .skip_unused_listener(true);
+    let handle = carrier.handle();
+    spawn(carrier, network.transport("a"));
+
+    exchange(&mut outgoing, 1).await;
+    // The established connection is kept.
+    handle.set_addr("b", addr("b-new")).await.unwrap();
+    exchange(&mut outgoing, 2).await;
+    assert_eq!(new.debug_state().nodes["a"].connections, 0);
+
+    // Stopping the old node breaks the connection, and the next one dials
+    // the new address.
+    old.abort();
+    timeout(async {
+        while handle.debug_state().nodes["b"].connections > 0 {
+            sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await;
+    exchange(&mut outgoing, 3).await;
+    assert_eq!(new.debug_state().nodes["a"].connections, 1);
+}
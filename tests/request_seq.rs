@@ -0,0 +1,77 @@
+//! Requests correlated by `request_seq` or by `request_id`.
+
+mod common;
+
+use common::{carrier, spawn, timeout};
+use mpc_carrier::channels::Outgoing;
+use mpc_carrier::hello::{Feature, HelloConfig, HelloMode};
+use mpc_carrier::messages::{NodeRequest, NodeResponse};
+use mpc_carrier::transport::memory::MemoryNetwork;
+use mpc_carrier::Carrier;
+
+fn hello(carrier: Carrier, node_name: &str, request_seq: bool) -> Carrier {
+    carrier.hello(HelloConfig {
+        mode: HelloMode::Required,
+        node_name: node_name.to_string(),
+        features: if request_seq {
+            vec![Feature::RequestSeq]
+        } else {
+            Vec::new()
+        },
+    })
+}
+
+/// Starts `a` and `b`, with `b` answering every request with its payload,
+/// and returns the outgoing channels of `a`.
+fn start(a_seq: bool, b_seq: bool) -> Outgoing {
+    let network = MemoryNetwork::new();
+    let (carrier_a, _incoming, outgoing) = carrier(&["b"]);
+    spawn(hello(carrier_a, "a", a_seq), network.transport("a"));
+    let (carrier_b, mut incoming, _outgoing) = carrier(&["a"]);
+    spawn(hello(carrier_b, "b", b_seq), network.transport("b"));
+    tokio::spawn(async move {
+        while let Some((_, callback)) = incoming.recv().await {
+            let response = NodeResponse {
+                request_id: callback.message.request_id.clone(),
+                payload: callback.message.payload.clone(),
+                ..NodeResponse::default()
+            };
+            let _ = callback.respond(response);
+        }
+    });
+    outgoing
+}
+
+async fn exchange(outgoing: &mut Outgoing, request_id: &[u8]) {
+    for seed in 0..10_u8 {
+        let request = NodeRequest {
+            request_id: request_id.iter().chain([&seed]).copied().collect(),
+            payload: vec![seed],
+            ..NodeRequest::default()
+        };
+        let response = timeout(outgoing.send("b", request)).await.unwrap();
+        assert_eq!(response.payload, [seed]);
+    }
+}
+
+#[tokio::test]
+async fn seq_peers_correlate_without_request_id() {
+    let mut outgoing = start(true, true);
+    // Requests with an empty `request_id`, correlated by their `request_seq`
+    // alone.
+    for _ in 0..2 {
+        let request = NodeRequest {
+            payload: vec![1],
+            ..NodeRequest::default()
+        };
+        let response = timeout(outgoing.send("b", request)).await.unwrap();
+        assert_eq!(response.payload, [1]);
+    }
+    exchange(&mut outgoing, b"id").await;
+}
+
+#[tokio::test]
+async fn seq_peer_interoperates_with_bytes_peer() {
+    exchange(&mut start(true, false), b"id").await;
+    exchange(&mut start(false, true), b"id").await;
+}
@@ -0,0 +1,140 @@
+//! Typed dispatch of the incoming requests with a `Router`.
+
+mod common;
+
+use common::{client, server, spawn, timeout};
+use mpc_carrier::channels::{Outgoing, SendError};
+use mpc_carrier::messages::{fixtures, NodeRequest, NodeResponse};
+use mpc_carrier::router::{self, HandlerError, Router};
+use mpc_carrier::status;
+use mpc_carrier::transport::memory::MemoryNetwork;
+
+#[derive(Clone, PartialEq, prost::Message)]
+struct KeygenRequest {
+    #[prost(uint32, tag = "1")]
+    parties: u32,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+struct KeygenResponse {
+    #[prost(bytes = "vec", tag = "1")]
+    public_key: Vec<u8>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+struct SignRequest {
+    #[prost(bytes = "vec", tag = "1")]
+    message: Vec<u8>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+struct SignResponse {
+    #[prost(bytes = "vec", tag = "1")]
+    signature: Vec<u8>,
+}
+
+impl prost::Name for KeygenRequest {
+    const NAME: &'static str = "KeygenRequest";
+    const PACKAGE: &'static str = "test";
+}
+
+impl prost::Name for SignRequest {
+    const NAME: &'static str = "SignRequest";
+    const PACKAGE: &'static str = "test";
+}
+
+/// Starts `a` sending to `b`, which serves a keygen and a signing handler.
+fn start() -> Outgoing {
+    let network = MemoryNetwork::new();
+    let (carrier, incoming, _outgoing) = server(&["a"]);
+    let router = Router::new()
+        .handler_for(|node, request: KeygenRequest| async move {
+            assert_eq!(node, "a");
+            Ok(KeygenResponse {
+                public_key: vec![0x42; request.parties as usize],
+            })
+        })
+        .handler_for(|_, request: SignRequest| async move {
+            match request.message.as_slice() {
+                [] => Err(HandlerError::new(
+                    status::FIRST_APPLICATION,
+                    "empty message",
+                )),
+                b"panic" => panic!("signing failed"),
+                message => Ok(SignResponse {
+                    signature: message.iter().rev().copied().collect(),
+                }),
+            }
+        });
+    tokio::spawn(router.run(incoming));
+    spawn(carrier, network.transport("b"));
+    let (carrier, _incoming, outgoing) = client(&["b"]);
+    spawn(carrier, network.transport("a"));
+    outgoing
+}
+
+async fn send(outgoing: &mut Outgoing, request: NodeRequest) -> Result<NodeResponse, SendError> {
+    let request = NodeRequest {
+        request_id: fixtures::node_request(1).request_id,
+        ..request
+    };
+    timeout(outgoing.send("b", request)).await
+}
+
+fn assert_status(result: Result<NodeResponse, SendError>, expected: u32) {
+    assert!(
+        matches!(result, Err(SendError::Remote { status, .. }) if status == expected),
+        "{result:?}"
+    );
+}
+
+#[tokio::test]
+async fn typed_handlers_answer() {
+    let mut outgoing = start();
+    let response = send(
+        &mut outgoing,
+        router::request(&KeygenRequest { parties: 3 }),
+    )
+    .await;
+    let response = router::response::<KeygenResponse>(&response.unwrap()).unwrap();
+    assert_eq!(response.public_key, [0x42; 3]);
+    let request = router::request(&SignRequest {
+        message: b"abc".to_vec(),
+    });
+    let response = send(&mut outgoing, request).await;
+    let response = router::response::<SignResponse>(&response.unwrap()).unwrap();
+    assert_eq!(response.signature, b"cba");
+}
+
+#[tokio::test]
+async fn failures_answered_with_status() {
+    let mut outgoing = start();
+    let malformed = NodeRequest {
+        payload: vec![0xff],
+        ..router::request(&KeygenRequest::default())
+    };
+    assert_status(send(&mut outgoing, malformed).await, status::DECODE_FAILED);
+    let empty = router::request(&SignRequest::default());
+    let result = send(&mut outgoing, empty).await;
+    assert!(matches!(
+        result,
+        Err(SendError::Remote { status, ref detail })
+            if status == status::FIRST_APPLICATION && detail == "empty message"
+    ));
+    let panicking = router::request(&SignRequest {
+        message: b"panic".to_vec(),
+    });
+    assert_status(
+        send(&mut outgoing, panicking).await,
+        status::HANDLER_PANICKED,
+    );
+    let unrouted = fixtures::node_request(2);
+    assert_status(send(&mut outgoing, unrouted).await, status::UNSUPPORTED);
+    // The handlers keep serving.
+    let response = send(
+        &mut outgoing,
+        router::request(&KeygenRequest { parties: 1 }),
+    )
+    .await;
+    assert!(response.is_ok());
+}
@@ -0,0 +1,75 @@
+//! Estimation of the round-trip time of the network to the nodes.
+
+mod common;
+
+use common::{client, respond, server, spawn, timeout};
+use mpc_carrier::hello::{Feature, HelloConfig, HelloMode};
+use mpc_carrier::messages::fixtures;
+use mpc_carrier::testing::faults::{FaultPlan, FaultyTransport};
+use mpc_carrier::transport::memory::MemoryNetwork;
+use mpc_carrier::{Carrier, Error};
+use std::time::Duration;
+use tokio::task::JoinHandle;
+use tokio::time::sleep;
+
+/// Latency injected in the writes of `a`.
+const LATENCY: Duration = Duration::from_millis(40);
+
+fn hello(carrier: Carrier, node_name: &str) -> Carrier {
+    carrier.hello(HelloConfig {
+        mode: HelloMode::Required,
+        node_name: node_name.to_string(),
+        features: vec![Feature::Envelope],
+    })
+}
+
+fn start_b(network: &MemoryNetwork) -> JoinHandle<Result<(), Error>> {
+    let (carrier, incoming, _outgoing) = server(&["a"]);
+    let task = spawn(hello(carrier, "b"), network.transport("b"));
+    respond(incoming);
+    task
+}
+
+#[tokio::test]
+async fn estimate_converges_on_injected_latency() {
+    let network = MemoryNetwork::new();
+    let (carrier, _incoming, mut outgoing) = client(&["b"]);
+    let handle = carrier.handle();
+    let transport = FaultyTransport::new(network.transport("a")).dialed(FaultPlan::slow(LATENCY));
+    spawn(hello(carrier, "a"), transport);
+    let b = start_b(&network);
+    assert_eq!(handle.rtt("b"), None);
+
+    for seed in 0..20 {
+        timeout(outgoing.send("b", fixtures::node_request(seed)))
+            .await
+            .unwrap();
+    }
+    let estimate = handle.rtt("b").unwrap();
+    assert!(estimate.samples >= 20, "{estimate:?}");
+    assert!(
+        estimate.smoothed >= LATENCY && estimate.smoothed < LATENCY * 3 / 2,
+        "{estimate:?}"
+    );
+    assert!(estimate.var < LATENCY / 2, "{estimate:?}");
+    assert!(!estimate.stale);
+    assert_eq!(handle.debug_state().nodes["b"].network_rtt, Some(estimate));
+    assert_eq!(handle.rtt("c"), None);
+
+    // The samples of the previous connection are forgotten, the reconnection
+    // left without latency.
+    b.abort();
+    timeout(async {
+        while handle.debug_state().nodes["b"].connections > 0 {
+            sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await;
+    start_b(&network);
+    timeout(outgoing.send("b", fixtures::node_request(20)))
+        .await
+        .unwrap();
+    let estimate = handle.rtt("b").unwrap();
+    assert!(estimate.samples <= 2, "{estimate:?}");
+    assert!(estimate.smoothed < LATENCY / 2, "{estimate:?}");
+}
@@ -0,0 +1,95 @@
+//! Start and stop of a carrier, with its tasks named for tokio-console when
+//! built with `--cfg tokio_unstable`.
+
+mod common;
+
+use common::pki::{tcp_port, Pki, HOST};
+use common::{client, respond, server, spawn, timeout};
+use futures::channel::oneshot;
+use mpc_carrier::messages::fixtures;
+use mpc_carrier::tls::TlsMode;
+use mpc_carrier::transport::memory::MemoryNetwork;
+use std::fs;
+use std::time::{Duration, Instant};
+use tokio::time::sleep;
+
+#[tokio::test]
+async fn starts_and_stops() {
+    let network = MemoryNetwork::new();
+    let (mut carrier, incoming, _outgoing) = server(&["a"]);
+    let handle = carrier.handle();
+    let (stop, stopped) = oneshot::channel::<()>();
+    let transport = network.transport("b");
+    let run = tokio::spawn(async move {
+        carrier
+            .run_with_transport_until_shutdown(transport, stopped)
+            .await
+    });
+    respond(incoming);
+    let (mut peer, _incoming, mut outgoing) = client(&["b"]);
+    let transport = network.transport("a");
+    tokio::spawn(async move { peer.run_with_transport(transport).await });
+
+    let request = fixtures::node_request(1);
+    timeout(outgoing.send("b", request)).await.unwrap();
+    assert!(handle.is_running());
+    stop.send(()).unwrap();
+    timeout(run).await.unwrap().unwrap();
+    assert!(!handle.is_running());
+}
+
+#[tokio::test]
+async fn stops_on_shutdown_future() {
+    let pki = Pki::new();
+    let (cert, key) = pki.issue_pem(&["b.test"]);
+    let dir = std::env::temp_dir().join(format!("mpc-carrier-{}-run", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let (cert_chain, cert_priv_key) = (dir.join("b.pem"), dir.join("b.key"));
+    fs::write(&cert_chain, cert).unwrap();
+    fs::write(&cert_priv_key, key).unwrap();
+    let (mut carrier, _incoming, _outgoing) = server(&["a"]);
+    let handle = carrier.handle();
+
+    let started = Instant::now();
+    let tls_mode = TlsMode::Required {
+        cert_chain,
+        cert_priv_key,
+    };
+    let shutdown = sleep(Duration::from_millis(100));
+    let run = carrier.run_until_shutdown(HOST, tcp_port(), tls_mode, shutdown);
+    timeout(run).await.unwrap();
+    assert!(started.elapsed() >= Duration::from_millis(100));
+    assert!(!handle.is_running());
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn shutdown_future_drains_requests_in_flight() {
+    let network = MemoryNetwork::new();
+    let (carrier_b, mut incoming, _outgoing) = server(&["a"]);
+    spawn(carrier_b, network.transport("b"));
+    tokio::spawn(async move {
+        while let Some((_, callback)) = incoming.recv().await {
+            sleep(Duration::from_millis(300)).await;
+            let response = fixtures::node_response(&callback.message);
+            let _ = callback.respond(response);
+        }
+    });
+    let (mut carrier, _incoming, mut outgoing) = client(&["b"]);
+    let transport = network.transport("a");
+    let run = tokio::spawn(async move {
+        let shutdown = sleep(Duration::from_millis(100));
+        carrier
+            .run_with_transport_until_shutdown(transport, shutdown)
+            .await
+    });
+
+    // Answered after the shutdown started.
+    let started = Instant::now();
+    let request = fixtures::node_request(1);
+    let response = timeout(outgoing.send("b", request.clone())).await;
+    assert_eq!(response.unwrap().request_id, request.request_id);
+    assert!(started.elapsed() >= Duration::from_millis(300));
+    timeout(run).await.unwrap().unwrap();
+    assert!(outgoing.send("b", fixtures::node_request(2)).await.is_err());
+}
@@ -0,0 +1,78 @@
+//! Requests routed by service to channels of their own.
+
+mod common;
+
+use common::{client, server, spawn, timeout};
+use mpc_carrier::channels::{SendError, ServiceIncoming};
+use mpc_carrier::messages::fixtures;
+use mpc_carrier::status;
+use mpc_carrier::transport::memory::MemoryNetwork;
+use tokio::task::JoinHandle;
+
+/// Answers the requests to the service of `incoming`, echoing the service in
+/// the payload of the response.
+fn respond(mut incoming: ServiceIncoming) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        while let Some((_, callback)) = incoming.recv().await {
+            assert_eq!(callback.message.service, incoming.service());
+            let mut response = fixtures::node_response(&callback.message);
+            response.payload = incoming.service().as_bytes().to_vec();
+            let _ = callback.respond(response);
+        }
+    })
+}
+
+#[tokio::test]
+async fn interleaved_services_reach_their_channels() {
+    let network = MemoryNetwork::new();
+    let (carrier, incoming, _outgoing) = server(&["a"]);
+    respond(incoming.route("keygen"));
+    respond(incoming.route("signing"));
+    spawn(carrier, network.transport("b"));
+    let (carrier, _incoming, mut outgoing) = client(&["b"]);
+    spawn(carrier, network.transport("a"));
+
+    for seed in 0..30 {
+        let service = ["keygen", "signing", "health"][seed % 3];
+        let request = fixtures::node_request(seed as u64);
+        let result = timeout(outgoing.send_service("b", service, request.clone())).await;
+        if service == "health" {
+            assert!(matches!(
+                result,
+                Err(SendError::Remote { status, .. }) if status == status::UNSUPPORTED
+            ));
+        } else {
+            let response = result.unwrap();
+            assert_eq!(response.request_id, request.request_id);
+            assert_eq!(response.payload, service.as_bytes());
+        }
+    }
+}
+
+#[tokio::test]
+async fn slow_service_does_not_block_others() {
+    let network = MemoryNetwork::new();
+    let (carrier, incoming, _outgoing) = server(&["a", "c"]);
+    respond(incoming.route("keygen"));
+    let mut signing = incoming.route("signing");
+    spawn(carrier, network.transport("b"));
+    let (carrier, _incoming, mut keygen) = client(&["b"]);
+    spawn(carrier, network.transport("a"));
+    let (carrier, _incoming, mut signer) = client(&["b"]);
+    spawn(carrier, network.transport("c"));
+
+    let request = fixtures::node_request(0);
+    let signed = tokio::spawn(async move { signer.send_service("b", "signing", request).await });
+    // The signing request is held while the keygen ones are answered.
+    let (node, callback) = timeout(signing.recv()).await.unwrap();
+    assert_eq!(node, "c");
+    for seed in 1..10 {
+        let request = fixtures::node_request(seed);
+        let response = timeout(keygen.send_service("b", "keygen", request.clone())).await;
+        assert_eq!(response.unwrap().request_id, request.request_id);
+    }
+    assert!(!signed.is_finished());
+    let response = fixtures::node_response(&callback.message);
+    callback.respond(response).unwrap();
+    timeout(signed).await.unwrap().unwrap();
+}
@@ -0,0 +1,69 @@
+//! Two carriers behind one TLS listener, routed by SNI.
+
+mod common;
+
+use common::pki::{addr, tcp_port, Pki, HOST};
+use common::{spawn, timeout};
+use futures::future;
+use mpc_carrier::config::Direction;
+use mpc_carrier::messages::fixtures;
+use mpc_carrier::transport::shared::SharedListener;
+use mpc_carrier::transport::TlsTcpTransport;
+use mpc_carrier::Carrier;
+use rustls::pki_types::ServerName;
+use tokio::io::AsyncReadExt;
+use tokio::net::TcpStream;
+use tokio_rustls::TlsConnector;
+
+#[tokio::test]
+async fn routes_by_sni_and_rejects_unknown_name() {
+    let pki = Pki::new();
+    let port = tcp_port();
+    let server_config = pki.server_config(&["x.test", "y.test", "z.test"]);
+    let listener = SharedListener::new(HOST, port, server_config);
+    let shared = listener.clone();
+    tokio::spawn(async move { shared.run().await });
+
+    let mut carriers = Vec::new();
+    for name in ["x", "y"] {
+        // Each carrier accepts its client, identified by the name it dials.
+        let client = format!("{name}.test");
+        let (carrier, incoming, _outgoing) =
+            Carrier::with_addrs([(client.as_str(), addr(1, name))]);
+        let carrier = carrier.direction(client.as_str(), Direction::Accept);
+        spawn(
+            carrier,
+            listener.transport([client.as_str()], pki.client_config()),
+        );
+        let (carrier, _incoming, outgoing) = Carrier::with_addrs([(name, addr(port, &client))]);
+        let carrier = carrier
+            .direction(name, Direction::Dial)
+            .skip_unused_listener(true);
+        let transport = TlsTcpTransport::new(HOST, 0, pki.server_config(&[]), pki.client_config());
+        spawn(carrier, transport);
+        carriers.push((name, client, incoming, outgoing));
+    }
+
+    for (seed, (name, client, incoming, outgoing)) in carriers.iter_mut().enumerate() {
+        let request = fixtures::node_request(seed as u64);
+        let send = outgoing.send(*name, request.clone());
+        let answer = async {
+            let (node, callback) = incoming.recv().await.unwrap();
+            assert_eq!(node, client.as_str());
+            assert_eq!(callback.message, request);
+            callback.respond(fixtures::node_response(&request)).unwrap();
+        };
+        let (response, ()) = timeout(future::join(send, answer)).await;
+        assert_eq!(response.unwrap().request_id, request.request_id);
+    }
+
+    // The handshake of an unclaimed name completes, then the connection is
+    // closed.
+    let connector = TlsConnector::from(pki.client_config());
+    let stream = TcpStream::connect((HOST, port)).await.unwrap();
+    let name = ServerName::try_from("z.test").unwrap();
+    let mut stream = timeout(connector.connect(name, stream)).await.unwrap();
+    let mut buf = [0; 1];
+    let read = timeout(stream.read(&mut buf)).await;
+    assert!(matches!(read, Ok(0) | Err(_)), "{read:?}");
+}
@@ -0,0 +1,114 @@
+//! Reporting of the requests in flight for longer than the threshold.
+
+mod common;
+
+use common::{carrier, spawn, timeout, Logs};
+use mpc_carrier::lifecycle::{CarrierEvent, SlowOutcome};
+use mpc_carrier::messages::{fixtures, NodeRequest};
+use mpc_carrier::middleware::Direction;
+use mpc_carrier::transport::memory::MemoryNetwork;
+use std::time::Duration;
+use tokio::sync::mpsc::Receiver;
+use tokio::time::sleep;
+
+const THRESHOLD: Duration = Duration::from_millis(100);
+/// Delay of the handler of `b` for a request with a payload of 1.
+const DELAY: Duration = Duration::from_millis(500);
+
+/// Receives the slow request events, skipping the others.
+async fn next_slow(events: &mut Receiver<CarrierEvent>) -> CarrierEvent {
+    loop {
+        let event = timeout(events.recv()).await.unwrap();
+        if let CarrierEvent::SlowRequest { .. } | CarrierEvent::SlowRequestFinished { .. } = event {
+            return event;
+        }
+    }
+}
+
+fn request(seed: u64, delayed: bool) -> NodeRequest {
+    NodeRequest {
+        payload: vec![u8::from(delayed)],
+        ..fixtures::node_request(seed)
+    }
+}
+
+/// Checks that `event` reports a slow request with `node` in `direction`, and
+/// returns the hash of its id.
+fn slow(event: CarrierEvent, expected: (&str, Direction)) -> u64 {
+    let CarrierEvent::SlowRequest {
+        node,
+        direction,
+        age,
+        request_id_hash,
+        ..
+    } = event
+    else {
+        panic!("not a slow request: {event:?}");
+    };
+    assert_eq!((node.as_str(), direction), expected);
+    assert!(age >= THRESHOLD && age < DELAY, "{age:?}");
+    request_id_hash
+}
+
+/// Checks that `event` reports the completion of the slow request `hash`.
+fn finished(event: CarrierEvent, hash: u64) {
+    let CarrierEvent::SlowRequestFinished {
+        latency,
+        request_id_hash,
+        outcome,
+        ..
+    } = event
+    else {
+        panic!("not a finished slow request: {event:?}");
+    };
+    assert_eq!((request_id_hash, outcome), (hash, SlowOutcome::Completed));
+    assert!(latency >= DELAY, "{latency:?}");
+}
+
+#[tokio::test]
+async fn slow_handler_reported_both_ways() {
+    let (logs, _subscriber) = Logs::capture();
+    let network = MemoryNetwork::new();
+    let (carrier_a, _incoming, mut outgoing) = carrier(&["b"]);
+    let mut carrier_a = carrier_a.slow_request_threshold(THRESHOLD);
+    let mut events_a = carrier_a.events();
+    spawn(carrier_a, network.transport("a"));
+    let (carrier_b, mut incoming, _outgoing) = carrier(&["a"]);
+    let mut carrier_b = carrier_b.slow_request_threshold(THRESHOLD);
+    let mut events_b = carrier_b.events();
+    spawn(carrier_b, network.transport("b"));
+    tokio::spawn(async move {
+        while let Some((_, callback)) = incoming.recv().await {
+            if callback.message.payload == [1] {
+                sleep(DELAY).await;
+            }
+            let response = fixtures::node_response(&callback.message);
+            let _ = callback.respond(response);
+        }
+    });
+
+    // Requests within the threshold go unreported.
+    for seed in 0..3 {
+        timeout(outgoing.send("b", request(seed, false)))
+            .await
+            .unwrap();
+    }
+    timeout(outgoing.send("b", request(3, true))).await.unwrap();
+
+    let hash = slow(next_slow(&mut events_a).await, ("b", Direction::Outgoing));
+    finished(next_slow(&mut events_a).await, hash);
+    let hash_b = slow(next_slow(&mut events_b).await, ("a", Direction::Incoming));
+    assert_eq!(hash_b, hash);
+    finished(next_slow(&mut events_b).await, hash);
+
+    assert_eq!(logs.count("Slow request in flight"), 2);
+    let warning = logs.find("Slow request in flight").unwrap();
+    assert!(warning.contains("WARN"), "{warning}");
+    assert!(
+        warning.contains(&format!("request_id_hash={hash:x}")),
+        "{warning}"
+    );
+    assert_eq!(logs.count("Slow request finished"), 2);
+    let follow_up = logs.find("Slow request finished").unwrap();
+    assert!(follow_up.contains("outcome="), "{follow_up}");
+}
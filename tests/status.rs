@@ -0,0 +1,78 @@
+//! Status codes of the responses.
+
+mod common;
+
+use common::{carrier, spawn, timeout};
+use mpc_carrier::channels::SendError;
+use mpc_carrier::messages::compat::v0;
+use mpc_carrier::messages::{fixtures, NodeRequest, NodeResponse};
+use mpc_carrier::status;
+use mpc_carrier::transport::memory::MemoryNetwork;
+use prost::Message;
+
+/// Returns a request answered according to `payload` modulo 3: dropped, with
+/// an application status, or successfully.
+fn request(payload: u8) -> NodeRequest {
+    NodeRequest {
+        payload: vec![payload],
+        ..fixtures::node_request(payload.into())
+    }
+}
+
+#[tokio::test]
+async fn statuses_reach_sender() {
+    let network = MemoryNetwork::new();
+    let (carrier_a, _incoming, mut outgoing) = carrier(&["b"]);
+    spawn(carrier_a, network.transport("a"));
+    let (carrier_b, mut incoming, _outgoing) = carrier(&["a"]);
+    spawn(carrier_b, network.transport("b"));
+    tokio::spawn(async move {
+        while let Some((_, callback)) = incoming.recv().await {
+            match callback.message.payload[0] % 3 {
+                0 => drop(callback),
+                1 => callback.respond_err(101, "no such key").unwrap(),
+                _ => {
+                    let response = fixtures::node_response(&callback.message);
+                    callback.respond(response).unwrap();
+                }
+            }
+        }
+    });
+
+    let result = timeout(outgoing.send("b", request(0))).await;
+    let dropped = status::HANDLER_DROPPED;
+    assert!(matches!(result, Err(SendError::Remote { status, .. }) if status == dropped));
+    let result = timeout(outgoing.send("b", request(1))).await;
+    assert!(matches!(
+        result,
+        Err(SendError::Remote { status: 101, ref detail }) if detail == "no such key"
+    ));
+    assert_eq!(
+        timeout(outgoing.send("b", request(2)))
+            .await
+            .unwrap()
+            .status,
+        status::OK
+    );
+
+    // Raw responses are returned whatever their status.
+    outgoing.set_raw_responses(true);
+    let response = timeout(outgoing.send("b", request(4))).await;
+    assert_eq!(response.unwrap().status, 101);
+}
+
+#[test]
+fn old_peer_reads_failed_response() {
+    let response = NodeResponse {
+        request_id: b"id".to_vec(),
+        status: status::OVERLOADED,
+        error_detail: "request shed under load".to_string(),
+        ..NodeResponse::default()
+    };
+    let old = v0::NodeResponse::decode(response.encode_to_vec().as_slice()).unwrap();
+    assert_eq!(old.request_id, response.request_id);
+    // Without the fields, an old peer reads a success from the new one.
+    let new = NodeResponse::decode(old.encode_to_vec().as_slice()).unwrap();
+    assert_eq!(new.status, status::OK);
+    assert!(new.error_detail.is_empty());
+}
@@ -0,0 +1,78 @@
+//! Periodic status summary in the log, over the paused clock of the runtime.
+
+mod common;
+
+use common::{client, respond, server, spawn, Logs};
+use mpc_carrier::channels::Outgoing;
+use mpc_carrier::messages::fixtures;
+use mpc_carrier::transport::memory::MemoryNetwork;
+use std::ops::Range;
+use std::time::Duration;
+use tokio::time::sleep;
+
+/// Interval of the summaries, within the one of the round-trip probes for the
+/// frames of the second summary to be the requests only.
+const INTERVAL: Duration = Duration::from_secs(4);
+
+async fn send(outgoing: &mut Outgoing, seeds: Range<u64>) {
+    for seed in seeds {
+        outgoing
+            .send("b", fixtures::node_request(seed))
+            .await
+            .unwrap();
+    }
+}
+
+/// Returns the value of `key` in the summary `line`.
+fn field(line: &str, key: &str) -> u64 {
+    let (_, rest) = line
+        .split_once(&format!(" {key}="))
+        .unwrap_or_else(|| panic!("no {key} in {line}"));
+    rest.split(' ').next().unwrap().parse().unwrap()
+}
+
+#[tokio::test(start_paused = true)]
+async fn consecutive_summaries_count_frames_since_previous() {
+    let (logs, _subscriber) = Logs::capture();
+    let network = MemoryNetwork::new();
+    let (carrier_b, incoming, _outgoing) = server(&["a"]);
+    spawn(carrier_b, network.transport("b"));
+    respond(incoming);
+    // `c` never comes up.
+    let (carrier_a, _incoming, mut outgoing) = client(&["b", "c"]);
+    spawn(carrier_a.status_summary(INTERVAL), network.transport("a"));
+
+    // Half an interval past every summary.
+    send(&mut outgoing, 0..3).await;
+    sleep(INTERVAL * 3 / 2).await;
+    send(&mut outgoing, 3..5).await;
+    sleep(INTERVAL).await;
+
+    let b = logs.lines("Status node=b");
+    assert_eq!(b.len(), 2, "{b:?}");
+    assert!(
+        b[0].contains(" connected=yes connection=Connected "),
+        "{}",
+        b[0]
+    );
+    assert!(field(&b[0], "frames_sent") >= 3, "{}", b[0]);
+    assert!(field(&b[0], "frames_received") >= 3, "{}", b[0]);
+    assert!(
+        b[1].ends_with(" inflight=0 frames_sent=2 frames_received=2 errors=0"),
+        "{}",
+        b[1]
+    );
+    let c = logs.lines("Status node=c");
+    assert!(c[1].contains(" connected=no "), "{}", c[1]);
+    assert_eq!(field(&c[1], "frames_sent"), 0);
+    assert!(field(&c[1], "errors") > field(&c[0], "errors"), "{c:?}");
+    let totals = logs.lines("Status nodes=");
+    assert_eq!(totals.len(), 2, "{totals:?}");
+    assert!(
+        totals[1].contains("Status nodes=2 connected=1 "),
+        "{}",
+        totals[1]
+    );
+    assert_eq!(field(&totals[1], "frames_sent"), 2);
+    assert_eq!(field(&totals[1], "errors"), field(&c[1], "errors"));
+}
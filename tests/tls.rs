@@ -0,0 +1,264 @@
+//! TLS initialization from certificate files.
+
+mod common;
+
+use rcgen::{CertificateParams, KeyPair};
+use std::fs;
+use std::path::PathBuf;
+use std::time::SystemTime;
+
+/// Writes a self-signed certificate for `name`, valid until `not_after`, and
+/// its key into a directory of the test, returning the paths of the files.
+fn write_cert(test: &str, name: &str, not_after: SystemTime) -> (PathBuf, PathBuf) {
+    let dir = std::env::temp_dir().join(format!("mpc-carrier-{}-{test}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let key_pair = KeyPair::generate().unwrap();
+    let mut params = CertificateParams::new(vec![name.to_string()]).unwrap();
+    params.not_after = not_after.into();
+    let cert = params.self_signed(&key_pair).unwrap();
+    let (cert_path, key_path) = (
+        dir.join(format!("{name}.pem")),
+        dir.join(format!("{name}.key")),
+    );
+    fs::write(&cert_path, cert.pem()).unwrap();
+    fs::write(&key_path, key_pair.serialize_pem()).unwrap();
+    (cert_path, key_path)
+}
+
+#[cfg(feature = "cert-expiry-check")]
+mod expiry {
+    use super::common::pki::Pki;
+    use super::common::Logs;
+    use super::write_cert;
+    use mpc_carrier::tls::{self, Error};
+    use std::fs;
+    use std::time::{Duration, SystemTime};
+
+    const DAY: Duration = Duration::from_secs(24 * 60 * 60);
+
+    #[test]
+    fn expiring_certificate_warns() {
+        let (logs, _subscriber) = Logs::capture();
+        let (cert, key) = write_cert("expiring", "a.test", SystemTime::now() + DAY);
+        tls::init(&cert, &key).unwrap();
+        let line = logs.find("Certificate").expect("no expiry warning");
+        assert!(line.contains("WARN"), "{line}");
+        assert!(line.contains("(0 days remaining)"), "{line}");
+    }
+
+    #[test]
+    fn valid_certificate_is_quiet() {
+        let (logs, _subscriber) = Logs::capture();
+        let (cert, key) = write_cert("valid", "a.test", SystemTime::now() + 365 * DAY);
+        tls::init(&cert, &key).unwrap();
+        assert_eq!(logs.find("Certificate"), None);
+    }
+
+    #[test]
+    fn expired_certificate_fails() {
+        let (logs, _subscriber) = Logs::capture();
+        let (cert, key) = write_cert("expired", "a.test", SystemTime::now() - DAY);
+        let err = tls::init(&cert, &key).unwrap_err();
+        assert!(matches!(err, Error::CertificateExpired { .. }), "{err}");
+        let line = logs.find("expired at").expect("no expiry error");
+        assert!(line.contains("ERROR"), "{line}");
+    }
+
+    #[test]
+    fn private_ca_certificate_verified() {
+        let pki = Pki::new();
+        let dir = std::env::temp_dir().join(format!("mpc-carrier-{}-ca", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let (cert, key) = pki.issue_pem(&["a.test"]);
+        let (cert_path, key_path) = (dir.join("a.pem"), dir.join("a.key"));
+        fs::write(&cert_path, cert).unwrap();
+        fs::write(&key_path, key).unwrap();
+
+        let info = tls::verify_cert(&cert_path, &key_path, &["a.test"]).unwrap();
+        assert_eq!(info.sans, ["DNSName(a.test)"]);
+        tls::verify_chain(&cert_path, &pki.roots()).unwrap();
+        let err = tls::verify_chain(&cert_path, &Pki::new().roots()).unwrap_err();
+        assert!(matches!(err, Error::CertUntrusted(_)), "{err}");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
+
+mod versions {
+    use super::common::pki::Pki;
+    use futures::future;
+    use mpc_carrier::tls::{self, TlsConfig, TlsVersion};
+    use rustls::pki_types::ServerName;
+    use rustls::version::{TLS12, TLS13};
+    use rustls::{ClientConfig, ServerConfig, SupportedProtocolVersion};
+    use std::fs;
+    use std::sync::Arc;
+    use tokio::io::duplex;
+    use tokio_rustls::{TlsAcceptor, TlsConnector};
+
+    /// Initializes the configurations of `a.test` from files, limited to
+    /// `version`, with the CA of the certificate.
+    fn init(version: TlsVersion) -> (Arc<ServerConfig>, Pki) {
+        let pki = Pki::new();
+        let dir =
+            std::env::temp_dir().join(format!("mpc-carrier-{}-{version:?}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let (cert, key) = pki.issue_pem(&["a.test"]);
+        let (cert_path, key_path) = (dir.join("cert.pem"), dir.join("key.pem"));
+        fs::write(&cert_path, cert).unwrap();
+        fs::write(&key_path, key).unwrap();
+        let (server_config, _) =
+            tls::init_with_config(&cert_path, &key_path, TlsConfig { version }).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        (server_config, pki)
+    }
+
+    /// Runs a handshake with `server_config` from a client limited to
+    /// `versions`, returning the outcome on the server side.
+    async fn handshake(
+        server_config: Arc<ServerConfig>,
+        pki: &Pki,
+        versions: &[&'static SupportedProtocolVersion],
+    ) -> Result<(), std::io::Error> {
+        let client_config = ClientConfig::builder_with_protocol_versions(versions)
+            .with_root_certificates(pki.roots())
+            .with_no_client_auth();
+        let connector = TlsConnector::from(Arc::new(client_config));
+        let (client, server) = duplex(64 * 1024);
+        let name = ServerName::try_from("a.test").unwrap();
+        let (_client, server) = future::join(
+            connector.connect(name, client),
+            TlsAcceptor::from(server_config).accept(server),
+        )
+        .await;
+        server.map(drop)
+    }
+
+    #[tokio::test]
+    async fn tls13_only_refuses_tls12_clients() {
+        let (server_config, pki) = init(TlsVersion::Tls13Only);
+        let err = handshake(Arc::clone(&server_config), &pki, &[&TLS12])
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("incompatible"), "{err}");
+        handshake(server_config, &pki, &[&TLS13]).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn default_accepts_tls12_clients() {
+        let (server_config, pki) = init(TlsVersion::default());
+        handshake(server_config, &pki, &[&TLS12]).await.unwrap();
+    }
+}
+
+#[cfg(feature = "cert-watch")]
+mod watch {
+    use super::common::pki::Pki;
+    use super::common::timeout;
+    use futures::future;
+    use mpc_carrier::tls::{self, ReloadableAcceptor};
+    use rustls::pki_types::ServerName;
+    use std::fs;
+    use std::path::{Path, PathBuf};
+    use std::time::Duration;
+    use tokio::io::duplex;
+    use tokio::time::sleep;
+    use tokio_rustls::TlsConnector;
+
+    /// Writes a certificate for `a.test` issued by `pki` and its key into
+    /// `dir`.
+    fn write(dir: &Path, pki: &Pki) -> (PathBuf, PathBuf) {
+        let (cert, key) = pki.issue_pem(&["a.test"]);
+        let (cert_path, key_path) = (dir.join("cert.pem"), dir.join("key.pem"));
+        fs::write(&key_path, key).unwrap();
+        fs::write(&cert_path, cert).unwrap();
+        (cert_path, key_path)
+    }
+
+    /// Returns whether a client trusting `pki` completes a handshake with
+    /// `acceptor`.
+    async fn trusted(acceptor: &ReloadableAcceptor, pki: &Pki) -> bool {
+        let (client, server) = duplex(64 * 1024);
+        let connector = TlsConnector::from(pki.client_config());
+        let name = ServerName::try_from("a.test").unwrap();
+        let (client, _server) = future::join(
+            connector.connect(name, client),
+            acceptor.acceptor().accept(server),
+        )
+        .await;
+        client.is_ok()
+    }
+
+    #[tokio::test]
+    async fn reloads_written_certificate() {
+        let dir = std::env::temp_dir().join(format!("mpc-carrier-{}-watch", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let (old, new) = (Pki::new(), Pki::new());
+        let (cert, key) = write(&dir, &old);
+        let (acceptor, _watch) = tls::load_and_watch(&cert, &key).unwrap();
+        assert!(trusted(&acceptor, &old).await);
+        assert!(!trusted(&acceptor, &new).await);
+
+        write(&dir, &new);
+        timeout(async {
+            for _ in 0..50 {
+                if trusted(&acceptor, &new).await {
+                    return;
+                }
+                sleep(Duration::from_millis(10)).await;
+            }
+            panic!("certificate not reloaded within 500 ms");
+        })
+        .await;
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
+
+#[cfg(feature = "multi-cert")]
+mod multi_cert {
+    use super::common::pki::{addr, tcp_port, Pki, HOST};
+    use super::common::{respond, spawn, timeout};
+    use mpc_carrier::config::Direction;
+    use mpc_carrier::messages::fixtures;
+    use mpc_carrier::tls;
+    use mpc_carrier::transport::TlsTcpTransport;
+    use mpc_carrier::Carrier;
+
+    #[tokio::test]
+    async fn each_domain_gets_its_certificate() {
+        let domains = [(Pki::new(), "a.test"), (Pki::new(), "c.test")];
+        let certs = domains
+            .iter()
+            .map(|(pki, name)| pki.issue(&[name]))
+            .collect();
+        let server_config = tls::init_multi_cert(certs).unwrap();
+        let port = tcp_port();
+        // The dialers are identified by the names they dial.
+        let (carrier, incoming, _outgoing) =
+            Carrier::with_addrs(domains.iter().map(|(_, name)| (*name, addr(1, name))));
+        let carrier = domains.iter().fold(carrier, |carrier, (_, name)| {
+            carrier.direction(*name, Direction::Accept)
+        });
+        let client_config = domains[0].0.client_config();
+        spawn(
+            carrier,
+            TlsTcpTransport::new(HOST, port, server_config, client_config),
+        );
+        respond(incoming);
+
+        // Each dialer trusts the CA of its own domain only.
+        for (seed, (pki, name)) in domains.iter().enumerate() {
+            let (carrier, _incoming, mut outgoing) = Carrier::with_addrs([("s", addr(port, name))]);
+            let carrier = carrier
+                .direction("s", Direction::Dial)
+                .skip_unused_listener(true);
+            let server_config = pki.server_config(&[]);
+            spawn(
+                carrier,
+                TlsTcpTransport::new(HOST, 0, server_config, pki.client_config()),
+            );
+            let request = fixtures::node_request(seed as u64);
+            let response = timeout(outgoing.send("s", request.clone())).await;
+            assert_eq!(response.unwrap().request_id, request.request_id);
+        }
+    }
+}
@@ -0,0 +1,34 @@
+//! Nodes added and removed while the carrier runs.
+
+mod common;
+
+use common::{respond, spawn, timeout};
+use mpc_carrier::control::{AddError, RemoveError};
+use mpc_carrier::messages::fixtures;
+use mpc_carrier::transport::memory::MemoryNetwork;
+
+#[tokio::test]
+async fn add_then_remove_node() {
+    let network = MemoryNetwork::new();
+    let (carrier, incoming, _outgoing) = common::carrier(&["a"]);
+    spawn(carrier, network.transport("b"));
+    respond(incoming);
+    let (carrier, _incoming, _outgoing) = common::carrier(&[]);
+    let handle = carrier.handle();
+    spawn(carrier, network.transport("a"));
+
+    let mut outgoing = timeout(handle.add_node("b", 1)).await.unwrap();
+    let request = fixtures::node_request(1);
+    let response = timeout(outgoing.send("b", request.clone())).await.unwrap();
+    assert_eq!(response.request_id, request.request_id);
+    assert!(handle.debug_state().nodes.contains_key("b"));
+    let result = handle.add_node("b", 1).await;
+    assert!(matches!(result, Err(AddError::Exists(node)) if node == "b"));
+
+    timeout(handle.remove_node("b")).await.unwrap();
+    assert!(!handle.debug_state().nodes.contains_key("b"));
+    let request = fixtures::node_request(2);
+    assert!(timeout(outgoing.send("b", request)).await.is_err());
+    let err = handle.remove_node("b").await.unwrap_err();
+    assert!(matches!(err, RemoveError::NotFound(node) if node == "b"));
+}
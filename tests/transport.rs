@@ -0,0 +1,41 @@
+//! The same exchanges over the TLS transport and the in-memory one.
+
+mod common;
+
+use common::{carrier, exchange_all, spawn};
+use mpc_carrier::config::NodeId;
+use mpc_carrier::testing::Cluster;
+use mpc_carrier::transport::memory::MemoryNetwork;
+
+const NODES: [&str; 3] = ["a", "b", "c"];
+
+#[tokio::test]
+async fn tls_transport() {
+    let mut cluster = Cluster::start(NODES.len());
+    let mut nodes = cluster
+        .nodes()
+        .iter_mut()
+        .map(|node| (node.name.clone(), &mut node.incoming, &mut node.outgoing))
+        .collect::<Vec<_>>();
+    exchange_all(&mut nodes).await;
+    cluster.shutdown().await.unwrap();
+}
+
+#[tokio::test]
+async fn memory_transport() {
+    let network = MemoryNetwork::new();
+    let mut channels = NODES
+        .iter()
+        .map(|name| {
+            let peers = NODES.iter().copied().filter(|peer| peer != name);
+            let (carrier, incoming, outgoing) = carrier(&peers.collect::<Vec<_>>());
+            spawn(carrier, network.transport(*name));
+            (NodeId::from(*name), incoming, outgoing)
+        })
+        .collect::<Vec<_>>();
+    let mut nodes = channels
+        .iter_mut()
+        .map(|(name, incoming, outgoing)| (name.clone(), incoming, outgoing))
+        .collect::<Vec<_>>();
+    exchange_all(&mut nodes).await;
+}
@@ -0,0 +1,59 @@
+//! Hot reload of the node set from a peers file.
+
+mod common;
+
+use common::{carrier, spawn, timeout};
+use mpc_carrier::control::CarrierHandle;
+use mpc_carrier::transport::memory::MemoryNetwork;
+use mpc_carrier::watch::ConfigWatcher;
+use std::fs;
+use std::time::Duration;
+use tokio::time::sleep;
+
+/// Waits for the node set of the carrier to be `nodes`.
+async fn wait_for_nodes(handle: &CarrierHandle, nodes: &[&str]) {
+    timeout(async {
+        while handle.debug_state().nodes.keys().ne(nodes.iter()) {
+            sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await;
+}
+
+#[tokio::test]
+async fn follows_peers_file() {
+    let path = std::env::temp_dir().join(format!("mpc-carrier-{}-peers", std::process::id()));
+    fs::write(&path, "a:1\n").unwrap();
+    let network = MemoryNetwork::new();
+    let (carrier, _incoming, _outgoing) = carrier(&["a"]);
+    let handle = carrier.handle();
+    spawn(carrier, network.transport("self"));
+    let nodes = [("a".to_string(), 1)].into();
+    let watcher = ConfigWatcher::new(&path, handle.clone(), nodes)
+        .self_name("self")
+        .poll_interval(Duration::from_millis(10))
+        .debounce(Duration::from_millis(50));
+    let (added_tx, mut added) = tokio::sync::mpsc::unbounded_channel();
+    tokio::spawn(watcher.run(move |node, _| added_tx.send(node.to_string()).unwrap()));
+    // Lets the watcher read the modification time of the initial file.
+    sleep(Duration::from_millis(100)).await;
+
+    fs::write(&path, "# peers\nself:1\na:1\nb:1\n").unwrap();
+    wait_for_nodes(&handle, &["a", "b"]).await;
+    assert_eq!(added.recv().await.unwrap(), "b");
+
+    // An invalid file keeps the node set.
+    fs::write(&path, "a:1\nc:port\n").unwrap();
+    sleep(Duration::from_millis(200)).await;
+    assert!(handle.debug_state().nodes.keys().eq(["a", "b"]));
+
+    // A burst of writes is applied once, as of its last write.
+    fs::write(&path, "a:1\nd:1\n").unwrap();
+    fs::write(&path, "a:1\ne:10\n").unwrap();
+    wait_for_nodes(&handle, &["a", "e"]).await;
+    assert_eq!(added.recv().await.unwrap(), "e");
+
+    fs::write(&path, "a:1\n").unwrap();
+    wait_for_nodes(&handle, &["a"]).await;
+    fs::remove_file(&path).unwrap();
+}
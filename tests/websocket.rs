@@ -0,0 +1,97 @@
+//! Exchanges over WebSocket, directly and through an HTTP proxy.
+
+mod common;
+
+use common::pki::{addr, tcp_port, Pki, HOST};
+use common::{respond, spawn, timeout};
+use mpc_carrier::config::Direction;
+use mpc_carrier::messages::fixtures;
+use mpc_carrier::transport::websocket::WebSocketTransport;
+use mpc_carrier::Carrier;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+const MESSAGES: u64 = 100;
+
+/// Runs a carrier accepting the WebSocket connections of `a.test` on a port
+/// of its own, answering its requests, and returns the port.
+fn serve(pki: &Pki) -> u16 {
+    let port = tcp_port();
+    let (carrier, incoming, _outgoing) = Carrier::with_addrs([("a.test", addr(1, "b.test"))]);
+    let carrier = carrier.direction("a.test", Direction::Accept);
+    let server_config = pki.server_config(&["a.test"]);
+    let transport = WebSocketTransport::new(HOST, port, server_config, pki.client_config());
+    spawn(carrier, transport);
+    respond(incoming);
+    port
+}
+
+/// Sends requests over `transport` to the carrier of [`serve`] on `port`.
+async fn exchange(port: u16, transport: WebSocketTransport) {
+    let (carrier, _incoming, mut outgoing) = Carrier::with_addrs([("b", addr(port, "a.test"))]);
+    let carrier = carrier
+        .direction("b", Direction::Dial)
+        .skip_unused_listener(true);
+    spawn(carrier, transport);
+    for seed in 0..MESSAGES {
+        let request = fixtures::large_request(seed as usize * 1000);
+        let response = timeout(outgoing.send("b", request.clone())).await;
+        assert_eq!(response.unwrap().request_id, request.request_id);
+    }
+}
+
+/// Runs an HTTP proxy tunneling the `CONNECT` requests, counting them, and
+/// returns its port.
+async fn proxy(tunnels: Arc<AtomicUsize>) -> u16 {
+    let listener = TcpListener::bind((HOST, 0)).await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    tokio::spawn(async move {
+        loop {
+            let (mut client, _) = listener.accept().await.unwrap();
+            let tunnels = Arc::clone(&tunnels);
+            tokio::spawn(async move {
+                let mut request = Vec::new();
+                while !request.ends_with(b"\r\n\r\n") {
+                    request.push(client.read_u8().await.unwrap());
+                }
+                let request = String::from_utf8(request).unwrap();
+                let target = request
+                    .strip_prefix("CONNECT ")
+                    .and_then(|line| line.split(' ').next())
+                    .unwrap();
+                let mut server = TcpStream::connect(target).await.unwrap();
+                tunnels.fetch_add(1, Ordering::Relaxed);
+                client
+                    .write_all(b"HTTP/1.1 200 Connection established\r\n\r\n")
+                    .await
+                    .unwrap();
+                let _ = tokio::io::copy_bidirectional(&mut client, &mut server).await;
+            });
+        }
+    });
+    port
+}
+
+#[tokio::test]
+async fn exchange_over_websocket() {
+    let pki = Pki::new();
+    let port = serve(&pki);
+    let server_config = pki.server_config(&["b.test"]);
+    let transport = WebSocketTransport::new(HOST, 0, server_config, pki.client_config());
+    exchange(port, transport).await;
+}
+
+#[tokio::test]
+async fn exchange_through_connect_proxy() {
+    let pki = Pki::new();
+    let port = serve(&pki);
+    let tunnels = Arc::new(AtomicUsize::new(0));
+    let proxy_port = proxy(Arc::clone(&tunnels)).await;
+    let server_config = pki.server_config(&["b.test"]);
+    let transport = WebSocketTransport::new(HOST, 0, server_config, pki.client_config())
+        .proxy(HOST, proxy_port);
+    exchange(port, transport).await;
+    assert_eq!(tunnels.load(Ordering::Relaxed), 1);
+}